@@ -0,0 +1,67 @@
+use crate::{Mat4, Sphere, Vec4};
+
+/// View frustum as six planes, xyz is the inward facing normal and w the distance,
+/// so a point p is inside a plane when `dot(xyz, p) + w >= 0`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    pub planes: [Vec4; 6],
+}
+
+impl Frustum {
+    //Gribb-Hartmann extraction, with the -w..w depth range that Mat4::create_proj uses
+    pub fn from_view_proj(view_proj: &Mat4) -> Self {
+        let row0 = view_proj.extract_row(0);
+        let row1 = view_proj.extract_row(1);
+        let row2 = view_proj.extract_row(2);
+        let row3 = view_proj.extract_row(3);
+        let add = |a: &Vec4, b: &Vec4| Vec4([a[0] + b[0], a[1] + b[1], a[2] + b[2], a[3] + b[3]]);
+        let sub = |a: &Vec4, b: &Vec4| Vec4([a[0] - b[0], a[1] - b[1], a[2] - b[2], a[3] - b[3]]);
+        let planes = [
+            add(&row3, &row0),
+            sub(&row3, &row0),
+            add(&row3, &row1),
+            sub(&row3, &row1),
+            add(&row3, &row2),
+            sub(&row3, &row2),
+        ];
+        Self {
+            planes: planes.map(Self::normalize_plane),
+        }
+    }
+
+    fn normalize_plane(plane: Vec4) -> Vec4 {
+        let length = (plane[0] * plane[0] + plane[1] * plane[1] + plane[2] * plane[2]).sqrt();
+        if length > 0.0 {
+            Vec4([
+                plane[0] / length,
+                plane[1] / length,
+                plane[2] / length,
+                plane[3] / length,
+            ])
+        } else {
+            plane
+        }
+    }
+
+    /// True if any part of the sphere is inside the frustum. Spheres close to a corner
+    /// can be kept even though they are outside, never the other way around.
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        let center = Vec4::from_xyz(sphere.center[0], sphere.center[1], sphere.center[2]);
+        self.planes
+            .iter()
+            .all(|plane| Vec4::dot(plane, &center) >= -sphere.radius)
+    }
+
+    /// Indices of the spheres that intersect the frustum, in order.
+    pub fn visible_spheres<'a, I>(&self, spheres: I) -> Vec<u32>
+    where
+        I: IntoIterator<Item = &'a Sphere>,
+    {
+        spheres
+            .into_iter()
+            .enumerate()
+            .filter(|(_, sphere)| self.intersects_sphere(sphere))
+            .map(|(i, _)| i as u32)
+            .collect()
+    }
+}
@@ -1,7 +1,9 @@
 #![allow(dead_code)]
 
 pub mod aabb;
+pub mod frustum;
 pub mod mat4;
+pub mod meshgen;
 pub mod quat;
 pub mod sphere;
 pub mod transform;
@@ -9,6 +11,7 @@ pub mod vec3;
 pub mod vec4;
 
 pub use self::aabb::AABB;
+pub use self::frustum::Frustum;
 pub use self::mat4::Mat4;
 pub use self::quat::Quat;
 pub use self::sphere::Sphere;
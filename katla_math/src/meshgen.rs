@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+
+use bevy_mikktspace::Geometry;
+
+use crate::Vec3;
+
+const KINDA_SMALL_NUMBER: f32 = 0.00001f32;
+
+//`vector` with its component along the unit vector `normal` removed
+fn orthogonalize(vector: Vec3, normal: Vec3) -> Vec3 {
+    vector - normal * normal.dot(vector)
+}
+
+//Any unit vector perpendicular to the unit vector `normal`
+fn perpendicular(normal: Vec3) -> Vec3 {
+    let axis = if normal[0].abs() < 0.9 {
+        Vec3::new(1.0, 0.0, 0.0)
+    } else {
+        Vec3::new(0.0, 1.0, 0.0)
+    };
+    orthogonalize(axis, normal).normalize()
+}
+
+//Angle of the triangle at `corner`, between the edges towards `a` and `b`
+fn corner_angle(corner: Vec3, a: Vec3, b: Vec3) -> f32 {
+    let edge_a = (a - corner).normalize();
+    let edge_b = (b - corner).normalize();
+    edge_a.dot(edge_b).clamp(-1.0, 1.0).acos()
+}
+
+/// Tangents generated for an indexed triangle list. Vertices whose triangles need different
+/// tangents are split, so the result comes with its own vertices and indices. Input vertices
+/// keep their index, the copies come after them.
+#[derive(Clone, Debug)]
+pub struct GeneratedTangents {
+    /// xyz is the tangent and w the handedness, `bitangent = cross(normal, tangent.xyz) * w`
+    pub tangents: Vec<[f32; 4]>,
+    /// Input vertex each output vertex was copied from
+    pub sources: Vec<u32>,
+    pub indices: Vec<u32>,
+}
+
+impl GeneratedTangents {
+    /// Copies a vertex attribute of the input vertices to the output vertices.
+    pub fn remap<T: Copy>(&self, attribute: &[T]) -> Vec<T> {
+        self.sources
+            .iter()
+            .map(|&source| attribute[source as usize])
+            .collect()
+    }
+}
+
+//Triangle list as MikkTSpace sees it, collecting the tangent of every corner
+struct TangentGeometry<'a> {
+    positions: &'a [[f32; 3]],
+    normals: &'a [[f32; 3]],
+    tex_coords: &'a [[f32; 2]],
+    triangles: Vec<[u32; 3]>,
+    corner_tangents: Vec<[f32; 4]>,
+}
+
+impl<'a> TangentGeometry<'a> {
+    fn vertex(&self, face: usize, vert: usize) -> usize {
+        self.triangles[face][vert] as usize
+    }
+}
+
+impl<'a> Geometry for TangentGeometry<'a> {
+    fn num_faces(&self) -> usize {
+        self.triangles.len()
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.positions[self.vertex(face, vert)]
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.normals[self.vertex(face, vert)]
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        self.tex_coords[self.vertex(face, vert)]
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        self.corner_tangents[face * 3 + vert] = tangent;
+    }
+}
+
+/// Generates MikkTSpace tangents for glTF, where w is the handedness of the tangent frame,
+/// so normal maps baked with MikkTSpace shade as intended.
+///
+/// MikkTSpace gives every triangle corner a tangent. Corners of a vertex that get different
+/// tangents, e.g. where mirrored texture coordinates meet, get a copy of the vertex each.
+/// Vertices without usable texture coordinates get any tangent perpendicular to the normal,
+/// and triangles with out of range indices are skipped.
+pub fn generate_tangents(
+    positions: &[[f32; 3]],
+    normals: &[[f32; 3]],
+    tex_coords: &[[f32; 2]],
+    indices: &[u32],
+) -> GeneratedTangents {
+    let count = positions.len().min(normals.len());
+    let usable = count.min(tex_coords.len());
+    //Index of every triangle handed to MikkTSpace
+    let mut triangle_indices = vec![];
+    let mut triangles = vec![];
+    for (triangle_index, triangle) in indices.chunks_exact(3).enumerate() {
+        if triangle.iter().all(|&i| (i as usize) < usable) {
+            triangle_indices.push(triangle_index);
+            triangles.push([triangle[0], triangle[1], triangle[2]]);
+        }
+    }
+    let mut geometry = TangentGeometry {
+        positions,
+        normals,
+        tex_coords,
+        corner_tangents: vec![[0.0; 4]; triangles.len() * 3],
+        triangles,
+    };
+    if !geometry.triangles.is_empty() && !bevy_mikktspace::generate_tangents(&mut geometry) {
+        geometry
+            .corner_tangents
+            .iter_mut()
+            .for_each(|t| *t = [0.0; 4]);
+    }
+
+    let normal = |i: usize| Vec3(normals[i]).normalize();
+    let mut sources: Vec<u32> = (0..count as u32).collect();
+    let mut tangents: Vec<Option<[f32; 4]>> = vec![None; count];
+    //Copy of an input vertex for each further tangent its corners got
+    let mut copies: HashMap<(u32, [u32; 4]), u32> = HashMap::new();
+    let mut out_indices = indices.to_vec();
+    for (corner, &tangent) in geometry.corner_tangents.iter().enumerate() {
+        let source = geometry.triangles[corner / 3][corner % 3];
+        let mut tangent = tangent;
+        if Vec3([tangent[0], tangent[1], tangent[2]]).distance_squared() < KINDA_SMALL_NUMBER {
+            let perpendicular = perpendicular(normal(source as usize));
+            tangent = [perpendicular[0], perpendicular[1], perpendicular[2], 1.0];
+        }
+        let vertex = match tangents[source as usize] {
+            None => {
+                tangents[source as usize] = Some(tangent);
+                source
+            }
+            Some(existing) if existing == tangent => source,
+            Some(_) => *copies
+                .entry((source, tangent.map(f32::to_bits)))
+                .or_insert_with(|| {
+                    sources.push(source);
+                    tangents.push(Some(tangent));
+                    sources.len() as u32 - 1
+                }),
+        };
+        out_indices[triangle_indices[corner / 3] * 3 + corner % 3] = vertex;
+    }
+
+    let tangents = sources
+        .iter()
+        .zip(tangents)
+        .map(|(&source, tangent)| {
+            tangent.unwrap_or_else(|| {
+                let tangent = perpendicular(normal(source as usize));
+                [tangent[0], tangent[1], tangent[2], 1.0]
+            })
+        })
+        .collect();
+    GeneratedTangents {
+        tangents,
+        sources,
+        indices: out_indices,
+    }
+}
+
+/// How the triangles around a vertex contribute to its smooth normal.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NormalWeighting {
+    /// By triangle area, large triangles dominate
+    Area,
+    /// By the angle of the triangle at the vertex, independent of tessellation
+    Angle,
+}
+
+/// Normals generated for an indexed triangle list. Splitting creases or flat shading
+/// duplicates vertices, so the result comes with its own vertices and indices.
+#[derive(Clone, Debug)]
+pub struct GeneratedNormals {
+    pub normals: Vec<[f32; 3]>,
+    /// Input vertex each output vertex was copied from
+    pub sources: Vec<u32>,
+    pub indices: Vec<u32>,
+}
+
+impl GeneratedNormals {
+    /// Copies a vertex attribute of the input vertices to the output vertices.
+    pub fn remap<T: Copy>(&self, attribute: &[T]) -> Vec<T> {
+        self.sources
+            .iter()
+            .map(|&source| attribute[source as usize])
+            .collect()
+    }
+}
+
+//The complete triangles of `indices` whose vertices all exist
+fn valid_triangles(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    indices
+        .chunks_exact(3)
+        .filter(|triangle| triangle.iter().all(|&i| (i as usize) < vertex_count))
+        .flatten()
+        .copied()
+        .collect()
+}
+
+//Unit normal and weight of every corner, in index order
+fn corner_normals(
+    positions: &[[f32; 3]],
+    indices: &[u32],
+    weighting: NormalWeighting,
+) -> Vec<(Vec3, f32)> {
+    let mut corners = Vec::with_capacity(indices.len());
+    for triangle in indices.chunks_exact(3) {
+        let points = [
+            Vec3(positions[triangle[0] as usize]),
+            Vec3(positions[triangle[1] as usize]),
+            Vec3(positions[triangle[2] as usize]),
+        ];
+        let cross = (points[1] - points[0]).cross(points[2] - points[0]);
+        let normal = cross.normalize();
+        for corner in 0..3 {
+            let weight = match weighting {
+                NormalWeighting::Area => cross.distance() * 0.5,
+                NormalWeighting::Angle => corner_angle(
+                    points[corner],
+                    points[(corner + 1) % 3],
+                    points[(corner + 2) % 3],
+                ),
+            };
+            corners.push((normal, weight));
+        }
+    }
+    corners
+}
+
+/// Generates smooth normals for a triangle list, averaging the triangles that share a vertex.
+///
+/// With a `crease_angle` in radians, triangles only smooth each other when their normals
+/// differ by at most that angle, and vertices on sharper edges are split. Vertices are
+/// shared by index only, so positions duplicated along UV seams keep a visible seam.
+/// Vertices no triangle uses get a zero normal, or are dropped when splitting creases.
+/// Triangles with out of range indices are dropped.
+pub fn generate_smooth_normals(
+    positions: &[[f32; 3]],
+    indices: &[u32],
+    weighting: NormalWeighting,
+    crease_angle: Option<f32>,
+) -> GeneratedNormals {
+    let indices = &valid_triangles(indices, positions.len());
+    let corners = corner_normals(positions, indices, weighting);
+    let zero = Vec3::new(0.0, 0.0, 0.0);
+
+    let crease_cos = match crease_angle {
+        Some(angle) => angle.cos(),
+        None => {
+            let mut normals = vec![zero; positions.len()];
+            for (&index, &(normal, weight)) in indices.iter().zip(corners.iter()) {
+                normals[index as usize] = normals[index as usize] + normal * weight;
+            }
+            return GeneratedNormals {
+                normals: normals.iter().map(|normal| normal.normalize().0).collect(),
+                sources: (0..positions.len() as u32).collect(),
+                indices: indices.to_vec(),
+            };
+        }
+    };
+
+    let mut vertex_corners: Vec<Vec<usize>> = vec![vec![]; positions.len()];
+    for (corner, &index) in indices.iter().enumerate() {
+        vertex_corners[index as usize].push(corner);
+    }
+
+    let mut normals: Vec<[f32; 3]> = vec![];
+    let mut sources = vec![];
+    let mut new_indices = vec![0; indices.len()];
+    for (vertex, shared) in vertex_corners.iter().enumerate() {
+        //Output vertices of this input vertex, corners with equal normals share one
+        let first_output = normals.len();
+        for &corner in shared {
+            let face_normal = corners[corner].0;
+            let normal = shared
+                .iter()
+                .map(|&other| corners[other])
+                .filter(|(other_normal, _)| face_normal.dot(*other_normal) >= crease_cos)
+                .fold(zero, |sum, (other_normal, weight)| {
+                    sum + other_normal * weight
+                })
+                .normalize()
+                .0;
+            let existing = normals[first_output..]
+                .iter()
+                .position(|&output| output == normal);
+            new_indices[corner] = match existing {
+                Some(offset) => (first_output + offset) as u32,
+                None => {
+                    normals.push(normal);
+                    sources.push(vertex as u32);
+                    (normals.len() - 1) as u32
+                }
+            };
+        }
+    }
+    GeneratedNormals {
+        normals,
+        sources,
+        indices: new_indices,
+    }
+}
+
+/// Generates flat normals for a triangle list, every triangle gets its own three vertices.
+/// Triangles with out of range indices are dropped.
+pub fn generate_flat_normals(positions: &[[f32; 3]], indices: &[u32]) -> GeneratedNormals {
+    let indices = &valid_triangles(indices, positions.len());
+    let corners = corner_normals(positions, indices, NormalWeighting::Area);
+    GeneratedNormals {
+        normals: corners.iter().map(|(normal, _)| normal.0).collect(),
+        sources: indices.to_vec(),
+        indices: (0..indices.len() as u32).collect(),
+    }
+}
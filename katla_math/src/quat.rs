@@ -38,6 +38,11 @@ impl Quat {
         }
     }
 
+    /// From the components of a unit quaternion, in the x, y, z, w order glTF uses.
+    pub fn new_from_xyzw(x: f32, y: f32, z: f32, w: f32) -> Quat {
+        Quat { x, y, z, w }
+    }
+
     pub fn new_from_axis_angle(axis: Vec3, angle: f32) -> Quat {
         let axis = axis.normalize();
         let factor = f32::sin(angle / 2.0);
@@ -63,11 +68,11 @@ impl Quat {
     }
 
     pub fn normalize(&mut self) {
-        let len_sq = self.length_squared();
-        self.x = self.x / len_sq;
-        self.y = self.y / len_sq;
-        self.z = self.z / len_sq;
-        self.w = self.w / len_sq;
+        let len = self.length_squared().sqrt();
+        self.x = self.x / len;
+        self.y = self.y / len;
+        self.z = self.z / len;
+        self.w = self.w / len;
     }
 
     pub fn inverse(&self) -> Self {
@@ -93,30 +98,41 @@ impl Quat {
         2.0 * u.dot(v) * u + (s * s - u.dot(u)) * v + 2.0 * s * u.cross(v)
     }
 
-    //Spherical interpolation, a slow version
+    //Spherical interpolation along the shortest arc, a slow version
     pub fn slerp(mut a: Quat, mut b: Quat, ratio: f32) -> Self {
         a.normalize();
         b.normalize();
 
-        let cs = a.dot(b);
+        //b and -b are the same rotation, the one closer to a is the shorter way
+        let mut cs = a.dot(b);
+        if cs < 0.0 {
+            cs = -cs;
+            b = Quat {
+                x: -b.x,
+                y: -b.y,
+                z: -b.z,
+                w: -b.w,
+            };
+        }
 
-        let angle = f32::acos(cs);
-        let mut out = Self::new();
-        if f32::abs(angle) >= 0.001 {
+        let angle = f32::acos(cs.min(1.0));
+        let (coeff0, coeff1) = if f32::abs(angle) >= 0.001 {
             let inv_sin = 1.0f32 / f32::sin(angle);
             let t_angle = ratio * angle;
-            let coeff0 = f32::sin(angle - t_angle) * inv_sin;
-            let coeff1 = f32::sin(t_angle) * inv_sin;
-            out.x = coeff0 * a.x + coeff1 * b.x;
-            out.y = coeff0 * a.y + coeff1 * b.y;
-            out.z = coeff0 * a.z + coeff1 * b.z;
-            out.w = coeff0 * a.w + coeff1 * b.w;
+            (
+                f32::sin(angle - t_angle) * inv_sin,
+                f32::sin(t_angle) * inv_sin,
+            )
         } else {
-            out.x = a.x;
-            out.y = a.y;
-            out.z = a.z;
-            out.w = a.w;
-        }
+            //Nearly equal rotations, where linear interpolation is close enough
+            (1.0 - ratio, ratio)
+        };
+        let mut out = Quat {
+            x: coeff0 * a.x + coeff1 * b.x,
+            y: coeff0 * a.y + coeff1 * b.y,
+            z: coeff0 * a.z + coeff1 * b.z,
+            w: coeff0 * a.w + coeff1 * b.w,
+        };
 
         out.normalize(); // be safe
         out
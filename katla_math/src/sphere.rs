@@ -1,8 +1,8 @@
-use crate::Vec3;
+use crate::{mat4_mul_vec3, Mat4, Vec3};
 
 const KINDA_SMALL_NUMBER: f32 = 0.00001f32;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Sphere {
     pub center: Vec3,
     pub radius: f32,
@@ -32,6 +32,40 @@ impl Sphere {
         dist_sq <= radius_sum * radius_sum
     }
 
+    /// Grows the sphere just enough to contain `other`.
+    pub fn enclose(&mut self, other: &Sphere) {
+        let offset = other.center - self.center;
+        let distance = offset.distance();
+        if distance + other.radius <= self.radius {
+            return;
+        }
+        if distance + self.radius <= other.radius {
+            *self = other.clone();
+            return;
+        }
+        let radius = (distance + self.radius + other.radius) * 0.5;
+        self.center = self.center + offset.mul((radius - self.radius) / distance);
+        self.radius = radius;
+    }
+
+    /// Bounds of the sphere after `transform`, scaled by its largest axis scale.
+    pub fn transformed(&self, transform: &Mat4) -> Self {
+        let scale = (0..3)
+            .map(|column| {
+                Vec3::new(
+                    transform[column][0],
+                    transform[column][1],
+                    transform[column][2],
+                )
+            })
+            .map(|axis| axis.distance())
+            .fold(0.0, f32::max);
+        Self {
+            center: mat4_mul_vec3(transform, &self.center),
+            radius: self.radius * scale,
+        }
+    }
+
     //Create a bounding sphere from a slice that can be made into a vec3
     pub fn create_from_verts<'a, I, T: 'a>(verts: I) -> Self
     where
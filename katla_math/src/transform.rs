@@ -2,6 +2,7 @@ use std::ops::Mul;
 
 use crate::{Mat4, Quat, Vec3, Vec4};
 
+#[derive(Debug, Clone)]
 pub struct Transform {
     pub position: Vec3,
     pub scale: Vec3,
@@ -41,6 +42,7 @@ impl Transform {
         }
     }
 
+    /// Scales, then rotates, then translates, the same order as glTF node transforms.
     pub fn make_mat4(&self) -> Mat4 {
         let scale_mat = Mat4([
             Vec4([self.scale[0], 0.0, 0.0, 0.0]),
@@ -50,7 +52,7 @@ impl Transform {
         ]);
         let rot_mat = self.rotation.make_mat4();
         let pos_mat = Mat4::from_translation(self.position.0);
-        pos_mat.mul(&scale_mat.mul(&rot_mat))
+        pos_mat.mul(&rot_mat.mul(&scale_mat))
     }
 }
 
@@ -73,7 +75,7 @@ impl Mul<Vec3> for Transform {
     type Output = Vec3;
 
     fn mul(self, v: Vec3) -> Self::Output {
-        let out_pos = self.position + (self.scale * (self.rotation * v));
+        let out_pos = self.position + (self.rotation * (self.scale * v));
         out_pos
     }
 }
@@ -0,0 +1,54 @@
+use approx::assert_abs_diff_eq;
+use katla_math::{Frustum, Mat4, Sphere, Vec3};
+
+//Camera at the origin looking down -z with a 90 degree field of view
+fn make_frustum() -> Frustum {
+    let view = Mat4::create_lookat(
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+    )
+    .inverse();
+    let proj = Mat4::create_proj(90.0, 1.0, 0.1, 100.0);
+    Frustum::from_view_proj(&proj.mul(&view))
+}
+
+#[test]
+fn test_normalized_planes() {
+    let frustum = make_frustum();
+    for plane in &frustum.planes {
+        let length = (plane[0] * plane[0] + plane[1] * plane[1] + plane[2] * plane[2]).sqrt();
+        assert_abs_diff_eq!(length, 1.0, epsilon = 0.0001);
+    }
+}
+
+#[test]
+fn test_intersects_sphere() {
+    let frustum = make_frustum();
+    let in_front = Sphere::new(Vec3::new(0.0, 0.0, -10.0), 1.0);
+    assert!(frustum.intersects_sphere(&in_front));
+    let behind = Sphere::new(Vec3::new(0.0, 0.0, 10.0), 1.0);
+    assert!(!frustum.intersects_sphere(&behind));
+    let past_far = Sphere::new(Vec3::new(0.0, 0.0, -200.0), 1.0);
+    assert!(!frustum.intersects_sphere(&past_far));
+    let left = Sphere::new(Vec3::new(-50.0, 0.0, -10.0), 1.0);
+    assert!(!frustum.intersects_sphere(&left));
+    //Center outside the left plane, but the sphere reaches into the frustum
+    let left_edge = Sphere::new(Vec3::new(-10.5, 0.0, -10.0), 1.0);
+    assert!(frustum.intersects_sphere(&left_edge));
+    let above = Sphere::new(Vec3::new(0.0, 50.0, -10.0), 1.0);
+    assert!(!frustum.intersects_sphere(&above));
+}
+
+#[test]
+fn test_visible_spheres() {
+    let frustum = make_frustum();
+    let spheres = vec![
+        Sphere::new(Vec3::new(0.0, 0.0, -10.0), 1.0),
+        Sphere::new(Vec3::new(0.0, 0.0, 10.0), 1.0),
+        Sphere::new(Vec3::new(5.0, -5.0, -50.0), 1.0),
+        Sphere::new(Vec3::new(-50.0, 0.0, -10.0), 1.0),
+        Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0),
+    ];
+    assert_eq!(frustum.visible_spheres(&spheres), vec![0, 2, 4]);
+}
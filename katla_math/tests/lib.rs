@@ -1,4 +1,6 @@
 mod aabb;
+mod frustum;
+mod meshgen;
 mod quat;
 mod sphere;
 mod transform;
\ No newline at end of file
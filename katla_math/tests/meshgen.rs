@@ -0,0 +1,258 @@
+use approx::assert_relative_eq;
+use katla_math::meshgen::{
+    generate_flat_normals, generate_smooth_normals, generate_tangents, NormalWeighting,
+};
+
+//A quad in the xy plane facing +z, u along +x and v along +y
+const POSITIONS: [[f32; 3]; 4] = [
+    [0.0, 0.0, 0.0],
+    [1.0, 0.0, 0.0],
+    [1.0, 1.0, 0.0],
+    [0.0, 1.0, 0.0],
+];
+const NORMALS: [[f32; 3]; 4] = [[0.0, 0.0, 1.0]; 4];
+const INDICES: [u32; 6] = [0, 1, 2, 0, 2, 3];
+
+#[test]
+fn test_tangents_follow_uv() {
+    let tex_coords = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+    let generated = generate_tangents(&POSITIONS, &NORMALS, &tex_coords, &INDICES);
+    assert_eq!(generated.tangents.len(), 4);
+    assert_eq!(generated.indices, INDICES);
+    for tangent in generated.tangents {
+        assert_relative_eq!(tangent[0], 1.0, epsilon = 1e-5);
+        assert_relative_eq!(tangent[1], 0.0, epsilon = 1e-5);
+        assert_relative_eq!(tangent[2], 0.0, epsilon = 1e-5);
+        assert_eq!(tangent[3], 1.0);
+    }
+}
+
+#[test]
+fn test_tangents_mirrored_uv() {
+    //u runs along -x, so the bitangent stays +y and the handedness flips
+    let tex_coords = [[1.0, 0.0], [0.0, 0.0], [0.0, 1.0], [1.0, 1.0]];
+    let generated = generate_tangents(&POSITIONS, &NORMALS, &tex_coords, &INDICES);
+    assert_eq!(generated.tangents.len(), 4);
+    for tangent in generated.tangents {
+        assert_relative_eq!(tangent[0], -1.0, epsilon = 1e-5);
+        assert_eq!(tangent[3], -1.0);
+    }
+}
+
+#[test]
+fn test_tangents_degenerate_uv() {
+    let tex_coords = [[0.0, 0.0]; 4];
+    let generated = generate_tangents(&POSITIONS, &NORMALS, &tex_coords, &INDICES);
+    for tangent in generated.tangents {
+        //Still a unit vector perpendicular to the normal
+        let length =
+            (tangent[0] * tangent[0] + tangent[1] * tangent[1] + tangent[2] * tangent[2]).sqrt();
+        assert_relative_eq!(length, 1.0, epsilon = 1e-5);
+        assert_relative_eq!(tangent[2], 0.0, epsilon = 1e-5);
+    }
+}
+
+#[test]
+fn test_tangents_split_mirrored_triangles() {
+    //The second triangle is mirrored along the shared diagonal, u runs along +y there
+    let tex_coords = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [1.0, 0.0]];
+    let generated = generate_tangents(&POSITIONS, &NORMALS, &tex_coords, &INDICES);
+    //Vertices 0 and 2 on the diagonal get a copy for the mirrored triangle
+    assert_eq!(generated.sources, [0, 1, 2, 3, 0, 2]);
+    assert_eq!(generated.indices, [0, 1, 2, 4, 5, 3]);
+    for &vertex in &[0, 1, 2] {
+        let tangent = generated.tangents[vertex];
+        assert_relative_eq!(tangent[0], 1.0, epsilon = 1e-5);
+        assert_eq!(tangent[3], 1.0);
+    }
+    for &vertex in &[3, 4, 5] {
+        let tangent = generated.tangents[vertex];
+        assert_relative_eq!(tangent[1], 1.0, epsilon = 1e-5);
+        assert_eq!(tangent[3], -1.0);
+    }
+    assert_eq!(generated.remap(&tex_coords)[4], tex_coords[0]);
+}
+
+#[test]
+fn test_tangents_match_mikktspace() {
+    //The +x and -x sides of the cube in the regression test of the MikkTSpace port, each a
+    //fan of four triangles around a center vertex, with normals pointing away from the
+    //center of the cube. The expected tangents come from the reference implementation.
+    let corners: [([f32; 3], [f32; 2]); 10] = [
+        ([1.0, -1.0, 1.0], [0.0, 0.0]),
+        ([1.0, -1.0, -1.0], [0.0, 1.0]),
+        ([1.0, 1.0, -1.0], [1.0, 1.0]),
+        ([1.0, 1.0, 1.0], [1.0, 0.0]),
+        ([1.0, 0.0, 0.0], [0.5, 0.5]),
+        ([-1.0, 1.0, 1.0], [1.0, 0.0]),
+        ([-1.0, 1.0, -1.0], [1.0, 1.0]),
+        ([-1.0, -1.0, -1.0], [0.0, 1.0]),
+        ([-1.0, -1.0, 1.0], [0.0, 0.0]),
+        ([-1.0, 0.0, 0.0], [0.5, 0.5]),
+    ];
+    let positions: Vec<[f32; 3]> = corners
+        .iter()
+        .map(|(dir, _)| dir.map(|v| v * 0.5))
+        .collect();
+    let normals: Vec<[f32; 3]> = corners
+        .iter()
+        .map(|(dir, _)| {
+            let length = dir.iter().map(|v| v * v).sum::<f32>().sqrt();
+            dir.map(|v| v / length)
+        })
+        .collect();
+    let tex_coords: Vec<[f32; 2]> = corners.iter().map(|(_, uv)| *uv).collect();
+    let indices = [
+        0, 1, 4, 1, 2, 4, 2, 3, 4, 3, 0, 4, 5, 6, 9, 6, 7, 9, 7, 8, 9, 8, 5, 9,
+    ];
+
+    let (a, b) = (0.40824825, 0.81649655);
+    let expected = [
+        [a, b, a, -1.0],
+        [a, b, -a, -1.0],
+        [0.0, 1.0, 0.0, -1.0],
+        [a, b, -a, -1.0],
+        [-a, b, a, -1.0],
+        [0.0, 1.0, 0.0, -1.0],
+        [-a, b, a, -1.0],
+        [-a, b, -a, -1.0],
+        [0.0, 1.0, 0.0, -1.0],
+        [-a, b, -a, -1.0],
+        [a, b, a, -1.0],
+        [0.0, 1.0, 0.0, -1.0],
+        [a, b, -a, 1.0],
+        [a, b, a, 1.0],
+        [0.0, 1.0, 0.0, 1.0],
+        [a, b, a, 1.0],
+        [-a, b, -a, 1.0],
+        [0.0, 1.0, 0.0, 1.0],
+        [-a, b, -a, 1.0],
+        [-a, b, a, 1.0],
+        [0.0, 1.0, 0.0, 1.0],
+        [-a, b, a, 1.0],
+        [a, b, -a, 1.0],
+        [0.0, 1.0, 0.0, 1.0],
+    ];
+
+    let generated = generate_tangents(&positions, &normals, &tex_coords, &indices);
+    //Every vertex gets one tangent, so nothing is split
+    assert_eq!(generated.tangents.len(), 10);
+    assert_eq!(generated.indices, indices);
+    for (&vertex, expected) in generated.indices.iter().zip(&expected) {
+        let tangent = generated.tangents[vertex as usize];
+        for (component, expected) in tangent.iter().zip(expected) {
+            assert_relative_eq!(*component, *expected, epsilon = 1e-5);
+        }
+    }
+}
+
+//Unit cube around the origin, two triangles per face wound counter-clockwise from outside
+const CUBE_POSITIONS: [[f32; 3]; 8] = [
+    [-0.5, -0.5, -0.5],
+    [0.5, -0.5, -0.5],
+    [0.5, 0.5, -0.5],
+    [-0.5, 0.5, -0.5],
+    [-0.5, -0.5, 0.5],
+    [0.5, -0.5, 0.5],
+    [0.5, 0.5, 0.5],
+    [-0.5, 0.5, 0.5],
+];
+const CUBE_INDICES: [u32; 36] = [
+    4, 5, 6, 4, 6, 7, //+z
+    1, 0, 3, 1, 3, 2, //-z
+    5, 1, 2, 5, 2, 6, //+x
+    0, 4, 7, 0, 7, 3, //-x
+    7, 6, 2, 7, 2, 3, //+y
+    0, 1, 5, 0, 5, 4, //-y
+];
+
+#[test]
+fn test_smooth_normals_angle_weighted() {
+    let generated =
+        generate_smooth_normals(&CUBE_POSITIONS, &CUBE_INDICES, NormalWeighting::Angle, None);
+    assert_eq!(generated.normals.len(), 8);
+    assert_eq!(generated.indices, CUBE_INDICES.to_vec());
+    //Every face meets a corner at 90 degrees, however it is triangulated
+    let diagonal = 1.0 / 3.0f32.sqrt();
+    for (normal, position) in generated.normals.iter().zip(CUBE_POSITIONS.iter()) {
+        for axis in 0..3 {
+            assert_relative_eq!(
+                normal[axis],
+                diagonal * position[axis].signum(),
+                epsilon = 1e-5
+            );
+        }
+    }
+}
+
+#[test]
+fn test_smooth_normals_area_weighted() {
+    let generated =
+        generate_smooth_normals(&CUBE_POSITIONS, &CUBE_INDICES, NormalWeighting::Area, None);
+    for (normal, position) in generated.normals.iter().zip(CUBE_POSITIONS.iter()) {
+        let length = (normal[0] * normal[0] + normal[1] * normal[1] + normal[2] * normal[2]).sqrt();
+        assert_relative_eq!(length, 1.0, epsilon = 1e-5);
+        for axis in 0..3 {
+            assert_eq!(normal[axis].signum(), position[axis].signum());
+        }
+    }
+}
+
+#[test]
+fn test_smooth_normals_crease() {
+    let generated = generate_smooth_normals(
+        &CUBE_POSITIONS,
+        &CUBE_INDICES,
+        NormalWeighting::Angle,
+        Some(std::f32::consts::FRAC_PI_4),
+    );
+    //Every corner is split into one vertex per face
+    assert_eq!(generated.normals.len(), 24);
+    assert_eq!(generated.sources.len(), 24);
+    for (corner, &index) in generated.indices.iter().enumerate() {
+        assert_eq!(generated.sources[index as usize], CUBE_INDICES[corner]);
+    }
+    let positions = generated.remap(&CUBE_POSITIONS);
+    for triangle in generated.indices.chunks(3) {
+        let normal = generated.normals[triangle[0] as usize];
+        assert_eq!(normal, generated.normals[triangle[1] as usize]);
+        assert_eq!(normal, generated.normals[triangle[2] as usize]);
+        //Axis aligned and pointing out of the cube
+        let axis = (0..3).find(|&axis| normal[axis] != 0.0).unwrap();
+        assert_relative_eq!(normal[axis].abs(), 1.0, epsilon = 1e-5);
+        assert_eq!(
+            normal[axis].signum(),
+            positions[triangle[0] as usize][axis].signum()
+        );
+    }
+}
+
+#[test]
+fn test_flat_normals() {
+    let generated = generate_flat_normals(&POSITIONS, &INDICES);
+    assert_eq!(generated.normals.len(), 6);
+    assert_eq!(generated.sources, INDICES.to_vec());
+    assert_eq!(generated.indices, (0..6).collect::<Vec<u32>>());
+    for normal in generated.normals {
+        assert_eq!(normal, [0.0, 0.0, 1.0]);
+    }
+}
+
+#[test]
+fn test_normals_skip_out_of_range_triangles() {
+    //The middle triangle points past the last vertex
+    let indices = [0, 1, 2, 0, 2, 7, 0, 2, 3];
+    let generated = generate_flat_normals(&POSITIONS, &indices);
+    assert_eq!(generated.sources, INDICES.to_vec());
+    assert_eq!(generated.normals.len(), 6);
+
+    let generated = generate_smooth_normals(&POSITIONS, &indices, NormalWeighting::Angle, None);
+    assert_eq!(generated.indices, INDICES.to_vec());
+    for normal in generated.normals {
+        assert_eq!(normal, [0.0, 0.0, 1.0]);
+    }
+
+    let generated = generate_smooth_normals(&POSITIONS, &indices, NormalWeighting::Area, Some(0.5));
+    assert_eq!(generated.indices, INDICES.to_vec());
+    assert_eq!(generated.sources, [0, 1, 2, 3]);
+}
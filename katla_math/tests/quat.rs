@@ -1,5 +1,5 @@
 use approx::assert_abs_diff_eq;
-use std::f32::consts::FRAC_PI_2;
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
 
 use katla_math::{mat4_mul_vec3, Quat, Vec3};
 
@@ -60,3 +60,44 @@ fn test_quat_mat() {
     assert_abs_diff_eq!(mat_rotated[1], quat_rotated[1], epsilon = 0.0001);
     assert_abs_diff_eq!(mat_rotated[2], quat_rotated[2], epsilon = 0.0001);
 }
+
+#[test]
+fn test_quat_slerp() {
+    let y_axis = Vec3::new(0.0, 1.0, 0.0);
+    let a = Quat::new();
+    let b = Quat::new_from_axis_angle(y_axis, FRAC_PI_2);
+    let half = Quat::slerp(a, b, 0.5);
+    let expected = Quat::new_from_axis_angle(y_axis, FRAC_PI_4);
+    for i in 0..4 {
+        assert_abs_diff_eq!(half[i], expected[i], epsilon = 0.0001);
+    }
+    let end = Quat::slerp(a, b, 1.0);
+    for i in 0..4 {
+        assert_abs_diff_eq!(end[i], b[i], epsilon = 0.0001);
+    }
+}
+
+#[test]
+fn test_quat_slerp_shortest_arc() {
+    let y_axis = Vec3::new(0.0, 1.0, 0.0);
+    let a = Quat::new_from_axis_angle(y_axis, 0.1);
+    let b = Quat::new_from_axis_angle(y_axis, 0.3);
+    //The same rotation as b, on the other side of the hypersphere
+    let negated_b = Quat::new_from_xyzw(-b[0], -b[1], -b[2], -b[3]);
+    let half = Quat::slerp(a, negated_b, 0.5);
+    let vec = Vec3::new(1.0, 0.0, 0.0);
+    let rotated = half * vec;
+    let expected = Quat::new_from_axis_angle(y_axis, 0.2) * vec;
+    assert_abs_diff_eq!(rotated[0], expected[0], epsilon = 0.0001);
+    assert_abs_diff_eq!(rotated[1], expected[1], epsilon = 0.0001);
+    assert_abs_diff_eq!(rotated[2], expected[2], epsilon = 0.0001);
+}
+
+#[test]
+fn test_quat_slerp_equal() {
+    let quat = Quat::new_from_axis_angle(Vec3::new(1.0, 2.0, 3.0), 0.7);
+    let slerped = Quat::slerp(quat, quat, 0.3);
+    for i in 0..4 {
+        assert_abs_diff_eq!(slerped[i], quat[i], epsilon = 0.0001);
+    }
+}
@@ -1,5 +1,6 @@
 use katla_math::Sphere;
 use katla_math::Vec3;
+use katla_math::{Quat, Transform};
 
 #[test]
 fn test_expand() {
@@ -53,3 +54,28 @@ fn test_into() {
     let sphere = Sphere::create_from_verts(&list_of_verts);
     assert_eq!(sphere.radius, 0.5);
 }
+
+#[test]
+fn test_enclose() {
+    let mut sphere = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0);
+    sphere.enclose(&Sphere::new(Vec3::new(0.5, 0.0, 0.0), 0.5));
+    assert_eq!(sphere.radius, 1.0);
+    sphere.enclose(&Sphere::new(Vec3::new(3.0, 0.0, 0.0), 1.0));
+    assert_eq!(sphere.radius, 2.5);
+    assert_eq!(sphere.center[0], 1.5);
+    sphere.enclose(&Sphere::new(Vec3::new(0.0, 0.0, 0.0), 10.0));
+    assert_eq!(sphere.radius, 10.0);
+    assert_eq!(sphere.center[0], 0.0);
+}
+
+#[test]
+fn test_transformed() {
+    let sphere = Sphere::new(Vec3::new(1.0, 0.0, 0.0), 1.0);
+    let mut transform = Transform::new_from_position(Vec3::new(0.0, 2.0, 0.0));
+    transform.scale = Vec3::new(1.0, 3.0, 1.0);
+    transform.rotation = Quat::new_from_axis_angle(Vec3::new(0.0, 0.0, 1.0), std::f32::consts::PI);
+    let transformed = sphere.transformed(&transform.make_mat4());
+    assert!((transformed.center[0] + 1.0).abs() < 0.0001);
+    assert!((transformed.center[1] - 2.0).abs() < 0.0001);
+    assert!((transformed.radius - 3.0).abs() < 0.0001);
+}
@@ -82,3 +82,25 @@ fn test_transform() {
     assert_abs_diff_eq!(transformed_vertex[1], -2.0, epsilon = 0.0001);
     assert_abs_diff_eq!(transformed_vertex[2], 0.0, epsilon = 0.0001);
 }
+
+#[test]
+fn test_transform_non_uniform_scale() {
+    //Scaled along x first, then rotated onto y
+    let position = Vec3::new(0.0, 0.0, 1.0);
+    let scale = Vec3::new(2.0, 1.0, 1.0);
+    let rotation = Quat::new_from_axis_angle(Vec3::new(0.0, 0.0, 1.0), FRAC_PI_2);
+    let transform = Transform {
+        position,
+        scale,
+        rotation,
+    };
+    let vertex = Vec3::new(1.0, 0.0, 0.0);
+    let transformed_vertex = mat4_mul_vec3(&transform.make_mat4(), &vertex);
+    assert_abs_diff_eq!(transformed_vertex[0], 0.0, epsilon = 0.0001);
+    assert_abs_diff_eq!(transformed_vertex[1], 2.0, epsilon = 0.0001);
+    assert_abs_diff_eq!(transformed_vertex[2], 1.0, epsilon = 0.0001);
+    let transformed_vertex = transform * vertex;
+    assert_abs_diff_eq!(transformed_vertex[0], 0.0, epsilon = 0.0001);
+    assert_abs_diff_eq!(transformed_vertex[1], 2.0, epsilon = 0.0001);
+    assert_abs_diff_eq!(transformed_vertex[2], 1.0, epsilon = 0.0001);
+}
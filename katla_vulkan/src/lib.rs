@@ -6,24 +6,85 @@ use ash::vk;
 
 use std::{ffi::CString, sync::Arc};
 
-pub use ash::vk::{Format, IndexType, PipelineBindPoint};
+pub use ash::vk::{
+    DrawIndexedIndirectCommand, Format, IndexType, PipelineBindPoint, ShaderStageFlags,
+    VertexInputRate,
+};
+
+/// User facing renderer configuration, handed to `VulkanRenderer::init`.
+#[derive(Clone, Debug)]
+pub struct RendererSettings {
+    /// MSAA sample count for the opaque pass: 1, 2, 4 or 8.
+    /// Clamped to what the device supports.
+    pub sample_count: u32,
+    /// Pick an HDR10 or scRGB swapchain format when the surface offers one.
+    pub hdr_output: bool,
+    pub tonemapper: Tonemapper,
+    /// Scene color is multiplied by this before tonemapping.
+    pub exposure: f32,
+    /// Brightness of a tonemapped 1.0 on HDR outputs.
+    pub paper_white_nits: f32,
+    pub present_mode: PresentMode,
+    /// Time the passes of every frame with GPU timestamps.
+    pub gpu_profiling: bool,
+    /// Also gather vertex and fragment invocation counts per pass, costs a bit more.
+    pub pipeline_statistics: bool,
+    /// Frustum cull indirect draws in a compute shader rather than on the CPU.
+    pub gpu_culling: bool,
+    /// Skip draws hidden behind what was drawn in previous frames.
+    pub occlusion_culling: OcclusionCulling,
+    /// Threads recording the opaque pass into secondary command buffers, 0 records it
+    /// directly into the frame's command buffer.
+    pub recording_threads: usize,
+    /// Physical device to render on, overridden by the `KATLA_DEVICE` environment variable.
+    pub device: DeviceSelection,
+}
+
+impl Default for RendererSettings {
+    fn default() -> Self {
+        Self {
+            sample_count: 1,
+            hdr_output: false,
+            tonemapper: Tonemapper::Aces,
+            exposure: 1.0,
+            paper_white_nits: 200.0,
+            present_mode: PresentMode::Mailbox,
+            gpu_profiling: true,
+            pipeline_statistics: false,
+            gpu_culling: true,
+            occlusion_culling: OcclusionCulling::HiZ,
+            recording_threads: std::thread::available_parallelism()
+                .map(|threads| threads.get().min(4))
+                .unwrap_or(1),
+            device: DeviceSelection::Auto,
+        }
+    }
+}
 
 pub struct VulkanRenderer {
     pub context: Arc<VulkanContext>,
     pub frame_context: VulkanFrameCtx,
     pub render_pass: RenderPass,
-    pub swapchain_framebuffers: Vec<vk::Framebuffer>,
+    pub opaque_framebuffer: vk::Framebuffer,
+    tonemap_pass: TonemapPass,
+    profiler: Option<GpuProfiler>,
+    depth_pyramid: DepthPyramid,
+    indirect_draws: IndirectDraws,
+    recorder: Option<ParallelRecorder>,
+    settings: RendererSettings,
     swap_data: SwapData,
     current_framedata: Option<FrameData>,
 }
 struct FrameData {
     available_sem: vk::Semaphore,
     finished_sem: vk::Semaphore,
-    in_flight_fence: vk::Fence,
     image_index: u32,
 }
 
 const FRAMES_IN_FLIGHT: usize = 2;
+const MAX_PROFILER_SCOPES: u32 = 16;
+const MAX_INDIRECT_DRAWS: u32 = 16384;
+const MAX_INDIRECT_BATCHES: u32 = 1024;
 
 impl VulkanRenderer {
     pub fn init(
@@ -32,6 +93,7 @@ impl VulkanRenderer {
         with_validation_layers: bool,
         app_name: CString,
         engine_name: CString,
+        settings: RendererSettings,
     ) -> Self {
         let context = Arc::new(VulkanContext::init(
             display,
@@ -39,30 +101,39 @@ impl VulkanRenderer {
             with_validation_layers,
             app_name,
             engine_name,
+            &settings.device,
         ));
 
-        let frame_context = VulkanFrameCtx::init(&context);
+        let mut settings = settings;
+        let sample_count = context.clamp_sample_count(settings.sample_count);
+        settings.sample_count = sample_count.as_raw();
+        println!("Using {} MSAA samples", sample_count.as_raw());
+        let frame_context = VulkanFrameCtx::init(
+            &context,
+            sample_count,
+            settings.hdr_output,
+            settings.present_mode,
+        );
 
-        let color_format = frame_context.swapchain.format.format;
+        let color_format = frame_context.hdr_render_texture.format;
         let depth_format = frame_context.depth_render_texture.format;
-        let render_pass =
-            RenderPass::create_opaque(context.device.clone(), color_format, depth_format);
-
-        let swapchain_framebuffers: Vec<_> = frame_context
-            .swapchain_image_views
-            .iter()
-            .map(|image_view| {
-                let attachments = vec![*image_view, frame_context.depth_render_texture.image_view];
-                let create_info = vk::FramebufferCreateInfo::default()
-                    .render_pass(render_pass.get_vk_renderpass())
-                    .attachments(&attachments)
-                    .width(frame_context.swapchain.get_extent().width)
-                    .height(frame_context.swapchain.get_extent().height)
-                    .layers(1);
-
-                unsafe { context.device.create_framebuffer(&create_info, None) }.unwrap()
-            })
-            .collect();
+        let render_pass = RenderPass::create_opaque(
+            context.device.clone(),
+            color_format,
+            depth_format,
+            sample_count,
+        );
+
+        let opaque_framebuffer = Self::create_framebuffer(&context, &frame_context, &render_pass);
+        let tonemap_pass = TonemapPass::new(context.clone(), &frame_context);
+        let profiler = Self::create_profiler(&context, &frame_context, &settings);
+        let depth_pyramid = Self::create_depth_pyramid(&context, &frame_context);
+        let indirect_draws =
+            Self::create_indirect_draws(&context, &frame_context, &settings, &depth_pyramid);
+        if !context.capabilities.draw_indirect_count {
+            println!("drawIndirectCount is not supported, culling on the CPU");
+        }
+        let recorder = Self::create_recorder(&context, &frame_context, &settings, &profiler);
 
         let swap_data = SwapData::new(
             &context.device,
@@ -74,42 +145,143 @@ impl VulkanRenderer {
             context,
             frame_context,
             render_pass,
-            swapchain_framebuffers,
+            opaque_framebuffer,
+            tonemap_pass,
+            profiler,
+            depth_pyramid,
+            indirect_draws,
+            recorder,
+            settings,
             swap_data,
             current_framedata: None,
         };
         renderer
     }
 
-    // fn create_framebuffers(context: &VulkanCtx) -> Vec<Framebuffer> {
-    //     context
-    //         .swapchain_image_views
-    //         .iter()
-    //         .map(|image_view| {
-    //             let attachments = vec![*image_view];
-    //             let create_info = FramebufferCreateInfo::builder()
-    //                 .render_pass(render_pass)
-    //                 .attachments(&attachments)
-    //                 .width(context.current_extent.width)
-    //                 .height(context.current_extent.height)
-    //                 .layers(1);
-
-    //             unsafe { context.device.create_framebuffer(&create_info, None, None) }.unwrap()
-    //         })
-    //         .collect()
-    // }
+    fn create_framebuffer(
+        context: &VulkanContext,
+        frame_context: &VulkanFrameCtx,
+        render_pass: &RenderPass,
+    ) -> vk::Framebuffer {
+        let attachments = frame_context.framebuffer_attachments();
+        let create_info = vk::FramebufferCreateInfo::default()
+            .render_pass(render_pass.get_vk_renderpass())
+            .attachments(&attachments)
+            .width(frame_context.swapchain.get_extent().width)
+            .height(frame_context.swapchain.get_extent().height)
+            .layers(1);
+
+        let framebuffer = unsafe { context.device.create_framebuffer(&create_info, None) }.unwrap();
+        context.set_object_name(render_pass.get_vk_renderpass(), "opaque pass");
+        context.set_object_name(framebuffer, "opaque framebuffer");
+        framebuffer
+    }
 
-    pub fn destroy(&mut self) {
-        unsafe {
-            self.context.pre_destroy();
-            self.swap_data.destroy(&self.context.device);
-            self.render_pass.destroy();
-            for &framebuffer in &self.swapchain_framebuffers {
-                self.context.device.destroy_framebuffer(framebuffer, None);
+    fn create_profiler(
+        context: &Arc<VulkanContext>,
+        frame_context: &VulkanFrameCtx,
+        settings: &RendererSettings,
+    ) -> Option<GpuProfiler> {
+        if settings.gpu_profiling {
+            //The opaque scope's statistics query stays active while the secondaries execute
+            let inherited = context.capabilities.features.inherited_queries != 0;
+            let pipeline_statistics =
+                settings.pipeline_statistics && (settings.recording_threads == 0 || inherited);
+            if settings.pipeline_statistics && !pipeline_statistics {
+                println!("Pipeline statistics disabled, parallel recording needs inheritedQueries");
             }
+            GpuProfiler::new(
+                context.clone(),
+                frame_context.swapchain_image_views.len(),
+                MAX_PROFILER_SCOPES,
+                pipeline_statistics,
+            )
+        } else {
+            None
+        }
+    }
 
-            self.frame_context.destroy();
+    fn create_depth_pyramid(
+        context: &Arc<VulkanContext>,
+        frame_context: &VulkanFrameCtx,
+    ) -> DepthPyramid {
+        DepthPyramid::new(
+            context.clone(),
+            &frame_context.depth_render_texture,
+            frame_context.sample_count,
+        )
+    }
+
+    fn create_indirect_draws(
+        context: &Arc<VulkanContext>,
+        frame_context: &VulkanFrameCtx,
+        settings: &RendererSettings,
+        depth_pyramid: &DepthPyramid,
+    ) -> IndirectDraws {
+        let cull_mode = if settings.gpu_culling {
+            CullMode::Gpu
+        } else {
+            CullMode::Cpu
+        };
+        IndirectDraws::new(
+            context.clone(),
+            frame_context.swapchain_image_views.len(),
+            MAX_INDIRECT_DRAWS,
+            MAX_INDIRECT_BATCHES,
+            cull_mode,
+            settings.occlusion_culling,
+            depth_pyramid,
+        )
+    }
+
+    fn create_recorder(
+        context: &VulkanContext,
+        frame_context: &VulkanFrameCtx,
+        settings: &RendererSettings,
+        profiler: &Option<GpuProfiler>,
+    ) -> Option<ParallelRecorder> {
+        if settings.recording_threads > 0 {
+            let pipeline_statistics = profiler
+                .as_ref()
+                .map_or(vk::QueryPipelineStatisticFlags::empty(), |profiler| {
+                    profiler.statistics_flags()
+                });
+            Some(ParallelRecorder::new(
+                context,
+                frame_context.swapchain_image_views.len(),
+                settings.recording_threads,
+                pipeline_statistics,
+            ))
+        } else {
+            None
+        }
+    }
+
+    //Destroys everything that refers to the swapchain or the scene render textures
+    fn destroy_passes(&mut self) {
+        self.tonemap_pass.destroy();
+        self.depth_pyramid.destroy();
+        self.render_pass.destroy();
+        unsafe {
+            self.context
+                .device
+                .destroy_framebuffer(self.opaque_framebuffer, None);
+        }
+    }
+
+    pub fn destroy(&mut self) {
+        self.context.pre_destroy();
+        self.swap_data.destroy(&self.context.device);
+        self.destroy_passes();
+        if let Some(profiler) = &mut self.profiler {
+            profiler.destroy();
         }
+        self.indirect_draws.destroy();
+        if let Some(recorder) = &mut self.recorder {
+            recorder.destroy();
+        }
+
+        self.frame_context.destroy();
         println!("Clean shutdown!");
     }
 
@@ -123,72 +295,227 @@ impl VulkanRenderer {
         self.wait_for_device();
         self.frame_context.recreate_swapchain();
         //Destroy the previous state:
-        unsafe {
-            self.render_pass.destroy();
-            for &framebuffer in &self.swapchain_framebuffers {
-                self.context.device.destroy_framebuffer(framebuffer, None);
-            }
+        self.destroy_passes();
+
+        self.recreate_passes();
+        //The number of swapchain images, and with that frame slots, may have changed
+        if let Some(profiler) = &mut self.profiler {
+            profiler.destroy();
+        }
+        self.profiler = Self::create_profiler(&self.context, &self.frame_context, &self.settings);
+        self.indirect_draws.destroy();
+        self.indirect_draws = Self::create_indirect_draws(
+            &self.context,
+            &self.frame_context,
+            &self.settings,
+            &self.depth_pyramid,
+        );
+        if let Some(recorder) = &mut self.recorder {
+            recorder.destroy();
         }
+        self.recorder = Self::create_recorder(
+            &self.context,
+            &self.frame_context,
+            &self.settings,
+            &self.profiler,
+        );
+    }
 
-        let color_format = self.frame_context.swapchain.format.format;
+    fn recreate_passes(&mut self) {
+        let color_format = self.frame_context.hdr_render_texture.format;
         let depth_format = self.frame_context.depth_render_texture.format;
-        self.render_pass =
-            RenderPass::create_opaque(self.context.device.clone(), color_format, depth_format);
-
-        self.swapchain_framebuffers = self
-            .frame_context
-            .swapchain_image_views
-            .iter()
-            .map(|image_view| {
-                let attachments = vec![
-                    *image_view,
-                    self.frame_context.depth_render_texture.image_view,
-                ];
-                let create_info = vk::FramebufferCreateInfo::default()
-                    .render_pass(self.render_pass.get_vk_renderpass())
-                    .attachments(&attachments)
-                    .width(self.frame_context.swapchain.get_extent().width)
-                    .height(self.frame_context.swapchain.get_extent().height)
-                    .layers(1);
-
-                unsafe { self.context.device.create_framebuffer(&create_info, None) }.unwrap()
-            })
-            .collect();
+        self.render_pass = RenderPass::create_opaque(
+            self.context.device.clone(),
+            color_format,
+            depth_format,
+            self.frame_context.sample_count,
+        );
+
+        self.opaque_framebuffer =
+            Self::create_framebuffer(&self.context, &self.frame_context, &self.render_pass);
+        self.tonemap_pass = TonemapPass::new(self.context.clone(), &self.frame_context);
+        self.depth_pyramid = Self::create_depth_pyramid(&self.context, &self.frame_context);
+        self.indirect_draws.set_depth_pyramid(&self.depth_pyramid);
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.frame_context.sample_count.as_raw()
+    }
+
+    /// Changes the MSAA sample count, rebuilding the render targets, render pass and framebuffers.
+    /// Returns the sample count actually used after clamping. Pipelines created against the
+    /// previous render pass have to be recreated by their owners when this returns a new value.
+    pub fn set_sample_count(&mut self, samples: u32) -> u32 {
+        let sample_count = self.context.clamp_sample_count(samples);
+        if sample_count == self.frame_context.sample_count {
+            return sample_count.as_raw();
+        }
+        self.wait_for_device();
+        self.destroy_passes();
+        self.frame_context.set_sample_count(sample_count);
+        self.settings.sample_count = sample_count.as_raw();
+        self.recreate_passes();
+        println!("Using {} MSAA samples", sample_count.as_raw());
+        sample_count.as_raw()
+    }
+
+    /// The present mode the swapchain was created with, after falling back on unsupported modes.
+    pub fn present_mode(&self) -> vk::PresentModeKHR {
+        self.frame_context.swapchain.present_mode
+    }
+
+    /// Recreates the swapchain with a new present mode preference, returns the mode actually used.
+    pub fn set_present_mode(&mut self, present_mode: PresentMode) -> vk::PresentModeKHR {
+        self.settings.present_mode = present_mode;
+        self.frame_context.preferred_present_mode = present_mode;
+        self.recreate_swapchain();
+        self.present_mode()
+    }
+
+    /// Per pass GPU times of a frame a few frames back, empty without GPU profiling.
+    pub fn gpu_timings(&self) -> &[GpuScopeTiming] {
+        match &self.profiler {
+            Some(profiler) => profiler.results(),
+            None => &[],
+        }
+    }
+
+    /// The profiler, to add scopes around custom work recorded into the frame's command buffer.
+    pub fn profiler_mut(&mut self) -> Option<&mut GpuProfiler> {
+        self.profiler.as_mut()
+    }
+
+    /// Draws of the frame for GPU-driven rendering, filled before `get_commandbuffer_opaque_pass`
+    /// which culls them.
+    pub fn indirect_draws_mut(&mut self) -> &mut IndirectDraws {
+        &mut self.indirect_draws
+    }
+
+    pub fn indirect_draws(&self) -> &IndirectDraws {
+        &self.indirect_draws
+    }
+
+    /// Returns the mode actually used, CPU culling if the device lacks drawIndirectCount.
+    pub fn set_cull_mode(&mut self, cull_mode: CullMode) -> CullMode {
+        self.settings.gpu_culling = cull_mode == CullMode::Gpu;
+        self.indirect_draws.set_cull_mode(cull_mode);
+        self.indirect_draws.cull_mode()
+    }
+
+    /// Returns the mode actually used, occlusion queries instead of Hi-Z when culling on the CPU.
+    pub fn set_occlusion_culling(
+        &mut self,
+        occlusion_culling: OcclusionCulling,
+    ) -> OcclusionCulling {
+        self.settings.occlusion_culling = occlusion_culling;
+        self.indirect_draws.set_occlusion_culling(occlusion_culling);
+        self.indirect_draws.occlusion_culling()
+    }
+
+    /// True when the opaque pass has to be recorded with `record_opaque_parallel`, nothing
+    /// can then be recorded directly into the buffer from `get_commandbuffer_opaque_pass`.
+    pub fn records_in_parallel(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// Records the opaque pass from draw packets on the recording threads, plus whatever
+    /// `serial` records on this thread, see `ParallelRecorder::record`.
+    pub fn record_opaque_parallel<F>(
+        &mut self,
+        command_buffer: &CommandBuffer,
+        packets: &[DrawPacket<'_>],
+        serial: F,
+    ) where
+        F: FnOnce(&CommandBuffer, &IndirectDraws),
+    {
+        let image_index = match &self.current_framedata {
+            Some(frame_data) => frame_data.image_index as usize,
+            None => panic!("No available frame index!"),
+        };
+        let render_area = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.frame_context.swapchain.get_extent(),
+        };
+        let indirect_draws = &self.indirect_draws;
+        let recorder = self
+            .recorder
+            .as_mut()
+            .expect("Parallel recording is disabled");
+        recorder.record(
+            command_buffer,
+            image_index,
+            self.render_pass.get_vk_renderpass(),
+            self.opaque_framebuffer,
+            render_area,
+            packets,
+            |secondary| serial(secondary, indirect_draws),
+        );
+    }
+
+    /// Validation errors and warnings reported so far, only counted with validation layers enabled.
+    pub fn validation_log(&self) -> &ValidationLog {
+        &self.context.validation_log
+    }
+
+    pub fn settings(&self) -> &RendererSettings {
+        &self.settings
+    }
+
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.settings.exposure = exposure.max(0.0);
+    }
+
+    pub fn set_tonemapper(&mut self, tonemapper: Tonemapper) {
+        self.settings.tonemapper = tonemapper;
+    }
+
+    /// How the final image is encoded, depends on the swapchain format that was picked.
+    pub fn output_encoding(&self) -> OutputEncoding {
+        self.tonemap_pass.encoding()
     }
 
     pub fn num_images(&self) -> usize {
         self.frame_context.swapchain_image_views.len()
     }
 
+    /// Frame timeline value the frame being recorded will signal. Resources used by it can
+    /// be reused or destroyed once `context.frame_timeline` reaches this value.
+    pub fn frame_value(&self) -> u64 {
+        self.context.frame_timeline.pending_value()
+    }
+
     pub fn swap_frames(&mut self) {
-        self.swap_data.wait_for_fence(&self.context.device);
+        self.swap_data.wait_for_frame(&self.context.frame_timeline);
 
-        let (available_sem, finished_sem, in_flight_fence, image_index) =
-            self.swap_data.swap_images(
-                &self.context.device,
-                &self.context.swapchain_loader,
-                self.frame_context.swapchain.swapchain,
-            );
+        let (available_sem, finished_sem, image_index) = self.swap_data.swap_images(
+            &self.context.swapchain_loader,
+            self.frame_context.swapchain.swapchain,
+            &self.context.frame_timeline,
+        );
         self.current_framedata = Some(FrameData {
             available_sem,
             finished_sem,
-            in_flight_fence,
             image_index,
         });
     }
 
-    pub fn get_commandbuffer_opaque_pass(&self) -> CommandBuffer {
-        let (framebuffer, command_buffer) = {
-            if let Some(frame_data) = &self.current_framedata {
-                (
-                    self.swapchain_framebuffers[frame_data.image_index as usize],
-                    self.frame_context.command_buffers[frame_data.image_index as usize].clone(),
-                )
-            } else {
-                panic!("No available frame index!");
-            }
+    pub fn get_commandbuffer_opaque_pass(&mut self) -> CommandBuffer {
+        let image_index = match &self.current_framedata {
+            Some(frame_data) => frame_data.image_index as usize,
+            None => panic!("No available frame index!"),
         };
+        let command_buffer = self.frame_context.command_buffers[image_index].clone();
         command_buffer.begin_command(vk::CommandBufferUsageFlags::default());
+        if let Some(profiler) = &mut self.profiler {
+            profiler.begin_frame(&command_buffer, image_index);
+            profiler.begin_scope(&command_buffer, "cull");
+        }
+        self.indirect_draws.cull(&command_buffer, image_index);
+        if let Some(profiler) = &mut self.profiler {
+            profiler.end_scope(&command_buffer);
+            profiler.begin_scope(&command_buffer, "opaque");
+        }
+        command_buffer.begin_label("opaque pass");
 
         let clear_values = vec![
             vk::ClearValue {
@@ -208,34 +535,79 @@ impl VulkanRenderer {
             offset: vk::Offset2D { x: 0, y: 0 },
             extent: current_extent,
         };
-        command_buffer.begin_render_pass(
-            framebuffer,
-            self.render_pass.get_vk_renderpass(),
-            render_area,
-            &clear_values,
-        );
+        if self.recorder.is_some() {
+            command_buffer.begin_render_pass_secondary(
+                self.opaque_framebuffer,
+                self.render_pass.get_vk_renderpass(),
+                render_area,
+                &clear_values,
+            );
+        } else {
+            command_buffer.begin_render_pass(
+                self.opaque_framebuffer,
+                self.render_pass.get_vk_renderpass(),
+                render_area,
+                &clear_values,
+            );
+        }
         command_buffer
     }
 
+    /// Ends the opaque pass started by `get_commandbuffer_opaque_pass`, records the
+    /// tonemapping pass into the swapchain image and ends the command buffer.
+    pub fn end_commandbuffer_opaque_pass(&mut self, command_buffer: &CommandBuffer) {
+        let image_index = match &self.current_framedata {
+            Some(frame_data) => frame_data.image_index as usize,
+            None => panic!("No available frame index!"),
+        };
+        command_buffer.end_render_pass();
+        command_buffer.end_label();
+        if let Some(profiler) = &mut self.profiler {
+            profiler.end_scope(command_buffer);
+        }
+        //Only the cull shader reads the pyramid
+        if self.indirect_draws.occlusion_culling() == OcclusionCulling::HiZ {
+            if let Some(profiler) = &mut self.profiler {
+                profiler.begin_scope(command_buffer, "hi-z");
+            }
+            self.depth_pyramid.build(command_buffer);
+            self.indirect_draws.depth_pyramid_built();
+            if let Some(profiler) = &mut self.profiler {
+                profiler.end_scope(command_buffer);
+            }
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.begin_scope(command_buffer, "tonemap");
+        }
+        command_buffer.begin_label("tonemap pass");
+        self.tonemap_pass.record(
+            command_buffer,
+            image_index,
+            self.settings.tonemapper,
+            self.settings.exposure,
+            self.settings.paper_white_nits,
+        );
+        command_buffer.end_label();
+        if let Some(profiler) = &mut self.profiler {
+            profiler.end_scope(command_buffer);
+        }
+        command_buffer.end_command();
+    }
+
     pub fn submit_frame(&mut self, command_buffers: Vec<&CommandBuffer>) {
         let frame_data = self.current_framedata.take().unwrap();
 
         let wait_semaphores = vec![frame_data.available_sem];
 
         let signal_semaphores = vec![frame_data.finished_sem];
-        let in_flight_fence = frame_data.in_flight_fence;
-        unsafe {
-            self.context
-                .device
-                .reset_fences(&[in_flight_fence])
-                .unwrap();
-        }
-        self.context.gfx_queue.submit(
+        let value = self.context.gfx_queue.submit_timeline(
             &command_buffers,
             &wait_semaphores,
             &signal_semaphores,
-            in_flight_fence,
+            &self.context.frame_timeline,
         );
+        self.swap_data
+            .frame_submitted(frame_data.image_index, value);
 
         let swapchains = vec![self.frame_context.swapchain.swapchain];
         let image_indices = vec![frame_data.image_index];
@@ -0,0 +1,162 @@
+use ash::{vk, Instance};
+use std::{ffi::CStr, fmt};
+
+use super::DeviceInfo;
+
+//Upper bound for sampler anisotropy, higher rarely looks any different
+const MAX_ANISOTROPY: f32 = 16.0;
+
+/// Optional device features and extensions. Each is requested when the device has it,
+/// what ended up enabled is recorded here so subsystems can fall back when it is missing.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceCapabilities {
+    /// Core features passed to vkCreateDevice
+    pub features: vk::PhysicalDeviceFeatures,
+    /// vkCmdDrawIndexedIndirectCount is available, needed for GPU culling
+    pub draw_indirect_count: bool,
+    /// Timeline semaphores are available, `TimelineSemaphore` uses fences otherwise
+    pub timeline_semaphore: bool,
+    /// 8 bit index buffers can be bound, otherwise they are widened to 16 bit
+    pub index_type_uint8: bool,
+    /// VK_EXT_memory_budget reports heap usage and budgets, otherwise they are estimated
+    pub memory_budget: bool,
+    /// Device extensions to enable, swapchain included
+    pub extensions: Vec<&'static CStr>,
+    max_sampler_anisotropy: f32,
+}
+
+impl DeviceCapabilities {
+    /// Checks the optional features against what `physical_device` supports.
+    ///
+    /// # Safety
+    /// `physical_device` has to come from `instance`, and `info` has to describe it.
+    pub unsafe fn negotiate(
+        instance: &Instance,
+        physical_device: vk::PhysicalDevice,
+        info: &DeviceInfo,
+    ) -> Self {
+        let supported = instance.get_physical_device_features(physical_device);
+        let features = vk::PhysicalDeviceFeatures {
+            sampler_anisotropy: supported.sampler_anisotropy,
+            //Only used by the GPU profiler, so it is fine to go without
+            pipeline_statistics_query: supported.pipeline_statistics_query,
+            //Indirect draws fall back to one call per draw without it
+            multi_draw_indirect: supported.multi_draw_indirect,
+            //Indirect draws have to start at instance 0 without it
+            draw_indirect_first_instance: supported.draw_indirect_first_instance,
+            //Secondary command buffers can run inside a pipeline statistics query
+            inherited_queries: supported.inherited_queries,
+            ..Default::default()
+        };
+
+        let mut extensions = vec![ash::khr::swapchain::NAME];
+
+        //Features2 is core in 1.1, the instance is always created for 1.2
+        let is_vulkan_11 = info.api_version >= vk::API_VERSION_1_1;
+        //drawIndirectCount is core in 1.2, older devices cull on the CPU instead
+        let is_vulkan_12 = info.api_version >= vk::API_VERSION_1_2;
+        let uint8_extension = [
+            ash::khr::index_type_uint8::NAME,
+            ash::ext::index_type_uint8::NAME,
+        ]
+        .iter()
+        .copied()
+        .find(|name| info.supports_extension(name));
+
+        let mut supported_12 = vk::PhysicalDeviceVulkan12Features::default();
+        let mut supported_uint8 = vk::PhysicalDeviceIndexTypeUint8FeaturesKHR::default();
+        if is_vulkan_11 {
+            let mut supported2 = vk::PhysicalDeviceFeatures2::default();
+            if is_vulkan_12 {
+                supported2 = supported2.push_next(&mut supported_12);
+            }
+            if uint8_extension.is_some() {
+                supported2 = supported2.push_next(&mut supported_uint8);
+            }
+            instance.get_physical_device_features2(physical_device, &mut supported2);
+        }
+
+        let draw_indirect_count = supported_12.draw_indirect_count != 0;
+        let timeline_semaphore = supported_12.timeline_semaphore != 0;
+        let index_type_uint8 = match uint8_extension {
+            Some(name) if supported_uint8.index_type_uint8 != 0 => {
+                extensions.push(name);
+                true
+            }
+            _ => false,
+        };
+        let memory_budget = is_vulkan_11 && info.supports_extension(ash::ext::memory_budget::NAME);
+        if memory_budget {
+            extensions.push(ash::ext::memory_budget::NAME);
+        }
+
+        Self {
+            features,
+            draw_indirect_count,
+            timeline_semaphore,
+            index_type_uint8,
+            memory_budget,
+            extensions,
+            max_sampler_anisotropy: info.limits.max_sampler_anisotropy,
+        }
+    }
+
+    /// Max anisotropy for texture samplers, None when samplerAnisotropy is unsupported.
+    pub fn sampler_anisotropy(&self) -> Option<f32> {
+        if self.features.sampler_anisotropy != 0 {
+            Some(self.max_sampler_anisotropy.min(MAX_ANISOTROPY))
+        } else {
+            None
+        }
+    }
+
+    pub fn supports_index_type(&self, index_type: vk::IndexType) -> bool {
+        match index_type {
+            vk::IndexType::UINT8_KHR => self.index_type_uint8,
+            vk::IndexType::UINT16 | vk::IndexType::UINT32 => true,
+            _ => false,
+        }
+    }
+
+    pub fn has_extension(&self, name: &CStr) -> bool {
+        self.extensions.contains(&name)
+    }
+}
+
+impl fmt::Display for DeviceCapabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let optional = [
+            ("samplerAnisotropy", self.features.sampler_anisotropy != 0),
+            (
+                "pipelineStatisticsQuery",
+                self.features.pipeline_statistics_query != 0,
+            ),
+            ("multiDrawIndirect", self.features.multi_draw_indirect != 0),
+            (
+                "drawIndirectFirstInstance",
+                self.features.draw_indirect_first_instance != 0,
+            ),
+            ("inheritedQueries", self.features.inherited_queries != 0),
+            ("drawIndirectCount", self.draw_indirect_count),
+            ("timelineSemaphore", self.timeline_semaphore),
+            ("indexTypeUint8", self.index_type_uint8),
+            ("memoryBudget", self.memory_budget),
+        ];
+        let names = |enabled: bool| {
+            optional
+                .iter()
+                .filter(|(_, on)| *on == enabled)
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        writeln!(f, "Enabled features: {}", names(true))?;
+        writeln!(f, "Missing features: {}", names(false))?;
+        let extensions: Vec<_> = self
+            .extensions
+            .iter()
+            .map(|name| name.to_string_lossy())
+            .collect();
+        write!(f, "Enabled extensions: {}", extensions.join(", "))
+    }
+}
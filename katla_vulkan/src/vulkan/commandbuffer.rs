@@ -1,18 +1,34 @@
-use ash::{vk, Device};
+use ash::{ext::debug_utils::Device as DebugDevice, vk, Device};
 
-use super::CommandPool;
+use std::ffi::CString;
+
+use super::{CommandPool, ComputeBarrier};
 
 #[derive(Clone)]
 pub struct CommandBuffer {
     device: Device,
+    debug_utils: Option<DebugDevice>,
     command_pool: vk::CommandPool,
     command_buffer: vk::CommandBuffer,
 }
 
 impl CommandBuffer {
     pub fn new(device: &Device, command_pool: &CommandPool) -> Self {
+        Self::allocate(device, command_pool, vk::CommandBufferLevel::PRIMARY)
+    }
+
+    /// Command buffer that is executed from a primary one, see `begin_secondary`.
+    pub fn new_secondary(device: &Device, command_pool: &CommandPool) -> Self {
+        Self::allocate(device, command_pool, vk::CommandBufferLevel::SECONDARY)
+    }
+
+    fn allocate(
+        device: &Device,
+        command_pool: &CommandPool,
+        level: vk::CommandBufferLevel,
+    ) -> Self {
         let create_info = vk::CommandBufferAllocateInfo::default()
-            .level(vk::CommandBufferLevel::PRIMARY)
+            .level(level)
             .command_pool(command_pool.vk_command_pool())
             .command_buffer_count(1);
         let command_buffer: vk::CommandBuffer =
@@ -20,6 +36,7 @@ impl CommandBuffer {
 
         Self {
             device: device.clone(),
+            debug_utils: command_pool.debug_utils().cloned(),
             command_pool: command_pool.vk_command_pool(),
             command_buffer,
         }
@@ -54,12 +71,77 @@ impl CommandBuffer {
         }
     }
 
+    /// Begins a secondary command buffer that continues `render_pass`, with the viewport
+    /// and scissor set to `render_area` since secondaries do not inherit dynamic state.
+    /// `pipeline_statistics` are the statistics a query active in the primary may count,
+    /// anything but empty needs inheritedQueries.
+    pub fn begin_secondary(
+        &self,
+        render_pass: vk::RenderPass,
+        subpass: u32,
+        framebuffer: vk::Framebuffer,
+        render_area: vk::Rect2D,
+        pipeline_statistics: vk::QueryPipelineStatisticFlags,
+    ) {
+        let inheritance_info = vk::CommandBufferInheritanceInfo::default()
+            .render_pass(render_pass)
+            .subpass(subpass)
+            .framebuffer(framebuffer)
+            .pipeline_statistics(pipeline_statistics);
+        let begin_info = vk::CommandBufferBeginInfo::default()
+            .flags(
+                vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE
+                    | vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+            )
+            .inheritance_info(&inheritance_info);
+        unsafe {
+            self.device
+                .begin_command_buffer(self.command_buffer, &begin_info)
+                .unwrap();
+        }
+        self.set_viewport_scissor(render_area);
+    }
+
+    pub fn execute_commands(&self, command_buffers: &[CommandBuffer]) {
+        let command_buffers: Vec<_> = command_buffers
+            .iter()
+            .map(|command_buffer| command_buffer.command_buffer)
+            .collect();
+        unsafe {
+            self.device
+                .cmd_execute_commands(self.command_buffer, &command_buffers)
+        }
+    }
+
     pub fn end_command(&self) {
         unsafe {
             self.device.end_command_buffer(self.command_buffer).unwrap();
         }
     }
 
+    /// Opens a labeled region, shown in graphics debuggers and validation messages.
+    pub fn begin_label(&self, name: &str) {
+        if let Some(debug_utils) = &self.debug_utils {
+            let name = CString::new(name).unwrap();
+            let label = vk::DebugUtilsLabelEXT::default().label_name(&name);
+            unsafe { debug_utils.cmd_begin_debug_utils_label(self.command_buffer, &label) };
+        }
+    }
+
+    pub fn end_label(&self) {
+        if let Some(debug_utils) = &self.debug_utils {
+            unsafe { debug_utils.cmd_end_debug_utils_label(self.command_buffer) };
+        }
+    }
+
+    pub fn insert_label(&self, name: &str) {
+        if let Some(debug_utils) = &self.debug_utils {
+            let name = CString::new(name).unwrap();
+            let label = vk::DebugUtilsLabelEXT::default().label_name(&name);
+            unsafe { debug_utils.cmd_insert_debug_utils_label(self.command_buffer, &label) };
+        }
+    }
+
     pub fn begin_render_pass(
         &self,
         framebuffer: vk::Framebuffer,
@@ -79,6 +161,36 @@ impl CommandBuffer {
                 &begin_info,
                 vk::SubpassContents::INLINE,
             );
+        }
+        self.set_viewport_scissor(render_area);
+    }
+
+    /// Begins a render pass whose contents are recorded in secondary command buffers,
+    /// only `execute_commands` may be recorded into this buffer until it ends.
+    pub fn begin_render_pass_secondary(
+        &self,
+        framebuffer: vk::Framebuffer,
+        render_pass: vk::RenderPass,
+        render_area: vk::Rect2D,
+        clear_values: &[vk::ClearValue],
+    ) {
+        let begin_info = vk::RenderPassBeginInfo::default()
+            .render_pass(render_pass)
+            .framebuffer(framebuffer)
+            .render_area(render_area)
+            .clear_values(clear_values);
+
+        unsafe {
+            self.device.cmd_begin_render_pass(
+                self.command_buffer,
+                &begin_info,
+                vk::SubpassContents::SECONDARY_COMMAND_BUFFERS,
+            );
+        }
+    }
+
+    fn set_viewport_scissor(&self, render_area: vk::Rect2D) {
+        unsafe {
             self.device
                 .cmd_set_scissor(self.command_buffer, 0, &[render_area]);
 
@@ -132,6 +244,85 @@ impl CommandBuffer {
         }
     }
 
+    /// Binds sets with dynamic uniform buffers, one offset per dynamic binding in set order.
+    pub fn bind_descriptor_sets_dynamic(
+        &self,
+        pipeline_bind_point: vk::PipelineBindPoint,
+        pipeline_layout: vk::PipelineLayout,
+        descriptor_sets: &[vk::DescriptorSet],
+        dynamic_offsets: &[u32],
+    ) {
+        unsafe {
+            self.device.cmd_bind_descriptor_sets(
+                self.command_buffer,
+                pipeline_bind_point,
+                pipeline_layout,
+                0,
+                descriptor_sets,
+                dynamic_offsets,
+            );
+        }
+    }
+
+    pub fn push_constants(
+        &self,
+        pipeline_layout: vk::PipelineLayout,
+        stage_flags: vk::ShaderStageFlags,
+        offset: u32,
+        constants: &[u8],
+    ) {
+        unsafe {
+            self.device.cmd_push_constants(
+                self.command_buffer,
+                pipeline_layout,
+                stage_flags,
+                offset,
+                constants,
+            );
+        }
+    }
+
+    pub fn reset_query_pool(&self, query_pool: vk::QueryPool, first_query: u32, query_count: u32) {
+        unsafe {
+            self.device.cmd_reset_query_pool(
+                self.command_buffer,
+                query_pool,
+                first_query,
+                query_count,
+            );
+        }
+    }
+
+    pub fn write_timestamp(
+        &self,
+        pipeline_stage: vk::PipelineStageFlags,
+        query_pool: vk::QueryPool,
+        query: u32,
+    ) {
+        unsafe {
+            self.device
+                .cmd_write_timestamp(self.command_buffer, pipeline_stage, query_pool, query);
+        }
+    }
+
+    pub fn begin_query(&self, query_pool: vk::QueryPool, query: u32) {
+        unsafe {
+            self.device.cmd_begin_query(
+                self.command_buffer,
+                query_pool,
+                query,
+                vk::QueryControlFlags::empty(),
+            );
+        }
+    }
+
+    pub fn end_query(&self, query_pool: vk::QueryPool, query: u32) {
+        unsafe {
+            self.device
+                .cmd_end_query(self.command_buffer, query_pool, query);
+        }
+    }
+
     pub fn bind_index_buffer(&self, buffer: vk::Buffer, offset: u64, index_type: vk::IndexType) {
         unsafe {
             self.device
@@ -170,6 +361,49 @@ impl CommandBuffer {
         }
     }
 
+    /// `draw_count` `vk::DrawIndexedIndirectCommand`s read from `buffer`, `stride` bytes apart.
+    pub fn draw_indexed_indirect(
+        &self,
+        buffer: vk::Buffer,
+        offset: u64,
+        draw_count: u32,
+        stride: u32,
+    ) {
+        unsafe {
+            self.device.cmd_draw_indexed_indirect(
+                self.command_buffer,
+                buffer,
+                offset,
+                draw_count,
+                stride,
+            )
+        }
+    }
+
+    /// Like `draw_indexed_indirect`, with the draw count read from `count_buffer` on the GPU.
+    /// Needs the drawIndirectCount feature.
+    pub fn draw_indexed_indirect_count(
+        &self,
+        buffer: vk::Buffer,
+        offset: u64,
+        count_buffer: vk::Buffer,
+        count_offset: u64,
+        max_draw_count: u32,
+        stride: u32,
+    ) {
+        unsafe {
+            self.device.cmd_draw_indexed_indirect_count(
+                self.command_buffer,
+                buffer,
+                offset,
+                count_buffer,
+                count_offset,
+                max_draw_count,
+                stride,
+            )
+        }
+    }
+
     pub fn draw_array(
         &self,
         vertex_count: u32,
@@ -188,6 +422,140 @@ impl CommandBuffer {
         }
     }
 
+    pub fn fill_buffer(&self, buffer: vk::Buffer, offset: u64, size: u64, data: u32) {
+        unsafe {
+            self.device
+                .cmd_fill_buffer(self.command_buffer, buffer, offset, size, data)
+        }
+    }
+
+    pub fn dispatch(&self, group_count_x: u32, group_count_y: u32, group_count_z: u32) {
+        unsafe {
+            self.device.cmd_dispatch(
+                self.command_buffer,
+                group_count_x,
+                group_count_y,
+                group_count_z,
+            )
+        }
+    }
+
+    /// Dispatch with the group counts read from a `vk::DispatchIndirectCommand` in `buffer`.
+    pub fn dispatch_indirect(&self, buffer: vk::Buffer, offset: u64) {
+        unsafe {
+            self.device
+                .cmd_dispatch_indirect(self.command_buffer, buffer, offset)
+        }
+    }
+
+    pub fn memory_barrier(
+        &self,
+        src_stage: vk::PipelineStageFlags,
+        src_access: vk::AccessFlags,
+        dst_stage: vk::PipelineStageFlags,
+        dst_access: vk::AccessFlags,
+    ) {
+        let barrier = vk::MemoryBarrier::default()
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access);
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                self.command_buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[barrier],
+                &[],
+                &[],
+            );
+        }
+    }
+
+    /// Buffer barrier, pass different queue families to release/acquire the buffer
+    /// when it moves between e.g. the async compute and the graphics queue.
+    #[allow(clippy::too_many_arguments)]
+    pub fn buffer_barrier(
+        &self,
+        buffer: vk::Buffer,
+        src_stage: vk::PipelineStageFlags,
+        src_access: vk::AccessFlags,
+        dst_stage: vk::PipelineStageFlags,
+        dst_access: vk::AccessFlags,
+        src_queue_family: u32,
+        dst_queue_family: u32,
+    ) {
+        let barrier = vk::BufferMemoryBarrier::default()
+            .buffer(buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access)
+            .src_queue_family_index(src_queue_family)
+            .dst_queue_family_index(dst_queue_family);
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                self.command_buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[barrier],
+                &[],
+            );
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn image_barrier(
+        &self,
+        image: vk::Image,
+        aspect_mask: vk::ImageAspectFlags,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+        src_stage: vk::PipelineStageFlags,
+        src_access: vk::AccessFlags,
+        dst_stage: vk::PipelineStageFlags,
+        dst_access: vk::AccessFlags,
+    ) {
+        let subresource_range = vk::ImageSubresourceRange::default()
+            .aspect_mask(aspect_mask)
+            .base_mip_level(0)
+            .level_count(vk::REMAINING_MIP_LEVELS)
+            .base_array_layer(0)
+            .layer_count(vk::REMAINING_ARRAY_LAYERS);
+        let barrier = vk::ImageMemoryBarrier::default()
+            .image(image)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .subresource_range(subresource_range);
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                self.command_buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier],
+            );
+        }
+    }
+
+    /// Makes compute shader writes visible to whatever `barrier` consumes them.
+    pub fn compute_barrier(&self, barrier: ComputeBarrier) {
+        let (dst_stage, dst_access) = barrier.dst_stage_access();
+        self.memory_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_WRITE,
+            dst_stage,
+            dst_access,
+        );
+    }
+
     pub fn return_to_pool(&self) {
         unsafe {
             self.device
@@ -1,25 +1,32 @@
 use ash::{
+    ext::debug_utils::Device as DebugDevice,
     vk::{self},
     Device,
 };
 
 pub struct CommandPool {
     device: Device,
+    debug_utils: Option<DebugDevice>,
     command_pool: vk::CommandPool,
 }
 
 impl CommandPool {
-    pub fn new(device: Device, queue_family_idx: u32) -> Self {
+    pub fn new(device: Device, queue_family_idx: u32, debug_utils: Option<DebugDevice>) -> Self {
         let create_info = vk::CommandPoolCreateInfo::default()
             .queue_family_index(queue_family_idx)
             .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
         let command_pool = unsafe { device.create_command_pool(&create_info, None) }.unwrap();
         Self {
             device,
+            debug_utils,
             command_pool,
         }
     }
 
+    pub fn debug_utils(&self) -> Option<&DebugDevice> {
+        self.debug_utils.as_ref()
+    }
+
     pub fn vk_command_pool(&self) -> vk::CommandPool {
         self.command_pool
     }
@@ -32,6 +39,24 @@ impl CommandPool {
         command_buffers
     }
 
+    pub fn create_secondary_command_buffers(
+        &self,
+        num_cmd_buffers: u32,
+    ) -> Vec<super::CommandBuffer> {
+        (0..num_cmd_buffers)
+            .map(|_| super::CommandBuffer::new_secondary(&self.device, self))
+            .collect()
+    }
+
+    /// Resets every command buffer allocated from the pool, none of them may be in use.
+    pub fn reset(&self) {
+        unsafe {
+            self.device
+                .reset_command_pool(self.command_pool, vk::CommandPoolResetFlags::empty())
+        }
+        .unwrap();
+    }
+
     pub fn destroy(&self) {
         unsafe {
             self.device.destroy_command_pool(self.command_pool, None);
@@ -0,0 +1,380 @@
+use ash::{util::read_spv, vk};
+use gpu_allocator::vulkan::Allocation;
+
+use std::{ffi::CString, io::Cursor, sync::Arc};
+
+use super::{CommandBuffer, VulkanContext, VulkanFrameCtx};
+
+/// What consumes the results of a compute dispatch, see `CommandBuffer::compute_barrier`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComputeBarrier {
+    /// Another dispatch reads the output
+    Compute,
+    /// The output holds indirect draw or dispatch arguments
+    DrawIndirect,
+    /// The output is bound as vertex or index buffer
+    VertexInput,
+    VertexShader,
+    FragmentShader,
+    Transfer,
+}
+
+impl ComputeBarrier {
+    pub fn dst_stage_access(&self) -> (vk::PipelineStageFlags, vk::AccessFlags) {
+        match self {
+            ComputeBarrier::Compute => (
+                vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            ),
+            ComputeBarrier::DrawIndirect => (
+                vk::PipelineStageFlags::DRAW_INDIRECT,
+                vk::AccessFlags::INDIRECT_COMMAND_READ,
+            ),
+            ComputeBarrier::VertexInput => (
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::AccessFlags::VERTEX_ATTRIBUTE_READ | vk::AccessFlags::INDEX_READ,
+            ),
+            ComputeBarrier::VertexShader => (
+                vk::PipelineStageFlags::VERTEX_SHADER,
+                vk::AccessFlags::SHADER_READ,
+            ),
+            ComputeBarrier::FragmentShader => (
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::AccessFlags::SHADER_READ,
+            ),
+            ComputeBarrier::Transfer => (
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_READ,
+            ),
+        }
+    }
+}
+
+/// A resource bound to a compute descriptor set, binding numbers follow the slice order.
+#[derive(Clone, Copy, Debug)]
+pub enum ComputeBinding {
+    UniformBuffer(vk::Buffer),
+    StorageBuffer(vk::Buffer),
+    /// Image view in the GENERAL layout
+    StorageImage(vk::ImageView),
+    SampledImage(vk::ImageView, vk::Sampler),
+    /// Image view in the GENERAL layout read through a sampler, e.g. all mips of a storage image
+    SampledStorageImage(vk::ImageView, vk::Sampler),
+}
+
+impl ComputeBinding {
+    pub fn descriptor_type(&self) -> vk::DescriptorType {
+        match self {
+            ComputeBinding::UniformBuffer(_) => vk::DescriptorType::UNIFORM_BUFFER,
+            ComputeBinding::StorageBuffer(_) => vk::DescriptorType::STORAGE_BUFFER,
+            ComputeBinding::StorageImage(_) => vk::DescriptorType::STORAGE_IMAGE,
+            ComputeBinding::SampledImage(_, _) | ComputeBinding::SampledStorageImage(_, _) => {
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+            }
+        }
+    }
+}
+
+/// 2D image that compute shaders write to, kept in the GENERAL layout.
+pub struct StorageImage {
+    pub extent: vk::Extent2D,
+    pub format: vk::Format,
+    pub image_view: vk::ImageView,
+    image: vk::Image,
+    image_memory: Option<Allocation>,
+    context: Arc<VulkanContext>,
+}
+
+impl StorageImage {
+    pub fn new(context: Arc<VulkanContext>, extent: vk::Extent2D, format: vk::Format) -> Self {
+        let create_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .mip_levels(1)
+            .array_layers(1)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .usage(
+                vk::ImageUsageFlags::STORAGE
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_SRC
+                    | vk::ImageUsageFlags::TRANSFER_DST,
+            )
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let (image, image_memory) =
+            context.create_image(create_info, gpu_allocator::MemoryLocation::GpuOnly);
+        let image_view = VulkanFrameCtx::create_image_view(
+            &context.device,
+            image,
+            format,
+            vk::ImageAspectFlags::COLOR,
+        );
+
+        let command_buffer = context.begin_single_time_commands();
+        command_buffer.image_barrier(
+            image,
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::GENERAL,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::AccessFlags::empty(),
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+        );
+        context.end_single_time_commands(command_buffer);
+
+        Self {
+            extent,
+            format,
+            image_view,
+            image,
+            image_memory: Some(image_memory),
+            context,
+        }
+    }
+
+    pub fn image(&self) -> vk::Image {
+        self.image
+    }
+
+    pub fn set_name(&self, name: &str) {
+        self.context.set_object_name(self.image, name);
+        self.context.set_object_name(self.image_view, name);
+    }
+}
+
+impl Drop for StorageImage {
+    fn drop(&mut self) {
+        unsafe {
+            self.context
+                .device
+                .destroy_image_view(self.image_view, None);
+        }
+        if let Some(image_memory) = self.image_memory.take() {
+            self.context.free_image(self.image, image_memory);
+        }
+    }
+}
+
+pub struct ComputePipeline {
+    context: Arc<VulkanContext>,
+    pub pipeline: vk::Pipeline,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub desc_layout: vk::DescriptorSetLayout,
+    desc_pool: vk::DescriptorPool,
+    binding_types: Vec<vk::DescriptorType>,
+    push_constant_size: u32,
+    shader_module: vk::ShaderModule,
+}
+
+impl ComputePipeline {
+    /// Creates a pipeline from compute shader SPIR-V. Binding `i` of set 0 has type
+    /// `binding_types[i]`, and at most `max_sets` descriptor sets can be created.
+    pub fn new(
+        context: Arc<VulkanContext>,
+        shader_spv: &[u8],
+        binding_types: &[vk::DescriptorType],
+        push_constant_size: u32,
+        max_sets: u32,
+    ) -> Self {
+        let shader_decoded = read_spv(&mut Cursor::new(shader_spv)).unwrap();
+        let create_info = vk::ShaderModuleCreateInfo::default().code(&shader_decoded);
+        let shader_module =
+            unsafe { context.device.create_shader_module(&create_info, None) }.unwrap();
+
+        let desc_layout_bindings: Vec<_> = binding_types
+            .iter()
+            .enumerate()
+            .map(|(binding, descriptor_type)| {
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(binding as u32)
+                    .descriptor_count(1)
+                    .descriptor_type(*descriptor_type)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            })
+            .collect();
+        let desc_layout_info =
+            vk::DescriptorSetLayoutCreateInfo::default().bindings(&desc_layout_bindings);
+        let desc_layout = unsafe {
+            context
+                .device
+                .create_descriptor_set_layout(&desc_layout_info, None)
+        }
+        .unwrap();
+
+        let desc_pool_sizes: Vec<_> = binding_types
+            .iter()
+            .map(|descriptor_type| {
+                vk::DescriptorPoolSize::default()
+                    .descriptor_count(max_sets)
+                    .ty(*descriptor_type)
+            })
+            .collect();
+        let desc_pool_info = vk::DescriptorPoolCreateInfo::default()
+            .pool_sizes(&desc_pool_sizes)
+            .max_sets(max_sets);
+        let desc_pool =
+            unsafe { context.device.create_descriptor_pool(&desc_pool_info, None) }.unwrap();
+
+        let desc_layouts = [desc_layout];
+        let push_constant_ranges = [vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .offset(0)
+            .size(push_constant_size)];
+        let mut create_info = vk::PipelineLayoutCreateInfo::default().set_layouts(&desc_layouts);
+        if push_constant_size > 0 {
+            create_info = create_info.push_constant_ranges(&push_constant_ranges);
+        }
+        let pipeline_layout =
+            unsafe { context.device.create_pipeline_layout(&create_info, None) }.unwrap();
+
+        let entry_point = CString::new("main").unwrap();
+        let stage = vk::PipelineShaderStageCreateInfo::default()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(shader_module)
+            .name(&entry_point);
+        let create_info = vk::ComputePipelineCreateInfo::default()
+            .stage(stage)
+            .layout(pipeline_layout);
+        let pipeline = unsafe {
+            context
+                .device
+                .create_compute_pipelines(vk::PipelineCache::null(), &[create_info], None)
+        }
+        .unwrap()[0];
+
+        Self {
+            context,
+            pipeline,
+            pipeline_layout,
+            desc_layout,
+            desc_pool,
+            binding_types: binding_types.to_vec(),
+            push_constant_size,
+            shader_module,
+        }
+    }
+
+    pub fn create_descriptor_set(&self, bindings: &[ComputeBinding]) -> vk::DescriptorSet {
+        let desc_layouts = [self.desc_layout];
+        let desc_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(self.desc_pool)
+            .set_layouts(&desc_layouts);
+        let desc_set =
+            unsafe { self.context.device.allocate_descriptor_sets(&desc_info) }.unwrap()[0];
+        self.update_descriptor_set(desc_set, bindings);
+        desc_set
+    }
+
+    /// Points the bindings of `desc_set` to new resources, the set must not be in use.
+    pub fn update_descriptor_set(&self, desc_set: vk::DescriptorSet, bindings: &[ComputeBinding]) {
+        assert_eq!(
+            bindings
+                .iter()
+                .map(|binding| binding.descriptor_type())
+                .collect::<Vec<_>>(),
+            self.binding_types,
+            "Compute bindings do not match the pipeline layout"
+        );
+        //Collected first, the writes point into these
+        let buffer_infos: Vec<_> = bindings
+            .iter()
+            .map(|binding| match binding {
+                ComputeBinding::UniformBuffer(buffer) | ComputeBinding::StorageBuffer(buffer) => {
+                    [vk::DescriptorBufferInfo::default()
+                        .buffer(*buffer)
+                        .offset(0)
+                        .range(vk::WHOLE_SIZE)]
+                }
+                _ => [vk::DescriptorBufferInfo::default()],
+            })
+            .collect();
+        let image_infos: Vec<_> = bindings
+            .iter()
+            .map(|binding| match binding {
+                ComputeBinding::StorageImage(image_view) => [vk::DescriptorImageInfo::default()
+                    .image_layout(vk::ImageLayout::GENERAL)
+                    .image_view(*image_view)],
+                ComputeBinding::SampledImage(image_view, sampler) => {
+                    [vk::DescriptorImageInfo::default()
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                        .image_view(*image_view)
+                        .sampler(*sampler)]
+                }
+                ComputeBinding::SampledStorageImage(image_view, sampler) => {
+                    [vk::DescriptorImageInfo::default()
+                        .image_layout(vk::ImageLayout::GENERAL)
+                        .image_view(*image_view)
+                        .sampler(*sampler)]
+                }
+                _ => [vk::DescriptorImageInfo::default()],
+            })
+            .collect();
+        let desc_writes: Vec<_> = bindings
+            .iter()
+            .enumerate()
+            .map(|(i, binding)| {
+                let write = vk::WriteDescriptorSet::default()
+                    .dst_set(desc_set)
+                    .dst_binding(i as u32)
+                    .descriptor_type(binding.descriptor_type());
+                match binding {
+                    ComputeBinding::UniformBuffer(_) | ComputeBinding::StorageBuffer(_) => {
+                        write.buffer_info(&buffer_infos[i])
+                    }
+                    _ => write.image_info(&image_infos[i]),
+                }
+            })
+            .collect();
+        unsafe {
+            self.context
+                .device
+                .update_descriptor_sets(&desc_writes, &[])
+        };
+    }
+
+    pub fn bind(&self, command_buffer: &CommandBuffer, desc_set: vk::DescriptorSet) {
+        command_buffer.bind_pipeline(self.pipeline, vk::PipelineBindPoint::COMPUTE);
+        command_buffer.bind_descriptor_sets(
+            vk::PipelineBindPoint::COMPUTE,
+            self.pipeline_layout,
+            &[desc_set],
+        );
+    }
+
+    pub fn push_constants(&self, command_buffer: &CommandBuffer, constants: &[u8]) {
+        assert!(constants.len() as u32 <= self.push_constant_size);
+        command_buffer.push_constants(
+            self.pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            constants,
+        );
+    }
+
+    pub fn set_name(&self, name: &str) {
+        self.context
+            .set_object_name(self.pipeline, &format!("{} pipeline", name));
+        self.context
+            .set_object_name(self.pipeline_layout, &format!("{} pipeline layout", name));
+        self.context
+            .set_object_name(self.shader_module, &format!("{} compute shader", name));
+    }
+
+    pub fn destroy(&mut self) {
+        unsafe {
+            let device = &self.context.device;
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_shader_module(self.shader_module, None);
+            device.destroy_descriptor_pool(self.desc_pool, None);
+            device.destroy_descriptor_set_layout(self.desc_layout, None);
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use ash::{
-    ext::debug_utils::Instance as DebugInstance,
+    ext::debug_utils::{Device as DebugDevice, Instance as DebugInstance},
     khr::{surface::Instance as SurfaceInstance, swapchain::Device as SwapchainDevice},
     vk::{self},
     Device, Entry, Instance,
@@ -20,13 +20,21 @@ use std::{
 //     window::Window,
 // };
 
-use super::SwapchainInfo;
+use super::{
+    heap_of, query_devices, DeviceCapabilities, DeviceInfo, DeviceSelection, HeapBudget,
+    MemoryCategory, MemoryReport, MemoryTracker, SwapchainInfo, ValidationLog,
+};
 
 const LAYER_KHRONOS_VALIDATION: &str = concat!("VK_LAYER_KHRONOS_validation", "\0");
 
+/// Format of the offscreen target the scene is rendered into before tonemapping.
+pub const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
 struct QueueFamilyIndices {
     pub graphics_idx: Option<u32>,
     pub transfer_idx: Option<u32>,
+    //A compute family without graphics, i.e. one that can run async next to rendering
+    pub compute_idx: Option<u32>,
 }
 
 pub struct RenderTexture {
@@ -64,22 +72,46 @@ pub struct VulkanContext {
     pub surface_loader: SurfaceInstance,
     pub swapchain_loader: Arc<SwapchainDevice>,
     pub physical_device: vk::PhysicalDevice,
+    /// Properties, memory heaps, queue families and extensions of `physical_device`
+    pub device_info: DeviceInfo,
+    /// Optional features and extensions that were enabled on `device`
+    pub capabilities: DeviceCapabilities,
     pub allocator: ManuallyDrop<RefCell<Allocator>>,
+    memory_tracker: RefCell<MemoryTracker>,
     pub surface: vk::SurfaceKHR,
     pub graphics_queue: vk::Queue,
     pub gfx_queue: super::Queue,
     pub gfx_cmdpool: super::CommandPool,
+    /// Dedicated compute queue, if the device exposes a compute-only queue family
+    pub async_compute_queue: Option<super::Queue>,
+    pub async_compute_cmdpool: Option<super::CommandPool>,
     pub transfer_command_pool: vk::CommandPool,
     pub transfer_queue: vk::Queue,
     debug_utils_loader: DebugInstance,
+    //Only loaded when VK_EXT_debug_utils is available, used for object names and labels
+    pub debug_utils: Option<DebugDevice>,
     debug_callback: Option<vk::DebugUtilsMessengerEXT>,
+    /// Counts the validation errors and warnings reported for this context.
+    pub validation_log: Arc<ValidationLog>,
+    /// Signaled by every frame submitted on the graphics queue
+    pub frame_timeline: super::TimelineSemaphore,
+    /// Signaled by every upload, see `submit_upload`
+    pub upload_timeline: super::TimelineSemaphore,
+    pending_uploads: RefCell<Vec<(u64, super::CommandBuffer)>>,
 }
 pub struct VulkanFrameCtx {
     pub context: Arc<VulkanContext>,
     pub swapchain_image_views: Vec<vk::ImageView>,
     pub swapchain: super::Swapchain,
     pub swapchain_images: Vec<vk::Image>,
+    pub sample_count: vk::SampleCountFlags,
+    pub prefer_hdr_output: bool,
+    pub preferred_present_mode: super::PresentMode,
+    //Only present when multisampling, it is then resolved into the hdr render texture
+    pub color_render_texture: Option<RenderTexture>,
     pub depth_render_texture: RenderTexture,
+    //Single sampled scene color, read by the tonemapping pass
+    pub hdr_render_texture: RenderTexture,
     pub command_buffers: Vec<super::CommandBuffer>,
 }
 
@@ -93,12 +125,19 @@ impl QueueFamilyIndices {
         let mut queue_family_indices = Self {
             graphics_idx: None,
             transfer_idx: None,
+            compute_idx: None,
         };
         unsafe {
             let family_props =
                 instance.get_physical_device_queue_family_properties(physical_device);
             println!("Num family indices: {}", family_props.len());
             for (idx, properties) in family_props.iter().enumerate() {
+                if properties.queue_flags.contains(vk::QueueFlags::COMPUTE)
+                    && !properties.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+                    && queue_family_indices.compute_idx.is_none()
+                {
+                    queue_family_indices.compute_idx = Some(idx as u32);
+                }
                 if properties.queue_flags.contains(vk::QueueFlags::GRAPHICS)
                     && surface_loader
                         .get_physical_device_surface_support(physical_device, idx as u32, surface)
@@ -135,29 +174,34 @@ impl VulkanContext {
     ) -> (vk::Buffer, Allocation) {
         let buffer = unsafe { self.device.create_buffer(&buffer_info, None) }.unwrap();
         let requirements = unsafe { self.device.get_buffer_memory_requirements(buffer) };
-        //TODO: Find better names...
+        let category = MemoryCategory::for_buffer(buffer_info.usage);
         let allocation_info = gpu_allocator::vulkan::AllocationCreateDesc {
-            name: "Buffer Allocation",
+            name: category.name(),
             requirements,
             location,
             linear: true,
             allocation_scheme: AllocationScheme::GpuAllocatorManaged,
         };
 
-        let mut allocator = self.allocator.borrow_mut();
-        let allocation = allocator.allocate(&allocation_info).unwrap();
+        let allocation = self
+            .allocator
+            .borrow_mut()
+            .allocate(&allocation_info)
+            .unwrap();
 
         unsafe {
             self.device
                 .bind_buffer_memory(buffer, allocation.memory(), allocation.offset())
                 .unwrap()
         };
+        self.track_allocation(buffer, category, &requirements, &allocation);
         (buffer, allocation)
     }
 
     pub fn free_buffer(&self, buffer: vk::Buffer, allocation: Allocation) {
         let mut allocator = self.allocator.borrow_mut();
         allocator.free(allocation).unwrap();
+        self.memory_tracker.borrow_mut().untrack(buffer);
         unsafe { self.device.destroy_buffer(buffer, None) };
     }
 
@@ -173,28 +217,99 @@ impl VulkanContext {
     ) -> (vk::Image, Allocation) {
         let image = unsafe { self.device.create_image(&image_create_info, None) }.unwrap();
         let requirements = unsafe { self.device.get_image_memory_requirements(image) };
+        let category = MemoryCategory::for_image(image_create_info.usage);
         let allocation_info = gpu_allocator::vulkan::AllocationCreateDesc {
-            name: "Image Allocation",
+            name: category.name(),
             requirements,
             location,
             linear: true,
             allocation_scheme: AllocationScheme::GpuAllocatorManaged,
         };
 
-        let mut allocator = self.allocator.borrow_mut();
-        let allocation = allocator.allocate(&allocation_info).unwrap();
+        let allocation = self
+            .allocator
+            .borrow_mut()
+            .allocate(&allocation_info)
+            .unwrap();
 
         unsafe {
             self.device
                 .bind_image_memory(image, allocation.memory(), allocation.offset())
                 .unwrap();
         }
+        self.track_allocation(image, category, &requirements, &allocation);
         (image, allocation)
     }
 
+    fn track_allocation(
+        &self,
+        handle: impl vk::Handle,
+        category: MemoryCategory,
+        requirements: &vk::MemoryRequirements,
+        allocation: &Allocation,
+    ) {
+        let heap = heap_of(
+            &self.device_info,
+            requirements.memory_type_bits,
+            allocation.memory_properties(),
+        );
+        let mut tracker = self.memory_tracker.borrow_mut();
+        tracker.track(handle, category, allocation.size(), heap);
+        let heaps = self.heap_budgets(&tracker);
+        tracker.warn_near_budget(&heaps);
+    }
+
+    fn heap_budgets(&self, tracker: &MemoryTracker) -> Vec<HeapBudget> {
+        let mut budget_props = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        if self.capabilities.memory_budget {
+            let mut props2 =
+                vk::PhysicalDeviceMemoryProperties2::default().push_next(&mut budget_props);
+            unsafe {
+                self.instance
+                    .get_physical_device_memory_properties2(self.physical_device, &mut props2)
+            };
+        }
+        self.device_info
+            .memory_heaps
+            .iter()
+            .enumerate()
+            .map(|(index, heap)| {
+                let (usage, budget) = if self.capabilities.memory_budget {
+                    (
+                        budget_props.heap_usage[index],
+                        budget_props.heap_budget[index],
+                    )
+                } else {
+                    (tracker.heap_usage(index), heap.size)
+                };
+                HeapBudget {
+                    index,
+                    size: heap.size,
+                    device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+                    usage,
+                    budget,
+                }
+            })
+            .collect()
+    }
+
+    /// Per heap usage and budget, and what the allocations are used for.
+    pub fn memory_report(&self) -> MemoryReport {
+        let tracker = self.memory_tracker.borrow();
+        let allocator_report = self.allocator.borrow().generate_report();
+        MemoryReport {
+            driver_budget: self.capabilities.memory_budget,
+            heaps: self.heap_budgets(&tracker),
+            categories: tracker.categories(),
+            allocated_bytes: allocator_report.total_allocated_bytes,
+            reserved_bytes: allocator_report.total_reserved_bytes,
+        }
+    }
+
     pub fn free_image(&self, image: vk::Image, allocation: Allocation) {
         let mut allocator = self.allocator.borrow_mut();
         allocator.free(allocation).unwrap();
+        self.memory_tracker.borrow_mut().untrack(image);
         unsafe {
             self.device.destroy_image(image, None);
         }
@@ -206,7 +321,7 @@ impl VulkanContext {
         engine_name: &CStr,
         display: &dyn HasDisplayHandle,
         entry: &Entry,
-    ) -> Instance {
+    ) -> (Instance, bool) {
         if with_validation_layers && !check_validation_support(entry) {
             panic!("Validation layers requested, but unavailable!");
         }
@@ -217,9 +332,25 @@ impl VulkanContext {
             .iter()
             .map(|ext| *ext)
             .collect::<Vec<_>>();
+        //Exposes the HDR color spaces on the surface, if the platform supports them
+        let available_extensions =
+            unsafe { entry.enumerate_instance_extension_properties(None) }.unwrap();
+        let has_colorspace_ext = available_extensions
+            .iter()
+            .any(|ext| ext.extension_name_as_c_str() == Ok(ash::ext::swapchain_colorspace::NAME));
+        if has_colorspace_ext {
+            extension_names_raw.push(ash::ext::swapchain_colorspace::NAME.as_ptr());
+        }
+        //Object names and labels are useful in captures even without validation
+        let has_debug_utils_ext = with_validation_layers
+            || available_extensions
+                .iter()
+                .any(|ext| ext.extension_name_as_c_str() == Ok(ash::ext::debug_utils::NAME));
+        if has_debug_utils_ext {
+            extension_names_raw.push(ash::ext::debug_utils::NAME.as_ptr());
+        }
         let mut instance_layers = vec![];
         if with_validation_layers {
-            extension_names_raw.push(ash::ext::debug_utils::NAME.as_ptr());
             instance_layers.push(LAYER_KHRONOS_VALIDATION.as_ptr() as *const i8);
         }
         let app_info = vk::ApplicationInfo::default()
@@ -239,7 +370,7 @@ impl VulkanContext {
                 .expect("Vk Instance creation error")
         };
 
-        instance
+        (instance, has_debug_utils_ext)
     }
 
     //https://vulkan-tutorial.com/Depth_buffering
@@ -279,10 +410,72 @@ impl VulkanContext {
             vk::Format::D24_UNORM_S8_UINT,
         ];
         let tiling = vk::ImageTiling::OPTIMAL;
-        let features = vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT;
+        //Sampled to build the depth pyramid for occlusion culling
+        let features = vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE;
         self.find_supported_format(candidates, tiling, features)
     }
 
+    /// Highest sample count usable for both color and depth framebuffer attachments.
+    pub fn max_sample_count(&self) -> vk::SampleCountFlags {
+        let properties = unsafe {
+            self.instance
+                .get_physical_device_properties(self.physical_device)
+        };
+        let counts = properties.limits.framebuffer_color_sample_counts
+            & properties.limits.framebuffer_depth_sample_counts;
+        [
+            vk::SampleCountFlags::TYPE_8,
+            vk::SampleCountFlags::TYPE_4,
+            vk::SampleCountFlags::TYPE_2,
+        ]
+        .iter()
+        .find(|samples| counts.contains(**samples))
+        .copied()
+        .unwrap_or(vk::SampleCountFlags::TYPE_1)
+    }
+
+    /// Converts a requested sample count (1, 2, 4 or 8) into a supported one,
+    /// rounding down to what the device can render to.
+    pub fn clamp_sample_count(&self, samples: u32) -> vk::SampleCountFlags {
+        let max_samples = self.max_sample_count().as_raw();
+        let mut requested = match samples {
+            0..=1 => vk::SampleCountFlags::TYPE_1,
+            2..=3 => vk::SampleCountFlags::TYPE_2,
+            4..=7 => vk::SampleCountFlags::TYPE_4,
+            _ => vk::SampleCountFlags::TYPE_8,
+        }
+        .as_raw();
+        while requested > max_samples {
+            requested >>= 1;
+        }
+        vk::SampleCountFlags::from_raw(requested)
+    }
+
+    /// The async compute queue if there is one, otherwise the graphics queue.
+    pub fn compute_queue(&self) -> &super::Queue {
+        self.async_compute_queue.as_ref().unwrap_or(&self.gfx_queue)
+    }
+
+    /// Command pool matching `compute_queue`.
+    pub fn compute_cmdpool(&self) -> &super::CommandPool {
+        self.async_compute_cmdpool
+            .as_ref()
+            .unwrap_or(&self.gfx_cmdpool)
+    }
+
+    /// Names a Vulkan object for validation messages and graphics debuggers.
+    /// Does nothing when VK_EXT_debug_utils is unavailable.
+    pub fn set_object_name<H: vk::Handle>(&self, object: H, name: &str) {
+        if let Some(debug_utils) = &self.debug_utils {
+            let name = CString::new(name).unwrap();
+            let name_info = vk::DebugUtilsObjectNameInfoEXT::default()
+                .object_handle(object)
+                .object_name(&name);
+            unsafe { debug_utils.set_debug_utils_object_name(&name_info) }.unwrap();
+        }
+    }
+
     pub fn pre_destroy(&self) {
         unsafe {
             self.device.device_wait_idle().unwrap();
@@ -296,16 +489,43 @@ impl VulkanContext {
         command_buffer
     }
 
+    /// Submits and waits for the commands, only waiting for the upload itself rather than
+    /// for the whole queue to drain.
     pub fn end_single_time_commands(&self, command_buffer: super::CommandBuffer) {
+        let value = self.submit_upload(command_buffer);
+        self.wait_for_upload(value);
+    }
+
+    /// Submits the commands without waiting and returns the upload timeline value that
+    /// signals their completion. The command buffer is freed once that value is reached.
+    pub fn submit_upload(&self, command_buffer: super::CommandBuffer) -> u64 {
         command_buffer.end_single_time_command();
-        let command_buffers = vec![&command_buffer];
-        //TODO: This cannot be used by multiple frames at once,
-        //ensure that we're either using another queue/commandpool, or
-        //that we are doing this in a locked manner
-        self.gfx_queue
-            .submit(&command_buffers, &[], &[], vk::Fence::null());
-        self.gfx_queue.wait_idle();
-        command_buffer.return_to_pool();
+        let value =
+            self.gfx_queue
+                .submit_timeline(&[&command_buffer], &[], &[], &self.upload_timeline);
+        self.pending_uploads
+            .borrow_mut()
+            .push((value, command_buffer));
+        self.free_finished_uploads();
+        value
+    }
+
+    pub fn wait_for_upload(&self, value: u64) {
+        self.upload_timeline.wait(value);
+        self.free_finished_uploads();
+    }
+
+    fn free_finished_uploads(&self) {
+        let completed = self.upload_timeline.completed_value();
+        self.pending_uploads
+            .borrow_mut()
+            .retain(|(value, command_buffer)| {
+                let finished = *value <= completed;
+                if finished {
+                    command_buffer.return_to_pool();
+                }
+                !finished
+            });
     }
 
     pub fn init(
@@ -314,9 +534,10 @@ impl VulkanContext {
         with_validation_layers: bool,
         app_name: CString,
         engine_name: CString,
+        device_selection: &DeviceSelection,
     ) -> Self {
         let entry = unsafe { Entry::load() }.unwrap();
-        let instance = Self::create_instance(
+        let (instance, has_debug_utils_ext) = Self::create_instance(
             with_validation_layers,
             &app_name,
             &engine_name,
@@ -324,7 +545,9 @@ impl VulkanContext {
             &entry,
         );
         let debug_utils_loader = DebugInstance::new(&entry, &instance);
-        let debug_callback = create_debug_messenger(&debug_utils_loader, with_validation_layers);
+        let validation_log = Arc::new(ValidationLog::default());
+        let debug_callback =
+            create_debug_messenger(&debug_utils_loader, with_validation_layers, &validation_log);
         let surface_loader = SurfaceInstance::new(&entry, &instance);
         let surface = unsafe {
             ash_window::create_surface(
@@ -337,8 +560,9 @@ impl VulkanContext {
         }
         .unwrap();
 
-        let physical_device =
-            unsafe { pick_physical_device(&instance, &surface_loader, surface) }.unwrap();
+        let (physical_device, device_info) =
+            unsafe { pick_physical_device(&instance, &surface_loader, surface, device_selection) }
+                .expect("No physical device can present to the window");
 
         let queue_indices = QueueFamilyIndices::find_queue_families(
             &instance,
@@ -347,7 +571,7 @@ impl VulkanContext {
             physical_device,
         );
 
-        let queue_create_infos = vec![
+        let mut queue_create_infos = vec![
             vk::DeviceQueueCreateInfo::default()
                 .queue_family_index(queue_indices.graphics_idx.unwrap())
                 .queue_priorities(&[1.0]),
@@ -356,22 +580,49 @@ impl VulkanContext {
             //     .queue_priorities(&[0.5])
             //     .build(),
         ];
+        if let Some(compute_idx) = queue_indices.compute_idx {
+            queue_create_infos.push(
+                vk::DeviceQueueCreateInfo::default()
+                    .queue_family_index(compute_idx)
+                    .queue_priorities(&[0.5]),
+            );
+        }
         let graphics_queue_idx = queue_indices.graphics_idx.unwrap();
         let transfer_queue_idx = 0; //queue_indices.transfer_idx.unwrap();
 
+        let capabilities =
+            unsafe { DeviceCapabilities::negotiate(&instance, physical_device, &device_info) };
+        println!("{}", capabilities);
         let device = create_device(
             &instance,
             physical_device,
             queue_create_infos,
             with_validation_layers,
+            &capabilities,
         );
 
         let swapchain_loader = Arc::new(SwapchainDevice::new(&instance, &device));
+        let debug_utils = if has_debug_utils_ext {
+            Some(DebugDevice::new(&instance, &device))
+        } else {
+            None
+        };
 
         let graphics_queue = unsafe { device.get_device_queue(graphics_queue_idx, 0) };
 
         let gfx_queue = super::Queue::new(device.clone(), graphics_queue_idx, 0);
-        let gfx_cmdpool = super::CommandPool::new(device.clone(), graphics_queue_idx);
+        let gfx_cmdpool =
+            super::CommandPool::new(device.clone(), graphics_queue_idx, debug_utils.clone());
+
+        let async_compute_queue = queue_indices
+            .compute_idx
+            .map(|compute_idx| super::Queue::new(device.clone(), compute_idx, 0));
+        let async_compute_cmdpool = queue_indices.compute_idx.map(|compute_idx| {
+            super::CommandPool::new(device.clone(), compute_idx, debug_utils.clone())
+        });
+        if let Some(compute_idx) = queue_indices.compute_idx {
+            println!("Async compute queue family: {}", compute_idx);
+        }
 
         let transfer_queue = unsafe { device.get_device_queue(transfer_queue_idx, 0) };
         let create_info = vk::CommandPoolCreateInfo::default()
@@ -394,6 +645,10 @@ impl VulkanContext {
         };
 
         let allocator = ManuallyDrop::new(RefCell::new(Allocator::new(&create_info).unwrap()));
+        let frame_timeline =
+            super::TimelineSemaphore::new(device.clone(), capabilities.timeline_semaphore);
+        let upload_timeline =
+            super::TimelineSemaphore::new(device.clone(), capabilities.timeline_semaphore);
 
         Self {
             entry,
@@ -402,15 +657,25 @@ impl VulkanContext {
             surface_loader,
             swapchain_loader,
             physical_device,
+            device_info,
+            capabilities,
             allocator,
+            memory_tracker: RefCell::new(MemoryTracker::default()),
             surface,
             graphics_queue,
             gfx_queue,
             gfx_cmdpool,
+            async_compute_queue,
+            async_compute_cmdpool,
             transfer_command_pool,
             transfer_queue,
             debug_utils_loader,
+            debug_utils,
             debug_callback,
+            validation_log,
+            frame_timeline,
+            upload_timeline,
+            pending_uploads: RefCell::new(vec![]),
         }
     }
 }
@@ -419,9 +684,15 @@ impl Drop for VulkanContext {
         unsafe {
             self.device.device_wait_idle().unwrap();
 
+            self.free_finished_uploads();
+            self.frame_timeline.destroy();
+            self.upload_timeline.destroy();
             self.device
                 .destroy_command_pool(self.transfer_command_pool, None);
             self.gfx_cmdpool.destroy();
+            if let Some(cmdpool) = &self.async_compute_cmdpool {
+                cmdpool.destroy();
+            }
             ManuallyDrop::drop(&mut self.allocator);
             self.device.destroy_device(None);
             self.surface_loader.destroy_surface(self.surface, None);
@@ -463,13 +734,20 @@ impl VulkanFrameCtx {
         unsafe { device.create_image_view(&create_info, None) }.unwrap()
     }
 
-    pub fn init(context: &Arc<VulkanContext>) -> Self {
+    pub fn init(
+        context: &Arc<VulkanContext>,
+        sample_count: vk::SampleCountFlags,
+        prefer_hdr_output: bool,
+        preferred_present_mode: super::PresentMode,
+    ) -> Self {
         let swapchain = super::Swapchain::create_swapchain(
             context.swapchain_loader.clone(),
             &context.surface_loader,
             context.physical_device,
             context.surface,
             None,
+            prefer_hdr_output,
+            preferred_present_mode,
         );
 
         let swapchain_images = swapchain.get_swapchain_images();
@@ -485,8 +763,8 @@ impl VulkanFrameCtx {
                 )
             })
             .collect();
-        let depth_render_texture =
-            create_depth_render_texture(context.clone(), swapchain.get_extent());
+        let (color_render_texture, depth_render_texture, hdr_render_texture) =
+            create_scene_render_textures(context, swapchain.get_extent(), sample_count);
 
         let command_buffers = context
             .gfx_cmdpool
@@ -497,12 +775,70 @@ impl VulkanFrameCtx {
             swapchain,
             swapchain_image_views,
             swapchain_images,
+            sample_count,
+            prefer_hdr_output,
+            preferred_present_mode,
+            color_render_texture,
             depth_render_texture,
+            hdr_render_texture,
             command_buffers,
         };
+        ctx.name_swapchain_objects();
         ctx
     }
 
+    fn name_swapchain_objects(&self) {
+        self.context
+            .set_object_name(self.swapchain.swapchain, "swapchain");
+        for (i, (image, image_view)) in self
+            .swapchain_images
+            .iter()
+            .zip(&self.swapchain_image_views)
+            .enumerate()
+        {
+            let name = format!("swapchain image {}", i);
+            self.context.set_object_name(*image, &name);
+            self.context.set_object_name(*image_view, &name);
+        }
+        for (i, command_buffer) in self.command_buffers.iter().enumerate() {
+            self.context.set_object_name(
+                command_buffer.vk_command_buffer(),
+                &format!("frame command buffer {}", i),
+            );
+        }
+    }
+
+    /// Recreates the render targets that depend on the sample count, the swapchain is kept as is.
+    pub fn set_sample_count(&mut self, sample_count: vk::SampleCountFlags) {
+        self.sample_count = sample_count;
+        self.recreate_render_textures();
+    }
+
+    fn recreate_render_textures(&mut self) {
+        let (color_render_texture, depth_render_texture, hdr_render_texture) =
+            create_scene_render_textures(
+                &self.context,
+                self.swapchain.get_extent(),
+                self.sample_count,
+            );
+        self.color_render_texture = color_render_texture;
+        self.depth_render_texture = depth_render_texture;
+        self.hdr_render_texture = hdr_render_texture;
+    }
+
+    /// Attachments for the opaque pass framebuffer, matching the attachment
+    /// order of `RenderPass::create_opaque`.
+    pub fn framebuffer_attachments(&self) -> Vec<vk::ImageView> {
+        let hdr_view = self.hdr_render_texture.image_view;
+        let depth_view = self.depth_render_texture.image_view;
+        match &self.color_render_texture {
+            Some(color_render_texture) => {
+                vec![color_render_texture.image_view, depth_view, hdr_view]
+            }
+            None => vec![hdr_view, depth_view],
+        }
+    }
+
     pub fn recreate_swapchain(&mut self) {
         let swapchain = super::Swapchain::create_swapchain(
             self.context.swapchain_loader.clone(),
@@ -510,6 +846,8 @@ impl VulkanFrameCtx {
             self.context.physical_device,
             self.context.surface,
             Some(self.swapchain.swapchain),
+            self.prefer_hdr_output,
+            self.preferred_present_mode,
         );
         self.destroy();
         self.swapchain = swapchain;
@@ -528,8 +866,8 @@ impl VulkanFrameCtx {
                 )
             })
             .collect();
-        self.depth_render_texture =
-            create_depth_render_texture(self.context.clone(), self.swapchain.get_extent());
+        self.recreate_render_textures();
+        self.name_swapchain_objects();
     }
 
     pub fn destroy(&mut self) {
@@ -547,52 +885,43 @@ unsafe fn pick_physical_device(
     instance: &Instance,
     surface_loader: &SurfaceInstance,
     surface: vk::SurfaceKHR,
-) -> Option<vk::PhysicalDevice> {
-    let physical_devices = instance.enumerate_physical_devices().unwrap();
-
-    let physical_device = physical_devices.into_iter().max_by_key(|physical_device| {
-        is_physical_device_suitable(instance, surface_loader, *physical_device, surface)
-    });
-    if let Some(device) = physical_device {
-        let properties = instance.get_physical_device_properties(device);
-        println!(
-            "Picking physical device: {:?}",
-            CStr::from_ptr(properties.device_name.as_ptr())
-        );
+    selection: &DeviceSelection,
+) -> Option<(vk::PhysicalDevice, DeviceInfo)> {
+    let mut devices = query_devices(instance);
+    for (_, info) in &devices {
+        println!("Found physical device: {}", info.summary());
     }
-    physical_device
+
+    let (handles, infos): (Vec<_>, Vec<_>) = devices.iter().cloned().unzip();
+    let selection = DeviceSelection::from_env().unwrap_or_else(|| selection.clone());
+    let picked = selection.pick(&infos, |info| {
+        can_present(surface_loader, handles[info.index], surface)
+    })?;
+    let (device, info) = devices.swap_remove(picked);
+    println!("Picking physical device: {}", info.name);
+    Some((device, info))
 }
 
-unsafe fn is_physical_device_suitable(
-    instance: &Instance,
+unsafe fn can_present(
     surface_loader: &SurfaceInstance,
     physical_device: vk::PhysicalDevice,
     surface: vk::SurfaceKHR,
-) -> u32 {
-    let properties = instance.get_physical_device_properties(physical_device);
-    let mut score = 0;
-
-    match properties.device_type {
-        vk::PhysicalDeviceType::DISCRETE_GPU => score += 1000,
-        vk::PhysicalDeviceType::INTEGRATED_GPU => score += 100,
-        vk::PhysicalDeviceType::CPU => score += 10,
-        _ => {}
-    }
-
-    score += properties.limits.max_image_dimension2_d;
-
+) -> bool {
     let swapchain_support =
         SwapchainInfo::query_swapchain_support(surface_loader, physical_device, surface);
 
-    if swapchain_support.surface_formats.is_empty() && swapchain_support.present_modes.is_empty() {
-        score = 0;
-    }
-
-    score
+    !(swapchain_support.surface_formats.is_empty() && swapchain_support.present_modes.is_empty())
 }
 
-fn create_depth_render_texture(context: Arc<VulkanContext>, extent: vk::Extent2D) -> RenderTexture {
-    let depth_format = context.find_depth_format();
+fn create_render_texture(
+    context: Arc<VulkanContext>,
+    name: &str,
+    extent: vk::Extent2D,
+    format: vk::Format,
+    sample_count: vk::SampleCountFlags,
+    usage: vk::ImageUsageFlags,
+    aspect_mask: vk::ImageAspectFlags,
+) -> RenderTexture {
     let extent_3d = vk::Extent3D {
         width: extent.width,
         height: extent.height,
@@ -602,67 +931,119 @@ fn create_depth_render_texture(context: Arc<VulkanContext>, extent: vk::Extent2D
         .image_type(vk::ImageType::TYPE_2D)
         .mip_levels(1)
         .array_layers(1)
-        .format(depth_format)
+        .format(format)
         .extent(extent_3d)
         .tiling(vk::ImageTiling::OPTIMAL)
-        .samples(vk::SampleCountFlags::TYPE_1)
-        .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT);
+        .samples(sample_count)
+        .usage(usage);
 
     //https://vulkan-tutorial.com/Depth_buffering
-    let (depth_image, image_memory) =
+    let (image, image_memory) =
         context.create_image(create_info, gpu_allocator::MemoryLocation::GpuOnly);
 
-    let image_view = VulkanFrameCtx::create_image_view(
-        &context.device,
-        depth_image,
-        depth_format,
-        vk::ImageAspectFlags::DEPTH,
-    );
+    let image_view = VulkanFrameCtx::create_image_view(&context.device, image, format, aspect_mask);
+    context.set_object_name(image, name);
+    context.set_object_name(image_view, name);
     RenderTexture {
         extent,
         image_view,
-        image: depth_image,
+        image,
         image_memory: Some(image_memory),
-        format: depth_format,
+        format,
         context,
     }
 }
 
+///Creates the (optional multisampled color, depth, hdr) targets the scene is rendered into
+fn create_scene_render_textures(
+    context: &Arc<VulkanContext>,
+    extent: vk::Extent2D,
+    sample_count: vk::SampleCountFlags,
+) -> (Option<RenderTexture>, RenderTexture, RenderTexture) {
+    let color_render_texture = if sample_count != vk::SampleCountFlags::TYPE_1 {
+        Some(create_render_texture(
+            context.clone(),
+            "scene msaa color",
+            extent,
+            HDR_FORMAT,
+            sample_count,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+            vk::ImageAspectFlags::COLOR,
+        ))
+    } else {
+        None
+    };
+    let depth_render_texture = create_render_texture(
+        context.clone(),
+        "scene depth",
+        extent,
+        context.find_depth_format(),
+        sample_count,
+        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        vk::ImageAspectFlags::DEPTH,
+    );
+    let hdr_render_texture = create_render_texture(
+        context.clone(),
+        "scene hdr color",
+        extent,
+        HDR_FORMAT,
+        vk::SampleCountFlags::TYPE_1,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        vk::ImageAspectFlags::COLOR,
+    );
+    (
+        color_render_texture,
+        depth_render_texture,
+        hdr_render_texture,
+    )
+}
+
 fn create_device(
     instance: &Instance,
     physical_device: vk::PhysicalDevice,
     queue_create_infos: Vec<vk::DeviceQueueCreateInfo>,
     with_validation_layers: bool,
+    capabilities: &DeviceCapabilities,
 ) -> Device {
-    let device_extensions = [ash::khr::swapchain::NAME.as_ptr()];
+    let device_extensions: Vec<_> = capabilities
+        .extensions
+        .iter()
+        .map(|name| name.as_ptr())
+        .collect();
     let mut device_layers = vec![];
     if with_validation_layers {
         device_layers.push(LAYER_KHRONOS_VALIDATION.as_ptr() as *const i8);
     }
 
     // https://vulkan-tutorial.com/Drawing_a_triangle/Setup/Logical_device_and_queues
-    let features = vk::PhysicalDeviceFeatures {
-        sampler_anisotropy: 1,
-        ..Default::default()
-    };
+    let mut features_12 = vk::PhysicalDeviceVulkan12Features::default()
+        .draw_indirect_count(capabilities.draw_indirect_count)
+        .timeline_semaphore(capabilities.timeline_semaphore);
+    let mut features_uint8 =
+        vk::PhysicalDeviceIndexTypeUint8FeaturesKHR::default().index_type_uint8(true);
 
-    let create_info = vk::DeviceCreateInfo::default()
+    let mut create_info = vk::DeviceCreateInfo::default()
         .enabled_extension_names(&device_extensions)
         .enabled_layer_names(&device_layers)
         .queue_create_infos(&queue_create_infos)
-        .enabled_features(&features);
-    let device = unsafe {
+        .enabled_features(&capabilities.features);
+    if capabilities.draw_indirect_count || capabilities.timeline_semaphore {
+        create_info = create_info.push_next(&mut features_12);
+    }
+    if capabilities.index_type_uint8 {
+        create_info = create_info.push_next(&mut features_uint8);
+    }
+    unsafe {
         instance
             .create_device(physical_device, &create_info, None)
             .unwrap()
-    };
-
-    device
+    }
 }
 
 fn create_debug_messenger(
     debug_utils_loader: &DebugInstance,
     with_validation_layers: bool,
+    validation_log: &Arc<ValidationLog>,
 ) -> Option<vk::DebugUtilsMessengerEXT> {
     if with_validation_layers {
         let create_info = vk::DebugUtilsMessengerCreateInfoEXT::default()
@@ -675,7 +1056,9 @@ fn create_debug_messenger(
                 vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
                     | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
             )
-            .pfn_user_callback(Some(debug_callback));
+            .pfn_user_callback(Some(super::validation::debug_callback))
+            //The context keeps the log alive for as long as the messenger exists
+            .user_data(Arc::as_ptr(validation_log) as *mut c_void);
 
         Some(
             unsafe { debug_utils_loader.create_debug_utils_messenger(&create_info, None) }.unwrap(),
@@ -685,20 +1068,6 @@ fn create_debug_messenger(
     }
 }
 
-unsafe extern "system" fn debug_callback(
-    _message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
-    _message_types: vk::DebugUtilsMessageTypeFlagsEXT,
-    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _p_user_data: *mut c_void,
-) -> vk::Bool32 {
-    println!(
-        "{}",
-        CStr::from_ptr((*p_callback_data).p_message).to_string_lossy()
-    );
-
-    vk::FALSE
-}
-
 fn check_validation_support(entry: &Entry) -> bool {
     unsafe {
         let available_layers = entry.enumerate_instance_layer_properties().unwrap();
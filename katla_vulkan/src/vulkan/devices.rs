@@ -0,0 +1,488 @@
+use ash::{vk, Entry, Instance};
+use std::{
+    ffi::{CStr, CString},
+    fmt,
+};
+
+/// Environment variable overriding the device choice: an index, `cpu`, or part of a name.
+pub const DEVICE_ENV_VAR: &str = "KATLA_DEVICE";
+
+//Formats the renderer uses, or that assets commonly come in
+const REPORTED_FORMATS: [vk::Format; 12] = [
+    vk::Format::R8G8B8A8_UNORM,
+    vk::Format::R8G8B8A8_SRGB,
+    vk::Format::B8G8R8A8_SRGB,
+    vk::Format::A2B10G10R10_UNORM_PACK32,
+    vk::Format::R16G16B16A16_SFLOAT,
+    vk::Format::R32G32B32A32_SFLOAT,
+    vk::Format::D32_SFLOAT,
+    vk::Format::D24_UNORM_S8_UINT,
+    vk::Format::D32_SFLOAT_S8_UINT,
+    vk::Format::BC7_SRGB_BLOCK,
+    vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK,
+    vk::Format::ASTC_4X4_SRGB_BLOCK,
+];
+
+/// Which physical device the context is created on.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum DeviceSelection {
+    /// Highest scoring device: discrete, then integrated, then CPU.
+    #[default]
+    Auto,
+    /// Index into the list returned by `list_devices`.
+    Index(usize),
+    /// First device whose name contains this, ignoring case.
+    Name(String),
+    /// A software rasterizer such as lavapipe, e.g. for tests on machines without a GPU.
+    PreferCpu,
+}
+
+impl DeviceSelection {
+    /// Reads `KATLA_DEVICE`, if set.
+    pub fn from_env() -> Option<Self> {
+        let value = std::env::var(DEVICE_ENV_VAR).ok()?;
+        let value = value.trim();
+        if value.is_empty() {
+            return None;
+        }
+        Some(if let Ok(index) = value.parse() {
+            Self::Index(index)
+        } else if value.eq_ignore_ascii_case("cpu") {
+            Self::PreferCpu
+        } else {
+            Self::Name(value.to_owned())
+        })
+    }
+
+    /// Picks one of `devices`, skipping those `usable` rejects. Falls back to `Auto` when
+    /// the requested device is missing or unusable.
+    pub fn pick(
+        &self,
+        devices: &[DeviceInfo],
+        usable: impl Fn(&DeviceInfo) -> bool,
+    ) -> Option<usize> {
+        let mut candidates = devices.iter().filter(|device| usable(device));
+        let picked = match self {
+            Self::Auto => None,
+            Self::Index(index) => candidates.find(|device| device.index == *index),
+            Self::Name(name) => {
+                let name = name.to_lowercase();
+                candidates.find(|device| device.name.to_lowercase().contains(&name))
+            }
+            Self::PreferCpu => {
+                candidates.find(|device| device.device_type == vk::PhysicalDeviceType::CPU)
+            }
+        };
+        if picked.is_none() && *self != Self::Auto {
+            println!("No usable device matches {:?}, picking automatically", self);
+        }
+        picked
+            .or_else(|| {
+                devices
+                    .iter()
+                    .filter(|device| usable(device))
+                    .max_by_key(|device| device.score())
+            })
+            .map(|device| device.index)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FormatSupport {
+    pub format: vk::Format,
+    pub optimal_tiling: vk::FormatFeatureFlags,
+}
+
+/// Everything the report shows about one physical device.
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+    pub index: usize,
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub api_version: u32,
+    pub driver_version: u32,
+    pub limits: vk::PhysicalDeviceLimits,
+    pub memory_heaps: Vec<vk::MemoryHeap>,
+    pub memory_types: Vec<vk::MemoryType>,
+    pub queue_families: Vec<vk::QueueFamilyProperties>,
+    pub extensions: Vec<String>,
+    pub formats: Vec<FormatSupport>,
+}
+
+impl DeviceInfo {
+    /// # Safety
+    /// `physical_device` has to come from `instance`.
+    pub unsafe fn query(
+        instance: &Instance,
+        index: usize,
+        physical_device: vk::PhysicalDevice,
+    ) -> Self {
+        let properties = instance.get_physical_device_properties(physical_device);
+        let memory = instance.get_physical_device_memory_properties(physical_device);
+        let queue_families = instance.get_physical_device_queue_family_properties(physical_device);
+        let mut extensions: Vec<String> = instance
+            .enumerate_device_extension_properties(physical_device)
+            .unwrap()
+            .iter()
+            .filter_map(|ext| ext.extension_name_as_c_str().ok())
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect();
+        extensions.sort();
+        let formats = REPORTED_FORMATS
+            .iter()
+            .map(|&format| FormatSupport {
+                format,
+                optimal_tiling: instance
+                    .get_physical_device_format_properties(physical_device, format)
+                    .optimal_tiling_features,
+            })
+            .collect();
+
+        Self {
+            index,
+            name: CStr::from_ptr(properties.device_name.as_ptr())
+                .to_string_lossy()
+                .into_owned(),
+            device_type: properties.device_type,
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            api_version: properties.api_version,
+            driver_version: properties.driver_version,
+            limits: properties.limits,
+            memory_heaps: memory.memory_heaps_as_slice().to_vec(),
+            memory_types: memory.memory_types_as_slice().to_vec(),
+            queue_families,
+            extensions,
+            formats,
+        }
+    }
+
+    /// Device preference when nothing else decides.
+    pub fn score(&self) -> u32 {
+        let type_score = match self.device_type {
+            vk::PhysicalDeviceType::DISCRETE_GPU => 1000,
+            vk::PhysicalDeviceType::INTEGRATED_GPU => 100,
+            vk::PhysicalDeviceType::CPU => 10,
+            _ => 0,
+        };
+        type_score + self.limits.max_image_dimension2_d
+    }
+
+    pub fn supports_extension(&self, name: &CStr) -> bool {
+        let name = name.to_string_lossy();
+        self.extensions.iter().any(|ext| *ext == name)
+    }
+
+    /// Size of the device local heaps, i.e. video memory on a discrete GPU.
+    pub fn device_local_memory(&self) -> u64 {
+        self.memory_heaps
+            .iter()
+            .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|heap| heap.size)
+            .sum()
+    }
+
+    /// One line summary, e.g. for picking a device by index.
+    pub fn summary(&self) -> String {
+        format!(
+            "[{}] {} ({}, Vulkan {}, {} MiB device local)",
+            self.index,
+            self.name,
+            device_type_name(self.device_type),
+            version_string(self.api_version),
+            self.device_local_memory() >> 20
+        )
+    }
+}
+
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.summary())?;
+        writeln!(
+            f,
+            "  vendor 0x{:04x}, device 0x{:04x}, driver version {}",
+            self.vendor_id, self.device_id, self.driver_version
+        )?;
+        writeln!(
+            f,
+            "  max image 2D {}, max push constants {} B, max sampler anisotropy {}",
+            self.limits.max_image_dimension2_d,
+            self.limits.max_push_constants_size,
+            self.limits.max_sampler_anisotropy
+        )?;
+
+        writeln!(f, "  Memory heaps:")?;
+        for (i, heap) in self.memory_heaps.iter().enumerate() {
+            let types: Vec<String> = self
+                .memory_types
+                .iter()
+                .filter(|ty| ty.heap_index as usize == i)
+                .map(|ty| memory_property_names(ty.property_flags))
+                .collect();
+            writeln!(
+                f,
+                "    {}: {} MiB{} [{}]",
+                i,
+                heap.size >> 20,
+                if heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL) {
+                    " device local"
+                } else {
+                    ""
+                },
+                types.join(", ")
+            )?;
+        }
+
+        writeln!(f, "  Queue families:")?;
+        for (i, family) in self.queue_families.iter().enumerate() {
+            writeln!(
+                f,
+                "    {}: {} x{}, {} timestamp bits",
+                i,
+                queue_flag_names(family.queue_flags),
+                family.queue_count,
+                family.timestamp_valid_bits
+            )?;
+        }
+
+        writeln!(f, "  Formats (optimal tiling):")?;
+        for support in &self.formats {
+            writeln!(
+                f,
+                "    {:?}: {}",
+                support.format,
+                format_feature_names(support.optimal_tiling)
+            )?;
+        }
+
+        writeln!(f, "  Extensions ({}):", self.extensions.len())?;
+        for extension in &self.extensions {
+            writeln!(f, "    {}", extension)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lists every physical device without creating a window, for reports and device pickers.
+/// Empty when there is no Vulkan loader or driver.
+pub fn list_devices() -> Vec<DeviceInfo> {
+    let entry = match unsafe { Entry::load() } {
+        Ok(entry) => entry,
+        Err(error) => {
+            println!("Could not load the Vulkan loader: {}", error);
+            return vec![];
+        }
+    };
+    let engine_name = CString::new("Katla").unwrap();
+    let app_info = vk::ApplicationInfo::default()
+        .engine_name(&engine_name)
+        .api_version(vk::make_api_version(0, 1, 2, 0));
+    let create_info = vk::InstanceCreateInfo::default().application_info(&app_info);
+    let instance = match unsafe { entry.create_instance(&create_info, None) } {
+        Ok(instance) => instance,
+        Err(error) => {
+            println!("Could not create a Vulkan instance: {}", error);
+            return vec![];
+        }
+    };
+    let devices = unsafe { query_devices(&instance) }
+        .into_iter()
+        .map(|(_, info)| info)
+        .collect();
+    unsafe { instance.destroy_instance(None) };
+    devices
+}
+
+/// # Safety
+/// `instance` has to be a valid instance.
+pub unsafe fn query_devices(instance: &Instance) -> Vec<(vk::PhysicalDevice, DeviceInfo)> {
+    instance
+        .enumerate_physical_devices()
+        .unwrap()
+        .into_iter()
+        .enumerate()
+        .map(|(index, device)| (device, DeviceInfo::query(instance, index, device)))
+        .collect()
+}
+
+pub fn device_type_name(device_type: vk::PhysicalDeviceType) -> &'static str {
+    match device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU => "discrete GPU",
+        vk::PhysicalDeviceType::INTEGRATED_GPU => "integrated GPU",
+        vk::PhysicalDeviceType::VIRTUAL_GPU => "virtual GPU",
+        vk::PhysicalDeviceType::CPU => "CPU",
+        _ => "other",
+    }
+}
+
+fn version_string(version: u32) -> String {
+    format!(
+        "{}.{}.{}",
+        vk::api_version_major(version),
+        vk::api_version_minor(version),
+        vk::api_version_patch(version)
+    )
+}
+
+fn flag_names<T: Copy>(flags: T, names: &[(T, &str)], contains: impl Fn(T, T) -> bool) -> String {
+    let names: Vec<&str> = names
+        .iter()
+        .filter(|(flag, _)| contains(flags, *flag))
+        .map(|(_, name)| *name)
+        .collect();
+    if names.is_empty() {
+        "-".to_owned()
+    } else {
+        names.join(" ")
+    }
+}
+
+fn memory_property_names(flags: vk::MemoryPropertyFlags) -> String {
+    use vk::MemoryPropertyFlags as F;
+    flag_names(
+        flags,
+        &[
+            (F::DEVICE_LOCAL, "device"),
+            (F::HOST_VISIBLE, "visible"),
+            (F::HOST_COHERENT, "coherent"),
+            (F::HOST_CACHED, "cached"),
+            (F::LAZILY_ALLOCATED, "lazy"),
+        ],
+        |flags, flag| flags.contains(flag),
+    )
+}
+
+fn queue_flag_names(flags: vk::QueueFlags) -> String {
+    use vk::QueueFlags as F;
+    flag_names(
+        flags,
+        &[
+            (F::GRAPHICS, "graphics"),
+            (F::COMPUTE, "compute"),
+            (F::TRANSFER, "transfer"),
+            (F::SPARSE_BINDING, "sparse"),
+        ],
+        |flags, flag| flags.contains(flag),
+    )
+}
+
+fn format_feature_names(flags: vk::FormatFeatureFlags) -> String {
+    use vk::FormatFeatureFlags as F;
+    flag_names(
+        flags,
+        &[
+            (F::SAMPLED_IMAGE, "sampled"),
+            (F::SAMPLED_IMAGE_FILTER_LINEAR, "linear"),
+            (F::STORAGE_IMAGE, "storage"),
+            (F::COLOR_ATTACHMENT, "color"),
+            (F::COLOR_ATTACHMENT_BLEND, "blend"),
+            (F::DEPTH_STENCIL_ATTACHMENT, "depth"),
+            (F::BLIT_SRC, "blit-src"),
+            (F::BLIT_DST, "blit-dst"),
+        ],
+        |flags, flag| flags.contains(flag),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(index: usize, name: &str, device_type: vk::PhysicalDeviceType) -> DeviceInfo {
+        DeviceInfo {
+            index,
+            name: name.to_owned(),
+            device_type,
+            vendor_id: 0,
+            device_id: 0,
+            api_version: 0,
+            driver_version: 0,
+            limits: vk::PhysicalDeviceLimits::default(),
+            memory_heaps: vec![],
+            memory_types: vec![],
+            queue_families: vec![],
+            extensions: vec![],
+            formats: vec![],
+        }
+    }
+
+    fn devices() -> Vec<DeviceInfo> {
+        vec![
+            device(
+                0,
+                "llvmpipe (LLVM 15.0.7, 256 bits)",
+                vk::PhysicalDeviceType::CPU,
+            ),
+            device(
+                1,
+                "Intel(R) UHD Graphics 630",
+                vk::PhysicalDeviceType::INTEGRATED_GPU,
+            ),
+            device(
+                2,
+                "NVIDIA GeForce RTX 3070",
+                vk::PhysicalDeviceType::DISCRETE_GPU,
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_pick_auto_prefers_discrete() {
+        let devices = devices();
+        assert_eq!(DeviceSelection::Auto.pick(&devices, |_| true), Some(2));
+        //Then integrated, then CPU
+        assert_eq!(
+            DeviceSelection::Auto.pick(&devices, |device| device.index != 2),
+            Some(1)
+        );
+        assert_eq!(
+            DeviceSelection::Auto.pick(&devices, |device| device.index == 0),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn test_pick_requested() {
+        let devices = devices();
+        assert_eq!(DeviceSelection::Index(1).pick(&devices, |_| true), Some(1));
+        assert_eq!(
+            DeviceSelection::Name("geforce".to_owned()).pick(&devices, |_| true),
+            Some(2)
+        );
+        assert_eq!(
+            DeviceSelection::Name("UHD".to_owned()).pick(&devices, |_| true),
+            Some(1)
+        );
+        assert_eq!(DeviceSelection::PreferCpu.pick(&devices, |_| true), Some(0));
+    }
+
+    #[test]
+    fn test_pick_falls_back_to_auto() {
+        let devices = devices();
+        //Missing
+        assert_eq!(DeviceSelection::Index(7).pick(&devices, |_| true), Some(2));
+        assert_eq!(
+            DeviceSelection::Name("radeon".to_owned()).pick(&devices, |_| true),
+            Some(2)
+        );
+        //Unusable
+        assert_eq!(
+            DeviceSelection::PreferCpu.pick(&devices, |device| device.index != 0),
+            Some(2)
+        );
+        assert_eq!(
+            DeviceSelection::Index(1).pick(&devices, |device| device.index == 0),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn test_pick_nothing_usable() {
+        let devices = devices();
+        assert_eq!(DeviceSelection::Auto.pick(&devices, |_| false), None);
+        assert_eq!(DeviceSelection::Index(1).pick(&devices, |_| false), None);
+        assert_eq!(DeviceSelection::Auto.pick(&[], |_| true), None);
+    }
+}
@@ -0,0 +1,261 @@
+use ash::vk;
+use gpu_allocator::vulkan::Allocation;
+
+use std::sync::Arc;
+
+use super::{
+    CommandBuffer, ComputeBarrier, ComputeBinding, ComputePipeline, RenderTexture, VulkanContext,
+};
+
+const SHADER_INIT: &[u8] = include_bytes!("../../../resources/shaders/hiz_init.comp.spv");
+const SHADER_INIT_MS: &[u8] = include_bytes!("../../../resources/shaders/hiz_init_ms.comp.spv");
+const SHADER_REDUCE: &[u8] = include_bytes!("../../../resources/shaders/hiz_reduce.comp.spv");
+const GROUP_SIZE: u32 = 8;
+const FORMAT: vk::Format = vk::Format::R32_SFLOAT;
+
+//Matches the push constant block in hiz_init.comp
+#[repr(C)]
+struct InitSizes {
+    depth_size: [i32; 2],
+    level_size: [i32; 2],
+}
+
+fn previous_power_of_two(value: u32) -> u32 {
+    1 << (31 - value.max(1).leading_zeros())
+}
+
+/// Mip chain of the scene depth where every texel holds the farthest depth of the area it
+/// covers, used to test bounds for occlusion in the cull shader.
+///
+/// Level 0 is the largest power of two that fits in the depth buffer, so every level is
+/// exactly half the size of the one above. The image stays in the GENERAL layout.
+pub struct DepthPyramid {
+    context: Arc<VulkanContext>,
+    /// Size of level 0
+    pub extent: vk::Extent2D,
+    pub mip_levels: u32,
+    depth_extent: vk::Extent2D,
+    image: vk::Image,
+    image_memory: Option<Allocation>,
+    //All levels, for sampling
+    image_view: vk::ImageView,
+    level_views: Vec<vk::ImageView>,
+    sampler: vk::Sampler,
+    init_pipeline: ComputePipeline,
+    init_set: vk::DescriptorSet,
+    reduce_pipeline: ComputePipeline,
+    //Set `i` reduces level `i` into level `i + 1`
+    reduce_sets: Vec<vk::DescriptorSet>,
+}
+
+impl DepthPyramid {
+    /// Creates the pyramid for `depth`, which has to be sampled and left in the
+    /// SHADER_READ_ONLY_OPTIMAL layout by the opaque pass.
+    pub fn new(
+        context: Arc<VulkanContext>,
+        depth: &RenderTexture,
+        sample_count: vk::SampleCountFlags,
+    ) -> Self {
+        let extent = vk::Extent2D {
+            width: previous_power_of_two(depth.extent.width),
+            height: previous_power_of_two(depth.extent.height),
+        };
+        let mip_levels = 32 - extent.width.max(extent.height).leading_zeros();
+
+        let create_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .mip_levels(mip_levels)
+            .array_layers(1)
+            .format(FORMAT)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let (image, image_memory) =
+            context.create_image(create_info, gpu_allocator::MemoryLocation::GpuOnly);
+
+        let create_view = |base_mip_level: u32, level_count: u32| {
+            let subresource_range = vk::ImageSubresourceRange::default()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .base_mip_level(base_mip_level)
+                .level_count(level_count)
+                .base_array_layer(0)
+                .layer_count(1);
+            let create_info = vk::ImageViewCreateInfo::default()
+                .image(image)
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(FORMAT)
+                .subresource_range(subresource_range);
+            unsafe { context.device.create_image_view(&create_info, None) }.unwrap()
+        };
+        let image_view = create_view(0, mip_levels);
+        let level_views: Vec<_> = (0..mip_levels).map(|level| create_view(level, 1)).collect();
+
+        //Only read with texelFetch, the filter does not matter
+        let sampler_info = vk::SamplerCreateInfo::default()
+            .min_filter(vk::Filter::NEAREST)
+            .mag_filter(vk::Filter::NEAREST)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .max_lod(mip_levels as f32);
+        let sampler = unsafe { context.device.create_sampler(&sampler_info, None) }.unwrap();
+
+        let command_buffer = context.begin_single_time_commands();
+        command_buffer.image_barrier(
+            image,
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::GENERAL,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            vk::AccessFlags::empty(),
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+        );
+        context.end_single_time_commands(command_buffer);
+
+        let init_shader = if sample_count == vk::SampleCountFlags::TYPE_1 {
+            SHADER_INIT
+        } else {
+            SHADER_INIT_MS
+        };
+        let init_pipeline = ComputePipeline::new(
+            context.clone(),
+            init_shader,
+            &[
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                vk::DescriptorType::STORAGE_IMAGE,
+            ],
+            std::mem::size_of::<InitSizes>() as u32,
+            1,
+        );
+        init_pipeline.set_name("depth pyramid init");
+        let init_set = init_pipeline.create_descriptor_set(&[
+            ComputeBinding::SampledImage(depth.image_view, sampler),
+            ComputeBinding::StorageImage(level_views[0]),
+        ]);
+
+        let reduce_pipeline = ComputePipeline::new(
+            context.clone(),
+            SHADER_REDUCE,
+            &[
+                vk::DescriptorType::STORAGE_IMAGE,
+                vk::DescriptorType::STORAGE_IMAGE,
+            ],
+            0,
+            mip_levels.max(2) - 1,
+        );
+        reduce_pipeline.set_name("depth pyramid reduce");
+        let reduce_sets = level_views
+            .windows(2)
+            .map(|views| {
+                reduce_pipeline.create_descriptor_set(&[
+                    ComputeBinding::StorageImage(views[0]),
+                    ComputeBinding::StorageImage(views[1]),
+                ])
+            })
+            .collect();
+
+        context.set_object_name(image, "depth pyramid");
+        context.set_object_name(image_view, "depth pyramid");
+
+        Self {
+            context,
+            extent,
+            mip_levels,
+            depth_extent: depth.extent,
+            image,
+            image_memory: Some(image_memory),
+            image_view,
+            level_views,
+            sampler,
+            init_pipeline,
+            init_set,
+            reduce_pipeline,
+            reduce_sets,
+        }
+    }
+
+    /// The whole mip chain, for a `sampler2D` read with texelFetch.
+    pub fn binding(&self) -> ComputeBinding {
+        ComputeBinding::SampledStorageImage(self.image_view, self.sampler)
+    }
+
+    fn level_extent(&self, level: u32) -> vk::Extent2D {
+        vk::Extent2D {
+            width: (self.extent.width >> level).max(1),
+            height: (self.extent.height >> level).max(1),
+        }
+    }
+
+    /// Rebuilds every level from the depth buffer. Has to be recorded after the opaque pass,
+    /// the result is visible to compute shaders recorded after this.
+    pub fn build(&self, command_buffer: &CommandBuffer) {
+        command_buffer.begin_label("depth pyramid");
+        //The cull shader of this frame still reads the previous pyramid
+        command_buffer.memory_barrier(
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_WRITE,
+        );
+
+        let sizes = InitSizes {
+            depth_size: [
+                self.depth_extent.width as i32,
+                self.depth_extent.height as i32,
+            ],
+            level_size: [self.extent.width as i32, self.extent.height as i32],
+        };
+        let sizes_data = unsafe {
+            std::slice::from_raw_parts(
+                &sizes as *const InitSizes as *const u8,
+                std::mem::size_of::<InitSizes>(),
+            )
+        };
+        self.init_pipeline.bind(command_buffer, self.init_set);
+        self.init_pipeline
+            .push_constants(command_buffer, sizes_data);
+        command_buffer.dispatch(
+            self.extent.width.div_ceil(GROUP_SIZE),
+            self.extent.height.div_ceil(GROUP_SIZE),
+            1,
+        );
+
+        for (i, &desc_set) in self.reduce_sets.iter().enumerate() {
+            command_buffer.compute_barrier(ComputeBarrier::Compute);
+            let extent = self.level_extent(i as u32 + 1);
+            self.reduce_pipeline.bind(command_buffer, desc_set);
+            command_buffer.dispatch(
+                extent.width.div_ceil(GROUP_SIZE),
+                extent.height.div_ceil(GROUP_SIZE),
+                1,
+            );
+        }
+        command_buffer.compute_barrier(ComputeBarrier::Compute);
+        command_buffer.end_label();
+    }
+
+    pub fn destroy(&mut self) {
+        self.init_pipeline.destroy();
+        self.reduce_pipeline.destroy();
+        unsafe {
+            let device = &self.context.device;
+            device.destroy_sampler(self.sampler, None);
+            device.destroy_image_view(self.image_view, None);
+            for &level_view in &self.level_views {
+                device.destroy_image_view(level_view, None);
+            }
+        }
+        if let Some(image_memory) = self.image_memory.take() {
+            self.context.free_image(self.image, image_memory);
+        }
+    }
+}
@@ -0,0 +1,687 @@
+use ash::vk;
+use katla_math::{Frustum, Mat4, Sphere};
+
+use std::sync::Arc;
+
+use super::context::VulkanContext;
+use super::{
+    CommandBuffer, ComputeBarrier, ComputeBinding, ComputePipeline, DepthPyramid, StorageBuffer,
+};
+
+const SHADER_CULL: &[u8] = include_bytes!("../../../resources/shaders/cull.comp.spv");
+const CULL_GROUP_SIZE: u32 = 64;
+const COMMAND_STRIDE: u32 = std::mem::size_of::<vk::DrawIndexedIndirectCommand>() as u32;
+//Frames an occluded batch is skipped before it is drawn again to query its visibility
+const RETEST_INTERVAL: u32 = 4;
+
+/// Where the draws of a frame are culled against the frustum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CullMode {
+    /// A compute shader compacts the visible draws, drawn with vkCmdDrawIndexedIndirectCount
+    Gpu,
+    /// Same test on the CPU, used when the device lacks drawIndirectCount
+    Cpu,
+}
+
+/// How draws hidden behind other geometry are skipped, on top of frustum culling.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OcclusionCulling {
+    Off,
+    /// One occlusion query per batch. A batch that had no visible samples the last time its
+    /// frame slot was drawn is skipped, and drawn again every few frames to retest it.
+    Queries,
+    /// The cull shader tests every draw against a depth pyramid of the previous frame.
+    /// Needs `CullMode::Gpu`, falls back to queries otherwise.
+    HiZ,
+}
+
+/// The indirect call of a batch, see `IndirectBatchDraw`.
+#[derive(Clone, Copy, Debug)]
+pub enum IndirectCall {
+    /// Draw count read from `count_buffer`, written by the cull shader
+    Count {
+        buffer: vk::Buffer,
+        offset: u64,
+        count_buffer: vk::Buffer,
+        count_offset: u64,
+        max_draw_count: u32,
+    },
+    /// Draw count known on the CPU, one call per draw without multiDrawIndirect
+    Direct {
+        buffer: vk::Buffer,
+        offset: u64,
+        draw_count: u32,
+        multi_draw: bool,
+    },
+}
+
+/// One indirect call drawing the visible draws of a batch. Only holds handles, so it
+/// can be recorded on any thread.
+#[derive(Clone, Copy, Debug)]
+pub struct IndirectBatchDraw {
+    pub call: IndirectCall,
+    /// Occlusion query wrapped around the call
+    pub occlusion_query: Option<(vk::QueryPool, u32)>,
+}
+
+impl IndirectBatchDraw {
+    pub fn record(&self, command_buffer: &CommandBuffer) {
+        if let Some((query_pool, query)) = self.occlusion_query {
+            command_buffer.begin_query(query_pool, query);
+        }
+        match self.call {
+            IndirectCall::Count {
+                buffer,
+                offset,
+                count_buffer,
+                count_offset,
+                max_draw_count,
+            } => command_buffer.draw_indexed_indirect_count(
+                buffer,
+                offset,
+                count_buffer,
+                count_offset,
+                max_draw_count,
+                COMMAND_STRIDE,
+            ),
+            IndirectCall::Direct {
+                buffer,
+                offset,
+                draw_count,
+                multi_draw,
+            } => {
+                if multi_draw {
+                    command_buffer.draw_indexed_indirect(
+                        buffer,
+                        offset,
+                        draw_count,
+                        COMMAND_STRIDE,
+                    );
+                } else {
+                    for i in 0..draw_count {
+                        command_buffer.draw_indexed_indirect(
+                            buffer,
+                            offset + (i * COMMAND_STRIDE) as u64,
+                            1,
+                            COMMAND_STRIDE,
+                        );
+                    }
+                }
+            }
+        }
+        if let Some((query_pool, query)) = self.occlusion_query {
+            command_buffer.end_query(query_pool, query);
+        }
+    }
+}
+
+//Layout shared with cull.comp
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct DrawInput {
+    command: vk::DrawIndexedIndirectCommand,
+    batch: u32,
+    output_base: u32,
+    pad: u32,
+    sphere: [f32; 4],
+}
+
+//Layout shared with cull.comp
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct OcclusionData {
+    view_proj: [[f32; 4]; 4],
+    pyramid_size: [f32; 2],
+    enabled: u32,
+    max_level: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CullConstants {
+    planes: [[f32; 4]; 6],
+    draw_count: u32,
+}
+
+#[derive(Clone, Copy)]
+struct Batch {
+    first_draw: u32,
+    draw_count: u32,
+}
+
+struct IndirectFrame {
+    draws: StorageBuffer,
+    commands: StorageBuffer,
+    counts: StorageBuffer,
+    occlusion: StorageBuffer,
+    desc_set: vk::DescriptorSet,
+    //Visible draws per batch when culled on the CPU
+    cpu_counts: Vec<u32>,
+    //One occlusion query per batch
+    query_pool: vk::QueryPool,
+    //Batches drawn with a query, empty when occlusion queries are off
+    queried: Vec<bool>,
+}
+
+impl IndirectFrame {
+    fn bindings(&self, pyramid: &DepthPyramid) -> [ComputeBinding; 5] {
+        [
+            ComputeBinding::StorageBuffer(self.draws.object()),
+            ComputeBinding::StorageBuffer(self.commands.object()),
+            ComputeBinding::StorageBuffer(self.counts.object()),
+            ComputeBinding::StorageBuffer(self.occlusion.object()),
+            pyramid.binding(),
+        ]
+    }
+}
+
+/// All draws of a frame in one indirect buffer, culled against the camera frustum before
+/// the opaque pass. Draws are grouped in batches that share pipeline and buffers, each
+/// batch is drawn with one indirect call.
+///
+/// Per frame: `begin_frame`, then `begin_batch` and `add_draw` for every batch, `cull`
+/// outside of the render pass and `draw_batch` inside it. With Hi-Z occlusion culling the
+/// depth pyramid is built after the pass, followed by `depth_pyramid_built`.
+///
+/// Occlusion results carry over between frames by batch index, so batches are expected
+/// to be added in the same order every frame.
+pub struct IndirectDraws {
+    context: Arc<VulkanContext>,
+    pipeline: ComputePipeline,
+    frames: Vec<IndirectFrame>,
+    max_draws: u32,
+    max_batches: u32,
+    draws: Vec<DrawInput>,
+    bounds: Vec<Sphere>,
+    batches: Vec<Batch>,
+    frustum: Option<Frustum>,
+    view_proj: Option<Mat4>,
+    cull_mode: CullMode,
+    occlusion_culling: OcclusionCulling,
+    //View projection the depth pyramid was built with, None until it is built
+    pyramid_view_proj: Option<Mat4>,
+    pyramid_size: [f32; 2],
+    pyramid_max_level: u32,
+    //Per batch result of its latest occlusion query, and frames skipped since
+    batch_visible: Vec<bool>,
+    batch_skipped: Vec<u32>,
+    current_frame: usize,
+}
+
+impl IndirectDraws {
+    pub fn new(
+        context: Arc<VulkanContext>,
+        num_frames: usize,
+        max_draws: u32,
+        max_batches: u32,
+        cull_mode: CullMode,
+        occlusion_culling: OcclusionCulling,
+        pyramid: &DepthPyramid,
+    ) -> Self {
+        let pipeline = ComputePipeline::new(
+            context.clone(),
+            SHADER_CULL,
+            &[
+                vk::DescriptorType::STORAGE_BUFFER,
+                vk::DescriptorType::STORAGE_BUFFER,
+                vk::DescriptorType::STORAGE_BUFFER,
+                vk::DescriptorType::STORAGE_BUFFER,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            ],
+            std::mem::size_of::<CullConstants>() as u32,
+            num_frames as u32,
+        );
+        pipeline.set_name("frustum cull");
+
+        //Everything is host visible so the CPU path can write the culled draws directly
+        let location = gpu_allocator::MemoryLocation::CpuToGpu;
+        let frames = (0..num_frames)
+            .map(|i| {
+                let draws = StorageBuffer::new(
+                    context.clone(),
+                    (max_draws as usize * std::mem::size_of::<DrawInput>()) as u64,
+                    vk::BufferUsageFlags::empty(),
+                    location,
+                );
+                let commands = StorageBuffer::new(
+                    context.clone(),
+                    (max_draws * COMMAND_STRIDE) as u64,
+                    vk::BufferUsageFlags::INDIRECT_BUFFER,
+                    location,
+                );
+                let counts = StorageBuffer::new(
+                    context.clone(),
+                    (max_batches as usize * std::mem::size_of::<u32>()) as u64,
+                    vk::BufferUsageFlags::INDIRECT_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+                    location,
+                );
+                let occlusion = StorageBuffer::new(
+                    context.clone(),
+                    std::mem::size_of::<OcclusionData>() as u64,
+                    vk::BufferUsageFlags::empty(),
+                    location,
+                );
+                draws.set_name(&format!("cull input draws {}", i));
+                commands.set_name(&format!("culled draw commands {}", i));
+                counts.set_name(&format!("culled draw counts {}", i));
+                occlusion.set_name(&format!("cull occlusion data {}", i));
+                let create_info = vk::QueryPoolCreateInfo::default()
+                    .query_type(vk::QueryType::OCCLUSION)
+                    .query_count(max_batches);
+                let query_pool =
+                    unsafe { context.device.create_query_pool(&create_info, None) }.unwrap();
+                context.set_object_name(query_pool, &format!("batch occlusion queries {}", i));
+                let mut frame = IndirectFrame {
+                    draws,
+                    commands,
+                    counts,
+                    occlusion,
+                    desc_set: vk::DescriptorSet::null(),
+                    cpu_counts: vec![],
+                    query_pool,
+                    queried: vec![],
+                };
+                frame.desc_set = pipeline.create_descriptor_set(&frame.bindings(pyramid));
+                frame
+            })
+            .collect();
+
+        Self {
+            context,
+            pipeline,
+            frames,
+            max_draws,
+            max_batches,
+            draws: vec![],
+            bounds: vec![],
+            batches: vec![],
+            frustum: None,
+            view_proj: None,
+            cull_mode,
+            occlusion_culling,
+            pyramid_view_proj: None,
+            pyramid_size: [pyramid.extent.width as f32, pyramid.extent.height as f32],
+            pyramid_max_level: pyramid.mip_levels - 1,
+            batch_visible: vec![],
+            batch_skipped: vec![],
+            current_frame: 0,
+        }
+    }
+
+    /// The requested mode, unless the device forces culling on the CPU.
+    pub fn cull_mode(&self) -> CullMode {
+        if self.context.capabilities.draw_indirect_count {
+            self.cull_mode
+        } else {
+            CullMode::Cpu
+        }
+    }
+
+    /// Whether draws can have a nonzero `first_instance`. Without drawIndirectFirstInstance
+    /// it has to be 0, so draws that rely on it have to be drawn directly instead.
+    pub fn first_instance_supported(&self) -> bool {
+        self.context
+            .capabilities
+            .features
+            .draw_indirect_first_instance
+            != 0
+    }
+
+    pub fn set_cull_mode(&mut self, cull_mode: CullMode) {
+        self.cull_mode = cull_mode;
+        //The pyramid is not rebuilt while Hi-Z is inactive
+        self.pyramid_view_proj = None;
+    }
+
+    /// The requested occlusion culling, Hi-Z falls back to queries when culling on the CPU.
+    pub fn occlusion_culling(&self) -> OcclusionCulling {
+        match self.occlusion_culling {
+            OcclusionCulling::HiZ if self.cull_mode() == CullMode::Cpu => OcclusionCulling::Queries,
+            occlusion_culling => occlusion_culling,
+        }
+    }
+
+    pub fn set_occlusion_culling(&mut self, occlusion_culling: OcclusionCulling) {
+        self.occlusion_culling = occlusion_culling;
+        self.pyramid_view_proj = None;
+    }
+
+    /// Points the cull shader to a new depth pyramid, e.g. after the swapchain was resized.
+    /// No frame using the previous one may be in flight.
+    pub fn set_depth_pyramid(&mut self, pyramid: &DepthPyramid) {
+        for frame in &self.frames {
+            self.pipeline
+                .update_descriptor_set(frame.desc_set, &frame.bindings(pyramid));
+        }
+        self.pyramid_size = [pyramid.extent.width as f32, pyramid.extent.height as f32];
+        self.pyramid_max_level = pyramid.mip_levels - 1;
+        self.pyramid_view_proj = None;
+    }
+
+    /// Records that the depth pyramid now holds the depth of this frame, later frames
+    /// test their draws against it.
+    pub fn depth_pyramid_built(&mut self) {
+        self.pyramid_view_proj = self.view_proj.clone();
+    }
+
+    /// Clears the draws of the previous frame. Draws are culled against the frustum of
+    /// `view_proj`, without it nothing is culled.
+    pub fn begin_frame(&mut self, view_proj: Option<&Mat4>) {
+        self.frustum = view_proj.map(Frustum::from_view_proj);
+        self.view_proj = view_proj.cloned();
+        self.draws.clear();
+        self.bounds.clear();
+        self.batches.clear();
+    }
+
+    /// Starts a new batch, the draws added after this are drawn by `draw_batch` with the
+    /// returned index. Returns None when `max_batches` is reached.
+    pub fn begin_batch(&mut self) -> Option<u32> {
+        if self.batches.len() as u32 >= self.max_batches {
+            return None;
+        }
+        self.batches.push(Batch {
+            first_draw: self.draws.len() as u32,
+            draw_count: 0,
+        });
+        Some(self.batches.len() as u32 - 1)
+    }
+
+    /// How many more batches `begin_batch` can start this frame.
+    pub fn remaining_batches(&self) -> u32 {
+        self.max_batches.saturating_sub(self.batches.len() as u32)
+    }
+
+    /// Adds a draw with world space bounds to the current batch. Returns false, and drops
+    /// the draw, when `max_draws` is reached.
+    pub fn add_draw(&mut self, command: vk::DrawIndexedIndirectCommand, bounds: &Sphere) -> bool {
+        if self.draws.len() as u32 >= self.max_draws {
+            return false;
+        }
+        let batch_index = self.batches.len() - 1;
+        let batch = self
+            .batches
+            .last_mut()
+            .expect("begin_batch has to be called before add_draw");
+        batch.draw_count += 1;
+        self.draws.push(DrawInput {
+            command,
+            batch: batch_index as u32,
+            output_base: batch.first_draw,
+            pad: 0,
+            sphere: [
+                bounds.center[0],
+                bounds.center[1],
+                bounds.center[2],
+                bounds.radius,
+            ],
+        });
+        self.bounds.push(bounds.clone());
+        true
+    }
+
+    pub fn num_draws(&self) -> u32 {
+        self.draws.len() as u32
+    }
+
+    /// Indices of the draws that pass the frustum test, computed on the CPU. The GPU path
+    /// keeps the same set, although not in the same order within a batch.
+    pub fn visible_draws_cpu(&self) -> Vec<u32> {
+        match &self.frustum {
+            Some(frustum) => frustum.visible_spheres(&self.bounds),
+            None => (0..self.draws.len() as u32).collect(),
+        }
+    }
+
+    fn frustum_planes(&self) -> [[f32; 4]; 6] {
+        match &self.frustum {
+            Some(frustum) => frustum.planes.map(|plane| plane.0),
+            //Planes that every sphere is in front of
+            None => [[0.0, 0.0, 0.0, f32::MAX]; 6],
+        }
+    }
+
+    //Reads the queries issued the last time this frame slot was used
+    fn read_occlusion_queries(&mut self, frame_index: usize) {
+        let frame = &self.frames[frame_index];
+        if !frame.queried.contains(&true) {
+            return;
+        }
+        //Samples passed and availability
+        let mut results = vec![[0u64; 2]; frame.queried.len()];
+        let result = unsafe {
+            self.context.device.get_query_pool_results(
+                frame.query_pool,
+                0,
+                &mut results,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WITH_AVAILABILITY,
+            )
+        };
+        if let Err(err) = result {
+            //Some queries are not available, the rest is still written
+            assert_eq!(err, vk::Result::NOT_READY);
+        }
+        //Draws each batch issued, read back from the cull shader when culled on the GPU.
+        //The frame slot is done on the GPU, so its counts are final.
+        let drawn = if frame.cpu_counts.is_empty() {
+            let mut counts = vec![0u32; frame.queried.len()];
+            let counts_data = unsafe {
+                std::slice::from_raw_parts_mut(
+                    counts.as_mut_ptr() as *mut u8,
+                    std::mem::size_of_val(counts.as_slice()),
+                )
+            };
+            frame.counts.read_data(counts_data);
+            counts
+        } else {
+            frame.cpu_counts.clone()
+        };
+        for (i, (&queried, result)) in frame.queried.iter().zip(&results).enumerate() {
+            if queried && result[1] != 0 && i < self.batch_visible.len() {
+                //No samples from a batch that drew nothing, e.g. frustum culled, says
+                //nothing about occlusion
+                let drew = drawn.get(i).is_some_and(|&count| count > 0);
+                self.batch_visible[i] = result[0] > 0 || !drew;
+            }
+        }
+    }
+
+    //Picks the batches drawn this frame, each with an occlusion query
+    fn select_queried_batches(&mut self, command_buffer: &CommandBuffer, frame_index: usize) {
+        let num_batches = self.batches.len();
+        let use_queries = self.occlusion_culling() == OcclusionCulling::Queries;
+        let frame = &mut self.frames[frame_index];
+        frame.queried.clear();
+        if !use_queries {
+            return;
+        }
+        command_buffer.reset_query_pool(frame.query_pool, 0, self.max_batches);
+        self.batch_visible.resize(num_batches, true);
+        self.batch_skipped.resize(num_batches, 0);
+        for i in 0..num_batches {
+            let visible = &mut self.batch_visible[i];
+            let skipped = &mut self.batch_skipped[i];
+            //Outside of the frustum says nothing about occlusion, draw it once it is inside.
+            //Only known here when culled on the CPU, the GPU path checks the draw count
+            //when reading the query.
+            let frustum_culled = frame.cpu_counts.get(i) == Some(&0);
+            let queried = if frustum_culled {
+                *visible = true;
+                *skipped = 0;
+                false
+            } else if *visible || *skipped >= RETEST_INTERVAL {
+                *skipped = 0;
+                true
+            } else {
+                *skipped += 1;
+                false
+            };
+            frame.queried.push(queried);
+        }
+    }
+
+    fn occlusion_data(&self) -> OcclusionData {
+        let enabled = self.occlusion_culling() == OcclusionCulling::HiZ;
+        match &self.pyramid_view_proj {
+            Some(view_proj) if enabled => OcclusionData {
+                view_proj: view_proj.0.map(|column| column.0),
+                pyramid_size: self.pyramid_size,
+                enabled: 1,
+                max_level: self.pyramid_max_level,
+            },
+            _ => OcclusionData {
+                view_proj: [[0.0; 4]; 4],
+                pyramid_size: self.pyramid_size,
+                enabled: 0,
+                max_level: self.pyramid_max_level,
+            },
+        }
+    }
+
+    /// Uploads the draws of this frame and culls them. Has to be recorded outside of a
+    /// render pass, before any `draw_batch`.
+    pub fn cull(&mut self, command_buffer: &CommandBuffer, frame_index: usize) {
+        self.current_frame = frame_index;
+        self.read_occlusion_queries(frame_index);
+        let draws_data = unsafe {
+            std::slice::from_raw_parts(
+                self.draws.as_ptr() as *const u8,
+                std::mem::size_of_val(self.draws.as_slice()),
+            )
+        };
+        let cull_mode = self.cull_mode();
+        let visible_draws = match cull_mode {
+            CullMode::Cpu => Some(self.visible_draws_cpu()),
+            CullMode::Gpu => None,
+        };
+        let planes = self.frustum_planes();
+        let occlusion = self.occlusion_data();
+        let frame = &mut self.frames[frame_index];
+        frame.draws.upload_data(draws_data);
+
+        match visible_draws {
+            Some(visible_draws) => {
+                //Compact per batch, the same output layout as the compute shader
+                let mut commands =
+                    vec![vk::DrawIndexedIndirectCommand::default(); self.draws.len()];
+                let mut counts = vec![0u32; self.batches.len()];
+                for index in visible_draws {
+                    let draw = &self.draws[index as usize];
+                    let slot = &mut counts[draw.batch as usize];
+                    commands[(draw.output_base + *slot) as usize] = draw.command;
+                    *slot += 1;
+                }
+                let commands_data = unsafe {
+                    std::slice::from_raw_parts(
+                        commands.as_ptr() as *const u8,
+                        std::mem::size_of_val(commands.as_slice()),
+                    )
+                };
+                let counts_data = unsafe {
+                    std::slice::from_raw_parts(
+                        counts.as_ptr() as *const u8,
+                        std::mem::size_of_val(counts.as_slice()),
+                    )
+                };
+                frame.commands.upload_data(commands_data);
+                frame.counts.upload_data(counts_data);
+                frame.cpu_counts = counts;
+            }
+            None if !self.draws.is_empty() => {
+                frame.cpu_counts.clear();
+                let occlusion_data = unsafe {
+                    std::slice::from_raw_parts(
+                        &occlusion as *const OcclusionData as *const u8,
+                        std::mem::size_of::<OcclusionData>(),
+                    )
+                };
+                frame.occlusion.upload_data(occlusion_data);
+                command_buffer.begin_label("frustum cull");
+                command_buffer.fill_buffer(
+                    frame.counts.object(),
+                    0,
+                    (self.batches.len() * std::mem::size_of::<u32>()) as u64,
+                    0,
+                );
+                command_buffer.memory_barrier(
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+                );
+                let constants = CullConstants {
+                    planes,
+                    draw_count: self.draws.len() as u32,
+                };
+                let constants_data = unsafe {
+                    std::slice::from_raw_parts(
+                        &constants as *const CullConstants as *const u8,
+                        std::mem::size_of::<CullConstants>(),
+                    )
+                };
+                self.pipeline.bind(command_buffer, frame.desc_set);
+                self.pipeline.push_constants(command_buffer, constants_data);
+                let group_count = (self.draws.len() as u32).div_ceil(CULL_GROUP_SIZE);
+                command_buffer.dispatch(group_count, 1, 1);
+                command_buffer.compute_barrier(ComputeBarrier::DrawIndirect);
+                command_buffer.end_label();
+            }
+            None => frame.cpu_counts.clear(),
+        }
+        self.select_queried_batches(command_buffer, frame_index);
+    }
+
+    /// The indirect call that draws the visible draws of `batch`, None if the batch is empty
+    /// or skipped as occluded.
+    pub fn batch_draw(&self, batch_index: u32) -> Option<IndirectBatchDraw> {
+        let batch = self.batches.get(batch_index as usize)?;
+        if batch.draw_count == 0 {
+            return None;
+        }
+        let frame = &self.frames[self.current_frame];
+        let occlusion_query = match frame.queried.get(batch_index as usize) {
+            Some(true) => Some((frame.query_pool, batch_index)),
+            Some(false) => return None,
+            //Occlusion queries are off
+            None => None,
+        };
+        let offset = (batch.first_draw * COMMAND_STRIDE) as u64;
+        let call = match self.cull_mode() {
+            CullMode::Gpu => IndirectCall::Count {
+                buffer: frame.commands.object(),
+                offset,
+                count_buffer: frame.counts.object(),
+                count_offset: (batch_index as usize * std::mem::size_of::<u32>()) as u64,
+                max_draw_count: batch.draw_count,
+            },
+            CullMode::Cpu => IndirectCall::Direct {
+                buffer: frame.commands.object(),
+                offset,
+                draw_count: frame.cpu_counts[batch_index as usize],
+                multi_draw: self.context.capabilities.features.multi_draw_indirect != 0,
+            },
+        };
+        Some(IndirectBatchDraw {
+            call,
+            occlusion_query,
+        })
+    }
+
+    /// Draws the visible draws of `batch`, with the pipeline and buffers of the batch bound.
+    pub fn draw_batch(&self, command_buffer: &CommandBuffer, batch_index: u32) {
+        if let Some(draw) = self.batch_draw(batch_index) {
+            draw.record(command_buffer);
+        }
+    }
+
+    pub fn destroy(&mut self) {
+        for frame in self.frames.drain(..) {
+            unsafe {
+                self.context
+                    .device
+                    .destroy_query_pool(frame.query_pool, None)
+            };
+        }
+        self.pipeline.destroy();
+    }
+}
@@ -0,0 +1,287 @@
+use ash::vk::{self, Handle};
+use std::{collections::HashMap, fmt};
+
+use super::DeviceInfo;
+
+/// Fraction of a heap's budget at which allocations start warning.
+pub const BUDGET_WARNING_THRESHOLD: f64 = 0.9;
+
+/// What an allocation is used for, derived from the usage flags of its buffer or image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MemoryCategory {
+    Mesh,
+    Texture,
+    Uniform,
+    RenderTarget,
+    Staging,
+    Other,
+}
+
+impl MemoryCategory {
+    pub const ALL: [Self; 6] = [
+        Self::Mesh,
+        Self::Texture,
+        Self::Uniform,
+        Self::RenderTarget,
+        Self::Staging,
+        Self::Other,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Mesh => "mesh",
+            Self::Texture => "texture",
+            Self::Uniform => "uniform",
+            Self::RenderTarget => "render target",
+            Self::Staging => "staging",
+            Self::Other => "other",
+        }
+    }
+
+    pub fn for_buffer(usage: vk::BufferUsageFlags) -> Self {
+        use vk::BufferUsageFlags as U;
+        if usage.intersects(U::VERTEX_BUFFER | U::INDEX_BUFFER) {
+            Self::Mesh
+        } else if usage.contains(U::UNIFORM_BUFFER) {
+            Self::Uniform
+        } else if usage == U::TRANSFER_SRC {
+            Self::Staging
+        } else {
+            Self::Other
+        }
+    }
+
+    pub fn for_image(usage: vk::ImageUsageFlags) -> Self {
+        use vk::ImageUsageFlags as U;
+        //Storage images are written by compute every frame, like attachments
+        if usage.intersects(U::COLOR_ATTACHMENT | U::DEPTH_STENCIL_ATTACHMENT | U::STORAGE) {
+            Self::RenderTarget
+        } else if usage.contains(U::SAMPLED) {
+            Self::Texture
+        } else {
+            Self::Other
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HeapBudget {
+    pub index: usize,
+    pub size: u64,
+    pub device_local: bool,
+    /// Bytes used by this process
+    pub usage: u64,
+    /// Bytes this process can use before allocations start failing or paging
+    pub budget: u64,
+}
+
+impl HeapBudget {
+    pub fn fraction_used(&self) -> f64 {
+        if self.budget == 0 {
+            0.0
+        } else {
+            self.usage as f64 / self.budget as f64
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CategoryUsage {
+    pub category: MemoryCategory,
+    pub count: usize,
+    pub bytes: u64,
+}
+
+/// Snapshot of GPU memory use, from `VulkanContext::memory_report`.
+#[derive(Clone, Debug)]
+pub struct MemoryReport {
+    /// Usage and budget come from VK_EXT_memory_budget. Without it usage only counts
+    /// allocations made through the context and the budget is the heap size.
+    pub driver_budget: bool,
+    pub heaps: Vec<HeapBudget>,
+    pub categories: Vec<CategoryUsage>,
+    /// Bytes handed out by the allocator
+    pub allocated_bytes: u64,
+    /// Bytes of device memory the allocator holds, including free space in its blocks
+    pub reserved_bytes: u64,
+}
+
+impl MemoryReport {
+    /// Heaps above `BUDGET_WARNING_THRESHOLD` of their budget.
+    pub fn heaps_near_budget(&self) -> impl Iterator<Item = &HeapBudget> {
+        self.heaps
+            .iter()
+            .filter(|heap| heap.fraction_used() >= BUDGET_WARNING_THRESHOLD)
+    }
+
+    pub fn to_json(&self) -> String {
+        let heaps: Vec<String> = self
+            .heaps
+            .iter()
+            .map(|heap| {
+                format!(
+                    "{{\"index\":{},\"device_local\":{},\"size\":{},\"usage\":{},\"budget\":{}}}",
+                    heap.index, heap.device_local, heap.size, heap.usage, heap.budget
+                )
+            })
+            .collect();
+        let categories: Vec<String> = self
+            .categories
+            .iter()
+            .map(|usage| {
+                format!(
+                    "{{\"category\":\"{}\",\"count\":{},\"bytes\":{}}}",
+                    usage.category.name(),
+                    usage.count,
+                    usage.bytes
+                )
+            })
+            .collect();
+        format!(
+            "{{\"driver_budget\":{},\"allocated_bytes\":{},\"reserved_bytes\":{},\"heaps\":[{}],\"categories\":[{}]}}",
+            self.driver_budget,
+            self.allocated_bytes,
+            self.reserved_bytes,
+            heaps.join(","),
+            categories.join(",")
+        )
+    }
+}
+
+fn mib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "GPU memory: {:.1} MiB allocated, {:.1} MiB reserved{}",
+            mib(self.allocated_bytes),
+            mib(self.reserved_bytes),
+            if self.driver_budget {
+                ""
+            } else {
+                " (no VK_EXT_memory_budget, budgets are heap sizes)"
+            }
+        )?;
+        for heap in &self.heaps {
+            writeln!(
+                f,
+                "  heap {}{}: {:.1} / {:.1} MiB ({:.0}%), size {:.1} MiB",
+                heap.index,
+                if heap.device_local {
+                    " device local"
+                } else {
+                    ""
+                },
+                mib(heap.usage),
+                mib(heap.budget),
+                heap.fraction_used() * 100.0,
+                mib(heap.size)
+            )?;
+        }
+        for usage in &self.categories {
+            writeln!(
+                f,
+                "  {:<14} {:>5} allocations {:>10.1} MiB",
+                usage.category.name(),
+                usage.count,
+                mib(usage.bytes)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+struct TrackedAllocation {
+    category: MemoryCategory,
+    size: u64,
+    heap: usize,
+}
+
+/// Remembers the category and heap of every live allocation, keyed by its buffer or image.
+#[derive(Default)]
+pub(crate) struct MemoryTracker {
+    allocations: HashMap<u64, TrackedAllocation>,
+    //Heaps that already warned, cleared once they drop below the threshold again
+    warned_heaps: Vec<bool>,
+}
+
+impl MemoryTracker {
+    pub fn track(&mut self, handle: impl Handle, category: MemoryCategory, size: u64, heap: usize) {
+        self.allocations.insert(
+            handle.as_raw(),
+            TrackedAllocation {
+                category,
+                size,
+                heap,
+            },
+        );
+    }
+
+    pub fn untrack(&mut self, handle: impl Handle) {
+        self.allocations.remove(&handle.as_raw());
+    }
+
+    pub fn heap_usage(&self, heap: usize) -> u64 {
+        self.allocations
+            .values()
+            .filter(|allocation| allocation.heap == heap)
+            .map(|allocation| allocation.size)
+            .sum()
+    }
+
+    pub fn categories(&self) -> Vec<CategoryUsage> {
+        MemoryCategory::ALL
+            .iter()
+            .map(|&category| {
+                let (count, bytes) = self
+                    .allocations
+                    .values()
+                    .filter(|allocation| allocation.category == category)
+                    .fold((0, 0), |(count, bytes), allocation| {
+                        (count + 1, bytes + allocation.size)
+                    });
+                CategoryUsage {
+                    category,
+                    count,
+                    bytes,
+                }
+            })
+            .collect()
+    }
+
+    /// Logs a warning the first time a heap crosses the threshold.
+    pub fn warn_near_budget(&mut self, heaps: &[HeapBudget]) {
+        self.warned_heaps.resize(heaps.len(), false);
+        for (heap, warned) in heaps.iter().zip(self.warned_heaps.iter_mut()) {
+            let near_budget = heap.fraction_used() >= BUDGET_WARNING_THRESHOLD;
+            if near_budget && !*warned {
+                log::warn!(
+                    "Memory heap {} is at {:.0}% of its budget ({:.1} / {:.1} MiB)",
+                    heap.index,
+                    heap.fraction_used() * 100.0,
+                    mib(heap.usage),
+                    mib(heap.budget)
+                );
+            }
+            *warned = near_budget;
+        }
+    }
+}
+
+/// Heap of the first memory type with exactly `properties` allowed by `type_bits`,
+/// which is the type gpu-allocator picks.
+pub(crate) fn heap_of(
+    info: &DeviceInfo,
+    type_bits: u32,
+    properties: vk::MemoryPropertyFlags,
+) -> usize {
+    info.memory_types
+        .iter()
+        .enumerate()
+        .find(|(i, ty)| type_bits & (1 << i) != 0 && ty.property_flags == properties)
+        .map(|(_, ty)| ty.heap_index as usize)
+        .unwrap_or(0)
+}
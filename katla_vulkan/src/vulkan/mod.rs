@@ -1,23 +1,45 @@
+pub mod capabilities;
 pub mod commandbuffer;
 pub mod commandpool;
+pub mod compute;
 pub mod context;
+pub mod devices;
+pub mod hiz;
+pub mod indirect;
+pub mod memory;
 pub mod pipeline;
+pub mod profiler;
 pub mod queue;
+pub mod recorder;
 pub mod renderpass;
 pub mod swapchain;
 pub mod swapdata;
 pub mod texture;
+pub mod timeline;
+pub mod tonemap;
+pub mod validation;
 pub mod vertexbinding;
 pub mod vertexbuffer;
 
+pub use capabilities::*;
 pub use commandbuffer::*;
 pub use commandpool::*;
+pub use compute::*;
 pub use context::*;
+pub use devices::*;
+pub use hiz::*;
+pub use indirect::*;
+pub use memory::*;
 pub use pipeline::*;
+pub use profiler::*;
 pub use queue::*;
+pub use recorder::*;
 pub use renderpass::*;
 pub use swapchain::*;
 pub use swapdata::*;
 pub use texture::*;
+pub use timeline::*;
+pub use tonemap::*;
+pub use validation::*;
 pub use vertexbinding::*;
 pub use vertexbuffer::*;
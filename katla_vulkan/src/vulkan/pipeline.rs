@@ -7,14 +7,69 @@ use std::{ffi::CString, io::Cursor, sync::Arc};
 use super::context::VulkanContext;
 //TODO: A more flexible shader system
 const SHADER_VERT: &[u8] = include_bytes!("../../../resources/shaders/model_pbr.vert.spv");
+const SHADER_VERT_INSTANCED: &[u8] =
+    include_bytes!("../../../resources/shaders/model_pbr_instanced.vert.spv");
+const SHADER_VERT_SKINNED: &[u8] =
+    include_bytes!("../../../resources/shaders/model_pbr_skinned.vert.spv");
+//The same shaders compiled with -DMORPH_TARGETS
+const SHADER_VERT_MORPH: &[u8] =
+    include_bytes!("../../../resources/shaders/model_pbr_instanced_morph.vert.spv");
+const SHADER_VERT_SKINNED_MORPH: &[u8] =
+    include_bytes!("../../../resources/shaders/model_pbr_skinned_morph.vert.spv");
 const SHADER_FRAG: &[u8] = include_bytes!("../../../resources/shaders/model.frag.spv");
 
-//TODO: Make these traits more usable and dynamic for a pipeline.
+/// SPIR-V of the stages of a `RenderPipeline`.
+#[derive(Clone, Copy)]
+pub struct ShaderStages {
+    pub vertex: &'static [u8],
+    pub fragment: &'static [u8],
+}
+
+impl ShaderStages {
+    pub const MODEL: ShaderStages = ShaderStages {
+        vertex: SHADER_VERT,
+        fragment: SHADER_FRAG,
+    };
+    /// Expects a per-instance world matrix at locations 4-7, after the `VertexPBR` attributes.
+    pub const MODEL_INSTANCED: ShaderStages = ShaderStages {
+        vertex: SHADER_VERT_INSTANCED,
+        fragment: SHADER_FRAG,
+    };
+    /// Expects joints and weights at locations 4-5 and the per-instance world matrix at
+    /// locations 6-9, with the joint matrices in a storage buffer at binding 3.
+    pub const MODEL_SKINNED: ShaderStages = ShaderStages {
+        vertex: SHADER_VERT_SKINNED,
+        fragment: SHADER_FRAG,
+    };
+    /// `MODEL_INSTANCED` with morph targets, see morph.glsl. Expects the morph targets of
+    /// every vertex at location 8, and the morph weights and deltas at bindings 3 and 4.
+    pub const MODEL_MORPH: ShaderStages = ShaderStages {
+        vertex: SHADER_VERT_MORPH,
+        fragment: SHADER_FRAG,
+    };
+    /// `MODEL_SKINNED` with morph targets, at location 10 and bindings 4 and 5.
+    pub const MODEL_SKINNED_MORPH: ShaderStages = ShaderStages {
+        vertex: SHADER_VERT_SKINNED_MORPH,
+        fragment: SHADER_FRAG,
+    };
+}
+
+/// Fixed function state that differs between materials.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PipelineOptions {
+    /// Disables back face culling
+    pub double_sided: bool,
+    /// Blends with the alpha of the fragment and stops writing depth
+    pub alpha_blend: bool,
+}
+
+//Descriptors whose resource never changes are written once per descriptor set,
+//while UpdateAlways descriptors are rewritten every time their set is updated.
 pub trait UpdateOnce {
-    fn update_once(&self, set: vk::DescriptorSet, binding: u32) -> vk::WriteDescriptorSet;
+    fn update_once(&self, set: vk::DescriptorSet, binding: u32) -> vk::WriteDescriptorSet<'_>;
 }
 pub trait UpdateAlways {
-    fn update_always(&self, set: vk::DescriptorSet, binding: u32) -> vk::WriteDescriptorSet;
+    fn update_always(&self, set: vk::DescriptorSet, binding: u32) -> vk::WriteDescriptorSet<'_>;
 }
 
 pub struct RenderPipeline {
@@ -23,36 +78,225 @@ pub struct RenderPipeline {
     pub pipeline_layout: vk::PipelineLayout,
     pub uniform: UniformHandle,
     pub desc_layout: vk::DescriptorSetLayout,
+    vertex_bindings: Vec<VertexBinding>,
+    options: PipelineOptions,
     vert_module: vk::ShaderModule,
     frag_module: vk::ShaderModule,
+    //Kept so the name can be reapplied when the pipeline is recreated
+    name: Option<String>,
+}
+
+/// Layout of one binding in a `UniformHandle`, binding numbers follow the slice order.
+#[derive(Clone, Copy, Debug)]
+pub enum UniformBinding {
+    UniformBuffer {
+        size: vk::DeviceSize,
+        stages: vk::ShaderStageFlags,
+    },
+    /// `count` elements of `element_size`, each aligned to minUniformBufferOffsetAlignment
+    /// and selected with a dynamic offset when binding.
+    DynamicUniformBuffer {
+        element_size: vk::DeviceSize,
+        count: u32,
+        stages: vk::ShaderStageFlags,
+    },
+    StorageBuffer {
+        size: vk::DeviceSize,
+        stages: vk::ShaderStageFlags,
+    },
+    /// A storage buffer owned by someone else, set with `UniformHandle::set_external_buffer`.
+    ExternalStorageBuffer { stages: vk::ShaderStageFlags },
+    /// Array of `count` combined image samplers
+    SampledImages {
+        count: u32,
+        stages: vk::ShaderStageFlags,
+    },
+}
+
+impl UniformBinding {
+    pub fn descriptor_type(&self) -> vk::DescriptorType {
+        match self {
+            UniformBinding::UniformBuffer { .. } => vk::DescriptorType::UNIFORM_BUFFER,
+            UniformBinding::DynamicUniformBuffer { .. } => {
+                vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC
+            }
+            UniformBinding::StorageBuffer { .. } | UniformBinding::ExternalStorageBuffer { .. } => {
+                vk::DescriptorType::STORAGE_BUFFER
+            }
+            UniformBinding::SampledImages { .. } => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+        }
+    }
+
+    fn descriptor_count(&self) -> u32 {
+        match self {
+            UniformBinding::SampledImages { count, .. } => *count,
+            _ => 1,
+        }
+    }
+
+    fn stages(&self) -> vk::ShaderStageFlags {
+        match self {
+            UniformBinding::UniformBuffer { stages, .. }
+            | UniformBinding::DynamicUniformBuffer { stages, .. }
+            | UniformBinding::StorageBuffer { stages, .. }
+            | UniformBinding::ExternalStorageBuffer { stages }
+            | UniformBinding::SampledImages { stages, .. } => *stages,
+        }
+    }
+
+    /// Creates the descriptor set layout matching `bindings`.
+    pub fn create_layout(
+        context: &VulkanContext,
+        bindings: &[UniformBinding],
+    ) -> vk::DescriptorSetLayout {
+        let desc_layout_bindings: Vec<_> = bindings
+            .iter()
+            .enumerate()
+            .map(|(i, binding)| {
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(i as u32)
+                    .descriptor_count(binding.descriptor_count())
+                    .descriptor_type(binding.descriptor_type())
+                    .stage_flags(binding.stages())
+            })
+            .collect();
+        let desc_layout_info =
+            vk::DescriptorSetLayoutCreateInfo::default().bindings(&desc_layout_bindings);
+        unsafe {
+            context
+                .device
+                .create_descriptor_set_layout(&desc_layout_info, None)
+        }
+        .unwrap()
+    }
 }
 
 pub struct UniformBuffer {
     allocation: Allocation,
     buffer: vk::Buffer,
     buf_size: vk::DeviceSize,
+    binding: u32,
+    descriptor_type: vk::DescriptorType,
+    //Distance between elements of a dynamic uniform buffer, otherwise the buffer size
+    stride: vk::DeviceSize,
+    buffer_info: Vec<vk::DescriptorBufferInfo>,
 }
 
+impl UniformBuffer {
+    fn new(
+        context: &VulkanContext,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        stride: vk::DeviceSize,
+        count: u32,
+    ) -> Self {
+        let buf_size = stride * count as vk::DeviceSize;
+        let usage = if descriptor_type == vk::DescriptorType::STORAGE_BUFFER {
+            vk::BufferUsageFlags::STORAGE_BUFFER
+        } else {
+            vk::BufferUsageFlags::UNIFORM_BUFFER
+        };
+        let create_info = vk::BufferCreateInfo::default()
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .usage(usage)
+            .size(buf_size);
+
+        let (buffer, allocation) =
+            context.allocate_buffer(&create_info, gpu_allocator::MemoryLocation::CpuToGpu);
+        //A dynamic buffer only exposes one element, the offset picks which one
+        let buffer_info = vec![vk::DescriptorBufferInfo::default()
+            .buffer(buffer)
+            .offset(0)
+            .range(stride)];
+        Self {
+            allocation,
+            buffer,
+            buf_size,
+            binding,
+            descriptor_type,
+            stride,
+            buffer_info,
+        }
+    }
+
+    fn write(&self, context: &VulkanContext, data: &[u8], element_size: vk::DeviceSize) {
+        let data_size = std::mem::size_of_val(data) as vk::DeviceSize;
+        let mapped_data = context.map_buffer(&self.allocation);
+        if self.descriptor_type == vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC {
+            //Tightly packed elements are spread out to the aligned stride
+            let num_elements = data_size.div_ceil(element_size);
+            if num_elements * self.stride > self.buf_size {
+                panic!(
+                    "Too little memory allocated for {} dynamic elements",
+                    num_elements
+                );
+            }
+            for (i, element) in data.chunks(element_size as usize).enumerate() {
+                unsafe {
+                    std::ptr::copy_nonoverlapping(
+                        element.as_ptr(),
+                        mapped_data.add(i * self.stride as usize),
+                        element.len(),
+                    );
+                }
+            }
+        } else {
+            if self.buf_size < data_size {
+                panic!(
+                    "Too little memory allocated for buffer of size {}",
+                    data_size
+                );
+            }
+            unsafe {
+                std::ptr::copy_nonoverlapping(data.as_ptr(), mapped_data, data_size as usize);
+            }
+        }
+    }
+}
+
+impl UpdateOnce for UniformBuffer {
+    fn update_once(&self, set: vk::DescriptorSet, binding: u32) -> vk::WriteDescriptorSet<'_> {
+        vk::WriteDescriptorSet::default()
+            .dst_set(set)
+            .dst_binding(binding)
+            .descriptor_type(self.descriptor_type)
+            .buffer_info(&self.buffer_info)
+    }
+}
+
+/// Storage buffer owned outside of the `UniformHandle`, e.g. compute output.
 #[derive(Clone)]
-pub struct ImageInfo {
-    pub image_view: vk::ImageView,
-    pub sampler: vk::Sampler,
-    pub is_updated: bool,
-    image_info: Vec<vk::DescriptorImageInfo>,
+pub struct ExternalBuffer {
+    buffer_info: Vec<vk::DescriptorBufferInfo>,
 }
 
-pub struct UniformHandle {
-    next_bind_index: usize,
-    next_update_index: usize,
-    descriptors: Vec<UniformDescriptor>,
+impl ExternalBuffer {
+    pub fn new(buffer: vk::Buffer) -> Self {
+        Self {
+            buffer_info: vec![vk::DescriptorBufferInfo::default()
+                .buffer(buffer)
+                .offset(0)
+                .range(vk::WHOLE_SIZE)],
+        }
+    }
 }
 
-pub struct UniformDescriptor {
-    pub desc_set: vk::DescriptorSet,
-    pub desc_pool: vk::DescriptorPool,
-    pub uniform_buffer: Option<UniformBuffer>,
-    pub image_info: Option<ImageInfo>,
-    pub static_descriptors: Vec<Box<dyn UpdateOnce>>,
+//The buffer may be swapped out between frames, so it is rewritten on every update
+impl UpdateAlways for ExternalBuffer {
+    fn update_always(&self, set: vk::DescriptorSet, binding: u32) -> vk::WriteDescriptorSet<'_> {
+        vk::WriteDescriptorSet::default()
+            .dst_set(set)
+            .dst_binding(binding)
+            .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+            .buffer_info(&self.buffer_info)
+    }
+}
+
+#[derive(Clone)]
+pub struct ImageInfo {
+    pub image_view: vk::ImageView,
+    pub sampler: vk::Sampler,
+    image_info: Vec<vk::DescriptorImageInfo>,
 }
 
 impl ImageInfo {
@@ -60,15 +304,16 @@ impl ImageInfo {
         Self {
             image_view,
             sampler,
-            is_updated: false,
             image_info: vec![vk::DescriptorImageInfo::default()
                 .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
                 .image_view(image_view)
                 .sampler(sampler)],
         }
     }
+}
 
-    fn update_once(&self, set: vk::DescriptorSet, binding: u32) -> vk::WriteDescriptorSet {
+impl UpdateOnce for ImageInfo {
+    fn update_once(&self, set: vk::DescriptorSet, binding: u32) -> vk::WriteDescriptorSet<'_> {
         vk::WriteDescriptorSet::default()
             .dst_set(set)
             .dst_binding(binding)
@@ -77,59 +322,156 @@ impl ImageInfo {
     }
 }
 
-// impl UpdateOnce for ImageInfo {
-//     fn update_once(&self, set: vk::DescriptorSet, binding: u32) -> vk::WriteDescriptorSet {
-//         // let mut image_infos = vec![];
-//         // image_infos.push(
-//         //     ,
-//         // );
-
-//         // vk::WriteDescriptorSet::default()
-//         //     .dst_set(set)
-//         //     .dst_binding(binding)
-//         //     .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-//         //     .image_info(&[vk::DescriptorImageInfo::default()
-//         //         .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-//         //         .image_view(self.image_view)
-//         //         .sampler(self.sampler)])
-//         todo!("Fix UpdateOnce?");
-//     }
-// }
+/// The images of a `SampledImages` binding, element `i` of the array is `images[i]`.
+#[derive(Clone)]
+pub struct ImageArray {
+    image_info: Vec<vk::DescriptorImageInfo>,
+}
+
+impl ImageArray {
+    pub fn new(images: &[ImageInfo]) -> Self {
+        Self {
+            image_info: images
+                .iter()
+                .flat_map(|image| image.image_info.iter().copied())
+                .collect(),
+        }
+    }
+}
+
+impl UpdateOnce for ImageArray {
+    fn update_once(&self, set: vk::DescriptorSet, binding: u32) -> vk::WriteDescriptorSet<'_> {
+        vk::WriteDescriptorSet::default()
+            .dst_set(set)
+            .dst_binding(binding)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&self.image_info)
+    }
+}
+
+/// Owns one descriptor set, and the buffers behind it, per buffered frame.
+/// Every update writes into the next frame's set, which is then the one bound.
+pub struct UniformHandle {
+    next_bind_index: usize,
+    next_update_index: usize,
+    bindings: Vec<UniformBinding>,
+    descriptors: Vec<UniformDescriptor>,
+}
+
+pub struct UniformDescriptor {
+    pub desc_set: vk::DescriptorSet,
+    pub desc_pool: vk::DescriptorPool,
+    pub buffers: Vec<UniformBuffer>,
+    pub static_descriptors: Vec<(u32, Box<dyn UpdateOnce>)>,
+    pub dynamic_descriptors: Vec<(u32, Box<dyn UpdateAlways>)>,
+    //Static descriptors that have not been written to the set yet
+    pending_static: Vec<usize>,
+}
 
 impl UniformHandle {
     pub fn new(
         num_buffered_frames: usize,
         context: &VulkanContext,
         desc_layout: &vk::DescriptorSetLayout,
+        bindings: &[UniformBinding],
     ) -> Self {
         let mut uniform_descs = vec![];
         for _ in 0..num_buffered_frames {
-            let uniform_desc = Self::create_descriptor_sets(context, &desc_layout);
+            let uniform_desc = Self::create_descriptor_sets(context, &desc_layout, bindings);
             uniform_descs.push(uniform_desc);
         }
 
         Self {
             next_bind_index: 0,
             next_update_index: 0,
+            bindings: bindings.to_vec(),
             descriptors: uniform_descs,
         }
     }
 
+    /// Sets the images of the first `SampledImages` binding, kept for single texture materials.
     pub fn add_image_info(&mut self, image_info: ImageInfo) {
+        let binding = self
+            .bindings
+            .iter()
+            .position(|binding| matches!(binding, UniformBinding::SampledImages { .. }))
+            .expect("No sampled image binding in the layout") as u32;
+        self.set_images(binding, &[image_info]);
+    }
+
+    pub fn set_images(&mut self, binding: u32, images: &[ImageInfo]) {
+        match self.bindings[binding as usize] {
+            UniformBinding::SampledImages { count, .. } => {
+                assert!(images.len() as u32 <= count, "Too many images for binding");
+            }
+            _ => panic!("Binding {} does not hold sampled images", binding),
+        }
         for descr in &mut self.descriptors {
-            descr.image_info = Some(image_info.clone());
-            //TODO: Something like this maybe? -
-            // descr.static_descriptors.push(Box::new(image_info));
+            descr.set_static(binding, Box::new(ImageArray::new(images)));
         }
     }
 
-    pub fn update_buffer(&mut self, context: &VulkanContext, data: &[u8]) {
-        self.descriptors[self.next_update_index].update_buffer(context, data);
+    pub fn set_external_buffer(&mut self, binding: u32, buffer: vk::Buffer) {
+        match self.bindings[binding as usize] {
+            UniformBinding::ExternalStorageBuffer { .. } => {}
+            _ => panic!("Binding {} is not an external storage buffer", binding),
+        }
+        for descr in &mut self.descriptors {
+            descr
+                .dynamic_descriptors
+                .retain(|(dyn_binding, _)| *dyn_binding != binding);
+            descr
+                .dynamic_descriptors
+                .push((binding, Box::new(ExternalBuffer::new(buffer))));
+        }
+    }
+
+    /// Writes the data of the given bindings into the next frame's buffers and makes that
+    /// frame's set the one to bind. Data for a dynamic uniform buffer is a tightly packed
+    /// array of elements.
+    pub fn update(&mut self, context: &VulkanContext, writes: &[(u32, &[u8])]) {
+        let descriptor = &mut self.descriptors[self.next_update_index];
+        for (binding, data) in writes {
+            let element_size = match self.bindings[*binding as usize] {
+                UniformBinding::DynamicUniformBuffer { element_size, .. } => element_size,
+                _ => 1,
+            };
+            match descriptor
+                .buffers
+                .iter()
+                .find(|buffer| buffer.binding == *binding)
+            {
+                Some(buffer) => buffer.write(context, data, element_size),
+                None => println!("No descriptor buffer at binding {}!!!", binding),
+            }
+        }
+        descriptor.write_descriptors(context);
 
         self.next_bind_index = self.next_update_index;
         self.next_update_index = (self.next_update_index + 1) % self.descriptors.len();
     }
 
+    /// Updates the first uniform buffer binding.
+    pub fn update_buffer(&mut self, context: &VulkanContext, data: &[u8]) {
+        let binding = self
+            .bindings
+            .iter()
+            .position(|binding| matches!(binding, UniformBinding::UniformBuffer { .. }))
+            .expect("No uniform buffer binding in the layout") as u32;
+        self.update(context, &[(binding, data)]);
+    }
+
+    /// Offset to bind element `index` of a dynamic uniform buffer binding with.
+    pub fn dynamic_offset(&self, binding: u32, index: u32) -> u32 {
+        let descriptor = &self.descriptors[self.next_bind_index];
+        let buffer = descriptor
+            .buffers
+            .iter()
+            .find(|buffer| buffer.binding == binding)
+            .unwrap();
+        (buffer.stride * index as vk::DeviceSize) as u32
+    }
+
     pub fn next_descriptor(&self) -> &UniformDescriptor {
         let out_descr = &self.descriptors[self.next_bind_index];
         out_descr
@@ -141,35 +483,75 @@ impl UniformHandle {
         }
     }
 
+    pub fn set_name(&self, context: &VulkanContext, name: &str) {
+        for (i, desc) in self.descriptors.iter().enumerate() {
+            context.set_object_name(desc.desc_set, &format!("{} descriptor set {}", name, i));
+            context.set_object_name(desc.desc_pool, &format!("{} descriptor pool {}", name, i));
+            for buffer in &desc.buffers {
+                context.set_object_name(
+                    buffer.buffer,
+                    &format!("{} binding {} buffer {}", name, buffer.binding, i),
+                );
+            }
+        }
+    }
+
     fn create_descriptor_sets(
         context: &VulkanContext,
         desc_layout: &vk::DescriptorSetLayout,
+        bindings: &[UniformBinding],
     ) -> UniformDescriptor {
-        let data_size = 4 * 16 * 3 as vk::DeviceSize;
-
-        let create_info = vk::BufferCreateInfo::default()
-            .sharing_mode(vk::SharingMode::EXCLUSIVE)
-            .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
-            .size(data_size);
+        let min_alignment = unsafe {
+            context
+                .instance
+                .get_physical_device_properties(context.physical_device)
+        }
+        .limits
+        .min_uniform_buffer_offset_alignment;
+
+        let mut buffers = vec![];
+        for (i, binding) in bindings.iter().enumerate() {
+            let binding_index = i as u32;
+            let descriptor_type = binding.descriptor_type();
+            match *binding {
+                UniformBinding::UniformBuffer { size, .. }
+                | UniformBinding::StorageBuffer { size, .. } => {
+                    buffers.push(UniformBuffer::new(
+                        context,
+                        binding_index,
+                        descriptor_type,
+                        size,
+                        1,
+                    ));
+                }
+                UniformBinding::DynamicUniformBuffer {
+                    element_size,
+                    count,
+                    ..
+                } => {
+                    let stride = element_size.div_ceil(min_alignment) * min_alignment;
+                    buffers.push(UniformBuffer::new(
+                        context,
+                        binding_index,
+                        descriptor_type,
+                        stride,
+                        count,
+                    ));
+                }
+                _ => {}
+            }
+        }
 
-        let (buffer, allocation) =
-            context.allocate_buffer(&create_info, gpu_allocator::MemoryLocation::CpuToGpu);
-        let uniform_buffer = Some(UniformBuffer {
-            allocation,
-            buffer,
-            buf_size: data_size,
-        });
-
-        let desc_pool_sizes = &[
-            vk::DescriptorPoolSize::default()
-                .descriptor_count(1)
-                .ty(vk::DescriptorType::UNIFORM_BUFFER),
-            vk::DescriptorPoolSize::default()
-                .descriptor_count(1)
-                .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER),
-        ];
+        let desc_pool_sizes: Vec<_> = bindings
+            .iter()
+            .map(|binding| {
+                vk::DescriptorPoolSize::default()
+                    .descriptor_count(binding.descriptor_count())
+                    .ty(binding.descriptor_type())
+            })
+            .collect();
         let desc_pool_info = vk::DescriptorPoolCreateInfo::default()
-            .pool_sizes(desc_pool_sizes)
+            .pool_sizes(&desc_pool_sizes)
             .max_sets(1);
         let desc_pool =
             unsafe { context.device.create_descriptor_pool(&desc_pool_info, None) }.unwrap();
@@ -180,70 +562,66 @@ impl UniformHandle {
             .set_layouts(desc_layouts);
         let desc_set = unsafe { context.device.allocate_descriptor_sets(&desc_info) }.unwrap()[0];
 
-        let image_info = None;
-
-        UniformDescriptor {
+        //Buffers owned by the handle never change, so their descriptors are written once
+        let uniform_desc = UniformDescriptor {
             desc_set,
             desc_pool,
-            uniform_buffer,
-            image_info,
+            buffers,
             static_descriptors: vec![],
-        }
+            dynamic_descriptors: vec![],
+            pending_static: vec![],
+        };
+        let buffer_writes: Vec<_> = uniform_desc
+            .buffers
+            .iter()
+            .map(|buffer| buffer.update_once(desc_set, buffer.binding))
+            .collect();
+        unsafe { context.device.update_descriptor_sets(&buffer_writes, &[]) };
+        uniform_desc
     }
 }
 
 impl UniformDescriptor {
-    //TODO: Uniform buffer updates:
-    pub fn update_buffer(&mut self, context: &VulkanContext, data: &[u8]) {
-        if let Some(uniform_buffer) = &self.uniform_buffer {
-            let data_size = std::mem::size_of_val(data) as vk::DeviceSize;
-            if uniform_buffer.buf_size < data_size {
-                panic!(
-                    "Too little memory allocated for buffer of size {}",
-                    data_size
-                );
-            }
-
-            let mapped_data = context.map_buffer(&uniform_buffer.allocation);
-            unsafe {
-                std::ptr::copy_nonoverlapping(data.as_ptr(), mapped_data, data_size as usize);
-            }
-
-            let buf_info = [vk::DescriptorBufferInfo::default()
-                .buffer(uniform_buffer.buffer)
-                .offset(0)
-                .range(data_size)];
-            let mut desc_writes = vec![];
-            desc_writes.push(
-                vk::WriteDescriptorSet::default()
-                    .dst_set(self.desc_set)
-                    .dst_binding(0)
-                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                    .buffer_info(&buf_info),
-            );
-            if let Some(image_info) = &mut self.image_info {
-                if !image_info.is_updated {
-                    image_info.is_updated = true;
-                    let write_set = image_info.update_once(self.desc_set, 1);
-                    desc_writes.push(write_set);
+    fn set_static(&mut self, binding: u32, descriptor: Box<dyn UpdateOnce>) {
+        match self
+            .static_descriptors
+            .iter()
+            .position(|(static_binding, _)| *static_binding == binding)
+        {
+            Some(index) => {
+                self.static_descriptors[index].1 = descriptor;
+                if !self.pending_static.contains(&index) {
+                    self.pending_static.push(index);
                 }
-            } else {
-                println!("No descriptor image to update!!!");
             }
+            None => {
+                self.static_descriptors.push((binding, descriptor));
+                self.pending_static.push(self.static_descriptors.len() - 1);
+            }
+        }
+    }
 
+    fn write_descriptors(&mut self, context: &VulkanContext) {
+        let mut desc_writes = vec![];
+        for index in &self.pending_static {
+            let (binding, descriptor) = &self.static_descriptors[*index];
+            desc_writes.push(descriptor.update_once(self.desc_set, *binding));
+        }
+        for (binding, descriptor) in &self.dynamic_descriptors {
+            desc_writes.push(descriptor.update_always(self.desc_set, *binding));
+        }
+        if !desc_writes.is_empty() {
             unsafe {
                 context
                     .device
                     .update_descriptor_sets(desc_writes.as_slice(), &[])
             };
-        } else {
-            println!("No descriptor buffer to update!!!");
         }
+        self.pending_static.clear();
     }
 
     pub fn destroy(&mut self, context: &VulkanContext) {
-        if self.uniform_buffer.is_some() {
-            let buffer = self.uniform_buffer.take().unwrap();
+        for buffer in self.buffers.drain(..) {
             context.free_buffer(buffer.buffer, buffer.allocation);
         }
         unsafe {
@@ -253,25 +631,92 @@ impl UniformDescriptor {
 }
 
 impl RenderPipeline {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         context: Arc<VulkanContext>,
         render_pass: vk::RenderPass,
+        sample_count: vk::SampleCountFlags,
         num_buffered_frames: usize,
-        vertex_binding: VertexBinding,
+        shaders: ShaderStages,
+        vertex_bindings: Vec<VertexBinding>,
+        bindings: &[UniformBinding],
+        options: PipelineOptions,
     ) -> Self {
-        let entry_point = CString::new("main").unwrap();
-        let mut vertex_spv_file = Cursor::new(SHADER_VERT);
+        let mut vertex_spv_file = Cursor::new(shaders.vertex);
         let vert_decoded = read_spv(&mut vertex_spv_file).unwrap();
         let create_info = vk::ShaderModuleCreateInfo::default().code(&vert_decoded);
         let shader_vert =
             unsafe { context.device.create_shader_module(&create_info, None) }.unwrap();
 
-        let mut frag_spv_file = Cursor::new(SHADER_FRAG);
+        let mut frag_spv_file = Cursor::new(shaders.fragment);
         let frag_decoded = read_spv(&mut frag_spv_file).unwrap();
         let create_info = vk::ShaderModuleCreateInfo::default().code(&frag_decoded);
         let shader_frag =
             unsafe { context.device.create_shader_module(&create_info, None) }.unwrap();
 
+        let desc_layout = UniformBinding::create_layout(&context, bindings);
+        let uniform = UniformHandle::new(num_buffered_frames, &context, &desc_layout, bindings);
+
+        let pipeline_layout_desc_layouts = &[desc_layout];
+
+        let create_info =
+            vk::PipelineLayoutCreateInfo::default().set_layouts(pipeline_layout_desc_layouts);
+        let pipeline_layout =
+            unsafe { context.device.create_pipeline_layout(&create_info, None) }.unwrap();
+
+        let pipeline = Self::create_pipeline(
+            &context,
+            pipeline_layout,
+            render_pass,
+            sample_count,
+            &vertex_bindings,
+            options,
+            shader_vert,
+            shader_frag,
+        );
+
+        RenderPipeline {
+            context,
+            pipeline,
+            pipeline_layout,
+            desc_layout,
+            uniform,
+            vertex_bindings,
+            options,
+            vert_module: shader_vert,
+            frag_module: shader_frag,
+            name: None,
+        }
+    }
+
+    /// Names the pipeline and everything it owns, e.g. "Fox.glb pipeline".
+    pub fn set_name(&mut self, name: &str) {
+        self.context
+            .set_object_name(self.pipeline, &format!("{} pipeline", name));
+        self.context
+            .set_object_name(self.pipeline_layout, &format!("{} pipeline layout", name));
+        self.context
+            .set_object_name(self.desc_layout, &format!("{} descriptor set layout", name));
+        self.context
+            .set_object_name(self.vert_module, &format!("{} vertex shader", name));
+        self.context
+            .set_object_name(self.frag_module, &format!("{} fragment shader", name));
+        self.uniform.set_name(&self.context, name);
+        self.name = Some(name.to_owned());
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_pipeline(
+        context: &VulkanContext,
+        pipeline_layout: vk::PipelineLayout,
+        render_pass: vk::RenderPass,
+        sample_count: vk::SampleCountFlags,
+        vertex_bindings: &[VertexBinding],
+        options: PipelineOptions,
+        shader_vert: vk::ShaderModule,
+        shader_frag: vk::ShaderModule,
+    ) -> vk::Pipeline {
+        let entry_point = CString::new("main").unwrap();
         let shader_stages = vec![
             vk::PipelineShaderStageCreateInfo::default()
                 .stage(vk::ShaderStageFlags::VERTEX)
@@ -282,39 +727,17 @@ impl RenderPipeline {
                 .module(shader_frag)
                 .name(&entry_point),
         ];
-        //TODO: Descripitor sets
-        let desc_layout_bindings = &[
-            vk::DescriptorSetLayoutBinding::default()
-                .binding(0)
-                .descriptor_count(1)
-                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT),
-            vk::DescriptorSetLayoutBinding::default()
-                .binding(1)
-                .descriptor_count(1)
-                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                .stage_flags(vk::ShaderStageFlags::FRAGMENT),
-        ];
-        let desc_layout_info =
-            vk::DescriptorSetLayoutCreateInfo::default().bindings(desc_layout_bindings);
-        let desc_layout = unsafe {
-            context
-                .device
-                .create_descriptor_set_layout(&desc_layout_info, None)
-        }
-        .unwrap();
-
-        let uniform = UniformHandle::new(num_buffered_frames, &context, &desc_layout);
 
-        let pipeline_layout_desc_layouts = &[desc_layout];
-
-        let create_info =
-            vk::PipelineLayoutCreateInfo::default().set_layouts(pipeline_layout_desc_layouts);
-        let pipeline_layout =
-            unsafe { context.device.create_pipeline_layout(&create_info, None) }.unwrap();
-
-        let vertex_binding_desc = [vertex_binding.get_binding_desc(0)];
-        let vertex_attrib_descs = vertex_binding.get_attribute_desc(0);
+        //Vertex binding i is bound at index i, with its locations following the previous binding
+        let mut vertex_binding_desc = vec![];
+        let mut vertex_attrib_descs = vec![];
+        let mut first_location = 0;
+        for (binding, vertex_binding) in vertex_bindings.iter().enumerate() {
+            vertex_binding_desc.push(vertex_binding.get_binding_desc(binding as u32));
+            vertex_attrib_descs
+                .extend(vertex_binding.get_attribute_desc(binding as u32, first_location));
+            first_location += vertex_binding.num_locations();
+        }
         let vertex_input = vk::PipelineVertexInputStateCreateInfo::default()
             .vertex_binding_descriptions(&vertex_binding_desc)
             .vertex_attribute_descriptions(vertex_attrib_descs.as_slice());
@@ -333,12 +756,16 @@ impl RenderPipeline {
             .rasterizer_discard_enable(false)
             .polygon_mode(vk::PolygonMode::FILL)
             .line_width(1.0)
-            .cull_mode(vk::CullModeFlags::BACK)
+            .cull_mode(if options.double_sided {
+                vk::CullModeFlags::NONE
+            } else {
+                vk::CullModeFlags::BACK
+            })
             .front_face(vk::FrontFace::CLOCKWISE);
 
         let multisampling = vk::PipelineMultisampleStateCreateInfo::default()
             .sample_shading_enable(false)
-            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+            .rasterization_samples(sample_count);
 
         let color_blend_attachments = vec![vk::PipelineColorBlendAttachmentState::default()
             .color_write_mask(
@@ -347,7 +774,13 @@ impl RenderPipeline {
                     | vk::ColorComponentFlags::B
                     | vk::ColorComponentFlags::A,
             )
-            .blend_enable(false)];
+            .blend_enable(options.alpha_blend)
+            .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .alpha_blend_op(vk::BlendOp::ADD)];
 
         let color_blending = vk::PipelineColorBlendStateCreateInfo::default()
             .logic_op_enable(false)
@@ -355,7 +788,7 @@ impl RenderPipeline {
 
         let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::default()
             .depth_test_enable(true)
-            .depth_write_enable(true)
+            .depth_write_enable(!options.alpha_blend)
             .depth_compare_op(vk::CompareOp::LESS)
             .depth_bounds_test_enable(false)
             .min_depth_bounds(0.0)
@@ -378,23 +811,36 @@ impl RenderPipeline {
             .render_pass(render_pass)
             .subpass(0);
 
-        let pipeline = unsafe {
+        unsafe {
             context.device.create_graphics_pipelines(
                 vk::PipelineCache::null(),
                 &[create_info],
                 None,
             )
         }
-        .unwrap()[0];
+        .unwrap()[0]
+    }
 
-        RenderPipeline {
-            context,
-            pipeline,
-            pipeline_layout,
-            desc_layout,
-            uniform,
-            vert_module: shader_vert,
-            frag_module: shader_frag,
+    /// Rebuilds the pipeline object against a new render pass, e.g. after the sample count changed.
+    /// The caller needs to make sure the old pipeline is no longer in use.
+    pub fn recreate(&mut self, render_pass: vk::RenderPass, sample_count: vk::SampleCountFlags) {
+        let pipeline = Self::create_pipeline(
+            &self.context,
+            self.pipeline_layout,
+            render_pass,
+            sample_count,
+            &self.vertex_bindings,
+            self.options,
+            self.vert_module,
+            self.frag_module,
+        );
+        unsafe {
+            self.context.device.destroy_pipeline(self.pipeline, None);
+        }
+        self.pipeline = pipeline;
+        if let Some(name) = &self.name {
+            self.context
+                .set_object_name(self.pipeline, &format!("{} pipeline", name));
         }
     }
 
@@ -402,6 +848,10 @@ impl RenderPipeline {
         self.uniform.update_buffer(&self.context, data);
     }
 
+    pub fn update_bindings(&mut self, writes: &[(u32, &[u8])]) {
+        self.uniform.update(&self.context, writes);
+    }
+
     pub fn destroy(&mut self) {
         unsafe {
             self.context.device.destroy_pipeline(self.pipeline, None);
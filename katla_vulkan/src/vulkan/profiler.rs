@@ -0,0 +1,266 @@
+use ash::vk;
+
+use std::sync::Arc;
+
+use super::{CommandBuffer, VulkanContext};
+
+/// Pipeline statistics of a single scope, only gathered when the device supports
+/// `pipelineStatisticsQuery` and the profiler was created with statistics enabled.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PipelineStatistics {
+    pub input_assembly_vertices: u64,
+    pub vertex_shader_invocations: u64,
+    pub clipping_primitives: u64,
+    pub fragment_shader_invocations: u64,
+}
+
+//Results are written in bit order of these flags
+const STATISTICS_FLAGS: vk::QueryPipelineStatisticFlags = vk::QueryPipelineStatisticFlags::from_raw(
+    vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES.as_raw()
+        | vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS.as_raw()
+        | vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES.as_raw()
+        | vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS.as_raw(),
+);
+
+#[derive(Clone, Debug)]
+pub struct GpuScopeTiming {
+    pub name: String,
+    pub milliseconds: f64,
+    pub statistics: Option<PipelineStatistics>,
+}
+
+/// Measures named scopes of a frame with timestamp queries.
+///
+/// Every frame slot (one per swapchain image) owns its own range of queries, which
+/// are read back when the slot is recorded again, so results lag a few frames behind.
+/// Scopes cannot be nested.
+pub struct GpuProfiler {
+    context: Arc<VulkanContext>,
+    timestamp_pool: vk::QueryPool,
+    statistics_pool: Option<vk::QueryPool>,
+    //Nanoseconds per timestamp tick
+    timestamp_period: f64,
+    timestamp_mask: u64,
+    max_scopes: u32,
+    frame_scopes: Vec<Vec<String>>,
+    current_frame: usize,
+    open_scope: Option<u32>,
+    results: Vec<GpuScopeTiming>,
+}
+
+impl GpuProfiler {
+    /// Returns `None` if the graphics queue does not support timestamps.
+    pub fn new(
+        context: Arc<VulkanContext>,
+        num_frames: usize,
+        max_scopes: u32,
+        pipeline_statistics: bool,
+    ) -> Option<Self> {
+        let timestamp_valid_bits = unsafe {
+            context
+                .instance
+                .get_physical_device_queue_family_properties(context.physical_device)
+        }[context.gfx_queue.queue_family_index() as usize]
+            .timestamp_valid_bits;
+        if timestamp_valid_bits == 0 {
+            println!("GPU profiler disabled, the graphics queue has no timestamp support");
+            return None;
+        }
+        let timestamp_mask = if timestamp_valid_bits >= 64 {
+            u64::MAX
+        } else {
+            (1u64 << timestamp_valid_bits) - 1
+        };
+        let properties = unsafe {
+            context
+                .instance
+                .get_physical_device_properties(context.physical_device)
+        };
+        let timestamp_period = properties.limits.timestamp_period as f64;
+
+        let query_count = num_frames as u32 * max_scopes;
+        let create_info = vk::QueryPoolCreateInfo::default()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(query_count * 2);
+        let timestamp_pool =
+            unsafe { context.device.create_query_pool(&create_info, None) }.unwrap();
+
+        context.set_object_name(timestamp_pool, "profiler timestamps");
+
+        let statistics_pool = if pipeline_statistics
+            && context.capabilities.features.pipeline_statistics_query != 0
+        {
+            let create_info = vk::QueryPoolCreateInfo::default()
+                .query_type(vk::QueryType::PIPELINE_STATISTICS)
+                .pipeline_statistics(STATISTICS_FLAGS)
+                .query_count(query_count);
+            let statistics_pool =
+                unsafe { context.device.create_query_pool(&create_info, None) }.unwrap();
+            context.set_object_name(statistics_pool, "profiler pipeline statistics");
+            Some(statistics_pool)
+        } else {
+            if pipeline_statistics {
+                println!("Pipeline statistics queries are not supported by the device");
+            }
+            None
+        };
+
+        Some(Self {
+            context,
+            timestamp_pool,
+            statistics_pool,
+            timestamp_period,
+            timestamp_mask,
+            max_scopes,
+            frame_scopes: vec![vec![]; num_frames],
+            current_frame: 0,
+            open_scope: None,
+            results: vec![],
+        })
+    }
+
+    fn first_query(&self, frame_index: usize) -> u32 {
+        frame_index as u32 * self.max_scopes
+    }
+
+    /// Statistics a scope may be counting, which secondary command buffers have to inherit.
+    pub fn statistics_flags(&self) -> vk::QueryPipelineStatisticFlags {
+        match self.statistics_pool {
+            Some(_) => STATISTICS_FLAGS,
+            None => vk::QueryPipelineStatisticFlags::empty(),
+        }
+    }
+
+    /// Reads back the results of the previous use of `frame_index` and resets its queries.
+    /// Must be recorded outside of a render pass, before any scope of the frame.
+    pub fn begin_frame(&mut self, command_buffer: &CommandBuffer, frame_index: usize) {
+        self.current_frame = frame_index;
+        self.resolve(frame_index);
+        self.frame_scopes[frame_index].clear();
+
+        let first_query = self.first_query(frame_index);
+        command_buffer.reset_query_pool(self.timestamp_pool, first_query * 2, self.max_scopes * 2);
+        if let Some(statistics_pool) = self.statistics_pool {
+            command_buffer.reset_query_pool(statistics_pool, first_query, self.max_scopes);
+        }
+    }
+
+    /// Starts a named scope. Returns false, without recording anything, once `max_scopes`
+    /// scopes have been recorded this frame.
+    pub fn begin_scope(&mut self, command_buffer: &CommandBuffer, name: &str) -> bool {
+        assert!(
+            self.open_scope.is_none(),
+            "GPU profiler scopes cannot be nested"
+        );
+        let first_query = self.first_query(self.current_frame);
+        let scopes = &mut self.frame_scopes[self.current_frame];
+        if scopes.len() as u32 >= self.max_scopes {
+            return false;
+        }
+        let query = first_query + scopes.len() as u32;
+        scopes.push(name.to_owned());
+        self.open_scope = Some(query);
+
+        command_buffer.write_timestamp(
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            self.timestamp_pool,
+            query * 2,
+        );
+        if let Some(statistics_pool) = self.statistics_pool {
+            command_buffer.begin_query(statistics_pool, query);
+        }
+        true
+    }
+
+    pub fn end_scope(&mut self, command_buffer: &CommandBuffer) {
+        if let Some(query) = self.open_scope.take() {
+            if let Some(statistics_pool) = self.statistics_pool {
+                command_buffer.end_query(statistics_pool, query);
+            }
+            command_buffer.write_timestamp(
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.timestamp_pool,
+                query * 2 + 1,
+            );
+        }
+    }
+
+    fn resolve(&mut self, frame_index: usize) {
+        let scopes = &self.frame_scopes[frame_index];
+        if scopes.is_empty() {
+            return;
+        }
+        let first_query = self.first_query(frame_index);
+        let mut timestamps = vec![0u64; scopes.len() * 2];
+        let timestamps_ready = unsafe {
+            self.context.device.get_query_pool_results(
+                self.timestamp_pool,
+                first_query * 2,
+                &mut timestamps,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        }
+        .is_ok();
+        if !timestamps_ready {
+            //Keep the previous results rather than stalling
+            return;
+        }
+
+        let mut statistics = vec![[0u64; 4]; scopes.len()];
+        let statistics_ready = match self.statistics_pool {
+            Some(statistics_pool) => unsafe {
+                self.context.device.get_query_pool_results(
+                    statistics_pool,
+                    first_query,
+                    &mut statistics,
+                    vk::QueryResultFlags::TYPE_64,
+                )
+            }
+            .is_ok(),
+            None => false,
+        };
+
+        self.results = scopes
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let ticks =
+                    timestamps[i * 2 + 1].wrapping_sub(timestamps[i * 2]) & self.timestamp_mask;
+                let statistics = if statistics_ready {
+                    let values = statistics[i];
+                    Some(PipelineStatistics {
+                        input_assembly_vertices: values[0],
+                        vertex_shader_invocations: values[1],
+                        clipping_primitives: values[2],
+                        fragment_shader_invocations: values[3],
+                    })
+                } else {
+                    None
+                };
+                GpuScopeTiming {
+                    name: name.clone(),
+                    milliseconds: ticks as f64 * self.timestamp_period / 1_000_000.0,
+                    statistics,
+                }
+            })
+            .collect();
+    }
+
+    /// Scopes of the most recently resolved frame.
+    pub fn results(&self) -> &[GpuScopeTiming] {
+        &self.results
+    }
+
+    pub fn destroy(&mut self) {
+        unsafe {
+            self.context
+                .device
+                .destroy_query_pool(self.timestamp_pool, None);
+            if let Some(statistics_pool) = self.statistics_pool.take() {
+                self.context
+                    .device
+                    .destroy_query_pool(statistics_pool, None);
+            }
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use super::CommandBuffer;
+use super::{CommandBuffer, TimelineSemaphore};
 
 use ash::vk::{self, Fence, Semaphore};
 use ash::Device;
@@ -22,6 +22,10 @@ impl Queue {
         }
     }
 
+    pub fn queue_family_index(&self) -> u32 {
+        self.queue_family_index
+    }
+
     pub fn wait_idle(&self) {
         unsafe {
             self.device.queue_wait_idle(self.queue);
@@ -58,6 +62,49 @@ impl Queue {
         }
     }
 
+    /// Submits like `submit`, and also signals the next value of `timeline`, which is
+    /// returned. Waiting on that value means these command buffers have finished.
+    pub fn submit_timeline(
+        &self,
+        command_buffers: &[&CommandBuffer],
+        wait_semaphores: &[Semaphore],
+        signal_semaphores: &[Semaphore],
+        timeline: &TimelineSemaphore,
+    ) -> u64 {
+        let vk_cmd_buffers: Vec<_> = command_buffers
+            .iter()
+            .map(|command_buffer| command_buffer.vk_command_buffer())
+            .collect();
+        let wait_dst_stage_mask = vec![vk::PipelineStageFlags::ALL_COMMANDS; wait_semaphores.len()];
+
+        let (value, signal_fence) = timeline.next_signal();
+        let mut signal_semaphores = signal_semaphores.to_vec();
+        //Binary semaphores ignore their value
+        let mut signal_values = vec![0; signal_semaphores.len()];
+        if let Some(semaphore) = timeline.vk_semaphore() {
+            signal_semaphores.push(semaphore);
+            signal_values.push(value);
+        }
+        let mut timeline_info =
+            vk::TimelineSemaphoreSubmitInfo::default().signal_semaphore_values(&signal_values);
+
+        let mut submit_info = vk::SubmitInfo::default()
+            .wait_dst_stage_mask(&wait_dst_stage_mask)
+            .wait_semaphores(wait_semaphores)
+            .signal_semaphores(&signal_semaphores)
+            .command_buffers(&vk_cmd_buffers);
+        if timeline.vk_semaphore().is_some() {
+            submit_info = submit_info.push_next(&mut timeline_info);
+        }
+
+        unsafe {
+            self.device
+                .queue_submit(self.queue, &[submit_info], signal_fence)
+                .unwrap();
+        }
+        value
+    }
+
     pub fn present(
         &self,
         signal_semaphores: &[Semaphore],
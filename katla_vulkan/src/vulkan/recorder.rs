@@ -0,0 +1,317 @@
+use ash::vk;
+
+use std::{
+    panic::AssertUnwindSafe,
+    sync::mpsc::{channel, Receiver, Sender},
+    thread::JoinHandle,
+};
+
+use super::{CommandBuffer, CommandPool, IndirectBatchDraw, VulkanContext};
+
+#[derive(Clone, Copy, Debug)]
+pub enum PacketDraw {
+    Indexed {
+        index_count: u32,
+        instance_count: u32,
+        first_index: u32,
+        first_instance: u32,
+    },
+    Vertices {
+        vertex_count: u32,
+        instance_count: u32,
+    },
+    Indirect(IndirectBatchDraw),
+}
+
+/// Everything needed to record one draw. Only holds handles, not the objects that own
+/// them, so packets can be recorded on any thread while their owners stay put.
+#[derive(Clone, Debug)]
+pub struct DrawPacket<'a> {
+    /// Borrowed from the owner, so making packets every frame allocates no strings
+    pub label: &'a str,
+    pub pipeline: vk::Pipeline,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub descriptor_set: vk::DescriptorSet,
+    /// Bound to consecutive bindings starting at 0
+    pub vertex_buffers: Vec<vk::Buffer>,
+    pub index_buffer: Option<(vk::Buffer, vk::IndexType)>,
+    pub draw: PacketDraw,
+}
+
+impl DrawPacket<'_> {
+    pub fn record(&self, command_buffer: &CommandBuffer) {
+        command_buffer.begin_label(self.label);
+        command_buffer.bind_pipeline(self.pipeline, vk::PipelineBindPoint::GRAPHICS);
+        command_buffer.bind_descriptor_sets(
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline_layout,
+            &[self.descriptor_set],
+        );
+        if let Some((index_buffer, index_type)) = self.index_buffer {
+            command_buffer.bind_index_buffer(index_buffer, 0, index_type);
+        }
+        if !self.vertex_buffers.is_empty() {
+            let offsets = vec![0; self.vertex_buffers.len()];
+            command_buffer.bind_vertex_buffers(0, &self.vertex_buffers, &offsets);
+        }
+        match self.draw {
+            PacketDraw::Indexed {
+                index_count,
+                instance_count,
+                first_index,
+                first_instance,
+            } => command_buffer.draw_indexed(
+                index_count,
+                instance_count,
+                first_index,
+                0,
+                first_instance,
+            ),
+            PacketDraw::Vertices {
+                vertex_count,
+                instance_count,
+            } => command_buffer.draw_array(vertex_count, instance_count, 0, 0),
+            PacketDraw::Indirect(indirect) => indirect.record(command_buffer),
+        }
+        command_buffer.end_label();
+    }
+}
+
+//A command pool is only used by the thread recording into its buffer
+struct RecordingSlot {
+    pool: CommandPool,
+    command_buffer: CommandBuffer,
+}
+
+//Where a secondary continues the render pass
+#[derive(Clone, Copy)]
+struct SecondaryTarget {
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    render_area: vk::Rect2D,
+    pipeline_statistics: vk::QueryPipelineStatisticFlags,
+}
+
+impl SecondaryTarget {
+    fn begin(&self, command_buffer: &CommandBuffer) {
+        command_buffer.begin_secondary(
+            self.render_pass,
+            0,
+            self.framebuffer,
+            self.render_area,
+            self.pipeline_statistics,
+        );
+    }
+}
+
+//One chunk of packets for a worker to record into `command_buffer`
+struct RecordJob {
+    command_buffer: CommandBuffer,
+    target: SecondaryTarget,
+    packets: *const DrawPacket<'static>,
+    packet_count: usize,
+}
+
+//The packets are only read while `ParallelRecorder::record` waits for the job to finish
+unsafe impl Send for RecordJob {}
+
+impl RecordJob {
+    fn record(&self) {
+        let packets = unsafe { std::slice::from_raw_parts(self.packets, self.packet_count) };
+        self.target.begin(&self.command_buffer);
+        for packet in packets {
+            packet.record(&self.command_buffer);
+        }
+        self.command_buffer.end_command();
+    }
+}
+
+struct Worker {
+    jobs: Sender<RecordJob>,
+    thread: JoinHandle<()>,
+}
+
+//Waits for the jobs in flight, also when unwinding, so no worker outlives the packets
+struct PendingJobs<'a> {
+    done: &'a Receiver<bool>,
+    count: usize,
+}
+
+impl PendingJobs<'_> {
+    //False if a job panicked
+    fn wait(&mut self) -> bool {
+        let mut ok = true;
+        while self.count > 0 {
+            ok &= self.done.recv().unwrap_or(false);
+            self.count -= 1;
+        }
+        ok
+    }
+}
+
+impl Drop for PendingJobs<'_> {
+    fn drop(&mut self) {
+        self.wait();
+    }
+}
+
+/// Records a render pass in parallel: the draw packets are split in one chunk per worker
+/// thread, each recorded into a secondary command buffer from that thread's own pool.
+/// The workers live as long as the recorder.
+///
+/// Every frame slot owns its pools, so resetting them never touches buffers in flight.
+pub struct ParallelRecorder {
+    num_threads: usize,
+    //Inherited by the secondaries, the profiler's statistics query is active around them
+    pipeline_statistics: vk::QueryPipelineStatisticFlags,
+    //Per frame slot, one per thread plus one for draws recorded on the calling thread
+    frames: Vec<Vec<RecordingSlot>>,
+    workers: Vec<Worker>,
+    //Every finished job sends whether it recorded without panicking
+    done: Receiver<bool>,
+}
+
+impl ParallelRecorder {
+    /// `pipeline_statistics` are the statistics of the query active while the secondaries
+    /// execute, see `GpuProfiler::statistics_flags`.
+    pub fn new(
+        context: &VulkanContext,
+        num_frames: usize,
+        num_threads: usize,
+        pipeline_statistics: vk::QueryPipelineStatisticFlags,
+    ) -> Self {
+        let num_threads = num_threads.max(1);
+        let queue_family_index = context.gfx_queue.queue_family_index();
+        let frames = (0..num_frames)
+            .map(|frame| {
+                (0..=num_threads)
+                    .map(|thread| {
+                        let pool = CommandPool::new(
+                            context.device.clone(),
+                            queue_family_index,
+                            context.debug_utils.clone(),
+                        );
+                        let command_buffer = pool.create_secondary_command_buffers(1).remove(0);
+                        context.set_object_name(
+                            pool.vk_command_pool(),
+                            &format!("recording pool {} thread {}", frame, thread),
+                        );
+                        context.set_object_name(
+                            command_buffer.vk_command_buffer(),
+                            &format!("secondary command buffer {} thread {}", frame, thread),
+                        );
+                        RecordingSlot {
+                            pool,
+                            command_buffer,
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let (done_sender, done) = channel();
+        let workers = (0..num_threads)
+            .map(|i| {
+                let (jobs, job_receiver) = channel::<RecordJob>();
+                let done = done_sender.clone();
+                let thread = std::thread::Builder::new()
+                    .name(format!("recording thread {}", i))
+                    .spawn(move || {
+                        while let Ok(job) = job_receiver.recv() {
+                            let result =
+                                std::panic::catch_unwind(AssertUnwindSafe(|| job.record()));
+                            if done.send(result.is_ok()).is_err() {
+                                break;
+                            }
+                        }
+                    })
+                    .unwrap();
+                Worker { jobs, thread }
+            })
+            .collect();
+        Self {
+            num_threads,
+            pipeline_statistics,
+            frames,
+            workers,
+            done,
+        }
+    }
+
+    pub fn num_threads(&self) -> usize {
+        self.num_threads
+    }
+
+    /// Records `packets` on worker threads and `serial` on the calling thread, then executes
+    /// them, in that order, from `primary`. `primary` has to be inside `render_pass`, begun
+    /// with `begin_render_pass_secondary`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record<F>(
+        &mut self,
+        primary: &CommandBuffer,
+        frame_index: usize,
+        render_pass: vk::RenderPass,
+        framebuffer: vk::Framebuffer,
+        render_area: vk::Rect2D,
+        packets: &[DrawPacket<'_>],
+        serial: F,
+    ) where
+        F: FnOnce(&CommandBuffer),
+    {
+        let slots = &self.frames[frame_index];
+        for slot in slots {
+            slot.pool.reset();
+        }
+        let (thread_slots, serial_slot) = slots.split_at(self.num_threads);
+        let chunk_size = packets.len().div_ceil(self.num_threads).max(1);
+        let chunks: Vec<_> = packets.chunks(chunk_size).collect();
+        let target = SecondaryTarget {
+            render_pass,
+            framebuffer,
+            render_area,
+            pipeline_statistics: self.pipeline_statistics,
+        };
+
+        let mut pending = PendingJobs {
+            done: &self.done,
+            count: 0,
+        };
+        for ((slot, chunk), worker) in thread_slots.iter().zip(&chunks).zip(&self.workers) {
+            let job = RecordJob {
+                command_buffer: slot.command_buffer.clone(),
+                target,
+                packets: chunk.as_ptr().cast(),
+                packet_count: chunk.len(),
+            };
+            worker.jobs.send(job).expect("Recording thread exited");
+            pending.count += 1;
+        }
+
+        //Recorded while the workers run
+        let command_buffer = &serial_slot[0].command_buffer;
+        target.begin(command_buffer);
+        serial(command_buffer);
+        command_buffer.end_command();
+        if !pending.wait() {
+            panic!("A recording thread panicked");
+        }
+
+        let mut secondaries: Vec<CommandBuffer> = thread_slots
+            .iter()
+            .take(chunks.len())
+            .map(|slot| slot.command_buffer.clone())
+            .collect();
+        secondaries.push(serial_slot[0].command_buffer.clone());
+        primary.execute_commands(&secondaries);
+    }
+
+    pub fn destroy(&mut self) {
+        //Closing the job channel ends the worker
+        for worker in self.workers.drain(..) {
+            drop(worker.jobs);
+            worker.thread.join().unwrap();
+        }
+        for slot in self.frames.drain(..).flatten() {
+            slot.pool.destroy();
+        }
+    }
+}
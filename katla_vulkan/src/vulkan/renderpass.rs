@@ -2,36 +2,67 @@ use ash::{vk, Device};
 
 pub struct RenderPass {
     vk_renderpass: vk::RenderPass,
+    sample_count: vk::SampleCountFlags,
     device: Device,
 }
 
 impl RenderPass {
+    /// Creates the opaque pass. With a sample count above `TYPE_1` the color and
+    /// depth attachments are multisampled and the color is resolved into a third,
+    /// single sampled, attachment. The single sampled color ends up ready to be
+    /// sampled by the tonemapping pass, and the depth ready to be sampled by `DepthPyramid`.
     pub fn create_opaque(
         device: Device,
         color_format: vk::Format,
         depth_format: vk::Format,
+        sample_count: vk::SampleCountFlags,
     ) -> Self {
+        let multisampled = sample_count != vk::SampleCountFlags::TYPE_1;
+        let (color_store_op, color_final_layout) = if multisampled {
+            (
+                vk::AttachmentStoreOp::DONT_CARE,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            )
+        } else {
+            (
+                vk::AttachmentStoreOp::STORE,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            )
+        };
         let color_attachment = vk::AttachmentDescription::default()
             .format(color_format)
-            .samples(vk::SampleCountFlags::TYPE_1)
+            .samples(sample_count)
             .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::STORE)
+            .store_op(color_store_op)
             .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
             .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
             .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR);
+            .final_layout(color_final_layout);
 
         let depth_attachment = vk::AttachmentDescription::default()
             .format(depth_format)
-            .samples(vk::SampleCountFlags::TYPE_1)
+            .samples(sample_count)
             .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+        let resolve_attachment = vk::AttachmentDescription::default()
+            .format(color_format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
             .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
             .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
             .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+            .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
 
-        let attachments = [color_attachment, depth_attachment];
+        let mut attachments = vec![color_attachment, depth_attachment];
+        if multisampled {
+            attachments.push(resolve_attachment);
+        }
 
         let color_attachment_refs = [vk::AttachmentReference::default()
             .attachment(0)
@@ -39,10 +70,90 @@ impl RenderPass {
         let depth_attachment_ref = vk::AttachmentReference::default()
             .attachment(1)
             .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
-        let subpasses = [vk::SubpassDescription::default()
+        let resolve_attachment_refs = [vk::AttachmentReference::default()
+            .attachment(2)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+        let mut subpass = vk::SubpassDescription::default()
             .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
             .color_attachments(&color_attachment_refs)
-            .depth_stencil_attachment(&depth_attachment_ref)];
+            .depth_stencil_attachment(&depth_attachment_ref);
+        if multisampled {
+            subpass = subpass.resolve_attachments(&resolve_attachment_refs);
+        }
+        let subpasses = [subpass];
+        let dependencies = [
+            vk::SubpassDependency::default()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                        | vk::PipelineStageFlags::FRAGMENT_SHADER
+                        | vk::PipelineStageFlags::COMPUTE_SHADER,
+                )
+                .src_access_mask(vk::AccessFlags::empty())
+                .dst_stage_mask(
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                        | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                )
+                .dst_access_mask(
+                    vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                ),
+            //The tonemapping pass reads what we wrote
+            vk::SubpassDependency::default()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ),
+            //The depth pyramid is built from our depth
+            vk::SubpassDependency::default()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(
+                    vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                        | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                )
+                .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags::COMPUTE_SHADER)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ),
+        ];
+
+        let create_info = vk::RenderPassCreateInfo::default()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies);
+
+        let vk_renderpass = unsafe { device.create_render_pass(&create_info, None) }.unwrap();
+
+        Self {
+            vk_renderpass,
+            sample_count,
+            device,
+        }
+    }
+
+    /// Creates a single color attachment pass that writes the final image to the swapchain.
+    pub fn create_present(device: Device, color_format: vk::Format) -> Self {
+        let color_attachment = vk::AttachmentDescription::default()
+            .format(color_format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR);
+        let attachments = [color_attachment];
+
+        let color_attachment_refs = [vk::AttachmentReference::default()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+        let subpasses = [vk::SubpassDescription::default()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachment_refs)];
         let dependencies = [vk::SubpassDependency::default()
             .src_subpass(vk::SUBPASS_EXTERNAL)
             .dst_subpass(0)
@@ -60,6 +171,7 @@ impl RenderPass {
 
         Self {
             vk_renderpass,
+            sample_count: vk::SampleCountFlags::TYPE_1,
             device,
         }
     }
@@ -68,6 +180,14 @@ impl RenderPass {
         self.vk_renderpass
     }
 
+    pub fn sample_count(&self) -> vk::SampleCountFlags {
+        self.sample_count
+    }
+
+    pub fn is_multisampled(&self) -> bool {
+        self.sample_count != vk::SampleCountFlags::TYPE_1
+    }
+
     pub fn destroy(&self) {
         unsafe {
             self.device.destroy_render_pass(self.vk_renderpass, None);
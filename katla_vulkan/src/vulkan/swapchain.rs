@@ -6,6 +6,114 @@ use ash::{
     vk::{self, PhysicalDevice},
 };
 
+/// How the final pass has to encode its linear output for the chosen surface format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputEncoding {
+    /// sRGB swapchain format, the hardware applies the sRGB curve on write
+    SrgbFormat,
+    /// UNORM swapchain format in the sRGB color space, the shader applies the sRGB curve
+    SrgbCurve,
+    /// HDR10, Rec.2020 primaries encoded with the ST 2084 (PQ) curve
+    Hdr10,
+    /// scRGB, linear Rec.709 primaries where 1.0 maps to 80 nits
+    ScRgb,
+}
+
+impl OutputEncoding {
+    pub fn from_surface_format(surface_format: vk::SurfaceFormatKHR) -> Self {
+        match surface_format.color_space {
+            vk::ColorSpaceKHR::HDR10_ST2084_EXT => OutputEncoding::Hdr10,
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => OutputEncoding::ScRgb,
+            _ => match surface_format.format {
+                vk::Format::B8G8R8A8_SRGB
+                | vk::Format::R8G8B8A8_SRGB
+                | vk::Format::A8B8G8R8_SRGB_PACK32 => OutputEncoding::SrgbFormat,
+                _ => OutputEncoding::SrgbCurve,
+            },
+        }
+    }
+
+    pub fn is_hdr(&self) -> bool {
+        matches!(self, OutputEncoding::Hdr10 | OutputEncoding::ScRgb)
+    }
+}
+
+/// Present mode preference, falls back to a supported mode when the surface lacks it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PresentMode {
+    /// FIFO, strict vsync. Always supported.
+    Vsync,
+    /// IMMEDIATE, no vsync and possibly tearing. Falls back to mailbox, then FIFO.
+    Immediate,
+    /// MAILBOX, uncapped rendering without tearing. Falls back to FIFO.
+    Mailbox,
+    /// FIFO_RELAXED, vsync that tears instead of waiting when a frame is late. Falls back to FIFO.
+    FifoRelaxed,
+}
+
+impl PresentMode {
+    fn candidates(&self) -> &'static [vk::PresentModeKHR] {
+        match self {
+            PresentMode::Vsync => &[],
+            PresentMode::Immediate => &[vk::PresentModeKHR::IMMEDIATE, vk::PresentModeKHR::MAILBOX],
+            PresentMode::Mailbox => &[vk::PresentModeKHR::MAILBOX],
+            PresentMode::FifoRelaxed => &[vk::PresentModeKHR::FIFO_RELAXED],
+        }
+    }
+}
+
+fn pick_present_mode(
+    available: &[vk::PresentModeKHR],
+    preferred: PresentMode,
+) -> vk::PresentModeKHR {
+    preferred
+        .candidates()
+        .iter()
+        .find(|mode| available.contains(mode))
+        .cloned()
+        .unwrap_or(vk::PresentModeKHR::FIFO)
+}
+
+fn pick_surface_format(
+    available: &[vk::SurfaceFormatKHR],
+    prefer_hdr: bool,
+) -> Option<vk::SurfaceFormatKHR> {
+    if available.is_empty() {
+        None
+    } else {
+        if prefer_hdr {
+            let hdr_formats = [
+                (
+                    vk::Format::A2B10G10R10_UNORM_PACK32,
+                    vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+                ),
+                (
+                    vk::Format::R16G16B16A16_SFLOAT,
+                    vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+                ),
+            ];
+            for (format, color_space) in hdr_formats.iter() {
+                if let Some(surface_format) = available
+                    .iter()
+                    .find(|f| f.format == *format && f.color_space == *color_space)
+                {
+                    return Some(*surface_format);
+                }
+            }
+        }
+
+        for surface_format in available {
+            if surface_format.format == vk::Format::B8G8R8A8_SRGB
+                && surface_format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+            {
+                return Some(*surface_format);
+            }
+        }
+
+        Some(available[0])
+    }
+}
+
 pub struct SwapchainInfo {
     pub surface_caps: vk::SurfaceCapabilitiesKHR,
     pub surface_formats: Vec<vk::SurfaceFormatKHR>,
@@ -17,6 +125,8 @@ pub struct Swapchain {
     pub swapchain_info: SwapchainInfo,
     pub swapchain: vk::SwapchainKHR,
     pub format: vk::SurfaceFormatKHR,
+    pub encoding: OutputEncoding,
+    pub present_mode: vk::PresentModeKHR,
     //TODO: Change these to renderpasses?
     // pub swapchain_images: Vec<vk::Image>,
     // pub swapchain_image_views: Vec<vk::ImageView>,
@@ -29,14 +139,25 @@ impl Swapchain {
         physical_device: PhysicalDevice,
         surface: vk::SurfaceKHR,
         old_swapchain: Option<vk::SwapchainKHR>,
+        prefer_hdr: bool,
+        preferred_present_mode: PresentMode,
     ) -> Self {
         let swapchain_info =
             SwapchainInfo::query_swapchain_support(surface_loader, physical_device, surface);
 
         let surface_caps = &swapchain_info.surface_caps;
-        let format = swapchain_info.choose_surface_format().unwrap();
+        let format = swapchain_info.choose_surface_format(prefer_hdr).unwrap();
+        let encoding = OutputEncoding::from_surface_format(format);
+        println!(
+            "Swapchain format: {:?} {:?} ({:?})",
+            format.format, format.color_space, encoding
+        );
 
-        let present_mode = swapchain_info.choose_present_mode();
+        let present_mode = swapchain_info.choose_present_mode(preferred_present_mode);
+        println!(
+            "Present mode: {:?} (requested {:?})",
+            present_mode, preferred_present_mode
+        );
 
         let current_extent = surface_caps.current_extent;
 
@@ -67,6 +188,8 @@ impl Swapchain {
             swapchain_info,
             swapchain,
             format,
+            encoding,
+            present_mode,
         }
     }
 
@@ -89,31 +212,15 @@ impl Swapchain {
 }
 
 impl SwapchainInfo {
-    pub fn choose_present_mode(&self) -> vk::PresentModeKHR {
-        self.present_modes
-            .iter()
-            .find(|format| match **format {
-                vk::PresentModeKHR::MAILBOX => true,
-                _ => false,
-            })
-            .cloned()
-            .unwrap_or(vk::PresentModeKHR::FIFO)
+    /// FIFO is the only mode the spec guarantees, so it is the final fallback.
+    pub fn choose_present_mode(&self, preferred: PresentMode) -> vk::PresentModeKHR {
+        pick_present_mode(&self.present_modes, preferred)
     }
 
-    pub fn choose_surface_format(&self) -> Option<vk::SurfaceFormatKHR> {
-        if self.surface_formats.is_empty() {
-            None
-        } else {
-            for surface_format in &self.surface_formats {
-                if surface_format.format == vk::Format::B8G8R8A8_SRGB
-                    && surface_format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-                {
-                    return Some(*surface_format);
-                }
-            }
-
-            Some(self.surface_formats[0])
-        }
+    /// Picks the swapchain format. With `prefer_hdr` an HDR10 or scRGB format is picked
+    /// when the surface exposes one (needs VK_EXT_swapchain_colorspace), otherwise 8 bit sRGB.
+    pub fn choose_surface_format(&self, prefer_hdr: bool) -> Option<vk::SurfaceFormatKHR> {
+        pick_surface_format(&self.surface_formats, prefer_hdr)
     }
 
     pub fn query_swapchain_support(
@@ -140,3 +247,76 @@ impl SwapchainInfo {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(format: vk::Format, color_space: vk::ColorSpaceKHR) -> vk::SurfaceFormatKHR {
+        vk::SurfaceFormatKHR {
+            format,
+            color_space,
+        }
+    }
+
+    #[test]
+    fn test_present_mode() {
+        use vk::PresentModeKHR as M;
+        let all = [M::FIFO, M::FIFO_RELAXED, M::MAILBOX, M::IMMEDIATE];
+        assert_eq!(pick_present_mode(&all, PresentMode::Vsync), M::FIFO);
+        assert_eq!(
+            pick_present_mode(&all, PresentMode::Immediate),
+            M::IMMEDIATE
+        );
+        assert_eq!(pick_present_mode(&all, PresentMode::Mailbox), M::MAILBOX);
+        assert_eq!(
+            pick_present_mode(&all, PresentMode::FifoRelaxed),
+            M::FIFO_RELAXED
+        );
+        //Mailbox also avoids waiting for vblank
+        assert_eq!(
+            pick_present_mode(&[M::FIFO, M::MAILBOX], PresentMode::Immediate),
+            M::MAILBOX
+        );
+        //Unavailable modes fall back to FIFO
+        for preferred in [
+            PresentMode::Immediate,
+            PresentMode::Mailbox,
+            PresentMode::FifoRelaxed,
+        ] {
+            assert_eq!(pick_present_mode(&[M::FIFO], preferred), M::FIFO);
+        }
+    }
+
+    #[test]
+    fn test_surface_format() {
+        use vk::{ColorSpaceKHR as C, Format as F};
+        let srgb = format(F::B8G8R8A8_SRGB, C::SRGB_NONLINEAR);
+        let unorm = format(F::B8G8R8A8_UNORM, C::SRGB_NONLINEAR);
+        let hdr10 = format(F::A2B10G10R10_UNORM_PACK32, C::HDR10_ST2084_EXT);
+        let scrgb = format(F::R16G16B16A16_SFLOAT, C::EXTENDED_SRGB_LINEAR_EXT);
+        //The 10 bit format in the sRGB color space is not HDR
+        let srgb_10 = format(F::A2B10G10R10_UNORM_PACK32, C::SRGB_NONLINEAR);
+
+        assert_eq!(pick_surface_format(&[], false), None);
+        assert_eq!(
+            pick_surface_format(&[unorm, srgb, hdr10], false),
+            Some(srgb)
+        );
+        assert_eq!(
+            pick_surface_format(&[unorm, srgb, scrgb, hdr10], true),
+            Some(hdr10)
+        );
+        assert_eq!(
+            pick_surface_format(&[unorm, srgb, scrgb], true),
+            Some(scrgb)
+        );
+        //No HDR format, sRGB instead
+        assert_eq!(
+            pick_surface_format(&[srgb_10, unorm, srgb], true),
+            Some(srgb)
+        );
+        //Nothing known, the first format
+        assert_eq!(pick_surface_format(&[unorm, srgb_10], true), Some(unorm));
+    }
+}
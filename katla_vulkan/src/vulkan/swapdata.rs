@@ -1,9 +1,16 @@
 use ash::{khr::swapchain::Device as SwapchainDevice, vk, Device};
+
+use super::TimelineSemaphore;
+
+/// Acquire and present semaphores for the frames in flight. Frames are tracked by the
+/// frame timeline value their submission signals, rather than by per-frame fences.
 pub struct SwapData {
     frames_in_flight: usize,
     frame: usize,
-    images_in_flight: Vec<vk::Fence>,
-    in_flight_fences: Vec<vk::Fence>,
+    //Frame timeline value of the last submission that rendered into each swapchain image
+    images_in_flight: Vec<u64>,
+    //Frame timeline value of the last submission of each frame slot
+    frame_values: Vec<u64>,
     image_available_semaphores: Vec<vk::Semaphore>,
     render_finished_semaphores: Vec<vk::Semaphore>,
 }
@@ -22,42 +29,35 @@ impl SwapData {
             .map(|_| unsafe { device.create_semaphore(&create_info, None) }.unwrap())
             .collect();
 
-        let create_info = vk::FenceCreateInfo::default().flags(vk::FenceCreateFlags::SIGNALED);
-        let in_flight_fences: Vec<_> = (0..frames_in_flight)
-            .map(|_| unsafe { device.create_fence(&create_info, None) }.unwrap())
-            .collect();
-        let images_in_flight: Vec<_> = swapchain_images.iter().map(|_| vk::Fence::null()).collect();
+        let frame_values = vec![0; frames_in_flight];
+        let images_in_flight = vec![0; swapchain_images.len()];
 
         let frame = 0;
         Self {
             frames_in_flight,
             frame,
             images_in_flight,
-            in_flight_fences,
+            frame_values,
             image_available_semaphores,
             render_finished_semaphores,
         }
     }
 
-    pub fn wait_for_fence(&self, device: &Device) {
-        unsafe {
-            device
-                .wait_for_fences(&[self.in_flight_fences[self.frame]], true, u64::MAX)
-                .unwrap();
-        }
+    /// Waits until the frame slot about to be reused has finished on the GPU.
+    pub fn wait_for_frame(&self, timeline: &TimelineSemaphore) {
+        timeline.wait(self.frame_values[self.frame]);
     }
 
     ///Swaps the queued images and returns a tuple containing:
     ///- next available semaphore
     ///- finished semaphore
-    ///- in flight fence
     ///- swapimage index
     pub fn swap_images(
         &mut self,
-        device: &Device,
         swapchain_loader: &SwapchainDevice,
         swapchain: vk::SwapchainKHR,
-    ) -> (vk::Semaphore, vk::Semaphore, vk::Fence, u32) {
+        timeline: &TimelineSemaphore,
+    ) -> (vk::Semaphore, vk::Semaphore, u32) {
         //TODO: What is the bool for?
         let (image_index, _) = unsafe {
             swapchain_loader.acquire_next_image(
@@ -69,20 +69,22 @@ impl SwapData {
         }
         .unwrap();
 
-        let image_in_flight = self.images_in_flight[image_index as usize];
-        if image_in_flight != vk::Fence::null() {
-            unsafe { device.wait_for_fences(&[image_in_flight], true, u64::MAX) }.unwrap();
-        }
-        self.images_in_flight[image_index as usize] = self.in_flight_fences[self.frame];
+        timeline.wait(self.images_in_flight[image_index as usize]);
 
         (
             self.image_available_semaphores[self.frame],
             self.render_finished_semaphores[self.frame],
-            self.in_flight_fences[self.frame],
             image_index,
         )
     }
 
+    /// Records the frame timeline value signaled by the submission of the current frame,
+    /// rendering into `image_index`.
+    pub fn frame_submitted(&mut self, image_index: u32, value: u64) {
+        self.frame_values[self.frame] = value;
+        self.images_in_flight[image_index as usize] = value;
+    }
+
     pub fn step_frame(&mut self) {
         self.frame = (self.frame + 1) % self.frames_in_flight;
     }
@@ -96,10 +98,6 @@ impl SwapData {
             {
                 device.destroy_semaphore(semaphore, None);
             }
-
-            for &fence in &self.in_flight_fences {
-                device.destroy_fence(fence, None);
-            }
         }
     }
 }
@@ -117,9 +117,11 @@ impl Texture {
     }
 
     fn create_texture_sampler(context: &VulkanContext) -> vk::Sampler {
+        //Plain linear filtering when samplerAnisotropy is unsupported
+        let anisotropy = context.capabilities.sampler_anisotropy();
         let create_info = vk::SamplerCreateInfo::default()
-            .anisotropy_enable(true)
-            .max_anisotropy(16.0)
+            .anisotropy_enable(anisotropy.is_some())
+            .max_anisotropy(anisotropy.unwrap_or(1.0))
             .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
             .unnormalized_coordinates(false)
             .min_filter(vk::Filter::LINEAR)
@@ -287,6 +289,12 @@ impl Texture {
         }
     }
 
+    pub fn set_name(&self, context: &VulkanContext, name: &str) {
+        context.set_object_name(self.image, name);
+        context.set_object_name(self.image_view, name);
+        context.set_object_name(self.image_sampler, &format!("{} sampler", name));
+    }
+
     pub fn destroy(self, context: &VulkanContext) {
         unsafe {
             context.device.destroy_sampler(self.image_sampler, None);
@@ -0,0 +1,153 @@
+use ash::{vk, Device};
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+};
+
+enum Signal {
+    Semaphore(vk::Semaphore),
+    //Devices without timelineSemaphore get one fence per signaled value
+    Fences(RefCell<VecDeque<(u64, vk::Fence)>>),
+}
+
+/// A monotonically increasing counter the GPU signals as submissions finish.
+///
+/// Every submission through `Queue::submit_timeline` gets the next value, so a resource
+/// only has to remember the value of the last submission that used it, and the CPU can
+/// wait for or poll exactly that value, e.g. before destroying or overwriting it.
+pub struct TimelineSemaphore {
+    device: Device,
+    signal: Signal,
+    //Last value handed to a submission
+    last_value: Cell<u64>,
+    //Cached for the fence fallback, which can only poll
+    completed_value: Cell<u64>,
+}
+
+impl TimelineSemaphore {
+    /// Falls back to fences when `timeline_semaphore` is false, see `DeviceCapabilities`.
+    pub fn new(device: Device, timeline_semaphore: bool) -> Self {
+        let signal = if timeline_semaphore {
+            let mut type_info = vk::SemaphoreTypeCreateInfo::default()
+                .semaphore_type(vk::SemaphoreType::TIMELINE)
+                .initial_value(0);
+            let create_info = vk::SemaphoreCreateInfo::default().push_next(&mut type_info);
+            Signal::Semaphore(unsafe { device.create_semaphore(&create_info, None) }.unwrap())
+        } else {
+            Signal::Fences(RefCell::new(VecDeque::new()))
+        };
+        Self {
+            device,
+            signal,
+            last_value: Cell::new(0),
+            completed_value: Cell::new(0),
+        }
+    }
+
+    /// The timeline semaphore, None with the fence fallback.
+    pub fn vk_semaphore(&self) -> Option<vk::Semaphore> {
+        match &self.signal {
+            Signal::Semaphore(semaphore) => Some(*semaphore),
+            Signal::Fences(_) => None,
+        }
+    }
+
+    /// Value of the most recent submission, 0 before anything was submitted.
+    pub fn last_value(&self) -> u64 {
+        self.last_value.get()
+    }
+
+    /// Value the next submission will signal, for resources used by work being recorded.
+    pub fn pending_value(&self) -> u64 {
+        self.last_value.get() + 1
+    }
+
+    /// Reserves the next value and returns it with the fence to signal alongside it, which
+    /// is null unless this timeline falls back to fences.
+    pub(crate) fn next_signal(&self) -> (u64, vk::Fence) {
+        let value = self.pending_value();
+        self.last_value.set(value);
+        let fence = match &self.signal {
+            Signal::Semaphore(_) => vk::Fence::null(),
+            Signal::Fences(fences) => {
+                let create_info = vk::FenceCreateInfo::default();
+                let fence = unsafe { self.device.create_fence(&create_info, None) }.unwrap();
+                fences.borrow_mut().push_back((value, fence));
+                fence
+            }
+        };
+        (value, fence)
+    }
+
+    /// Highest value the GPU has finished.
+    pub fn completed_value(&self) -> u64 {
+        match &self.signal {
+            Signal::Semaphore(semaphore) => {
+                unsafe { self.device.get_semaphore_counter_value(*semaphore) }.unwrap()
+            }
+            Signal::Fences(fences) => {
+                let mut fences = fences.borrow_mut();
+                while let Some(&(value, fence)) = fences.front() {
+                    if !unsafe { self.device.get_fence_status(fence) }.unwrap() {
+                        break;
+                    }
+                    unsafe { self.device.destroy_fence(fence, None) };
+                    fences.pop_front();
+                    self.completed_value.set(value);
+                }
+                self.completed_value.get()
+            }
+        }
+    }
+
+    pub fn is_complete(&self, value: u64) -> bool {
+        value <= self.completed_value()
+    }
+
+    /// Blocks until the GPU has finished `value`.
+    pub fn wait(&self, value: u64) {
+        if value == 0 || self.is_complete(value) {
+            return;
+        }
+        match &self.signal {
+            Signal::Semaphore(semaphore) => {
+                let semaphores = [*semaphore];
+                let values = [value];
+                let wait_info = vk::SemaphoreWaitInfo::default()
+                    .semaphores(&semaphores)
+                    .values(&values);
+                unsafe { self.device.wait_semaphores(&wait_info, u64::MAX) }.unwrap();
+            }
+            Signal::Fences(fences) => {
+                //Submissions finish in order, so the first fence at or past `value` is enough
+                let fence = fences
+                    .borrow()
+                    .iter()
+                    .find(|(fence_value, _)| *fence_value >= value)
+                    .map(|(_, fence)| *fence);
+                if let Some(fence) = fence {
+                    unsafe { self.device.wait_for_fences(&[fence], true, u64::MAX) }.unwrap();
+                }
+                self.completed_value();
+            }
+        }
+    }
+
+    /// Waits for the last submission.
+    pub fn wait_idle(&self) {
+        self.wait(self.last_value());
+    }
+
+    pub fn destroy(&self) {
+        match &self.signal {
+            Signal::Semaphore(semaphore) => unsafe {
+                self.device.destroy_semaphore(*semaphore, None)
+            },
+            Signal::Fences(fences) => {
+                for (_, fence) in fences.borrow_mut().drain(..) {
+                    unsafe { self.device.destroy_fence(fence, None) };
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,326 @@
+use ash::{util::read_spv, vk};
+
+use std::{ffi::CString, io::Cursor, sync::Arc};
+
+use super::{CommandBuffer, OutputEncoding, RenderPass, VulkanContext, VulkanFrameCtx};
+
+const SHADER_VERT: &[u8] = include_bytes!("../../../resources/shaders/fullscreen.vert.spv");
+const SHADER_FRAG: &[u8] = include_bytes!("../../../resources/shaders/tonemap.frag.spv");
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tonemapper {
+    None,
+    Reinhard,
+    Aces,
+}
+
+impl Tonemapper {
+    fn shader_value(&self) -> u32 {
+        match self {
+            Tonemapper::None => 0,
+            Tonemapper::Reinhard => 1,
+            Tonemapper::Aces => 2,
+        }
+    }
+}
+
+impl OutputEncoding {
+    fn shader_value(&self) -> u32 {
+        match self {
+            OutputEncoding::SrgbFormat => 0,
+            OutputEncoding::SrgbCurve => 1,
+            OutputEncoding::Hdr10 => 2,
+            OutputEncoding::ScRgb => 3,
+        }
+    }
+}
+
+//Matches the push constant block in tonemap.frag
+#[repr(C)]
+struct TonemapParams {
+    exposure: f32,
+    tonemapper: u32,
+    encoding: u32,
+    paper_white_nits: f32,
+}
+
+/// Fullscreen pass reading the HDR scene color and writing the tonemapped, display
+/// encoded, result to the swapchain image.
+pub struct TonemapPass {
+    context: Arc<VulkanContext>,
+    pub render_pass: RenderPass,
+    framebuffers: Vec<vk::Framebuffer>,
+    extent: vk::Extent2D,
+    encoding: OutputEncoding,
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    desc_layout: vk::DescriptorSetLayout,
+    desc_pool: vk::DescriptorPool,
+    desc_set: vk::DescriptorSet,
+    sampler: vk::Sampler,
+    vert_module: vk::ShaderModule,
+    frag_module: vk::ShaderModule,
+}
+
+impl TonemapPass {
+    pub fn new(context: Arc<VulkanContext>, frame_context: &VulkanFrameCtx) -> Self {
+        let swapchain_format = frame_context.swapchain.format.format;
+        let extent = frame_context.swapchain.get_extent();
+        let render_pass = RenderPass::create_present(context.device.clone(), swapchain_format);
+
+        let framebuffers: Vec<_> = frame_context
+            .swapchain_image_views
+            .iter()
+            .map(|image_view| {
+                let attachments = [*image_view];
+                let create_info = vk::FramebufferCreateInfo::default()
+                    .render_pass(render_pass.get_vk_renderpass())
+                    .attachments(&attachments)
+                    .width(extent.width)
+                    .height(extent.height)
+                    .layers(1);
+
+                unsafe { context.device.create_framebuffer(&create_info, None) }.unwrap()
+            })
+            .collect();
+
+        let desc_layout_bindings = &[vk::DescriptorSetLayoutBinding::default()
+            .binding(0)
+            .descriptor_count(1)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)];
+        let desc_layout_info =
+            vk::DescriptorSetLayoutCreateInfo::default().bindings(desc_layout_bindings);
+        let desc_layout = unsafe {
+            context
+                .device
+                .create_descriptor_set_layout(&desc_layout_info, None)
+        }
+        .unwrap();
+
+        let desc_pool_sizes = &[vk::DescriptorPoolSize::default()
+            .descriptor_count(1)
+            .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)];
+        let desc_pool_info = vk::DescriptorPoolCreateInfo::default()
+            .pool_sizes(desc_pool_sizes)
+            .max_sets(1);
+        let desc_pool =
+            unsafe { context.device.create_descriptor_pool(&desc_pool_info, None) }.unwrap();
+        let desc_layouts = &[desc_layout];
+        let desc_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(desc_pool)
+            .set_layouts(desc_layouts);
+        let desc_set = unsafe { context.device.allocate_descriptor_sets(&desc_info) }.unwrap()[0];
+
+        let sampler_info = vk::SamplerCreateInfo::default()
+            .min_filter(vk::Filter::NEAREST)
+            .mag_filter(vk::Filter::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .max_lod(0.0);
+        let sampler = unsafe { context.device.create_sampler(&sampler_info, None) }.unwrap();
+
+        let image_infos = [vk::DescriptorImageInfo::default()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(frame_context.hdr_render_texture.image_view)
+            .sampler(sampler)];
+        let desc_writes = [vk::WriteDescriptorSet::default()
+            .dst_set(desc_set)
+            .dst_binding(0)
+            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_infos)];
+        unsafe { context.device.update_descriptor_sets(&desc_writes, &[]) };
+
+        let push_constant_ranges = [vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(std::mem::size_of::<TonemapParams>() as u32)];
+        let create_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(desc_layouts)
+            .push_constant_ranges(&push_constant_ranges);
+        let pipeline_layout =
+            unsafe { context.device.create_pipeline_layout(&create_info, None) }.unwrap();
+
+        let vert_decoded = read_spv(&mut Cursor::new(SHADER_VERT)).unwrap();
+        let create_info = vk::ShaderModuleCreateInfo::default().code(&vert_decoded);
+        let vert_module =
+            unsafe { context.device.create_shader_module(&create_info, None) }.unwrap();
+        let frag_decoded = read_spv(&mut Cursor::new(SHADER_FRAG)).unwrap();
+        let create_info = vk::ShaderModuleCreateInfo::default().code(&frag_decoded);
+        let frag_module =
+            unsafe { context.device.create_shader_module(&create_info, None) }.unwrap();
+
+        let pipeline = Self::create_pipeline(
+            &context,
+            pipeline_layout,
+            render_pass.get_vk_renderpass(),
+            vert_module,
+            frag_module,
+        );
+
+        context.set_object_name(render_pass.get_vk_renderpass(), "tonemap pass");
+        for (i, framebuffer) in framebuffers.iter().enumerate() {
+            context.set_object_name(*framebuffer, &format!("tonemap framebuffer {}", i));
+        }
+        context.set_object_name(pipeline, "tonemap pipeline");
+        context.set_object_name(pipeline_layout, "tonemap pipeline layout");
+        context.set_object_name(desc_set, "tonemap descriptor set");
+        context.set_object_name(sampler, "tonemap sampler");
+        context.set_object_name(vert_module, "fullscreen.vert");
+        context.set_object_name(frag_module, "tonemap.frag");
+
+        Self {
+            context,
+            render_pass,
+            framebuffers,
+            extent,
+            encoding: frame_context.swapchain.encoding,
+            pipeline,
+            pipeline_layout,
+            desc_layout,
+            desc_pool,
+            desc_set,
+            sampler,
+            vert_module,
+            frag_module,
+        }
+    }
+
+    fn create_pipeline(
+        context: &VulkanContext,
+        pipeline_layout: vk::PipelineLayout,
+        render_pass: vk::RenderPass,
+        vert_module: vk::ShaderModule,
+        frag_module: vk::ShaderModule,
+    ) -> vk::Pipeline {
+        let entry_point = CString::new("main").unwrap();
+        let shader_stages = [
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .module(vert_module)
+                .name(&entry_point),
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .module(frag_module)
+                .name(&entry_point),
+        ];
+
+        //The fullscreen triangle is generated from the vertex index
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::default();
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::default()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+            .primitive_restart_enable(false);
+        let viewport_state = vk::PipelineViewportStateCreateInfo::default()
+            .viewport_count(1)
+            .scissor_count(1);
+        let rasterizer = vk::PipelineRasterizationStateCreateInfo::default()
+            .polygon_mode(vk::PolygonMode::FILL)
+            .line_width(1.0)
+            .cull_mode(vk::CullModeFlags::NONE);
+        let multisampling = vk::PipelineMultisampleStateCreateInfo::default()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+        let color_blend_attachments = [vk::PipelineColorBlendAttachmentState::default()
+            .color_write_mask(vk::ColorComponentFlags::RGBA)
+            .blend_enable(false)];
+        let color_blending =
+            vk::PipelineColorBlendStateCreateInfo::default().attachments(&color_blend_attachments);
+        let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::default()
+            .depth_test_enable(false)
+            .depth_write_enable(false);
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo::default()
+            .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR]);
+
+        let create_info = vk::GraphicsPipelineCreateInfo::default()
+            .stages(&shader_stages)
+            .vertex_input_state(&vertex_input)
+            .input_assembly_state(&input_assembly)
+            .viewport_state(&viewport_state)
+            .depth_stencil_state(&depth_stencil_state)
+            .rasterization_state(&rasterizer)
+            .multisample_state(&multisampling)
+            .color_blend_state(&color_blending)
+            .dynamic_state(&dynamic_state)
+            .layout(pipeline_layout)
+            .render_pass(render_pass)
+            .subpass(0);
+
+        unsafe {
+            context.device.create_graphics_pipelines(
+                vk::PipelineCache::null(),
+                &[create_info],
+                None,
+            )
+        }
+        .unwrap()[0]
+    }
+
+    pub fn encoding(&self) -> OutputEncoding {
+        self.encoding
+    }
+
+    /// Records the whole pass into `command_buffer`, which must be outside of a render pass.
+    pub fn record(
+        &self,
+        command_buffer: &CommandBuffer,
+        image_index: usize,
+        tonemapper: Tonemapper,
+        exposure: f32,
+        paper_white_nits: f32,
+    ) {
+        let render_area = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.extent,
+        };
+        command_buffer.begin_render_pass(
+            self.framebuffers[image_index],
+            self.render_pass.get_vk_renderpass(),
+            render_area,
+            &[],
+        );
+        command_buffer.bind_pipeline(self.pipeline, vk::PipelineBindPoint::GRAPHICS);
+        command_buffer.bind_descriptor_sets(
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline_layout,
+            &[self.desc_set],
+        );
+        let params = TonemapParams {
+            exposure,
+            tonemapper: tonemapper.shader_value(),
+            encoding: self.encoding.shader_value(),
+            paper_white_nits,
+        };
+        let params_slice = unsafe {
+            std::slice::from_raw_parts(
+                &params as *const TonemapParams as *const u8,
+                std::mem::size_of::<TonemapParams>(),
+            )
+        };
+        command_buffer.push_constants(
+            self.pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            params_slice,
+        );
+        command_buffer.draw_array(3, 1, 0, 0);
+        command_buffer.end_render_pass();
+    }
+
+    pub fn destroy(&mut self) {
+        unsafe {
+            let device = &self.context.device;
+            for &framebuffer in &self.framebuffers {
+                device.destroy_framebuffer(framebuffer, None);
+            }
+            self.render_pass.destroy();
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_shader_module(self.vert_module, None);
+            device.destroy_shader_module(self.frag_module, None);
+            device.destroy_sampler(self.sampler, None);
+            device.destroy_descriptor_pool(self.desc_pool, None);
+            device.destroy_descriptor_set_layout(self.desc_layout, None);
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use ash::vk;
+
+use std::{
+    ffi::{c_void, CStr},
+    fmt::Write,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+/// A validation message as reported through VK_EXT_debug_utils.
+#[derive(Clone, Debug)]
+pub struct ValidationMessage {
+    pub severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    pub message_id_name: String,
+    pub message_id_number: i32,
+    pub message: String,
+    /// Type, raw handle and debug name of the objects the message refers to
+    pub objects: Vec<(vk::ObjectType, u64, Option<String>)>,
+}
+
+/// Counts, and optionally captures, the messages of the validation layers so that
+/// tests can assert that a frame produced no validation errors.
+#[derive(Default)]
+pub struct ValidationLog {
+    errors: AtomicUsize,
+    warnings: AtomicUsize,
+    capture: AtomicBool,
+    captured: Mutex<Vec<ValidationMessage>>,
+}
+
+impl ValidationLog {
+    pub fn error_count(&self) -> usize {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    pub fn warning_count(&self) -> usize {
+        self.warnings.load(Ordering::Relaxed)
+    }
+
+    pub fn reset_counts(&self) {
+        self.errors.store(0, Ordering::Relaxed);
+        self.warnings.store(0, Ordering::Relaxed);
+    }
+
+    /// When enabled, every message is kept until `take_captured` is called.
+    pub fn set_capture(&self, capture: bool) {
+        self.capture.store(capture, Ordering::Relaxed);
+    }
+
+    pub fn take_captured(&self) -> Vec<ValidationMessage> {
+        std::mem::take(&mut *self.captured.lock().unwrap())
+    }
+
+    fn record(&self, message: &ValidationMessage) {
+        if message
+            .severity
+            .contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR)
+        {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        } else if message
+            .severity
+            .contains(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING)
+        {
+            self.warnings.fetch_add(1, Ordering::Relaxed);
+        }
+        if self.capture.load(Ordering::Relaxed) {
+            self.captured.lock().unwrap().push(message.clone());
+        }
+    }
+}
+
+fn log_level(severity: vk::DebugUtilsMessageSeverityFlagsEXT) -> log::Level {
+    if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+        log::Level::Error
+    } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING) {
+        log::Level::Warn
+    } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::INFO) {
+        log::Level::Debug
+    } else {
+        log::Level::Trace
+    }
+}
+
+//Lets RUST_LOG filter e.g. performance warnings separately from validation
+fn log_target(message_type: vk::DebugUtilsMessageTypeFlagsEXT) -> &'static str {
+    if message_type.contains(vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION) {
+        "katla_vulkan::validation"
+    } else if message_type.contains(vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE) {
+        "katla_vulkan::performance"
+    } else {
+        "katla_vulkan::general"
+    }
+}
+
+unsafe fn to_string(ptr: *const std::os::raw::c_char) -> String {
+    if ptr.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(ptr).to_string_lossy().into_owned()
+    }
+}
+
+/// `p_user_data` has to point to the `ValidationLog` owned by the context.
+pub(crate) unsafe extern "system" fn debug_callback(
+    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_types: vk::DebugUtilsMessageTypeFlagsEXT,
+    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
+    p_user_data: *mut c_void,
+) -> vk::Bool32 {
+    let callback_data = &*p_callback_data;
+    let objects = if callback_data.p_objects.is_null() {
+        vec![]
+    } else {
+        std::slice::from_raw_parts(callback_data.p_objects, callback_data.object_count as usize)
+            .iter()
+            .map(|object| {
+                let name = if object.p_object_name.is_null() {
+                    None
+                } else {
+                    Some(to_string(object.p_object_name))
+                };
+                (object.object_type, object.object_handle, name)
+            })
+            .collect()
+    };
+    let message = ValidationMessage {
+        severity: message_severity,
+        message_type: message_types,
+        message_id_name: to_string(callback_data.p_message_id_name),
+        message_id_number: callback_data.message_id_number,
+        message: to_string(callback_data.p_message),
+        objects,
+    };
+
+    let mut objects_field = String::new();
+    for (object_type, handle, name) in &message.objects {
+        if !objects_field.is_empty() {
+            objects_field.push_str(", ");
+        }
+        write!(objects_field, "{:?} 0x{:x}", object_type, handle).unwrap();
+        if let Some(name) = name {
+            write!(objects_field, " \"{}\"", name).unwrap();
+        }
+    }
+    log::log!(
+        target: log_target(message_types),
+        log_level(message_severity),
+        message_id = message.message_id_name.as_str(),
+        message_id_number = message.message_id_number,
+        objects = objects_field.as_str();
+        "[{}] {}",
+        message.message_id_name,
+        message.message
+    );
+
+    if let Some(validation_log) = (p_user_data as *const ValidationLog).as_ref() {
+        validation_log.record(&message);
+    }
+
+    vk::FALSE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+
+    const SEVERITIES: [vk::DebugUtilsMessageSeverityFlagsEXT; 4] = [
+        vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+        vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
+        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+    ];
+
+    fn send(log: &ValidationLog, severity: vk::DebugUtilsMessageSeverityFlagsEXT, text: &str) {
+        let object_name = CString::new("vertex buffer").unwrap();
+        let message_id_name = CString::new("VUID-test").unwrap();
+        let text = CString::new(text).unwrap();
+        let objects = [vk::DebugUtilsObjectNameInfoEXT {
+            object_type: vk::ObjectType::BUFFER,
+            object_handle: 0x1234,
+            p_object_name: object_name.as_ptr(),
+            ..Default::default()
+        }];
+        let callback_data = vk::DebugUtilsMessengerCallbackDataEXT::default()
+            .message_id_name(&message_id_name)
+            .message_id_number(42)
+            .message(&text)
+            .objects(&objects);
+        let result = unsafe {
+            debug_callback(
+                severity,
+                vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+                &callback_data,
+                log as *const ValidationLog as *mut c_void,
+            )
+        };
+        assert_eq!(result, vk::FALSE);
+    }
+
+    #[test]
+    fn test_counts() {
+        let log = ValidationLog::default();
+        for severity in SEVERITIES {
+            send(&log, severity, "message");
+        }
+        send(
+            &log,
+            vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            "another error",
+        );
+        assert_eq!(log.error_count(), 2);
+        assert_eq!(log.warning_count(), 1);
+        //Nothing is kept without capture
+        assert!(log.take_captured().is_empty());
+
+        log.reset_counts();
+        assert_eq!(log.error_count(), 0);
+        assert_eq!(log.warning_count(), 0);
+    }
+
+    #[test]
+    fn test_capture() {
+        let log = ValidationLog::default();
+        log.set_capture(true);
+        let texts = ["verbose", "info", "warning", "error"];
+        for (severity, text) in SEVERITIES.iter().zip(texts) {
+            send(&log, *severity, text);
+        }
+        let captured = log.take_captured();
+        assert_eq!(captured.len(), 4);
+        for ((message, severity), text) in captured.iter().zip(SEVERITIES).zip(texts) {
+            assert_eq!(message.severity, severity);
+            assert_eq!(
+                message.message_type,
+                vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+            );
+            assert_eq!(message.message, text);
+            assert_eq!(message.message_id_name, "VUID-test");
+            assert_eq!(message.message_id_number, 42);
+            assert_eq!(
+                message.objects,
+                [(
+                    vk::ObjectType::BUFFER,
+                    0x1234,
+                    Some("vertex buffer".to_owned())
+                )]
+            );
+        }
+        //Taking empties the capture
+        assert!(log.take_captured().is_empty());
+
+        log.set_capture(false);
+        send(&log, vk::DebugUtilsMessageSeverityFlagsEXT::ERROR, "error");
+        assert!(log.take_captured().is_empty());
+        assert_eq!(log.error_count(), 2);
+    }
+}
@@ -45,6 +45,8 @@ impl VertexFormat {
 }
 pub struct VertexBinding {
     pub formats: Vec<VertexFormat>,
+    /// `INSTANCE` advances the binding once per instance instead of once per vertex
+    pub input_rate: vk::VertexInputRate,
 }
 
 impl VertexBinding {
@@ -56,12 +58,21 @@ impl VertexBinding {
         vk::VertexInputBindingDescription::default()
             .binding(binding)
             .stride(self.get_stride())
-            .input_rate(vk::VertexInputRate::VERTEX)
+            .input_rate(self.input_rate)
     }
 
-    pub fn get_attribute_desc(&self, binding: u32) -> Vec<vk::VertexInputAttributeDescription> {
+    pub fn num_locations(&self) -> u32 {
+        self.formats.len() as u32
+    }
+
+    //Locations are consecutive, starting at first_location so several bindings can be combined
+    pub fn get_attribute_desc(
+        &self,
+        binding: u32,
+        first_location: u32,
+    ) -> Vec<vk::VertexInputAttributeDescription> {
         let mut current_offset = 0;
-        let mut location = 0;
+        let mut location = first_location;
         self.formats
             .iter()
             .map(|format| {
@@ -33,6 +33,10 @@ pub struct IndexBuffer {
 }
 
 impl BufferObject {
+    fn set_name(&self, name: &str) {
+        self.context.set_object_name(self.buffer, name);
+    }
+
     fn upload_data(&mut self, data: &[u8]) {
         let data_size = std::mem::size_of_val(data) as vk::DeviceSize;
         if self.buf_size < data_size {
@@ -83,6 +87,10 @@ impl IndexBuffer {
         self.buffer.upload_data(data);
     }
 
+    pub fn set_name(&self, name: &str) {
+        self.buffer.set_name(name);
+    }
+
     pub fn object(&self) -> vk::Buffer {
         self.buffer.buffer
     }
@@ -124,4 +132,82 @@ impl VertexBuffer {
     pub fn upload_data(&mut self, data: &[u8]) {
         self.buffer.upload_data(data);
     }
+
+    pub fn set_name(&self, name: &str) {
+        self.buffer.set_name(name);
+    }
+}
+
+/// Buffer that shaders read and write, e.g. compute output or indirect draw arguments.
+pub struct StorageBuffer {
+    buffer: BufferObject,
+}
+
+impl StorageBuffer {
+    /// `usage` is added to STORAGE_BUFFER, e.g. INDIRECT_BUFFER or VERTEX_BUFFER.
+    /// Only CpuToGpu and GpuToCpu buffers can be uploaded to or read back.
+    pub fn new(
+        context: Arc<VulkanContext>,
+        buf_size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        location: gpu_allocator::MemoryLocation,
+    ) -> Self {
+        let create_info = vk::BufferCreateInfo::default()
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .usage(vk::BufferUsageFlags::STORAGE_BUFFER | usage)
+            .size(buf_size);
+        let (buffer, allocation) = context.allocate_buffer(&create_info, location);
+
+        Self {
+            buffer: BufferObject {
+                allocation: Some(allocation),
+                buffer,
+                buf_size,
+                count: 0,
+                context,
+            },
+        }
+    }
+
+    /// Buffer that shaders only read, filled with `data` once.
+    pub fn from_data(context: Arc<VulkanContext>, data: &[u8]) -> Self {
+        let mut buffer = Self::new(
+            context,
+            data.len() as vk::DeviceSize,
+            vk::BufferUsageFlags::empty(),
+            gpu_allocator::MemoryLocation::CpuToGpu,
+        );
+        buffer.upload_data(data);
+        buffer
+    }
+
+    pub fn upload_data(&mut self, data: &[u8]) {
+        self.buffer.upload_data(data);
+    }
+
+    /// Copies the buffer contents into `data`, the GPU must be done writing to it.
+    pub fn read_data(&self, data: &mut [u8]) {
+        let data_size = std::mem::size_of_val(data) as vk::DeviceSize;
+        if self.buffer.buf_size < data_size {
+            panic!("Reading {} bytes from a smaller buffer", data_size);
+        }
+        if let Some(allocation) = &self.buffer.allocation {
+            let mapped_ptr = self.buffer.context.map_buffer(allocation);
+            unsafe {
+                std::ptr::copy_nonoverlapping(mapped_ptr, data.as_mut_ptr(), data_size as usize);
+            }
+        }
+    }
+
+    pub fn set_name(&self, name: &str) {
+        self.buffer.set_name(name);
+    }
+
+    pub fn object(&self) -> vk::Buffer {
+        self.buffer.buffer
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.buffer.buf_size
+    }
 }
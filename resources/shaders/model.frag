@@ -1,68 +1,96 @@
 #version 450
 
-layout(binding=1) uniform sampler2D albedo_sampler;
-// layout(binding=1) uniform sampler2D normal_sampler;
-// layout(binding=2) uniform sampler2D roughness_sampler;
-// layout(binding=3) uniform sampler2D emissive_sampler;
+//Base color, metallic-roughness, normal, occlusion and emissive. Materials without one of
+//them get a 1x1 default, white or a flat normal.
+layout(binding=1) uniform sampler2D material_textures[5];
 
-const vec3 light_color = vec3(1.0, 1.0, 1.0);
-const float spec_strength = 0.5;
+layout(set = 0, binding = 2) uniform MaterialParams {
+    vec4 base_color_factor;
+    vec3 emissive_factor;
+    float alpha_cutoff;
+    float metallic_factor;
+    float roughness_factor;
+    float normal_scale;
+    float occlusion_strength;
+    uint alpha_mode;
+} material;
+
+const uint BASE_COLOR = 0;
+const uint METALLIC_ROUGHNESS = 1;
+const uint NORMAL = 2;
+const uint OCCLUSION = 3;
+const uint EMISSIVE = 4;
+
+const uint ALPHA_OPAQUE = 0;
+const uint ALPHA_MASK = 1;
+const uint ALPHA_BLEND = 2;
 
-// layout(set = 0, binding = 0) uniform FragData {
-//     uint u_texVis;
-//     vec3 u_camPos;
-//     vec3 u_lightpos;
-// } modeldatas;
+const float PI = 3.14159265359;
+const vec3 light_color = vec3(1.0, 1.0, 1.0);
+//Direction towards the light in world space
+const vec3 light_dir = vec3(0.4, 0.8, 0.45);
+const float ambient_strength = 0.2;
 
 layout(location=0) in vec3 vs_pos;
 layout(location=1) in vec2 tex_coords;
-layout(location=2) in vec3 vs_norm;
-// layout(location=2) in mat3 vs_TBN;
+layout(location=2) in mat3 vs_TBN;
+layout(location=5) in vec3 vs_cam_pos;
 
 layout(location=0) out vec4 out_col;
 
-void main()
+float distribution_ggx(float n_dot_h, float alpha)
 {
-    vec4 color = texture(albedo_sampler, tex_coords);
-    out_col = vec4(color.rgb, 1.0);
+    float alpha_sq = alpha * alpha;
+    float denom = n_dot_h * n_dot_h * (alpha_sq - 1.0) + 1.0;
+    return alpha_sq / (PI * denom * denom);
+}
 
-    // vec3 ambient_color = 0.1 * light_color;
+float visibility_smith(float n_dot_l, float n_dot_v, float alpha)
+{
+    float alpha_sq = alpha * alpha;
+    float ggx_l = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - alpha_sq) + alpha_sq);
+    float ggx_v = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - alpha_sq) + alpha_sq);
+    return 0.5 / max(ggx_l + ggx_v, 1e-5);
+}
+
+void main()
+{
+    vec4 base_color = texture(material_textures[BASE_COLOR], tex_coords) * material.base_color_factor;
+    if (material.alpha_mode == ALPHA_MASK && base_color.a < material.alpha_cutoff) {
+        discard;
+    }
 
-    // vec3 light_dir   = normalize(modeldatas.u_lightpos - vs_pos);
-    // vec3 view_dir    = normalize(modeldatas.u_camPos - vs_pos);
-    // vec3 halfway_dir = normalize(light_dir + view_dir);
+    vec4 metallic_roughness = texture(material_textures[METALLIC_ROUGHNESS], tex_coords);
+    float metallic = clamp(metallic_roughness.b * material.metallic_factor, 0.0, 1.0);
+    float roughness = clamp(metallic_roughness.g * material.roughness_factor, 0.04, 1.0);
+    float alpha = roughness * roughness;
 
-    // vec3 normalVector = vs_TBN * normalize(
-    //     (texture(normal_sampler, tex_coords) * 2.0 - 1.0).rgb
-    // );
+    vec3 tangent_normal = texture(material_textures[NORMAL], tex_coords).rgb * 2.0 - 1.0;
+    tangent_normal.xy *= material.normal_scale;
+    vec3 n = normalize(vs_TBN * normalize(tangent_normal));
+    //Double sided materials light back faces with the flipped normal, flipped after the
+    //normal map so the perturbation is not mirrored
+    if (!gl_FrontFacing) {
+        n = -n;
+    }
+    vec3 v = normalize(vs_cam_pos - vs_pos);
+    vec3 l = normalize(light_dir);
+    vec3 h = normalize(l + v);
+    float n_dot_l = max(dot(n, l), 0.0);
+    float n_dot_v = max(dot(n, v), 1e-4);
+    float n_dot_h = max(dot(n, h), 0.0);
+    float v_dot_h = max(dot(v, h), 0.0);
 
-    // float diff = max(dot(normalVector, light_dir), 0.0);
-    // vec3 diffuse_color = diff * light_color;
+    vec3 f0 = mix(vec3(0.04), base_color.rgb, metallic);
+    vec3 fresnel = f0 + (1.0 - f0) * pow(1.0 - v_dot_h, 5.0);
+    vec3 specular = fresnel * distribution_ggx(n_dot_h, alpha) * visibility_smith(n_dot_l, n_dot_v, alpha);
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - metallic) * base_color.rgb / PI;
+    vec3 color = (diffuse + specular) * light_color * n_dot_l * PI;
 
+    float occlusion = texture(material_textures[OCCLUSION], tex_coords).r;
+    occlusion = 1.0 + material.occlusion_strength * (occlusion - 1.0);
+    color += ambient_strength * base_color.rgb * occlusion;
+    color += texture(material_textures[EMISSIVE], tex_coords).rgb * material.emissive_factor;
 
-    // vec3 reflect_dir = reflect(-light_dir, normalVector);
-    // float spec = pow(max(dot(view_dir, reflect_dir), 0.0), 4);
-    // vec3 specular = spec_strength * spec * light_color;
-    
-    // vec3 light_color_factor = specular + diffuse_color + ambient_color;
-    // if(modeldatas.u_texVis == 1){
-    //     vec4 color = texture(albedo_sampler, tex_coords);
-    //     out_col = color;
-    // }else if(modeldatas.u_texVis == 2){
-    //     out_col = vec4(normalVector, 1.0);
-    // }else if(modeldatas.u_texVis == 3){
-    //     vec4 color = texture(roughness_sampler, tex_coords);
-    //     out_col = color;
-    // }else if(modeldatas.u_texVis == 4){
-    //     vec3 normal = vs_TBN[2];
-    //     vec3 normal_centered = (normal * 0.5) + 0.5;
-    //     out_col = vec4(normal_centered, 1.0);
-    // }else if(modeldatas.u_texVis == 5){
-    //     vec3 tangent = vs_TBN[0];
-    //     vec3 tangent_centered = (tangent * 0.5) + 0.5;
-    //     out_col = vec4(tangent_centered, 1.0);
-    // }else {
-    //     vec4 color = texture(albedo_sampler, tex_coords);
-    //     out_col = vec4(light_color_factor, 1.0) * color;
-    // }
+    out_col = vec4(color, material.alpha_mode == ALPHA_BLEND ? base_color.a : 1.0);
 }
@@ -0,0 +1,47 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+layout(location=0) in vec3 vert_position;
+layout(location=1) in vec3 vert_normal;
+layout(location=2) in vec4 vert_tangent;
+layout(location=3) in vec2 vert_texcoord0;
+//Per instance, occupies locations 4-7
+layout(location=4) in mat4 instance_world;
+
+layout(set = 0, binding = 0) uniform Data {
+    mat4 world;
+    mat4 view;
+    mat4 proj;
+} uniforms;
+
+#ifdef MORPH_TARGETS
+#define MORPH_LOCATION 8
+#define MORPH_WEIGHTS_BINDING 3
+#define MORPH_DELTAS_BINDING 4
+#include "morph.glsl"
+#endif
+
+layout(location=0) out vec3 vs_pos;
+layout(location=1) out vec2 tex_coords;
+//Occupies locations 2-4
+layout(location=2) out mat3 vs_TBN;
+layout(location=5) out vec3 vs_cam_pos;
+void main()
+{
+    vec3 position = vert_position;
+    vec3 normal = vert_normal;
+    vec3 tangent = vert_tangent.xyz;
+#ifdef MORPH_TARGETS
+    apply_morph_targets(position, normal, tangent);
+#endif
+    mat4 world = uniforms.world * instance_world;
+    vec3 vs_tangent = normalize((world * vec4(tangent, 0.0)).xyz);
+    vec3 vs_normal = normalize((world * vec4(normal, 0.0)).xyz);
+    vs_tangent = normalize(vs_tangent - dot(vs_tangent, vs_normal) * vs_normal);
+    vec3 vs_bitangent = cross(vs_normal, vs_tangent) * vert_tangent.w;
+    vs_TBN = mat3(vs_tangent, vs_bitangent, vs_normal);
+
+    vs_pos = (world * vec4(position, 1.0)).xyz;
+    tex_coords = vert_texcoord0;
+    vs_cam_pos = inverse(uniforms.view)[3].xyz;
+    gl_Position = uniforms.proj * uniforms.view * world * vec4(position, 1.0);
+}
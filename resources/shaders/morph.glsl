@@ -0,0 +1,52 @@
+//Morph targets for model vertex shaders compiled with MORPH_TARGETS, which define
+//MORPH_LOCATION, MORPH_WEIGHTS_BINDING and MORPH_DELTAS_BINDING before including this
+//
+//Both variants are built from the same source, run from resources/shaders:
+//  glslangValidator -V model_pbr_instanced.vert -o model_pbr_instanced.vert.spv
+//  glslangValidator -V -DMORPH_TARGETS model_pbr_instanced.vert -o model_pbr_instanced_morph.vert.spv
+//  glslangValidator -V model_pbr_skinned.vert -o model_pbr_skinned.vert.spv
+//  glslangValidator -V -DMORPH_TARGETS model_pbr_skinned.vert -o model_pbr_skinned_morph.vert.spv
+
+//Matches MAX_ACTIVE_MORPH_TARGETS in vertextypes.rs
+#define MAX_ACTIVE_MORPH_TARGETS 8
+
+struct NodeMorphWeights {
+    uint targets[MAX_ACTIVE_MORPH_TARGETS];
+    float weights[MAX_ACTIVE_MORPH_TARGETS];
+};
+
+struct MorphDelta {
+    vec4 position;
+    vec4 normal;
+    vec4 tangent;
+};
+
+//Indexed by node, which is the instance index
+layout(std430, set = 0, binding = MORPH_WEIGHTS_BINDING) readonly buffer MorphWeights {
+    NodeMorphWeights node_morphs[];
+};
+
+layout(std430, set = 0, binding = MORPH_DELTAS_BINDING) readonly buffer MorphDeltas {
+    MorphDelta morph_deltas[];
+};
+
+//First delta, stride between targets and target count
+layout(location=MORPH_LOCATION) in uvec3 vertex_morph;
+
+void apply_morph_targets(inout vec3 position, inout vec3 normal, inout vec3 tangent)
+{
+    if (vertex_morph.z == 0) {
+        return;
+    }
+    for (int i = 0; i < MAX_ACTIVE_MORPH_TARGETS; i++) {
+        uint target = node_morphs[gl_InstanceIndex].targets[i];
+        float weight = node_morphs[gl_InstanceIndex].weights[i];
+        if (weight == 0.0 || target >= vertex_morph.z) {
+            continue;
+        }
+        MorphDelta delta = morph_deltas[vertex_morph.x + target * vertex_morph.y];
+        position += weight * delta.position.xyz;
+        normal += weight * delta.normal.xyz;
+        tangent += weight * delta.tangent.xyz;
+    }
+}
@@ -0,0 +1,86 @@
+#version 450
+
+layout(binding=0) uniform sampler2D hdr_sampler;
+
+layout(push_constant) uniform TonemapParams {
+    float exposure;
+    uint tonemapper;
+    uint encoding;
+    float paper_white_nits;
+} params;
+
+const uint TONEMAPPER_NONE = 0;
+const uint TONEMAPPER_REINHARD = 1;
+const uint TONEMAPPER_ACES = 2;
+
+const uint ENCODING_SRGB_FORMAT = 0;
+const uint ENCODING_SRGB_CURVE = 1;
+const uint ENCODING_HDR10 = 2;
+const uint ENCODING_SCRGB = 3;
+
+layout(location=0) in vec2 tex_coords;
+
+layout(location=0) out vec4 out_col;
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+vec3 tonemap_aces(vec3 x)
+{
+    const float a = 2.51;
+    const float b = 0.03;
+    const float c = 2.43;
+    const float d = 0.59;
+    const float e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), 0.0, 1.0);
+}
+
+vec3 tonemap_reinhard(vec3 x)
+{
+    return x / (1.0 + x);
+}
+
+vec3 srgb_curve(vec3 linear)
+{
+    vec3 lower = linear * 12.92;
+    vec3 higher = 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055;
+    return mix(higher, lower, vec3(lessThanEqual(linear, vec3(0.0031308))));
+}
+
+// SMPTE ST 2084, input in nits
+vec3 pq_curve(vec3 nits)
+{
+    const float m1 = 0.1593017578125;
+    const float m2 = 78.84375;
+    const float c1 = 0.8359375;
+    const float c2 = 18.8515625;
+    const float c3 = 18.6875;
+    vec3 y = pow(clamp(nits / 10000.0, 0.0, 1.0), vec3(m1));
+    return pow((c1 + c2 * y) / (1.0 + c3 * y), vec3(m2));
+}
+
+const mat3 REC709_TO_REC2020 = mat3(
+    0.6274, 0.0691, 0.0164,
+    0.3293, 0.9195, 0.0880,
+    0.0433, 0.0114, 0.8956
+);
+
+void main()
+{
+    vec3 color = texture(hdr_sampler, tex_coords).rgb * params.exposure;
+
+    if (params.tonemapper == TONEMAPPER_ACES) {
+        color = tonemap_aces(color);
+    } else if (params.tonemapper == TONEMAPPER_REINHARD) {
+        color = tonemap_reinhard(color);
+    }
+
+    if (params.encoding == ENCODING_SRGB_CURVE) {
+        color = srgb_curve(clamp(color, 0.0, 1.0));
+    } else if (params.encoding == ENCODING_HDR10) {
+        color = pq_curve(REC709_TO_REC2020 * color * params.paper_white_nits);
+    } else if (params.encoding == ENCODING_SCRGB) {
+        // scRGB defines 1.0 as 80 nits
+        color = color * (params.paper_white_nits / 80.0);
+    }
+
+    out_col = vec4(color, 1.0);
+}
@@ -0,0 +1,381 @@
+use katla_math::{Quat, Transform, Vec3};
+
+use crate::util::{AnimationClip, AnimationProperty, ModelNode};
+
+//Playback position in one clip
+#[derive(Clone, Copy, Debug)]
+struct ClipState {
+    clip: usize,
+    time: f32,
+}
+
+//The clip fading out during a crossfade
+#[derive(Clone, Copy, Debug)]
+struct Fade {
+    from: ClipState,
+    elapsed: f32,
+    duration: f32,
+}
+
+//Transform and morph target weights of every node
+struct Pose {
+    transforms: Vec<Transform>,
+    weights: Vec<Vec<f32>>,
+}
+
+/// Plays the animation clips of a model on its nodes, one clip at a time with optional
+/// crossfades between them. Nodes a clip does not animate keep their transform from the file.
+pub struct AnimationPlayer {
+    clips: Vec<AnimationClip>,
+    rest: Pose,
+    current: Option<ClipState>,
+    fade: Option<Fade>,
+    /// Wraps around at the end of the clip, otherwise playback holds the last keyframe
+    pub looping: bool,
+    /// Playback rate, negative plays backwards
+    pub speed: f32,
+    pub paused: bool,
+    //Reused for every sampled channel
+    sample: Vec<f32>,
+}
+
+impl AnimationPlayer {
+    pub fn new(clips: Vec<AnimationClip>, nodes: &[ModelNode]) -> Self {
+        Self {
+            clips,
+            rest: Pose {
+                transforms: nodes.iter().map(|node| node.transform.clone()).collect(),
+                weights: nodes.iter().map(|node| node.weights.clone()).collect(),
+            },
+            current: None,
+            fade: None,
+            looping: true,
+            speed: 1.0,
+            paused: false,
+            sample: vec![],
+        }
+    }
+
+    pub fn clips(&self) -> &[AnimationClip] {
+        &self.clips
+    }
+
+    /// Index of the first clip called `name`.
+    pub fn find_clip(&self, name: &str) -> Option<usize> {
+        self.clips
+            .iter()
+            .position(|clip| clip.name.as_deref() == Some(name))
+    }
+
+    pub fn current_clip(&self) -> Option<usize> {
+        self.current.map(|state| state.clip)
+    }
+
+    /// Playback position in the current clip, in seconds.
+    pub fn time(&self) -> f32 {
+        self.current.map_or(0.0, |state| state.time)
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.current.is_some() && !self.paused
+    }
+
+    /// Starts `clip` from its beginning, or from its end when playing backwards.
+    pub fn play(&mut self, clip: usize) {
+        self.fade = None;
+        self.current = Some(self.start(clip));
+    }
+
+    /// Starts `clip` while the current clip fades out over `duration` seconds. A clip that
+    /// was already fading out is dropped.
+    pub fn crossfade(&mut self, clip: usize, duration: f32) {
+        match self.current {
+            Some(from) if duration > 0.0 => {
+                self.fade = Some(Fade {
+                    from,
+                    elapsed: 0.0,
+                    duration,
+                });
+                self.current = Some(self.start(clip));
+            }
+            _ => self.play(clip),
+        }
+    }
+
+    /// Stops playback, the nodes keep their last pose.
+    pub fn stop(&mut self) {
+        self.current = None;
+        self.fade = None;
+    }
+
+    /// Moves the current clip to `time` seconds.
+    pub fn seek(&mut self, time: f32) {
+        if let Some(mut state) = self.current {
+            state.time = self.wrap(state.clip, time);
+            self.current = Some(state);
+        }
+    }
+
+    /// Advances playback by `dt` seconds, scaled by `speed`. Crossfades advance unscaled.
+    pub fn update(&mut self, dt: f32) {
+        if self.paused {
+            return;
+        }
+        if let Some(mut state) = self.current {
+            state.time = self.wrap(state.clip, state.time + dt * self.speed);
+            self.current = Some(state);
+        }
+        if let Some(mut fade) = self.fade {
+            fade.from.time = self.wrap(fade.from.clip, fade.from.time + dt * self.speed);
+            fade.elapsed += dt;
+            self.fade = if fade.elapsed < fade.duration {
+                Some(fade)
+            } else {
+                None
+            };
+        }
+    }
+
+    /// Writes the pose at the playback position to `nodes`, false when there is no clip
+    /// and the nodes are left as they are.
+    pub fn apply(&mut self, nodes: &mut [ModelNode]) -> bool {
+        let current = match self.current {
+            Some(current) => current,
+            None => return false,
+        };
+        let pose = self.sample_pose(current);
+        let pose = match self.fade {
+            Some(fade) => {
+                let from = self.sample_pose(fade.from);
+                Self::blend(from, pose, fade.elapsed / fade.duration)
+            }
+            None => pose,
+        };
+        for ((node, transform), weights) in nodes.iter_mut().zip(pose.transforms).zip(pose.weights)
+        {
+            node.transform = transform;
+            node.weights = weights;
+        }
+        true
+    }
+
+    fn start(&self, clip: usize) -> ClipState {
+        let time = if self.speed < 0.0 {
+            self.clips[clip].duration
+        } else {
+            0.0
+        };
+        ClipState { clip, time }
+    }
+
+    fn wrap(&self, clip: usize, time: f32) -> f32 {
+        let duration = self.clips[clip].duration;
+        if duration <= 0.0 {
+            0.0
+        } else if self.looping {
+            time.rem_euclid(duration)
+        } else {
+            time.clamp(0.0, duration)
+        }
+    }
+
+    fn sample_pose(&mut self, state: ClipState) -> Pose {
+        let mut pose = Pose {
+            transforms: self.rest.transforms.clone(),
+            weights: self.rest.weights.clone(),
+        };
+        for channel in &self.clips[state.clip].channels {
+            if channel.node >= pose.transforms.len() {
+                continue;
+            }
+            channel.sample(state.time, &mut self.sample);
+            let value = &self.sample;
+            let transform = &mut pose.transforms[channel.node];
+            match channel.property {
+                AnimationProperty::Translation => {
+                    transform.position = Vec3([value[0], value[1], value[2]]);
+                }
+                AnimationProperty::Rotation => {
+                    transform.rotation =
+                        Quat::new_from_xyzw(value[0], value[1], value[2], value[3]);
+                }
+                AnimationProperty::Scale => {
+                    transform.scale = Vec3([value[0], value[1], value[2]]);
+                }
+                AnimationProperty::MorphWeights => {
+                    pose.weights[channel.node] = value.clone();
+                }
+            }
+        }
+        pose
+    }
+
+    //`from` at ratio 0, `to` at ratio 1
+    fn blend(from: Pose, to: Pose, ratio: f32) -> Pose {
+        let transforms = from
+            .transforms
+            .iter()
+            .zip(&to.transforms)
+            .map(|(from, to)| Transform {
+                position: Vec3::lerp(from.position, to.position, ratio),
+                scale: Vec3::lerp(from.scale, to.scale, ratio),
+                rotation: Quat::slerp(from.rotation, to.rotation, ratio),
+            })
+            .collect();
+        //Weights missing on one side count as 0
+        let weights = from
+            .weights
+            .iter()
+            .zip(&to.weights)
+            .map(|(from, to)| {
+                (0..from.len().max(to.len()))
+                    .map(|i| {
+                        let from = from.get(i).copied().unwrap_or(0.0);
+                        let to = to.get(i).copied().unwrap_or(0.0);
+                        from + (to - from) * ratio
+                    })
+                    .collect()
+            })
+            .collect();
+        Pose {
+            transforms,
+            weights,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{AnimationChannel, Interpolation};
+    use approx::assert_relative_eq;
+
+    fn node(weights: Vec<f32>) -> ModelNode {
+        ModelNode {
+            name: None,
+            parent: None,
+            children: vec![],
+            transform: Transform::new(),
+            submeshes: vec![],
+            weights,
+        }
+    }
+
+    //Moves node 0 along x from `from` to `to` over `duration` seconds
+    fn translation_clip(from: f32, to: f32, duration: f32) -> AnimationClip {
+        AnimationClip {
+            name: None,
+            channels: vec![AnimationChannel {
+                node: 0,
+                property: AnimationProperty::Translation,
+                interpolation: Interpolation::Linear,
+                times: vec![0.0, duration],
+                values: vec![from, 0.0, 0.0, to, 0.0, 0.0],
+                components: 3,
+            }],
+            duration,
+        }
+    }
+
+    fn weights_clip(weights: Vec<f32>) -> AnimationClip {
+        AnimationClip {
+            name: None,
+            channels: vec![AnimationChannel {
+                node: 0,
+                property: AnimationProperty::MorphWeights,
+                interpolation: Interpolation::Step,
+                times: vec![0.0],
+                components: weights.len(),
+                values: weights,
+            }],
+            duration: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_apply_without_clip() {
+        let mut nodes = vec![node(vec![])];
+        let mut player = AnimationPlayer::new(vec![translation_clip(0.0, 1.0, 1.0)], &nodes);
+        assert!(!player.apply(&mut nodes));
+        player.play(0);
+        assert!(player.apply(&mut nodes));
+    }
+
+    #[test]
+    fn test_negative_speed_wraps() {
+        let nodes = vec![node(vec![])];
+        let mut player = AnimationPlayer::new(vec![translation_clip(0.0, 1.0, 2.0)], &nodes);
+        player.speed = -1.0;
+        player.play(0);
+        assert_relative_eq!(player.time(), 2.0);
+        player.update(0.5);
+        assert_relative_eq!(player.time(), 1.5);
+        player.update(2.0);
+        assert_relative_eq!(player.time(), 1.5);
+    }
+
+    #[test]
+    fn test_no_looping_holds_the_end() {
+        let nodes = vec![node(vec![])];
+        let mut player = AnimationPlayer::new(vec![translation_clip(0.0, 1.0, 2.0)], &nodes);
+        player.looping = false;
+        player.play(0);
+        player.update(3.0);
+        assert_relative_eq!(player.time(), 2.0);
+        player.speed = -1.0;
+        player.update(5.0);
+        assert_relative_eq!(player.time(), 0.0);
+    }
+
+    #[test]
+    fn test_seek() {
+        let nodes = vec![node(vec![])];
+        let mut player = AnimationPlayer::new(vec![translation_clip(0.0, 1.0, 2.0)], &nodes);
+        player.play(0);
+        player.seek(5.0);
+        assert_relative_eq!(player.time(), 1.0);
+        player.seek(-0.5);
+        assert_relative_eq!(player.time(), 1.5);
+
+        player.looping = false;
+        player.seek(5.0);
+        assert_relative_eq!(player.time(), 2.0);
+        player.seek(-0.5);
+        assert_relative_eq!(player.time(), 0.0);
+    }
+
+    #[test]
+    fn test_crossfade_blends_poses() {
+        let mut nodes = vec![node(vec![])];
+        let clips = vec![
+            translation_clip(0.0, 0.0, 1.0),
+            translation_clip(10.0, 10.0, 1.0),
+        ];
+        let mut player = AnimationPlayer::new(clips, &nodes);
+        player.play(0);
+        player.crossfade(1, 1.0);
+        assert_eq!(player.current_clip(), Some(1));
+
+        player.update(0.25);
+        player.apply(&mut nodes);
+        assert_relative_eq!(nodes[0].transform.position.0[0], 2.5);
+
+        player.update(1.0);
+        player.apply(&mut nodes);
+        assert_relative_eq!(nodes[0].transform.position.0[0], 10.0);
+    }
+
+    #[test]
+    fn test_crossfade_missing_weights_are_zero() {
+        let mut nodes = vec![node(vec![])];
+        let clips = vec![weights_clip(vec![1.0]), weights_clip(vec![0.0, 1.0])];
+        let mut player = AnimationPlayer::new(clips, &nodes);
+        player.play(0);
+        player.crossfade(1, 1.0);
+        player.update(0.5);
+        player.apply(&mut nodes);
+        assert_eq!(nodes[0].weights.len(), 2);
+        assert_relative_eq!(nodes[0].weights[0], 0.5);
+        assert_relative_eq!(nodes[0].weights[1], 0.5);
+    }
+}
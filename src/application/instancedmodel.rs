@@ -0,0 +1,254 @@
+use std::{rc::Rc, sync::Arc};
+
+use katla_math::{Mat4, Sphere, Transform, Vec3};
+use katla_vulkan::{
+    CommandBuffer, DrawPacket, IndirectDraws, PacketDraw, RenderPass, VertexBuffer, VulkanContext,
+};
+
+use crate::{
+    rendering::{Drawable, InstanceTransform, Material, Mesh, TextureCache},
+    util::{AlphaMode, GLTFModel},
+};
+
+/// Many copies of one mesh drawn with a single pipeline and draw call, e.g. a forest or
+/// a crowd. Each instance has its own transform relative to `transform`. Node transforms,
+/// skins and morph targets of the glTF are not applied and everything uses the material of
+/// the first primitive, so it suits models made of a single node and material. With a
+/// blended material all instances are one blended draw, not sorted among each other.
+pub struct InstancedModel {
+    pub name: String,
+    pub mesh: Mesh,
+    pub material: Material,
+    pub transform: Transform,
+    pub instances: Vec<Transform>,
+    pub bounds: Sphere,
+    //Bounds of the model around the origin of one instance
+    model_bounds: Sphere,
+    //Name and instance count, for debug labels
+    label: String,
+    //One buffer per buffered frame, so a frame in flight never reads a buffer being written
+    instance_buffers: Vec<VertexBuffer>,
+    next_update_index: usize,
+    next_bind_index: usize,
+    instances_dirty: Vec<bool>,
+}
+
+impl InstancedModel {
+    pub fn new_from_gltf(
+        model: Rc<GLTFModel>,
+        context: Arc<VulkanContext>,
+        render_pass: &RenderPass,
+        num_images: usize,
+        position: Vec3,
+        instances: Vec<Transform>,
+    ) -> Self {
+        let first_material = model.submeshes.first().and_then(|submesh| submesh.material);
+        let material = Material::new_instanced(
+            model.clone(),
+            first_material,
+            &mut TextureCache::default(),
+            context.clone(),
+            render_pass,
+            num_images,
+        );
+        let name = model.name.clone();
+        let model_bounds = model.bounds.clone();
+        let buf_size = (instances.len().max(1) * std::mem::size_of::<InstanceTransform>()) as u64;
+        let instance_buffers = (0..num_images)
+            .map(|i| {
+                let buffer = VertexBuffer::new(context.clone(), buf_size, instances.len() as u32);
+                buffer.set_name(&format!("{} instance buffer {}", name, i));
+                buffer
+            })
+            .collect();
+
+        let mesh = Mesh::new_from_model(model, context);
+        let mut instanced_model = Self {
+            name,
+            mesh,
+            material,
+            transform: Transform::new_from_position(position),
+            instances,
+            bounds: Sphere::new(position, 0.0),
+            model_bounds,
+            label: String::new(),
+            instance_buffers,
+            next_update_index: 0,
+            next_bind_index: 0,
+            instances_dirty: vec![true; num_images],
+        };
+        instanced_model.update_bounds();
+        instanced_model.update_label();
+        instanced_model
+    }
+
+    /// Replaces the instance transforms, the number of instances cannot grow past
+    /// the count the model was created with.
+    pub fn set_instances(&mut self, instances: Vec<Transform>) {
+        assert!(
+            instances.len() <= self.instance_buffers[0].count() as usize,
+            "Too many instances for the instance buffer"
+        );
+        self.instances = instances;
+        self.instances_dirty
+            .iter_mut()
+            .for_each(|dirty| *dirty = true);
+        self.update_bounds();
+        self.update_label();
+    }
+
+    fn update_label(&mut self) {
+        self.label = format!("{} x{}", self.name, self.instances.len());
+    }
+
+    //Bounds in model space around the origin of `transform`
+    fn update_bounds(&mut self) {
+        let mut radius = self.model_bounds.center.distance() + self.model_bounds.radius;
+        for instance in &self.instances {
+            let bounds = self.model_bounds.transformed(&instance.make_mat4());
+            radius = radius.max(bounds.center.distance() + bounds.radius);
+        }
+        self.bounds = Sphere::new(self.transform.position, radius);
+    }
+
+    fn upload_instances(&mut self) {
+        let index = self.next_update_index;
+        if self.instances_dirty[index] {
+            let data: Vec<InstanceTransform> = self
+                .instances
+                .iter()
+                .map(|instance| InstanceTransform::from(&instance.make_mat4()))
+                .collect();
+            let data_slice = unsafe {
+                std::slice::from_raw_parts(
+                    data.as_ptr() as *const u8,
+                    std::mem::size_of_val(data.as_slice()),
+                )
+            };
+            self.instance_buffers[index].upload_data(data_slice);
+            self.instances_dirty[index] = false;
+        }
+        self.next_bind_index = index;
+        self.next_update_index = (index + 1) % self.instance_buffers.len();
+    }
+
+    fn is_blended(&self) -> bool {
+        self.material.alpha_mode == AlphaMode::Blend
+    }
+
+    fn draw_instances(&self, command_buffer: &CommandBuffer) {
+        if self.instances.is_empty() {
+            return;
+        }
+        command_buffer.begin_label(&self.label);
+        self.material.bind(command_buffer);
+        self.mesh.draw_instanced(
+            command_buffer,
+            &self.instance_buffers[self.next_bind_index],
+            self.instances.len() as u32,
+        );
+        command_buffer.end_label();
+    }
+}
+
+impl Drawable for InstancedModel {
+    fn update(&mut self, view: &Mat4, proj: &Mat4, _dt: f32) {
+        self.upload_instances();
+        let model = self.transform.make_mat4();
+        self.material
+            .upload_pipeline_data(view.clone(), proj.clone(), model);
+    }
+
+    fn recreate_pipelines(&mut self, render_pass: &RenderPass) {
+        self.material.recreate_pipeline(render_pass);
+    }
+
+    fn draw(&self, command_buffer: &CommandBuffer) {
+        if !self.is_blended() {
+            self.draw_instances(command_buffer);
+        }
+    }
+
+    //One draw per instance, so every instance is culled on its own. Without
+    //drawIndirectFirstInstance all instances are one draw, culled together.
+    fn collect_draws(&self, draws: &mut IndirectDraws) -> bool {
+        if self.is_blended() {
+            return true;
+        }
+        let mut command = match self.mesh.indirect_command(0) {
+            Some(command) => command,
+            None => return false,
+        };
+        if !draws.first_instance_supported() {
+            command.instance_count = self.instances.len() as u32;
+            draws.add_draw(command, &self.bounds);
+            return true;
+        }
+        let world = self.transform.make_mat4();
+        for (i, instance) in self.instances.iter().enumerate() {
+            let bounds = self
+                .model_bounds
+                .transformed(&instance.make_mat4())
+                .transformed(&world);
+            let command = self.mesh.indirect_command(i as u32).unwrap();
+            if !draws.add_draw(command, &bounds) {
+                break;
+            }
+        }
+        true
+    }
+
+    fn draw_indirect(&self, command_buffer: &CommandBuffer, draws: &IndirectDraws, batch: u32) {
+        if self.is_blended() {
+            return;
+        }
+        command_buffer.begin_label(&self.label);
+        self.material.bind(command_buffer);
+        self.mesh.bind_buffers(command_buffer);
+        command_buffer.bind_vertex_buffers(
+            1,
+            &[self.instance_buffers[self.next_bind_index].object()],
+            &[0],
+        );
+        draws.draw_batch(command_buffer, batch);
+        command_buffer.end_label();
+    }
+
+    fn draw_packets<'a>(
+        &'a self,
+        draws: &IndirectDraws,
+        batch: Option<u32>,
+        packets: &mut Vec<DrawPacket<'a>>,
+    ) -> bool {
+        if self.instances.is_empty() || self.is_blended() {
+            return true;
+        }
+        let draw = match batch {
+            Some(batch) => match draws.batch_draw(batch) {
+                Some(indirect) => PacketDraw::Indirect(indirect),
+                None => return true,
+            },
+            None => match self.mesh.packet_draw(self.instances.len() as u32) {
+                Some(draw) => draw,
+                None => return false,
+            },
+        };
+        let mut packet = self.material.draw_packet(&self.label, draw);
+        self.mesh.bind_packet(&mut packet);
+        packet
+            .vertex_buffers
+            .push(self.instance_buffers[self.next_bind_index].object());
+        packets.push(packet);
+        true
+    }
+
+    fn collect_blended(&self, blended: &mut Vec<(Sphere, usize)>) {
+        if self.is_blended() && !self.instances.is_empty() {
+            blended.push((self.bounds.clone(), 0));
+        }
+    }
+
+    fn draw_blended(&self, command_buffer: &CommandBuffer, _item: usize) {
+        self.draw_instances(command_buffer);
+    }
+}
@@ -1,11 +1,18 @@
+pub mod animationplayer;
+pub mod instancedmodel;
 pub mod model;
 pub mod scene;
 
 use std::{cell::RefCell, ffi::CString, path::PathBuf, rc::Rc, time::Instant};
 
+pub use animationplayer::*;
 use env_logger::Env;
-use katla_math::Vec3;
-use katla_vulkan::VulkanRenderer;
+pub use instancedmodel::*;
+use katla_math::{Transform, Vec3};
+use katla_vulkan::{
+    CullMode, DeviceSelection, OcclusionCulling, PresentMode, RendererSettings, Tonemapper,
+    VulkanRenderer,
+};
 pub use model::*;
 pub use scene::*;
 use winit::{
@@ -22,9 +29,13 @@ use crate::{
     util::GLTFModel, util::Timer,
 };
 
+//Written next to the text report printed with B
+const MEMORY_REPORT_PATH: &str = "memory_report.json";
+
 struct ApplicationInfo {
     name: String,
     validation_layer_enabled: bool,
+    renderer_settings: RendererSettings,
 }
 
 pub struct Application {
@@ -35,6 +46,7 @@ pub struct Application {
     scene: Scene,
     gltf_cache: FileCache<GLTFModel>,
     stage_upload: bool,
+    stage_instances: bool,
     timer: Timer,
     info: ApplicationInfo,
 }
@@ -62,7 +74,10 @@ impl ApplicationHandler for Application {
                 self.info.validation_layer_enabled,
                 CString::new(self.info.name.as_str()).unwrap(),
                 engine_name,
+                self.info.renderer_settings.clone(),
             );
+            self.info.renderer_settings.sample_count = renderer.sample_count();
+            println!("Output encoding: {:?}", renderer.output_encoding());
             let window_size = window.inner_size();
             let win_x = window_size.width as f32;
             let win_y = window_size.height as f32;
@@ -126,6 +141,92 @@ impl ApplicationHandler for Application {
                                 KeyCode::KeyT => {
                                     self.stage_upload = true;
                                 }
+                                KeyCode::KeyI => {
+                                    self.stage_instances = true;
+                                }
+                                KeyCode::KeyM => {
+                                    //Cycle through 1, 2, 4 and 8 samples
+                                    let old_samples = renderer.sample_count();
+                                    let samples = match old_samples {
+                                        8 => 1,
+                                        samples => samples * 2,
+                                    };
+                                    let mut new_samples = renderer.set_sample_count(samples);
+                                    if new_samples != samples {
+                                        //Wrapped past what the device supports
+                                        new_samples = renderer.set_sample_count(1);
+                                    }
+                                    if new_samples != old_samples {
+                                        self.scene.recreate_pipelines(&renderer.render_pass);
+                                    }
+                                    self.info.renderer_settings.sample_count = new_samples;
+                                }
+                                KeyCode::KeyV => {
+                                    let present_mode = match renderer.settings().present_mode {
+                                        PresentMode::Mailbox => PresentMode::Vsync,
+                                        PresentMode::Vsync => PresentMode::FifoRelaxed,
+                                        PresentMode::FifoRelaxed => PresentMode::Immediate,
+                                        PresentMode::Immediate => PresentMode::Mailbox,
+                                    };
+                                    renderer.set_present_mode(present_mode);
+                                    self.info.renderer_settings.present_mode = present_mode;
+                                }
+                                KeyCode::KeyG => {
+                                    let cull_mode = match renderer.indirect_draws().cull_mode() {
+                                        CullMode::Gpu => CullMode::Cpu,
+                                        CullMode::Cpu => CullMode::Gpu,
+                                    };
+                                    let cull_mode = renderer.set_cull_mode(cull_mode);
+                                    println!("Frustum culling on the {:?}", cull_mode);
+                                    self.info.renderer_settings.gpu_culling =
+                                        cull_mode == CullMode::Gpu;
+                                }
+                                KeyCode::KeyO => {
+                                    let occlusion_culling =
+                                        match renderer.indirect_draws().occlusion_culling() {
+                                            OcclusionCulling::Off => OcclusionCulling::Queries,
+                                            OcclusionCulling::Queries => OcclusionCulling::HiZ,
+                                            OcclusionCulling::HiZ => OcclusionCulling::Off,
+                                        };
+                                    let occlusion_culling =
+                                        renderer.set_occlusion_culling(occlusion_culling);
+                                    println!("Occlusion culling: {:?}", occlusion_culling);
+                                    self.info.renderer_settings.occlusion_culling =
+                                        occlusion_culling;
+                                }
+                                KeyCode::KeyP => {
+                                    println!("{}", self.timer.report(renderer.gpu_timings()));
+                                }
+                                KeyCode::KeyB => {
+                                    let report = renderer.context.memory_report();
+                                    print!("{}", report);
+                                    match std::fs::write(MEMORY_REPORT_PATH, report.to_json()) {
+                                        Ok(()) => println!("Wrote {}", MEMORY_REPORT_PATH),
+                                        Err(err) => {
+                                            println!(
+                                                "Could not write {}: {}",
+                                                MEMORY_REPORT_PATH, err
+                                            )
+                                        }
+                                    }
+                                }
+                                KeyCode::KeyY => {
+                                    let tonemapper = match renderer.settings().tonemapper {
+                                        Tonemapper::None => Tonemapper::Reinhard,
+                                        Tonemapper::Reinhard => Tonemapper::Aces,
+                                        Tonemapper::Aces => Tonemapper::None,
+                                    };
+                                    println!("Tonemapper: {:?}", tonemapper);
+                                    renderer.set_tonemapper(tonemapper);
+                                    self.info.renderer_settings.tonemapper = tonemapper;
+                                }
+                                KeyCode::Minus | KeyCode::Equal => {
+                                    let step = if keycode == KeyCode::Equal { 1.25 } else { 0.8 };
+                                    let exposure = renderer.settings().exposure * step;
+                                    println!("Exposure: {}", exposure);
+                                    renderer.set_exposure(exposure);
+                                    self.info.renderer_settings.exposure = exposure;
+                                }
                                 _ => {}
                             }
                         }
@@ -139,14 +240,21 @@ impl ApplicationHandler for Application {
                     let dt = self.timer.get_delta() as f32;
                     self.camera.borrow_mut().update(dt);
 
-                    self.scene.update(
-                        &self.camera.borrow().get_proj_mat(),
-                        &self.camera.borrow().get_view_mat().inverse(),
-                        dt,
-                    );
+                    let proj = self.camera.borrow().get_proj_mat().clone();
+                    let view = self.camera.borrow().get_view_mat().inverse();
+                    self.scene.update(&proj, &view, dt);
+                    let view_proj = proj.mul(&view);
+                    self.scene
+                        .collect_draws(renderer.indirect_draws_mut(), Some(&view_proj));
 
                     let command_buffer = renderer.get_commandbuffer_opaque_pass();
-                    self.scene.render(&command_buffer);
+                    if renderer.records_in_parallel() {
+                        self.scene.render_parallel(&command_buffer, renderer);
+                    } else {
+                        self.scene
+                            .render_indirect(&command_buffer, renderer.indirect_draws());
+                    }
+                    renderer.end_commandbuffer_opaque_pass(&command_buffer);
                     renderer.submit_frame(vec![&command_buffer]);
                     if self.stage_upload {
                         let start = Instant::now();
@@ -167,6 +275,34 @@ impl ApplicationHandler for Application {
                             .add_object(SceneObject::new(Box::new(mesh), bounds));
                         self.stage_upload = false;
                     }
+                    if self.stage_instances {
+                        //A 10x10 grid of foxes, all drawn with one instanced draw call
+                        let model = self
+                            .gltf_cache
+                            .read(PathBuf::from("resources/models/Fox.glb"));
+                        let spacing = model.bounds.radius * 2.0;
+                        let instances = (0..100)
+                            .map(|i| {
+                                Transform::new_from_position(Vec3::new(
+                                    (i % 10) as f32 * spacing,
+                                    0.0,
+                                    (i / 10) as f32 * spacing,
+                                ))
+                            })
+                            .collect();
+                        let forest = InstancedModel::new_from_gltf(
+                            model,
+                            renderer.context.clone(),
+                            &renderer.render_pass,
+                            renderer.num_images(),
+                            Vec3::new(0.0, 0.0, -200.0),
+                            instances,
+                        );
+                        let bounds = forest.bounds.clone();
+                        self.scene
+                            .add_object(SceneObject::new(Box::new(forest), bounds));
+                        self.stage_instances = false;
+                    }
                     if let Some(window) = &self.window {
                         window.request_redraw();
                     }
@@ -179,6 +315,14 @@ impl ApplicationHandler for Application {
     fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
         if let Some(mut renderer) = self.renderer.take() {
             renderer.wait_for_device();
+            let validation_log = renderer.validation_log();
+            if validation_log.error_count() > 0 || validation_log.warning_count() > 0 {
+                println!(
+                    "Validation reported {} errors and {} warnings",
+                    validation_log.error_count(),
+                    validation_log.warning_count()
+                );
+            }
             self.scene.teardown();
             renderer.destroy();
         }
@@ -212,6 +356,7 @@ impl Application {
 pub struct ApplicationBuilder {
     app_name: String,
     validation_layer_enabled: bool,
+    renderer_settings: RendererSettings,
     camera: Rc<RefCell<Camera>>,
     input_controller: InputController,
 }
@@ -231,6 +376,70 @@ impl ApplicationBuilder {
         self
     }
 
+    /// MSAA sample count for the opaque pass, 1, 2, 4 or 8. Toggled at runtime with M.
+    pub fn with_msaa_samples(mut self, samples: u32) -> Self {
+        self.renderer_settings.sample_count = samples;
+        self
+    }
+
+    /// Present mode preference, e.g. `PresentMode::Immediate` for uncapped benchmarks or
+    /// `PresentMode::Vsync` for strict vsync. Cycled at runtime with V.
+    pub fn with_present_mode(mut self, present_mode: PresentMode) -> Self {
+        self.renderer_settings.present_mode = present_mode;
+        self
+    }
+
+    /// Time passes on the GPU, with optional pipeline statistics. Printed with P.
+    pub fn with_gpu_profiling(mut self, on: bool, pipeline_statistics: bool) -> Self {
+        self.renderer_settings.gpu_profiling = on;
+        self.renderer_settings.pipeline_statistics = pipeline_statistics;
+        self
+    }
+
+    /// Frustum cull in a compute shader, or on the CPU when off. Toggled at runtime with G.
+    pub fn with_gpu_culling(mut self, on: bool) -> Self {
+        self.renderer_settings.gpu_culling = on;
+        self
+    }
+
+    /// Skip draws hidden behind other geometry, Hi-Z needs GPU culling and uses occlusion
+    /// queries otherwise. Cycled at runtime with O.
+    pub fn with_occlusion_culling(mut self, occlusion_culling: OcclusionCulling) -> Self {
+        self.renderer_settings.occlusion_culling = occlusion_culling;
+        self
+    }
+
+    /// Threads recording the scene into secondary command buffers, 0 records it serially.
+    pub fn with_recording_threads(mut self, threads: usize) -> Self {
+        self.renderer_settings.recording_threads = threads;
+        self
+    }
+
+    /// Physical device to render on, see `katla-info` for the list. The `KATLA_DEVICE`
+    /// environment variable takes precedence.
+    pub fn with_device(mut self, device: DeviceSelection) -> Self {
+        self.renderer_settings.device = device;
+        self
+    }
+
+    /// Tonemapping operator applied to the HDR scene color. Cycled at runtime with Y.
+    pub fn with_tonemapper(mut self, tonemapper: Tonemapper) -> Self {
+        self.renderer_settings.tonemapper = tonemapper;
+        self
+    }
+
+    /// Exposure multiplier applied before tonemapping. Adjusted at runtime with - and =.
+    pub fn with_exposure(mut self, exposure: f32) -> Self {
+        self.renderer_settings.exposure = exposure;
+        self
+    }
+
+    /// Request an HDR10 or scRGB swapchain, falls back to sRGB if the surface has neither.
+    pub fn with_hdr_output(mut self, on: bool) -> Self {
+        self.renderer_settings.hdr_output = on;
+        self
+    }
+
     pub fn with_axis_input<S>(mut self, key_event: KeyCode, input: S, value: f32) -> Self
     where
         S: Into<u32>,
@@ -255,6 +464,7 @@ impl ApplicationBuilder {
         let info = ApplicationInfo {
             name: self.app_name,
             validation_layer_enabled: self.validation_layer_enabled,
+            renderer_settings: self.renderer_settings,
         };
 
         let app = Application {
@@ -265,6 +475,7 @@ impl ApplicationBuilder {
             scene: Scene::new(),
             gltf_cache: FileCache::new(),
             stage_upload: false,
+            stage_instances: false,
             timer: Timer::new(100),
             info,
         };
@@ -1,18 +1,55 @@
-use std::{f32::consts::FRAC_PI_2, rc::Rc, sync::Arc};
+use std::{rc::Rc, sync::Arc};
 
-use katla_math::{Mat4, Quat, Sphere, Transform, Vec3};
-use katla_vulkan::{CommandBuffer, RenderPass, VulkanContext};
+use katla_math::{Mat4, Sphere, Transform, Vec3};
+use katla_vulkan::{
+    CommandBuffer, DrawIndexedIndirectCommand, DrawPacket, IndirectDraws, PacketDraw, RenderPass,
+    StorageBuffer, VertexBuffer, VulkanContext,
+};
 
+use super::AnimationPlayer;
 use crate::{
-    rendering::{Drawable, Material, Mesh},
-    util::GLTFModel,
+    rendering::{Drawable, InstanceTransform, Material, Mesh, NodeMorphWeights, TextureCache},
+    util::{AlphaMode, GLTFModel, ModelNode, ModelSkin, Submesh},
 };
 
+//Submeshes sharing a material, drawn in a batch of their own when drawn indirectly
+struct MaterialGroup {
+    material: Material,
+    submeshes: Vec<usize>,
+}
+
+/// A glTF model with its node tree. Every submesh is drawn with the model space matrix
+/// of its node, read per instance from a node transform buffer, or deformed by the
+/// joint matrices of its skin. Morph targets are blended with the weights of the node.
 pub struct Model {
-    pub meshes: Vec<Mesh>,
-    pub material: Material,
+    pub name: String,
+    pub mesh: Mesh,
     pub transform: Transform,
     pub bounds: Sphere,
+    pub nodes: Vec<ModelNode>,
+    pub roots: Vec<usize>,
+    pub submeshes: Vec<Submesh>,
+    pub skins: Vec<ModelSkin>,
+    /// Plays the first clip of the model from the start
+    pub animation: AnimationPlayer,
+    groups: Vec<MaterialGroup>,
+    //Drawn one submesh at a time with the blended draws of the scene
+    blended_groups: Vec<MaterialGroup>,
+    //Model space matrix per node, the instance index of a submesh is its node
+    node_world: Vec<Mat4>,
+    //Joints of all skins, uploaded with the materials every frame
+    joint_matrices: Vec<Mat4>,
+    //Active morph targets per node, uploaded with the materials every frame
+    morph_weights: Vec<NodeMorphWeights>,
+    //`VertexMorph` per vertex at vertex binding 2, and the deltas they point to
+    morph_buffer: Option<VertexBuffer>,
+    morph_deltas: Option<StorageBuffer>,
+    model_bounds: Sphere,
+    //One buffer per buffered frame, so a frame in flight never reads a buffer being written
+    node_buffers: Vec<VertexBuffer>,
+    next_update_index: usize,
+    next_bind_index: usize,
+    nodes_dirty: Vec<bool>,
 }
 
 impl Model {
@@ -23,35 +60,361 @@ impl Model {
         num_images: usize,
         position: Vec3,
     ) -> Self {
-        let material = Material::new(model.clone(), context.clone(), render_pass, num_images);
-        let mut bounds = model.bounds.clone();
-        bounds.center = position;
+        let mut groups: Vec<MaterialGroup> = vec![];
+        let mut group_materials: Vec<Option<usize>> = vec![];
+        let mut texture_cache = TextureCache::default();
+        for (i, submesh) in model.submeshes.iter().enumerate() {
+            match group_materials
+                .iter()
+                .position(|&material| material == submesh.material)
+            {
+                Some(group) => groups[group].submeshes.push(i),
+                None => {
+                    let material = Material::new_animated(
+                        model.clone(),
+                        submesh.material,
+                        &mut texture_cache,
+                        context.clone(),
+                        render_pass,
+                        num_images,
+                    );
+                    group_materials.push(submesh.material);
+                    groups.push(MaterialGroup {
+                        material,
+                        submeshes: vec![i],
+                    });
+                }
+            }
+        }
+        let (mut blended_groups, mut groups): (Vec<_>, Vec<_>) = groups
+            .into_iter()
+            .partition(|group| group.material.alpha_mode == AlphaMode::Blend);
         let transform = Transform::new_from_position(position);
+        let bounds = model.bounds.transformed(&transform.make_mat4());
+        let name = model.name.clone();
+        let nodes = model.nodes.clone();
+        let roots = model.roots.clone();
+        let submeshes = model.submeshes.clone();
+        let skins = model.skins.clone();
+        let model_bounds = model.bounds.clone();
+        let mut animation = AnimationPlayer::new(model.animations.clone(), &nodes);
+        if !animation.clips().is_empty() {
+            animation.play(0);
+        }
+
+        let buf_size = (nodes.len().max(1) * std::mem::size_of::<InstanceTransform>()) as u64;
+        let node_buffers = (0..num_images)
+            .map(|i| {
+                let buffer = VertexBuffer::new(context.clone(), buf_size, nodes.len() as u32);
+                buffer.set_name(&format!("{} node transforms {}", name, i));
+                buffer
+            })
+            .collect();
+
+        let (morph_buffer, morph_deltas) = if model.has_morph_targets() {
+            let morph_data = &model.morph_data;
+            let data_slice = unsafe {
+                std::slice::from_raw_parts(
+                    morph_data.as_ptr() as *const u8,
+                    std::mem::size_of_val(morph_data.as_slice()),
+                )
+            };
+            let mut morph_buffer = VertexBuffer::new(
+                context.clone(),
+                data_slice.len() as u64,
+                morph_data.len() as u32,
+            );
+            morph_buffer.upload_data(data_slice);
+            morph_buffer.set_name(&format!("{} morph targets", name));
 
-        let mesh = Mesh::new_from_model(model, context.clone());
+            let deltas = &model.morph_deltas;
+            let deltas_slice = unsafe {
+                std::slice::from_raw_parts(
+                    deltas.as_ptr() as *const u8,
+                    std::mem::size_of_val(deltas.as_slice()),
+                )
+            };
+            let morph_deltas = StorageBuffer::from_data(context.clone(), deltas_slice);
+            morph_deltas.set_name(&format!("{} morph deltas", name));
+            for group in groups.iter_mut().chain(&mut blended_groups) {
+                group.material.set_morph_deltas(&morph_deltas);
+            }
+            (Some(morph_buffer), Some(morph_deltas))
+        } else {
+            (None, None)
+        };
+
+        let mesh = if model.is_skinned() {
+            Mesh::new_skinned_from_model(model, context)
+        } else {
+            Mesh::new_from_model(model, context)
+        };
+        let node_world = ModelNode::world_matrices(&nodes, &roots);
+        let joint_matrices = ModelSkin::joint_matrices(&skins, &node_world);
+        let morph_weights = Self::morph_weights(&nodes, morph_deltas.is_some());
         Self {
-            meshes: vec![mesh],
-            material,
+            name,
+            mesh,
             transform,
             bounds,
+            nodes,
+            roots,
+            submeshes,
+            skins,
+            animation,
+            groups,
+            blended_groups,
+            node_world,
+            joint_matrices,
+            morph_weights,
+            morph_buffer,
+            morph_deltas,
+            model_bounds,
+            node_buffers,
+            next_update_index: 0,
+            next_bind_index: 0,
+            nodes_dirty: vec![true; num_images],
+        }
+    }
+
+    /// Recomputes the node and joint matrices, and the bounds, after `nodes` changed.
+    pub fn update_nodes(&mut self) {
+        self.node_world = ModelNode::world_matrices(&self.nodes, &self.roots);
+        self.joint_matrices = ModelSkin::joint_matrices(&self.skins, &self.node_world);
+        self.morph_weights = Self::morph_weights(&self.nodes, self.morph_deltas.is_some());
+        let mut submesh_bounds = self.submeshes.iter().map(|submesh| {
+            submesh.model_bounds(&self.skins, &self.node_world, &self.joint_matrices)
+        });
+        if let Some(mut bounds) = submesh_bounds.next() {
+            submesh_bounds.for_each(|other| bounds.enclose(&other));
+            self.model_bounds = bounds;
+        }
+        self.nodes_dirty.iter_mut().for_each(|dirty| *dirty = true);
+    }
+
+    //Empty for models without morph targets, which have nothing to upload
+    fn morph_weights(nodes: &[ModelNode], has_morph_targets: bool) -> Vec<NodeMorphWeights> {
+        if !has_morph_targets {
+            return vec![];
+        }
+        nodes
+            .iter()
+            .map(|node| NodeMorphWeights::from_weights(&node.weights))
+            .collect()
+    }
+
+    fn upload_nodes(&mut self) {
+        let index = self.next_update_index;
+        if self.nodes_dirty[index] && !self.node_world.is_empty() {
+            let data: Vec<InstanceTransform> = self
+                .node_world
+                .iter()
+                .map(InstanceTransform::from)
+                .collect();
+            let data_slice = unsafe {
+                std::slice::from_raw_parts(
+                    data.as_ptr() as *const u8,
+                    std::mem::size_of_val(data.as_slice()),
+                )
+            };
+            self.node_buffers[index].upload_data(data_slice);
+            self.nodes_dirty[index] = false;
+        }
+        self.next_bind_index = index;
+        self.next_update_index = (index + 1) % self.node_buffers.len();
+    }
+
+    fn node_buffer(&self) -> &VertexBuffer {
+        &self.node_buffers[self.next_bind_index]
+    }
+
+    //Node transforms at vertex binding 1, followed by the morph targets if there are any
+    fn bind_node_buffers(&self, command_buffer: &CommandBuffer) {
+        command_buffer.bind_vertex_buffers(1, &[self.node_buffer().object()], &[0]);
+        if let Some(morph_buffer) = &self.morph_buffer {
+            command_buffer.bind_vertex_buffers(2, &[morph_buffer.object()], &[0]);
+        }
+    }
+
+    fn submesh_command(submesh: &Submesh) -> DrawIndexedIndirectCommand {
+        DrawIndexedIndirectCommand {
+            index_count: submesh.index_count,
+            instance_count: 1,
+            first_index: submesh.first_index,
+            vertex_offset: 0,
+            first_instance: submesh.node as u32,
         }
     }
 }
 
 impl Drawable for Model {
     fn update(&mut self, view: &Mat4, proj: &Mat4, dt: f32) {
-        let quat = Quat::new_from_axis_angle(Vec3::new(0.0, 1.0, 0.0), FRAC_PI_2 * dt);
-        self.transform.rotation = self.transform.rotation * quat;
+        self.animation.update(dt);
+        if self.animation.apply(&mut self.nodes) {
+            self.update_nodes();
+        }
+        self.upload_nodes();
         let model = self.transform.make_mat4();
-        self.material
-            .upload_pipeline_data(view.clone(), proj.clone(), model);
+        self.bounds = self.model_bounds.transformed(&model);
+        for group in self.groups.iter_mut().chain(&mut self.blended_groups) {
+            group.material.upload_animated_pipeline_data(
+                view.clone(),
+                proj.clone(),
+                model.clone(),
+                &self.joint_matrices,
+                &self.morph_weights,
+            );
+        }
+    }
+
+    fn recreate_pipelines(&mut self, render_pass: &RenderPass) {
+        for group in self.groups.iter_mut().chain(&mut self.blended_groups) {
+            group.material.recreate_pipeline(render_pass);
+        }
     }
 
     fn draw(&self, command_buffer: &CommandBuffer) {
-        self.material.bind(command_buffer);
+        if self.mesh.index_buffer.is_none() {
+            return;
+        }
+        command_buffer.begin_label(&self.name);
+        self.mesh.bind_buffers(command_buffer);
+        self.bind_node_buffers(command_buffer);
+        for group in &self.groups {
+            group.material.bind(command_buffer);
+            for &i in &group.submeshes {
+                let submesh = &self.submeshes[i];
+                command_buffer.draw_indexed(
+                    submesh.index_count,
+                    1,
+                    submesh.first_index,
+                    0,
+                    submesh.node as u32,
+                );
+            }
+        }
+        command_buffer.end_label();
+    }
+
+    //One draw per submesh, so every submesh is culled on its own. The first material group
+    //uses the batch the scene started, every other group starts the next one. The node of a
+    //submesh is its first instance, so without drawIndirectFirstInstance it is drawn directly.
+    fn collect_draws(&self, draws: &mut IndirectDraws) -> bool {
+        if self.mesh.index_buffer.is_none()
+            || !draws.first_instance_supported()
+            || self.groups.len() as u32 > draws.remaining_batches() + 1
+        {
+            return false;
+        }
+        let model = self.transform.make_mat4();
+        for (i, group) in self.groups.iter().enumerate() {
+            if i > 0 {
+                draws.begin_batch();
+            }
+            for &submesh_index in &group.submeshes {
+                let submesh = &self.submeshes[submesh_index];
+                let bounds = submesh
+                    .model_bounds(&self.skins, &self.node_world, &self.joint_matrices)
+                    .transformed(&model);
+                if !draws.add_draw(Self::submesh_command(submesh), &bounds) {
+                    break;
+                }
+            }
+        }
+        true
+    }
+
+    fn draw_indirect(&self, command_buffer: &CommandBuffer, draws: &IndirectDraws, batch: u32) {
+        command_buffer.begin_label(&self.name);
+        self.mesh.bind_buffers(command_buffer);
+        self.bind_node_buffers(command_buffer);
+        for (i, group) in self.groups.iter().enumerate() {
+            group.material.bind(command_buffer);
+            draws.draw_batch(command_buffer, batch + i as u32);
+        }
+        command_buffer.end_label();
+    }
+
+    fn draw_packets<'a>(
+        &'a self,
+        draws: &IndirectDraws,
+        batch: Option<u32>,
+        packets: &mut Vec<DrawPacket<'a>>,
+    ) -> bool {
+        if self.mesh.index_buffer.is_none() {
+            return false;
+        }
+        for (i, group) in self.groups.iter().enumerate() {
+            let packet_draws: Vec<PacketDraw> = match batch {
+                Some(batch) => match draws.batch_draw(batch + i as u32) {
+                    Some(indirect) => vec![PacketDraw::Indirect(indirect)],
+                    //Nothing survived culling
+                    None => continue,
+                },
+                None => group
+                    .submeshes
+                    .iter()
+                    .map(|&submesh_index| {
+                        let submesh = &self.submeshes[submesh_index];
+                        PacketDraw::Indexed {
+                            index_count: submesh.index_count,
+                            instance_count: 1,
+                            first_index: submesh.first_index,
+                            first_instance: submesh.node as u32,
+                        }
+                    })
+                    .collect(),
+            };
+            for draw in packet_draws {
+                let mut packet = group.material.draw_packet(&self.name, draw);
+                self.mesh.bind_packet(&mut packet);
+                packet.vertex_buffers.push(self.node_buffer().object());
+                if let Some(morph_buffer) = &self.morph_buffer {
+                    packet.vertex_buffers.push(morph_buffer.object());
+                }
+                packets.push(packet);
+            }
+        }
+        true
+    }
 
-        for mesh in &self.meshes {
-            mesh.draw(command_buffer);
+    fn collect_blended(&self, blended: &mut Vec<(Sphere, usize)>) {
+        if self.mesh.index_buffer.is_none() {
+            return;
+        }
+        let model = self.transform.make_mat4();
+        for group in &self.blended_groups {
+            for &submesh_index in &group.submeshes {
+                let bounds = self.submeshes[submesh_index]
+                    .model_bounds(&self.skins, &self.node_world, &self.joint_matrices)
+                    .transformed(&model);
+                blended.push((bounds, submesh_index));
+            }
         }
     }
+
+    //The item is the index of the submesh
+    fn draw_blended(&self, command_buffer: &CommandBuffer, item: usize) {
+        let group = match self
+            .blended_groups
+            .iter()
+            .find(|group| group.submeshes.contains(&item))
+        {
+            Some(group) => group,
+            None => return,
+        };
+        let submesh = &self.submeshes[item];
+        command_buffer.begin_label(&self.name);
+        self.mesh.bind_buffers(command_buffer);
+        self.bind_node_buffers(command_buffer);
+        group.material.bind(command_buffer);
+        command_buffer.draw_indexed(
+            submesh.index_count,
+            1,
+            submesh.first_index,
+            0,
+            submesh.node as u32,
+        );
+        command_buffer.end_label();
+    }
 }
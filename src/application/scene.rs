@@ -1,6 +1,6 @@
 use crate::rendering::Drawable;
-use katla_math::{Mat4, Sphere, Vec3};
-use katla_vulkan::CommandBuffer;
+use katla_math::{mat4_mul_vec3, Mat4, Sphere, Vec3};
+use katla_vulkan::{CommandBuffer, IndirectDraws, RenderPass, VulkanRenderer};
 use std::rc::Rc;
 
 pub struct Player {
@@ -16,6 +16,10 @@ pub struct SceneObject {
 pub struct Scene {
     pub player: Player,
     pub scene_objects: Vec<SceneObject>,
+    //Batch of every scene object in the indirect draws, None if drawn directly
+    indirect_batches: Vec<Option<u32>>,
+    //Object and item of every blended draw, back to front
+    blended_draws: Vec<(usize, usize)>,
 }
 
 impl SceneObject {
@@ -39,6 +43,8 @@ impl Scene {
         Self {
             player,
             scene_objects,
+            indirect_batches: vec![],
+            blended_draws: vec![],
         }
     }
 
@@ -54,6 +60,38 @@ impl Scene {
         for object in &mut self.scene_objects {
             object.drawable.update(&view, &proj, dt);
         }
+        self.sort_blended(view);
+    }
+
+    //Sorts the blended draws of all objects by the distance of their bounds to the camera
+    fn sort_blended(&mut self, view: &Mat4) {
+        let camera = mat4_mul_vec3(&view.inverse(), &Vec3::new(0.0, 0.0, 0.0));
+        let mut blended = vec![];
+        let mut sorted = vec![];
+        for (i, object) in self.scene_objects.iter().enumerate() {
+            blended.clear();
+            object.drawable.collect_blended(&mut blended);
+            sorted.extend(blended.iter().map(|(bounds, item)| {
+                let distance = (bounds.center - camera).distance_squared();
+                (distance, i, *item)
+            }));
+        }
+        sorted.sort_by(|a, b| b.0.total_cmp(&a.0));
+        self.blended_draws = sorted.into_iter().map(|(_, i, item)| (i, item)).collect();
+    }
+
+    fn render_blended(&self, command_buffer: &CommandBuffer) {
+        for &(object, item) in &self.blended_draws {
+            self.scene_objects[object]
+                .drawable
+                .draw_blended(command_buffer, item);
+        }
+    }
+
+    pub fn recreate_pipelines(&mut self, render_pass: &RenderPass) {
+        for object in &mut self.scene_objects {
+            object.drawable.recreate_pipelines(render_pass);
+        }
     }
 
     pub fn add_object(&mut self, scene_object: SceneObject) {
@@ -64,7 +102,56 @@ impl Scene {
         for object in &self.scene_objects {
             object.drawable.draw(command_buffer);
         }
-        command_buffer.end_render_pass();
-        command_buffer.end_command();
+        self.render_blended(command_buffer);
+    }
+
+    /// Gathers the draws of all objects into `draws` for culling against the frustum of
+    /// `view_proj`, every object that supports it gets its own batch.
+    pub fn collect_draws(&mut self, draws: &mut IndirectDraws, view_proj: Option<&Mat4>) {
+        draws.begin_frame(view_proj);
+        self.indirect_batches.clear();
+        for object in &self.scene_objects {
+            let batch = match draws.begin_batch() {
+                Some(batch) if object.drawable.collect_draws(draws) => Some(batch),
+                _ => None,
+            };
+            self.indirect_batches.push(batch);
+        }
+    }
+
+    /// Like `render_indirect`, but records the draws on the renderer's recording threads.
+    /// Objects that cannot make draw packets are recorded on this thread meanwhile, followed
+    /// by the blended draws, which come last since the serial draws are executed last.
+    pub fn render_parallel(&self, command_buffer: &CommandBuffer, renderer: &mut VulkanRenderer) {
+        let mut packets = vec![];
+        let mut serial_objects = vec![];
+        let draws = renderer.indirect_draws();
+        for (i, object) in self.scene_objects.iter().enumerate() {
+            let batch = self.indirect_batches.get(i).copied().flatten();
+            if !object.drawable.draw_packets(draws, batch, &mut packets) {
+                serial_objects.push((object, batch));
+            }
+        }
+        renderer.record_opaque_parallel(command_buffer, &packets, |secondary, draws| {
+            for (object, batch) in serial_objects {
+                match batch {
+                    Some(batch) => object.drawable.draw_indirect(secondary, draws, batch),
+                    None => object.drawable.draw(secondary),
+                }
+            }
+            self.render_blended(secondary);
+        });
+    }
+
+    /// Renders the visible draws gathered by `collect_draws`, one indirect call per batch.
+    /// Objects without a batch are drawn directly, and the blended draws after everything.
+    pub fn render_indirect(&self, command_buffer: &CommandBuffer, draws: &IndirectDraws) {
+        for (object, batch) in self.scene_objects.iter().zip(&self.indirect_batches) {
+            match batch {
+                Some(batch) => object.drawable.draw_indirect(command_buffer, draws, *batch),
+                None => object.drawable.draw(command_buffer),
+            }
+        }
+        self.render_blended(command_buffer);
     }
 }
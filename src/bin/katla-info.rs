@@ -0,0 +1,29 @@
+use katla_vulkan::{list_devices, DeviceSelection};
+
+//Prints every Vulkan device, marking the one `KATLA_DEVICE` (or the default choice) picks.
+//`katla-info --brief` prints one line per device.
+fn main() {
+    let brief = std::env::args().any(|arg| arg == "--brief" || arg == "-b");
+    let devices = list_devices();
+    if devices.is_empty() {
+        println!("No Vulkan devices found");
+        return;
+    }
+
+    let selection = DeviceSelection::from_env().unwrap_or_default();
+    //Presentation support needs a window, so every device counts as usable here
+    let picked = selection.pick(&devices, |_| true);
+    for device in &devices {
+        let marker = if Some(device.index) == picked {
+            "*"
+        } else {
+            " "
+        };
+        if brief {
+            println!("{} {}", marker, device.summary());
+        } else {
+            //The report ends in a newline, this leaves an empty line between devices
+            println!("{} {}", marker, device);
+        }
+    }
+}
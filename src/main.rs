@@ -7,6 +7,7 @@ fn main() {
     let (mut application, event_loop) = ApplicationBuilder::new()
         .with_name("Katla")
         .validation_layer(true)
+        .with_msaa_samples(4)
         //TODO: This seems to be typical to reside in configuration files:
         .with_axis_input(KeyCode::KeyA, InputMapping::MoveHorizontal, -1.0)
         .with_axis_input(KeyCode::KeyD, InputMapping::MoveHorizontal, 1.0)
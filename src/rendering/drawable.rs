@@ -1,7 +1,32 @@
-use katla_math::Mat4;
-use katla_vulkan::CommandBuffer;
+use katla_math::{Mat4, Sphere};
+use katla_vulkan::{CommandBuffer, DrawPacket, IndirectDraws, RenderPass};
 
 pub trait Drawable {
     fn update(&mut self, view: &Mat4, proj: &Mat4, dt: f32);
     fn draw(&self, command_buffer: &CommandBuffer);
+    //Called when the render pass changed in a way that invalidates existing pipelines
+    fn recreate_pipelines(&mut self, render_pass: &RenderPass);
+    //Adds the draws of this drawable, with world space bounds, to the current batch of
+    //`draws`. Returns false if it can only be drawn through `draw`.
+    fn collect_draws(&self, _draws: &mut IndirectDraws) -> bool {
+        false
+    }
+    //Binds what the draws added by `collect_draws` need and draws `batch`
+    fn draw_indirect(&self, _command_buffer: &CommandBuffer, _draws: &IndirectDraws, _batch: u32) {}
+    //Same draws as `draw` or `draw_indirect` (when `batch` is set), as packets that can be
+    //recorded on any thread. Returns false if it can only be drawn through those.
+    fn draw_packets<'a>(
+        &'a self,
+        _draws: &IndirectDraws,
+        _batch: Option<u32>,
+        _packets: &mut Vec<DrawPacket<'a>>,
+    ) -> bool {
+        false
+    }
+    //Adds the draws that blend over what is behind them, as world space bounds and an item
+    //for `draw_blended`. These are left out of the other draws, since they have to be drawn
+    //after every opaque draw of the scene, back to front.
+    fn collect_blended(&self, _blended: &mut Vec<(Sphere, usize)>) {}
+    //Draws one item added by `collect_blended`
+    fn draw_blended(&self, _command_buffer: &CommandBuffer, _item: usize) {}
 }
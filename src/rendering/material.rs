@@ -1,93 +1,404 @@
-use crate::{rendering::vertextypes::*, util::GLTFModel};
+use crate::{
+    rendering::vertextypes::*,
+    util::{AlphaMode, GLTFModel, PbrMaterial},
+};
 
 use katla_math::Mat4;
 
 use katla_vulkan::{
-    context::VulkanContext, CommandBuffer, Format, ImageInfo, PipelineBindPoint, RenderPass,
-    RenderPipeline, Texture,
+    context::VulkanContext, CommandBuffer, DrawPacket, Format, ImageInfo, PacketDraw,
+    PipelineBindPoint, PipelineOptions, RenderPass, RenderPipeline, ShaderStageFlags, ShaderStages,
+    StorageBuffer, Texture, UniformBinding, VertexBinding,
+};
+
+use std::{
+    collections::HashMap,
+    rc::{Rc, Weak},
+    sync::Arc,
 };
 
-use std::{rc::Rc, sync::Arc};
+//Bindings in model.frag
+const TEXTURES_BINDING: u32 = 1;
+const PARAMS_BINDING: u32 = 2;
+//Binding in model_pbr_skinned.vert
+const JOINTS_BINDING: u32 = 3;
+
+//What the vertex shader of a material reads besides the textures and parameters
+struct VertexInputs {
+    shaders: ShaderStages,
+    vertex_bindings: Vec<VertexBinding>,
+    //Joint matrices of all skins, none when 0
+    joint_count: usize,
+    //`NodeMorphWeights` per node, no morph targets when 0
+    morph_nodes: usize,
+}
+
+/// Matches the MaterialParams block in model.frag
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct MaterialParams {
+    base_color_factor: [f32; 4],
+    emissive_factor: [f32; 3],
+    alpha_cutoff: f32,
+    metallic_factor: f32,
+    roughness_factor: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+    alpha_mode: u32,
+    pad: [u32; 3],
+}
+
+impl From<&PbrMaterial> for MaterialParams {
+    fn from(material: &PbrMaterial) -> Self {
+        Self {
+            base_color_factor: material.base_color_factor,
+            emissive_factor: material.emissive_factor,
+            alpha_cutoff: material.alpha_cutoff,
+            metallic_factor: material.metallic_factor,
+            roughness_factor: material.roughness_factor,
+            normal_scale: material.normal_scale,
+            occlusion_strength: material.occlusion_strength,
+            alpha_mode: match material.alpha_mode {
+                AlphaMode::Opaque => 0,
+                AlphaMode::Mask => 1,
+                AlphaMode::Blend => 2,
+            },
+            pad: [0; 3],
+        }
+    }
+}
+
+/// Textures of the images of one glTF model, by image index and format, so materials
+/// using the same image share one texture. Only holds weak references, a texture is
+/// destroyed along with the last material using it.
+#[derive(Default)]
+pub struct TextureCache {
+    textures: HashMap<(usize, Format), Weak<Texture>>,
+}
 
+/// Pipeline, textures and parameters of one glTF material.
 pub struct Material {
     pub renderpipeline: RenderPipeline,
-    pub texture: Option<Texture>,
+    pub alpha_mode: AlphaMode,
+    textures: Vec<Rc<Texture>>,
+    params: MaterialParams,
+    //Morph weights binding, the deltas follow it
+    morph_binding: Option<u32>,
     context: Arc<VulkanContext>,
 }
 
 impl Material {
+    /// Material for primitives using `material`, the index of a glTF material of `model`,
+    /// or the default material when it is None.
     pub fn new(
         model: Rc<GLTFModel>,
+        material: Option<usize>,
+        texture_cache: &mut TextureCache,
+        context: Arc<VulkanContext>,
+        render_pass: &RenderPass,
+        num_images: usize,
+    ) -> Self {
+        Self::create(
+            model,
+            material,
+            texture_cache,
+            context,
+            render_pass,
+            num_images,
+            VertexInputs {
+                shaders: ShaderStages::MODEL,
+                vertex_bindings: vec![VertexPBR::get_vertex_binding()],
+                joint_count: 0,
+                morph_nodes: 0,
+            },
+        )
+    }
+
+    /// Material whose pipeline takes an `InstanceTransform` per instance at vertex binding 1.
+    pub fn new_instanced(
+        model: Rc<GLTFModel>,
+        material: Option<usize>,
+        texture_cache: &mut TextureCache,
+        context: Arc<VulkanContext>,
+        render_pass: &RenderPass,
+        num_images: usize,
+    ) -> Self {
+        Self::create(
+            model,
+            material,
+            texture_cache,
+            context,
+            render_pass,
+            num_images,
+            VertexInputs {
+                shaders: ShaderStages::MODEL_INSTANCED,
+                vertex_bindings: vec![
+                    VertexPBR::get_vertex_binding(),
+                    InstanceTransform::get_vertex_binding(),
+                ],
+                joint_count: 0,
+                morph_nodes: 0,
+            },
+        )
+    }
+
+    /// Instanced material for the skins and morph targets of `model`. Skinned models take
+    /// `VertexSkinned` vertices, deformed by the joint matrices of all skins, and models
+    /// with morph targets a `VertexMorph` per vertex at vertex binding 2, blended by the
+    /// morph weights of every node. The deltas are set with `set_morph_deltas`.
+    pub fn new_animated(
+        model: Rc<GLTFModel>,
+        material: Option<usize>,
+        texture_cache: &mut TextureCache,
+        context: Arc<VulkanContext>,
+        render_pass: &RenderPass,
+        num_images: usize,
+    ) -> Self {
+        let joint_count = model.skins.iter().map(|skin| skin.joints.len()).sum();
+        let morph_nodes = if model.has_morph_targets() {
+            model.nodes.len().max(1)
+        } else {
+            0
+        };
+        let (shaders, vertex) = match (model.is_skinned(), morph_nodes > 0) {
+            (true, false) => (
+                ShaderStages::MODEL_SKINNED,
+                VertexSkinned::get_vertex_binding(),
+            ),
+            (true, true) => (
+                ShaderStages::MODEL_SKINNED_MORPH,
+                VertexSkinned::get_vertex_binding(),
+            ),
+            (false, false) => (
+                ShaderStages::MODEL_INSTANCED,
+                VertexPBR::get_vertex_binding(),
+            ),
+            (false, true) => (ShaderStages::MODEL_MORPH, VertexPBR::get_vertex_binding()),
+        };
+        let mut vertex_bindings = vec![vertex, InstanceTransform::get_vertex_binding()];
+        if morph_nodes > 0 {
+            vertex_bindings.push(VertexMorph::get_vertex_binding());
+        }
+        Self::create(
+            model,
+            material,
+            texture_cache,
+            context,
+            render_pass,
+            num_images,
+            VertexInputs {
+                shaders,
+                vertex_bindings,
+                joint_count,
+                morph_nodes,
+            },
+        )
+    }
+
+    fn create(
+        model: Rc<GLTFModel>,
+        material: Option<usize>,
+        texture_cache: &mut TextureCache,
         context: Arc<VulkanContext>,
         render_pass: &RenderPass,
         num_images: usize,
+        inputs: VertexInputs,
     ) -> Self {
-        let vertex_binding = VertexPBR::get_vertex_binding();
+        let pbr = material
+            .map(|index| model.materials[index].clone())
+            .unwrap_or_default();
+        let name = match (&pbr.name, material) {
+            (Some(name), _) => format!("{} {}", model.name, name),
+            (None, Some(index)) => format!("{} material {}", model.name, index),
+            (None, None) => format!("{} default material", model.name),
+        };
+        let mut bindings = vec![
+            UniformBinding::UniformBuffer {
+                size: std::mem::size_of::<[Mat4; 3]>() as u64,
+                stages: ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT,
+            },
+            UniformBinding::SampledImages {
+                count: 5,
+                stages: ShaderStageFlags::FRAGMENT,
+            },
+            UniformBinding::UniformBuffer {
+                size: std::mem::size_of::<MaterialParams>() as u64,
+                stages: ShaderStageFlags::FRAGMENT,
+            },
+        ];
+        if inputs.joint_count > 0 {
+            bindings.push(UniformBinding::StorageBuffer {
+                size: (inputs.joint_count * std::mem::size_of::<Mat4>()) as u64,
+                stages: ShaderStageFlags::VERTEX,
+            });
+        }
+        let mut morph_binding = None;
+        if inputs.morph_nodes > 0 {
+            morph_binding = Some(bindings.len() as u32);
+            bindings.push(UniformBinding::StorageBuffer {
+                size: (inputs.morph_nodes * std::mem::size_of::<NodeMorphWeights>()) as u64,
+                stages: ShaderStageFlags::VERTEX,
+            });
+            bindings.push(UniformBinding::ExternalStorageBuffer {
+                stages: ShaderStageFlags::VERTEX,
+            });
+        }
         let mut renderpipeline = RenderPipeline::new(
             context.clone(),
             render_pass.get_vk_renderpass(),
+            render_pass.sample_count(),
             num_images,
-            vertex_binding,
+            inputs.shaders,
+            inputs.vertex_bindings,
+            &bindings,
+            PipelineOptions {
+                double_sided: pbr.double_sided,
+                alpha_blend: pbr.alpha_mode == AlphaMode::Blend,
+            },
         );
-        let mut texture = None;
-        if !model.images.is_empty() {
-            let image = &model.images[0];
-            //TODO: Support more image formats:
-            match image.format {
-                gltf::image::Format::R8G8B8 => {
-                    let mut pad_vec = Vec::new();
-                    pad_vec.resize((image.width * image.height) as usize, 0u8);
-                    let pixels = &image.pixels;
-
-                    let pixel_chunks = pixels.chunks(3);
-
-                    let mut new_pixels = Vec::with_capacity(pixels.len() + pad_vec.len());
-                    for (pixel, pad) in pixel_chunks.zip(pad_vec) {
-                        new_pixels.push(pixel[0]);
-                        new_pixels.push(pixel[1]);
-                        new_pixels.push(pixel[2]);
-                        new_pixels.push(pad);
-                    }
-                    let tex = Texture::create_image(
-                        &context,
-                        image.width,
-                        image.height,
-                        Format::R8G8B8A8_SRGB,
-                        new_pixels.as_slice(),
-                    );
-                    renderpipeline
-                        .uniform
-                        .add_image_info(ImageInfo::new(tex.image_view, tex.image_sampler));
-                    texture = Some(tex);
-                }
-                gltf::image::Format::R8G8B8A8 => {
-                    let pixels = &image.pixels;
-
-                    let tex = Texture::create_image(
-                        &context,
-                        image.width,
-                        image.height,
-                        Format::R8G8B8A8_SRGB,
-                        pixels.as_slice(),
-                    );
-                    renderpipeline
-                        .uniform
-                        .add_image_info(ImageInfo::new(tex.image_view, tex.image_sampler));
-
-                    texture = Some(tex);
-                }
-                _ => {
-                    println!("Unsupported texture format: {:?}", image.format);
-                }
-            }
-        }
+        renderpipeline.set_name(&name);
+
+        //In the order of material_textures in model.frag, with the pixel of the default
+        const WHITE: [u8; 4] = [255, 255, 255, 255];
+        let slots = [
+            (
+                pbr.base_color_texture,
+                Format::R8G8B8A8_SRGB,
+                WHITE,
+                "base color",
+            ),
+            (
+                pbr.metallic_roughness_texture,
+                Format::R8G8B8A8_UNORM,
+                WHITE,
+                "metallic roughness",
+            ),
+            //Points straight along the vertex normal
+            (
+                pbr.normal_texture,
+                Format::R8G8B8A8_UNORM,
+                [128, 128, 255, 255],
+                "normal",
+            ),
+            (
+                pbr.occlusion_texture,
+                Format::R8G8B8A8_UNORM,
+                WHITE,
+                "occlusion",
+            ),
+            (
+                pbr.emissive_texture,
+                Format::R8G8B8A8_SRGB,
+                WHITE,
+                "emissive",
+            ),
+        ];
+        let textures: Vec<Rc<Texture>> = slots
+            .iter()
+            .map(|&(image, format, default, slot)| {
+                image
+                    .and_then(|image| {
+                        Self::cached_texture(&model, &context, texture_cache, image, format)
+                    })
+                    .unwrap_or_else(|| {
+                        let tex = Texture::create_image(&context, 1, 1, format, &default);
+                        tex.set_name(&context, &format!("{} {} default", name, slot));
+                        Rc::new(tex)
+                    })
+            })
+            .collect();
+        let image_infos: Vec<ImageInfo> = textures
+            .iter()
+            .map(|tex| ImageInfo::new(tex.image_view, tex.image_sampler))
+            .collect();
+        renderpipeline
+            .uniform
+            .set_images(TEXTURES_BINDING, &image_infos);
+
         Self {
             renderpipeline,
+            alpha_mode: pbr.alpha_mode,
+            textures,
+            params: MaterialParams::from(&pbr),
+            morph_binding,
             context,
-            texture,
         }
     }
 
+    //The texture of `image` in `format`, created when no other material uses it
+    fn cached_texture(
+        model: &GLTFModel,
+        context: &VulkanContext,
+        texture_cache: &mut TextureCache,
+        image: usize,
+        format: Format,
+    ) -> Option<Rc<Texture>> {
+        if let Some(texture) = texture_cache
+            .textures
+            .get(&(image, format))
+            .and_then(Weak::upgrade)
+        {
+            return Some(texture);
+        }
+        let name = format!("{} image {}", model.name, image);
+        let texture = Rc::new(Self::create_texture(
+            context,
+            model.images.get(image)?,
+            format,
+            &name,
+        )?);
+        texture_cache
+            .textures
+            .insert((image, format), Rc::downgrade(&texture));
+        Some(texture)
+    }
+
+    fn create_texture(
+        context: &VulkanContext,
+        image: &gltf::image::Data,
+        format: Format,
+        name: &str,
+    ) -> Option<Texture> {
+        //TODO: Support more image formats:
+        let tex = match image.format {
+            gltf::image::Format::R8G8B8 => {
+                let mut pad_vec = Vec::new();
+                pad_vec.resize((image.width * image.height) as usize, 0u8);
+                let pixels = &image.pixels;
+
+                let pixel_chunks = pixels.chunks(3);
+
+                let mut new_pixels = Vec::with_capacity(pixels.len() + pad_vec.len());
+                for (pixel, pad) in pixel_chunks.zip(pad_vec) {
+                    new_pixels.push(pixel[0]);
+                    new_pixels.push(pixel[1]);
+                    new_pixels.push(pixel[2]);
+                    new_pixels.push(pad);
+                }
+                Texture::create_image(
+                    context,
+                    image.width,
+                    image.height,
+                    format,
+                    new_pixels.as_slice(),
+                )
+            }
+            gltf::image::Format::R8G8B8A8 => Texture::create_image(
+                context,
+                image.width,
+                image.height,
+                format,
+                image.pixels.as_slice(),
+            ),
+            _ => {
+                println!("Unsupported texture format: {:?}", image.format);
+                return None;
+            }
+        };
+        tex.set_name(context, name);
+        Some(tex)
+    }
+
     //TODO: Can we in any way fix so that these bindings happen in a better way?
     //Maybe decouple the actual data of the uniform to the drawcall-creation and
     //let the material stop caring about the image_index
@@ -101,20 +412,120 @@ impl Material {
         );
     }
 
+    /// Packet drawing with this material, the mesh fills in its buffers.
+    pub fn draw_packet<'a>(&self, label: &'a str, draw: PacketDraw) -> DrawPacket<'a> {
+        DrawPacket {
+            label,
+            pipeline: self.renderpipeline.pipeline,
+            pipeline_layout: self.renderpipeline.pipeline_layout,
+            descriptor_set: self.renderpipeline.uniform.next_descriptor().desc_set,
+            vertex_buffers: vec![],
+            index_buffer: None,
+            draw,
+        }
+    }
+
+    pub fn recreate_pipeline(&mut self, render_pass: &RenderPass) {
+        self.renderpipeline
+            .recreate(render_pass.get_vk_renderpass(), render_pass.sample_count());
+    }
+
+    /// Morph target deltas of a material with morph targets, `GLTFModel::morph_deltas`.
+    pub fn set_morph_deltas(&mut self, deltas: &StorageBuffer) {
+        if let Some(binding) = self.morph_binding {
+            self.renderpipeline
+                .uniform
+                .set_external_buffer(binding + 1, deltas.object());
+        }
+    }
+
     pub fn upload_pipeline_data(&mut self, view: Mat4, proj: Mat4, model: Mat4) {
+        self.upload(view, proj, model, &[], &[]);
+    }
+
+    /// Like `upload_pipeline_data`, along with the joint matrices and morph weights of a
+    /// material from `new_animated`. Either can be empty when the model has none.
+    pub fn upload_animated_pipeline_data(
+        &mut self,
+        view: Mat4,
+        proj: Mat4,
+        model: Mat4,
+        joint_matrices: &[Mat4],
+        morph_weights: &[NodeMorphWeights],
+    ) {
+        self.upload(view, proj, model, joint_matrices, morph_weights);
+    }
+
+    fn upload(
+        &mut self,
+        view: Mat4,
+        proj: Mat4,
+        model: Mat4,
+        joint_matrices: &[Mat4],
+        morph_weights: &[NodeMorphWeights],
+    ) {
         let mat = [model, view, proj];
         let data_slice = unsafe {
             std::slice::from_raw_parts(mat.as_ptr() as *const u8, std::mem::size_of_val(&mat))
         };
-        self.renderpipeline.update_buffer(data_slice);
+        //Every buffered frame has its own parameter buffer, so they go along each time
+        let params_slice = unsafe {
+            std::slice::from_raw_parts(
+                &self.params as *const MaterialParams as *const u8,
+                std::mem::size_of::<MaterialParams>(),
+            )
+        };
+        let joints_slice = unsafe {
+            std::slice::from_raw_parts(
+                joint_matrices.as_ptr() as *const u8,
+                std::mem::size_of_val(joint_matrices),
+            )
+        };
+        let morph_slice = unsafe {
+            std::slice::from_raw_parts(
+                morph_weights.as_ptr() as *const u8,
+                std::mem::size_of_val(morph_weights),
+            )
+        };
+        let mut writes = vec![(0, data_slice), (PARAMS_BINDING, params_slice)];
+        if !joint_matrices.is_empty() {
+            writes.push((JOINTS_BINDING, joints_slice));
+        }
+        if let Some(binding) = self.morph_binding.filter(|_| !morph_weights.is_empty()) {
+            writes.push((binding, morph_slice));
+        }
+        self.renderpipeline.update_bindings(&writes);
     }
 }
 
 impl Drop for Material {
     fn drop(&mut self) {
-        if let Some(texture) = self.texture.take() {
-            texture.destroy(&self.context);
+        //Textures shared with other materials are left to the last of them
+        for texture in self.textures.drain(..) {
+            if let Ok(texture) = Rc::try_unwrap(texture) {
+                texture.destroy(&self.context);
+            }
         }
         self.renderpipeline.destroy();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::{offset_of, size_of};
+
+    #[test]
+    fn test_material_params_layout() {
+        //std140 offsets of the MaterialParams block in model.frag
+        assert_eq!(size_of::<MaterialParams>(), 64);
+        assert_eq!(offset_of!(MaterialParams, base_color_factor), 0);
+        assert_eq!(offset_of!(MaterialParams, emissive_factor), 16);
+        assert_eq!(offset_of!(MaterialParams, alpha_cutoff), 28);
+        assert_eq!(offset_of!(MaterialParams, metallic_factor), 32);
+        assert_eq!(offset_of!(MaterialParams, roughness_factor), 36);
+        assert_eq!(offset_of!(MaterialParams, normal_scale), 40);
+        assert_eq!(offset_of!(MaterialParams, occlusion_strength), 44);
+        assert_eq!(offset_of!(MaterialParams, alpha_mode), 48);
+    }
+}
@@ -1,7 +1,9 @@
 use crate::util::GLTFModel;
 
 use katla_vulkan::context::VulkanContext;
-use katla_vulkan::{self, IndexBuffer, IndexType, VertexBuffer};
+use katla_vulkan::{
+    self, DrawIndexedIndirectCommand, DrawPacket, IndexBuffer, IndexType, PacketDraw, VertexBuffer,
+};
 
 use std::{rc::Rc, sync::Arc};
 
@@ -18,14 +20,42 @@ pub struct Mesh {
 
 impl Mesh {
     pub fn new_from_model(model: Rc<GLTFModel>, context: Arc<VulkanContext>) -> Self {
+        let vertex_buffer = Self::create_vertex_buffer(&context, model.vertpbr());
+        Self::new_with_vertex_buffer(model, context, vertex_buffer)
+    }
+
+    /// Mesh of `VertexSkinned` vertices, for a model that `GLTFModel::is_skinned`.
+    pub fn new_skinned_from_model(model: Rc<GLTFModel>, context: Arc<VulkanContext>) -> Self {
+        let vertex_buffer = Self::create_vertex_buffer(&context, model.vertskinned());
+        Self::new_with_vertex_buffer(model, context, vertex_buffer)
+    }
+
+    fn new_with_vertex_buffer(
+        model: Rc<GLTFModel>,
+        context: Arc<VulkanContext>,
+        vertex_buffer: Option<VertexBuffer>,
+    ) -> Self {
         let index_type = match model.index_stride {
             1 => IndexType::UINT8_EXT,
             2 => IndexType::UINT16,
             4 => IndexType::UINT32,
             _ => IndexType::NONE_KHR,
         };
-        let index_buffer = Self::create_index_buffer(&context, model.index_data(), index_type);
-        let vertex_buffer = Self::create_vertex_buffer(&context, model.vertpbr());
+        let index_buffer = if index_type == IndexType::UINT8_EXT
+            && !context.capabilities.supports_index_type(index_type)
+        {
+            //Without VK_EXT_index_type_uint8 8 bit indices are widened to 16 bit
+            let widened: Vec<u16> = model.index_data().iter().map(|&i| i as u16).collect();
+            Self::create_index_buffer(&context, widened, IndexType::UINT16)
+        } else {
+            Self::create_index_buffer(&context, model.index_data(), index_type)
+        };
+        if let Some(index_buffer) = &index_buffer {
+            index_buffer.set_name(&format!("{} index buffer", model.name));
+        }
+        if let Some(vertex_buffer) = &vertex_buffer {
+            vertex_buffer.set_name(&format!("{} vertex buffer", model.name));
+        }
 
         Self {
             vertex_buffer,
@@ -82,17 +112,84 @@ impl Mesh {
     }
 
     pub fn draw(&self, command_buffer: &katla_vulkan::CommandBuffer) {
+        self.draw_instances(command_buffer, 1);
+    }
+
+    /// Draws `instance_count` copies in one call, reading per-instance data from
+    /// `instance_buffer` at vertex binding 1.
+    pub fn draw_instanced(
+        &self,
+        command_buffer: &katla_vulkan::CommandBuffer,
+        instance_buffer: &VertexBuffer,
+        instance_count: u32,
+    ) {
+        command_buffer.bind_vertex_buffers(1, &[instance_buffer.object()], &[0]);
+        self.draw_instances(command_buffer, instance_count);
+    }
+
+    /// Indirect draw of the whole mesh for instance `first_instance`, None if the mesh
+    /// has no index buffer.
+    pub fn indirect_command(&self, first_instance: u32) -> Option<DrawIndexedIndirectCommand> {
+        match (&self.index_buffer, &self.vertex_buffer) {
+            (Some(index_buffer), Some(_)) => Some(DrawIndexedIndirectCommand {
+                index_count: index_buffer.count(),
+                instance_count: 1,
+                first_index: 0,
+                vertex_offset: 0,
+                first_instance,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Draw of `instance_count` copies of the whole mesh, None if it has no vertices.
+    pub fn packet_draw(&self, instance_count: u32) -> Option<PacketDraw> {
+        match (&self.index_buffer, &self.vertex_buffer) {
+            (Some(index_buffer), Some(_)) => Some(PacketDraw::Indexed {
+                index_count: index_buffer.count(),
+                instance_count,
+                first_index: 0,
+                first_instance: 0,
+            }),
+            (None, Some(vertex_buffer)) => Some(PacketDraw::Vertices {
+                vertex_count: vertex_buffer.count(),
+                instance_count,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Sets the vertex buffer, at binding 0, and the index buffer of `packet`.
+    pub fn bind_packet(&self, packet: &mut DrawPacket) {
+        if let Some(vertex_buffer) = &self.vertex_buffer {
+            packet.vertex_buffers.insert(0, vertex_buffer.object());
+        }
+        if let Some(index_buffer) = &self.index_buffer {
+            packet.index_buffer = Some((index_buffer.object(), index_buffer.index_type));
+        }
+    }
+
+    /// Binds the vertex and index buffers for indirect draws.
+    pub fn bind_buffers(&self, command_buffer: &katla_vulkan::CommandBuffer) {
+        if let (Some(index_buffer), Some(vertex_buffer)) = (&self.index_buffer, &self.vertex_buffer)
+        {
+            command_buffer.bind_index_buffer(index_buffer.object(), 0, index_buffer.index_type);
+            command_buffer.bind_vertex_buffers(0, &[vertex_buffer.object()], &[0]);
+        }
+    }
+
+    fn draw_instances(&self, command_buffer: &katla_vulkan::CommandBuffer, instance_count: u32) {
         if let Some(index_buffer) = &self.index_buffer {
             command_buffer.bind_index_buffer(index_buffer.object(), 0, index_buffer.index_type);
 
             if let Some(vertex_buffer) = &self.vertex_buffer {
                 command_buffer.bind_vertex_buffers(0, &[vertex_buffer.object()], &[0]);
-                command_buffer.draw_indexed(index_buffer.count(), 1, 0, 0, 0);
+                command_buffer.draw_indexed(index_buffer.count(), instance_count, 0, 0, 0);
             }
         } else {
             if let Some(vertex_buffer) = &self.vertex_buffer {
                 command_buffer.bind_vertex_buffers(0, &[vertex_buffer.object()], &[0]);
-                command_buffer.draw_array(vertex_buffer.count(), 1, 0, 0);
+                command_buffer.draw_array(vertex_buffer.count(), instance_count, 0, 0);
             }
         }
     }
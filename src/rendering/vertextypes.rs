@@ -1,4 +1,8 @@
-use katla_vulkan::vertexbinding::{VertexBinding, VertexFormat};
+use katla_math::Mat4;
+use katla_vulkan::{
+    vertexbinding::{VertexBinding, VertexFormat},
+    VertexInputRate,
+};
 
 #[repr(C)]
 #[derive(Default, Debug, Clone)]
@@ -10,6 +14,7 @@ impl VertexPosition {
     pub fn get_vertex_binding() -> VertexBinding {
         VertexBinding {
             formats: vec![VertexFormat::RGB32f],
+            input_rate: VertexInputRate::VERTEX,
         }
     }
 }
@@ -32,6 +37,7 @@ impl VertexNormal {
     pub fn get_vertex_binding() -> VertexBinding {
         VertexBinding {
             formats: vec![VertexFormat::RGB32f, VertexFormat::RGB32f],
+            input_rate: VertexInputRate::VERTEX,
         }
     }
 }
@@ -62,6 +68,167 @@ impl VertexPBR {
                 VertexFormat::RGBA32f,
                 VertexFormat::RG32f,
             ],
+            input_rate: VertexInputRate::VERTEX,
         }
     }
 }
+
+/// The joints deforming a vertex and their weights, which sum to 1. Joints index the
+/// joint matrices of the whole model, vertices no skin deforms have all weights 0.
+#[repr(C)]
+#[derive(Default, Debug, Clone)]
+pub struct VertexJoints {
+    pub joints: [u32; 4],
+    pub weights: [f32; 4],
+}
+
+/// A `VertexPBR` followed by its `VertexJoints`, see model_pbr_skinned.vert.
+#[repr(C)]
+#[derive(Default, Debug, Clone)]
+pub struct VertexSkinned {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub tangent: [f32; 4],
+    pub tex_coord0: [f32; 2],
+    pub joints: [u32; 4],
+    pub weights: [f32; 4],
+}
+
+impl VertexSkinned {
+    pub fn get_vertex_binding() -> VertexBinding {
+        VertexBinding {
+            formats: vec![
+                VertexFormat::RGB32f,
+                VertexFormat::RGB32f,
+                VertexFormat::RGBA32f,
+                VertexFormat::RG32f,
+                VertexFormat::RGBA32u,
+                VertexFormat::RGBA32f,
+            ],
+            input_rate: VertexInputRate::VERTEX,
+        }
+    }
+}
+
+/// Most morph targets one node blends at a time, the ones with the largest weights win.
+/// Matches MAX_ACTIVE_MORPH_TARGETS in morph.glsl.
+pub const MAX_ACTIVE_MORPH_TARGETS: usize = 8;
+
+/// Where the morph target deltas of a vertex are, see morph.glsl. Delta `i` of the vertex
+/// is at `first_delta + i * target_stride`, vertices without targets have `target_count` 0.
+#[repr(C)]
+#[derive(Default, Debug, Clone)]
+pub struct VertexMorph {
+    pub first_delta: u32,
+    pub target_stride: u32,
+    pub target_count: u32,
+}
+
+impl VertexMorph {
+    pub fn get_vertex_binding() -> VertexBinding {
+        VertexBinding {
+            formats: vec![VertexFormat::RGB32u],
+            input_rate: VertexInputRate::VERTEX,
+        }
+    }
+}
+
+/// How much one morph target moves one vertex. The w components are padding.
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct MorphDelta {
+    pub position: [f32; 4],
+    pub normal: [f32; 4],
+    pub tangent: [f32; 4],
+}
+
+/// The morph targets one node blends, unused slots have weight 0. Matches
+/// NodeMorphWeights in morph.glsl.
+#[repr(C)]
+#[derive(Default, Debug, Clone)]
+pub struct NodeMorphWeights {
+    pub targets: [u32; MAX_ACTIVE_MORPH_TARGETS],
+    pub weights: [f32; MAX_ACTIVE_MORPH_TARGETS],
+}
+
+impl NodeMorphWeights {
+    /// Keeps the `MAX_ACTIVE_MORPH_TARGETS` weights furthest from 0.
+    pub fn from_weights(weights: &[f32]) -> Self {
+        let mut active: Vec<(usize, f32)> = weights
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|&(_, weight)| weight != 0.0)
+            .collect();
+        active.sort_by(|a, b| b.1.abs().total_cmp(&a.1.abs()));
+        let mut out = Self::default();
+        for (slot, (target, weight)) in active
+            .into_iter()
+            .take(MAX_ACTIVE_MORPH_TARGETS)
+            .enumerate()
+        {
+            out.targets[slot] = target as u32;
+            out.weights[slot] = weight;
+        }
+        out
+    }
+}
+
+/// Per-instance data of an `InstancedModel`, a column-major world matrix.
+#[repr(C)]
+#[derive(Default, Debug, Clone)]
+pub struct InstanceTransform {
+    pub world: [[f32; 4]; 4],
+}
+
+impl InstanceTransform {
+    pub fn get_vertex_binding() -> VertexBinding {
+        VertexBinding {
+            formats: vec![
+                VertexFormat::RGBA32f,
+                VertexFormat::RGBA32f,
+                VertexFormat::RGBA32f,
+                VertexFormat::RGBA32f,
+            ],
+            input_rate: VertexInputRate::INSTANCE,
+        }
+    }
+}
+
+impl From<&Mat4> for InstanceTransform {
+    fn from(mat: &Mat4) -> Self {
+        Self {
+            world: [mat.0[0].0, mat.0[1].0, mat.0[2].0, mat.0[3].0],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_morph_weights_keep_largest() {
+        //Ten targets, target 2 and 7 are unused
+        let weights = [0.1, -0.9, 0.0, 0.5, 0.2, -0.3, 0.8, 0.0, 0.05, 0.4];
+        let morph = NodeMorphWeights::from_weights(&weights);
+        assert_eq!(morph.targets, [1, 6, 3, 9, 5, 4, 0, 8]);
+        assert_eq!(morph.weights, [-0.9, 0.8, 0.5, 0.4, -0.3, 0.2, 0.1, 0.05]);
+
+        //More than 8 non-zero weights, the smallest magnitudes are dropped
+        let weights = [0.01, 1.0, 0.9, 0.8, 0.7, -0.6, 0.5, 0.4, 0.3, -0.02];
+        let morph = NodeMorphWeights::from_weights(&weights);
+        assert_eq!(morph.targets, [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(morph.weights, [1.0, 0.9, 0.8, 0.7, -0.6, 0.5, 0.4, 0.3]);
+    }
+
+    #[test]
+    fn test_morph_weights_unused_slots() {
+        let morph = NodeMorphWeights::from_weights(&[0.0, 0.25, 0.0]);
+        assert_eq!(morph.targets, [1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(morph.weights, [0.25, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+
+        let morph = NodeMorphWeights::from_weights(&[]);
+        assert_eq!(morph.weights, [0.0; MAX_ACTIVE_MORPH_TARGETS]);
+    }
+}
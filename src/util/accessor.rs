@@ -0,0 +1,324 @@
+use gltf::accessor::{Accessor, DataType, Item, Iter};
+use gltf::buffer::Data as BufferData;
+
+/// Component types a float attribute can be stored as. Integer types are either normalized
+/// to [0, 1] or [-1, 1], or converted as they are, see KHR_mesh_quantization.
+trait Component: Item + Copy {
+    fn to_f32(self, normalized: bool) -> f32;
+}
+
+impl Component for f32 {
+    fn to_f32(self, _normalized: bool) -> f32 {
+        self
+    }
+}
+
+impl Component for i8 {
+    fn to_f32(self, normalized: bool) -> f32 {
+        if normalized {
+            (self as f32 / i8::MAX as f32).max(-1.0)
+        } else {
+            self as f32
+        }
+    }
+}
+
+impl Component for u8 {
+    fn to_f32(self, normalized: bool) -> f32 {
+        if normalized {
+            self as f32 / u8::MAX as f32
+        } else {
+            self as f32
+        }
+    }
+}
+
+impl Component for i16 {
+    fn to_f32(self, normalized: bool) -> f32 {
+        if normalized {
+            (self as f32 / i16::MAX as f32).max(-1.0)
+        } else {
+            self as f32
+        }
+    }
+}
+
+impl Component for u16 {
+    fn to_f32(self, normalized: bool) -> f32 {
+        if normalized {
+            self as f32 / u16::MAX as f32
+        } else {
+            self as f32
+        }
+    }
+}
+
+impl Component for u32 {
+    fn to_f32(self, _normalized: bool) -> f32 {
+        self as f32
+    }
+}
+
+//The accessor iterator takes care of the byte stride and of sparse substitution
+fn iter<'a, T: Item>(accessor: &Accessor<'a>, buffers: &'a [BufferData]) -> Option<Iter<'a, T>> {
+    Iter::new(accessor.clone(), |buffer| {
+        buffers.get(buffer.index()).map(|data| &data[..])
+    })
+}
+
+fn read_components<T, const N: usize>(
+    accessor: &Accessor,
+    buffers: &[BufferData],
+) -> Option<Vec<[f32; N]>>
+where
+    T: Component,
+    [T; N]: Item,
+{
+    let normalized = accessor.normalized();
+    let items = iter::<[T; N]>(accessor, buffers)?;
+    Some(
+        items
+            .map(|item| item.map(|component| component.to_f32(normalized)))
+            .collect(),
+    )
+}
+
+/// Reads an accessor of `N` components per element as floats, whatever its component type.
+/// None when the accessor has another number of components or its data is missing.
+pub fn read_floats<const N: usize>(
+    accessor: &Accessor,
+    buffers: &[BufferData],
+) -> Option<Vec<[f32; N]>>
+where
+    [f32; N]: Item,
+    [i8; N]: Item,
+    [u8; N]: Item,
+    [i16; N]: Item,
+    [u16; N]: Item,
+    [u32; N]: Item,
+{
+    if accessor.dimensions().multiplicity() != N {
+        println!(
+            "Accessor {} has {:?} elements, expected {} components",
+            accessor.index(),
+            accessor.dimensions(),
+            N
+        );
+        return None;
+    }
+    match accessor.data_type() {
+        DataType::F32 => read_components::<f32, N>(accessor, buffers),
+        DataType::I8 => read_components::<i8, N>(accessor, buffers),
+        DataType::U8 => read_components::<u8, N>(accessor, buffers),
+        DataType::I16 => read_components::<i16, N>(accessor, buffers),
+        DataType::U16 => read_components::<u16, N>(accessor, buffers),
+        DataType::U32 => read_components::<u32, N>(accessor, buffers),
+    }
+}
+
+fn read_scalar_components<T: Component>(
+    accessor: &Accessor,
+    buffers: &[BufferData],
+) -> Option<Vec<f32>> {
+    let normalized = accessor.normalized();
+    Some(
+        iter::<T>(accessor, buffers)?
+            .map(|component| component.to_f32(normalized))
+            .collect(),
+    )
+}
+
+/// Reads a scalar accessor as floats, whatever its component type, like `read_floats`.
+pub fn read_scalars(accessor: &Accessor, buffers: &[BufferData]) -> Option<Vec<f32>> {
+    if accessor.dimensions().multiplicity() != 1 {
+        println!(
+            "Accessor {} has {:?} elements, expected scalars",
+            accessor.index(),
+            accessor.dimensions()
+        );
+        return None;
+    }
+    match accessor.data_type() {
+        DataType::F32 => read_scalar_components::<f32>(accessor, buffers),
+        DataType::I8 => read_scalar_components::<i8>(accessor, buffers),
+        DataType::U8 => read_scalar_components::<u8>(accessor, buffers),
+        DataType::I16 => read_scalar_components::<i16>(accessor, buffers),
+        DataType::U16 => read_scalar_components::<u16>(accessor, buffers),
+        DataType::U32 => read_scalar_components::<u32>(accessor, buffers),
+    }
+}
+
+/// Reads an index accessor widened to u32.
+pub fn read_indices(accessor: &Accessor, buffers: &[BufferData]) -> Option<Vec<u32>> {
+    match accessor.data_type() {
+        DataType::U8 => Some(iter::<u8>(accessor, buffers)?.map(u32::from).collect()),
+        DataType::U16 => Some(iter::<u16>(accessor, buffers)?.map(u32::from).collect()),
+        DataType::U32 => Some(iter::<u32>(accessor, buffers)?.collect()),
+        data_type => {
+            println!(
+                "Accessor {} has {:?} indices, expected unsigned integers",
+                accessor.index(),
+                data_type
+            );
+            None
+        }
+    }
+}
+
+/// Reads a JOINTS accessor widened to u32.
+pub fn read_joints(accessor: &Accessor, buffers: &[BufferData]) -> Option<Vec<[u32; 4]>> {
+    if accessor.dimensions().multiplicity() != 4 {
+        println!(
+            "Accessor {} has {:?} joints, expected 4",
+            accessor.index(),
+            accessor.dimensions()
+        );
+        return None;
+    }
+    match accessor.data_type() {
+        DataType::U8 => Some(
+            iter::<[u8; 4]>(accessor, buffers)?
+                .map(|joints| joints.map(u32::from))
+                .collect(),
+        ),
+        DataType::U16 => Some(
+            iter::<[u16; 4]>(accessor, buffers)?
+                .map(|joints| joints.map(u32::from))
+                .collect(),
+        ),
+        data_type => {
+            println!(
+                "Accessor {} has {:?} joints, expected u8 or u16",
+                accessor.index(),
+                data_type
+            );
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    //Interleaved vertices of two f32 and four u8, a quantized i16 position per 8 bytes, then
+    //a base, indices and values for sparse accessors
+    const GLTF: &str = r#"{
+        "asset": { "version": "2.0" },
+        "buffers": [{ "byteLength": 84 }],
+        "bufferViews": [
+            { "buffer": 0, "byteOffset": 0, "byteLength": 24, "byteStride": 12 },
+            { "buffer": 0, "byteOffset": 24, "byteLength": 16, "byteStride": 8 },
+            { "buffer": 0, "byteOffset": 40, "byteLength": 24 },
+            { "buffer": 0, "byteOffset": 64, "byteLength": 4 },
+            { "buffer": 0, "byteOffset": 68, "byteLength": 16 }
+        ],
+        "accessors": [
+            { "bufferView": 0, "byteOffset": 0, "componentType": 5126, "count": 2, "type": "VEC2" },
+            { "bufferView": 0, "byteOffset": 8, "componentType": 5121, "normalized": true,
+              "count": 2, "type": "VEC4" },
+            { "bufferView": 1, "componentType": 5122, "count": 2, "type": "VEC3" },
+            { "bufferView": 2, "componentType": 5126, "count": 3, "type": "VEC2",
+              "sparse": { "count": 2, "indices": { "bufferView": 3, "componentType": 5123 },
+                          "values": { "bufferView": 4 } } },
+            { "componentType": 5126, "count": 3, "type": "VEC2",
+              "sparse": { "count": 2, "indices": { "bufferView": 3, "componentType": 5123 },
+                          "values": { "bufferView": 4 } } },
+            { "bufferView": 0, "byteOffset": 8, "componentType": 5121, "count": 2, "type": "VEC4" },
+            { "bufferView": 3, "componentType": 5123, "count": 2, "type": "SCALAR" }
+        ]
+    }"#;
+
+    fn buffer_data() -> Vec<u8> {
+        let mut data = vec![];
+        for (position, color) in [
+            ([1.0f32, 2.0], [0u8, 51, 255, 1]),
+            ([3.0, 4.0], [5, 6, 7, 8]),
+        ] {
+            position.iter().for_each(|v| data.extend(v.to_le_bytes()));
+            data.extend(color);
+        }
+        for position in [[-3i16, 100, 32767], [1, -32768, 0]] {
+            position.iter().for_each(|v| data.extend(v.to_le_bytes()));
+            data.extend([0, 0]);
+        }
+        for v in [1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0] {
+            data.extend(v.to_le_bytes());
+        }
+        for index in [0u16, 2] {
+            data.extend(index.to_le_bytes());
+        }
+        for v in [10.0f32, 20.0, 50.0, 60.0] {
+            data.extend(v.to_le_bytes());
+        }
+        data
+    }
+
+    fn load() -> (gltf::Document, Vec<BufferData>) {
+        let gltf = gltf::Gltf::from_slice(GLTF.as_bytes()).unwrap();
+        let buffers = gltf::import_buffers(&gltf.document, None, Some(buffer_data())).unwrap();
+        (gltf.document, buffers)
+    }
+
+    #[test]
+    fn test_normalized_components() {
+        assert_eq!(127i8.to_f32(true), 1.0);
+        assert_eq!((-127i8).to_f32(true), -1.0);
+        //-128 would be below -1
+        assert_eq!((-128i8).to_f32(true), -1.0);
+        assert_eq!(0i8.to_f32(true), 0.0);
+        assert_eq!(255u8.to_f32(true), 1.0);
+        assert_relative_eq!(51u8.to_f32(true), 0.2);
+        assert_eq!(32767i16.to_f32(true), 1.0);
+        assert_eq!((-32768i16).to_f32(true), -1.0);
+        assert_relative_eq!((-16384i16).to_f32(true), -16384.0 / 32767.0);
+        assert_eq!(65535u16.to_f32(true), 1.0);
+        assert_eq!(0u16.to_f32(true), 0.0);
+    }
+
+    #[test]
+    fn test_unnormalized_components() {
+        assert_eq!((-128i8).to_f32(false), -128.0);
+        assert_eq!(255u8.to_f32(false), 255.0);
+        assert_eq!((-32768i16).to_f32(false), -32768.0);
+        assert_eq!(65535u16.to_f32(false), 65535.0);
+    }
+
+    #[test]
+    fn test_read_interleaved() {
+        let (document, buffers) = load();
+        let accessors: Vec<Accessor> = document.accessors().collect();
+        let positions = read_floats::<2>(&accessors[0], &buffers).unwrap();
+        assert_eq!(positions, [[1.0, 2.0], [3.0, 4.0]]);
+        let colors = read_floats::<4>(&accessors[1], &buffers).unwrap();
+        assert_relative_eq!(colors[0][1], 0.2);
+        assert_eq!(colors[0][2], 1.0);
+        assert_relative_eq!(colors[1][0], 5.0 / 255.0);
+        //The same bytes as joints
+        let joints = read_joints(&accessors[5], &buffers).unwrap();
+        assert_eq!(joints, [[0, 51, 255, 1], [5, 6, 7, 8]]);
+        //Wrong number of components
+        assert!(read_floats::<3>(&accessors[0], &buffers).is_none());
+    }
+
+    #[test]
+    fn test_read_quantized() {
+        let (document, buffers) = load();
+        let accessor = document.accessors().nth(2).unwrap();
+        let positions = read_floats::<3>(&accessor, &buffers).unwrap();
+        assert_eq!(positions, [[-3.0, 100.0, 32767.0], [1.0, -32768.0, 0.0]]);
+    }
+
+    #[test]
+    fn test_read_sparse() {
+        let (document, buffers) = load();
+        let accessors: Vec<Accessor> = document.accessors().collect();
+        let with_base = read_floats::<2>(&accessors[3], &buffers).unwrap();
+        assert_eq!(with_base, [[10.0, 20.0], [3.0, 4.0], [50.0, 60.0]]);
+        //Without a base buffer view the other elements are 0
+        let without_base = read_floats::<2>(&accessors[4], &buffers).unwrap();
+        assert_eq!(without_base, [[10.0, 20.0], [0.0, 0.0], [50.0, 60.0]]);
+        assert_eq!(read_indices(&accessors[6], &buffers).unwrap(), [0, 2]);
+    }
+}
@@ -0,0 +1,351 @@
+use gltf::buffer::Data as BufferData;
+use katla_math::{Quat, Vec3};
+
+use super::{read_floats, read_scalars};
+
+/// How values between two keyframes are found, see the glTF sampler interpolation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    /// The value of the previous keyframe
+    Step,
+    /// Lerp, or slerp for rotations
+    Linear,
+    /// Hermite spline through the keyframes, each keyframe has an in and out tangent
+    CubicSpline,
+}
+
+/// The node property an `AnimationChannel` animates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnimationProperty {
+    Translation,
+    /// Unit quaternion in x, y, z, w order
+    Rotation,
+    Scale,
+    /// Morph target weights of the mesh of the node
+    MorphWeights,
+}
+
+/// Keyframes of one property of one node.
+#[derive(Clone, Debug)]
+pub struct AnimationChannel {
+    pub node: usize,
+    pub property: AnimationProperty,
+    pub interpolation: Interpolation,
+    /// Keyframe times in seconds, increasing
+    pub times: Vec<f32>,
+    /// `components` floats per keyframe, or in tangent, value and out tangent per keyframe
+    /// with `Interpolation::CubicSpline`
+    pub values: Vec<f32>,
+    /// 3 for translations and scales, 4 for rotations, the morph target count for weights
+    pub components: usize,
+}
+
+impl AnimationChannel {
+    fn from_gltf(channel: &gltf::animation::Channel, buffers: &[BufferData]) -> Option<Self> {
+        let sampler = channel.sampler();
+        let interpolation = match sampler.interpolation() {
+            gltf::animation::Interpolation::Step => Interpolation::Step,
+            gltf::animation::Interpolation::Linear => Interpolation::Linear,
+            gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+        };
+        let times = read_scalars(&sampler.input(), buffers)?;
+        let output = sampler.output();
+        let (property, values, components) = match channel.target().property() {
+            gltf::animation::Property::Translation => (
+                AnimationProperty::Translation,
+                read_floats::<3>(&output, buffers)?.concat(),
+                3,
+            ),
+            gltf::animation::Property::Rotation => (
+                AnimationProperty::Rotation,
+                read_floats::<4>(&output, buffers)?.concat(),
+                4,
+            ),
+            gltf::animation::Property::Scale => (
+                AnimationProperty::Scale,
+                read_floats::<3>(&output, buffers)?.concat(),
+                3,
+            ),
+            gltf::animation::Property::MorphTargetWeights => {
+                let values = read_scalars(&output, buffers)?;
+                let per_keyframe = match interpolation {
+                    Interpolation::CubicSpline => 3,
+                    _ => 1,
+                };
+                let components = values.len() / (times.len() * per_keyframe).max(1);
+                (AnimationProperty::MorphWeights, values, components)
+            }
+        };
+        let per_keyframe = match interpolation {
+            Interpolation::CubicSpline => 3 * components,
+            _ => components,
+        };
+        if times.is_empty() || components == 0 || values.len() < times.len() * per_keyframe {
+            println!(
+                "Skipping animation channel of node {} with {} values for {} keyframes",
+                channel.target().node().index(),
+                values.len(),
+                times.len()
+            );
+            return None;
+        }
+        Some(Self {
+            node: channel.target().node().index(),
+            property,
+            interpolation,
+            times,
+            values,
+            components,
+        })
+    }
+
+    //Cubic splines store the in tangent, value and out tangent of a keyframe at offsets 0-2,
+    //the other interpolations only the value
+    fn value(&self, keyframe: usize, offset: usize) -> &[f32] {
+        let start = match self.interpolation {
+            Interpolation::CubicSpline => (3 * keyframe + offset) * self.components,
+            _ => keyframe * self.components,
+        };
+        &self.values[start..start + self.components]
+    }
+
+    /// Samples the channel at `time`, clamped to its first and last keyframe.
+    /// `out` gets `components` floats.
+    pub fn sample(&self, time: f32, out: &mut Vec<f32>) {
+        out.clear();
+        let last = self.times.len() - 1;
+        //Index of the first keyframe after time
+        let next = self.times.partition_point(|&keyframe| keyframe <= time);
+        if next == 0 || next > last {
+            let keyframe = if next == 0 { 0 } else { last };
+            out.extend_from_slice(self.value(keyframe, 1));
+            return;
+        }
+        let previous = next - 1;
+        let delta = self.times[next] - self.times[previous];
+        let ratio = if delta > 0.0 {
+            (time - self.times[previous]) / delta
+        } else {
+            0.0
+        };
+
+        match self.interpolation {
+            Interpolation::Step => out.extend_from_slice(self.value(previous, 1)),
+            Interpolation::Linear => {
+                let (a, b) = (self.value(previous, 1), self.value(next, 1));
+                match self.property {
+                    AnimationProperty::Rotation => {
+                        let rotation = Quat::slerp(
+                            Quat::new_from_xyzw(a[0], a[1], a[2], a[3]),
+                            Quat::new_from_xyzw(b[0], b[1], b[2], b[3]),
+                            ratio,
+                        );
+                        out.extend((0..4).map(|i| rotation[i]));
+                    }
+                    AnimationProperty::Translation | AnimationProperty::Scale => {
+                        let vector =
+                            Vec3::lerp(Vec3([a[0], a[1], a[2]]), Vec3([b[0], b[1], b[2]]), ratio);
+                        out.extend_from_slice(&vector.0);
+                    }
+                    AnimationProperty::MorphWeights => {
+                        out.extend(a.iter().zip(b).map(|(a, b)| a + (b - a) * ratio));
+                    }
+                }
+            }
+            Interpolation::CubicSpline => {
+                let (t, t2, t3) = (ratio, ratio * ratio, ratio * ratio * ratio);
+                let value_weight0 = 2.0 * t3 - 3.0 * t2 + 1.0;
+                let tangent_weight0 = (t3 - 2.0 * t2 + t) * delta;
+                let value_weight1 = -2.0 * t3 + 3.0 * t2;
+                let tangent_weight1 = (t3 - t2) * delta;
+                let value0 = self.value(previous, 1);
+                let out_tangent0 = self.value(previous, 2);
+                let value1 = self.value(next, 1);
+                let in_tangent1 = self.value(next, 0);
+                out.extend((0..self.components).map(|i| {
+                    value_weight0 * value0[i]
+                        + tangent_weight0 * out_tangent0[i]
+                        + value_weight1 * value1[i]
+                        + tangent_weight1 * in_tangent1[i]
+                }));
+                if self.property == AnimationProperty::Rotation {
+                    let mut rotation = Quat::new_from_xyzw(out[0], out[1], out[2], out[3]);
+                    rotation.normalize();
+                    for (i, component) in out.iter_mut().enumerate() {
+                        *component = rotation[i];
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A glTF animation, its channels play together.
+#[derive(Clone, Debug)]
+pub struct AnimationClip {
+    pub name: Option<String>,
+    pub channels: Vec<AnimationChannel>,
+    /// Time of the last keyframe of all channels, in seconds
+    pub duration: f32,
+}
+
+impl AnimationClip {
+    pub fn from_gltf(animation: &gltf::Animation, buffers: &[BufferData]) -> Self {
+        let channels: Vec<AnimationChannel> = animation
+            .channels()
+            .filter_map(|channel| AnimationChannel::from_gltf(&channel, buffers))
+            .collect();
+        let duration = channels
+            .iter()
+            .filter_map(|channel| channel.times.last().copied())
+            .fold(0.0, f32::max);
+        Self {
+            name: animation.name().map(str::to_owned),
+            channels,
+            duration,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use std::f32::consts::FRAC_PI_4;
+
+    fn channel(
+        property: AnimationProperty,
+        interpolation: Interpolation,
+        times: Vec<f32>,
+        values: Vec<f32>,
+        components: usize,
+    ) -> AnimationChannel {
+        AnimationChannel {
+            node: 0,
+            property,
+            interpolation,
+            times,
+            values,
+            components,
+        }
+    }
+
+    fn sample(channel: &AnimationChannel, time: f32) -> Vec<f32> {
+        let mut out = vec![];
+        channel.sample(time, &mut out);
+        out
+    }
+
+    #[test]
+    fn test_step_holds_previous_keyframe() {
+        let weights = channel(
+            AnimationProperty::MorphWeights,
+            Interpolation::Step,
+            vec![0.0, 1.0, 2.0],
+            vec![1.0, 2.0, 3.0],
+            1,
+        );
+        assert_eq!(sample(&weights, 0.5), [1.0]);
+        assert_eq!(sample(&weights, 1.0), [2.0]);
+        assert_eq!(sample(&weights, 1.99), [2.0]);
+    }
+
+    #[test]
+    fn test_clamped_to_first_and_last_keyframe() {
+        let weights = channel(
+            AnimationProperty::MorphWeights,
+            Interpolation::Linear,
+            vec![1.0, 2.0],
+            vec![1.0, 3.0],
+            1,
+        );
+        assert_eq!(sample(&weights, 0.0), [1.0]);
+        assert_eq!(sample(&weights, 5.0), [3.0]);
+    }
+
+    #[test]
+    fn test_linear_translation_and_weights() {
+        let translation = channel(
+            AnimationProperty::Translation,
+            Interpolation::Linear,
+            vec![0.0, 2.0],
+            vec![0.0, 0.0, 0.0, 2.0, 4.0, -2.0],
+            3,
+        );
+        let value = sample(&translation, 0.5);
+        assert_relative_eq!(value[0], 0.5);
+        assert_relative_eq!(value[1], 1.0);
+        assert_relative_eq!(value[2], -0.5);
+
+        let weights = channel(
+            AnimationProperty::MorphWeights,
+            Interpolation::Linear,
+            vec![0.0, 1.0],
+            vec![0.0, 1.0, 1.0, 0.0],
+            2,
+        );
+        let value = sample(&weights, 0.25);
+        assert_relative_eq!(value[0], 0.25);
+        assert_relative_eq!(value[1], 0.75);
+    }
+
+    #[test]
+    fn test_linear_rotation_slerps() {
+        //Identity to 90 degrees around z
+        let (sin, cos) = FRAC_PI_4.sin_cos();
+        let rotation = channel(
+            AnimationProperty::Rotation,
+            Interpolation::Linear,
+            vec![0.0, 1.0],
+            vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, sin, cos],
+            4,
+        );
+        let value = sample(&rotation, 0.5);
+        let (sin, cos) = (FRAC_PI_4 / 2.0).sin_cos();
+        assert_relative_eq!(value[2], sin, epsilon = 1e-5);
+        assert_relative_eq!(value[3], cos, epsilon = 1e-5);
+    }
+
+    #[test]
+    fn test_cubic_spline_uses_tangents() {
+        //In tangent, value and out tangent per keyframe. The unused first in tangent and
+        //last out tangent are large, so reading the wrong offset shows.
+        let weights = channel(
+            AnimationProperty::MorphWeights,
+            Interpolation::CubicSpline,
+            vec![0.0, 2.0],
+            vec![9.0, 0.0, 2.0, 0.0, 2.0, 9.0],
+            1,
+        );
+        //Hermite at ratio 0.5, tangents scaled by the keyframe delta of 2:
+        //0.5 * 0 + 0.125 * 2 * 2 + 0.5 * 2 - 0.125 * 2 * 0
+        assert_relative_eq!(sample(&weights, 1.0)[0], 1.5, epsilon = 1e-5);
+        //Keyframes are hit exactly
+        assert_relative_eq!(sample(&weights, 0.0)[0], 0.0);
+        assert_relative_eq!(sample(&weights, 2.0)[0], 2.0);
+    }
+
+    #[test]
+    fn test_cubic_spline_rotation_is_normalized() {
+        let (sin, cos) = FRAC_PI_4.sin_cos();
+        let mut values = vec![0.0; 4];
+        values.extend_from_slice(&[0.0, 0.0, 0.0, 1.0]);
+        values.extend_from_slice(&[0.0; 8]);
+        values.extend_from_slice(&[0.0, 0.0, sin, cos]);
+        values.extend_from_slice(&[0.0; 4]);
+        let rotation = channel(
+            AnimationProperty::Rotation,
+            Interpolation::CubicSpline,
+            vec![0.0, 1.0],
+            values,
+            4,
+        );
+        let value = sample(&rotation, 0.5);
+        let length = value.iter().map(|v| v * v).sum::<f32>().sqrt();
+        assert_relative_eq!(length, 1.0, epsilon = 1e-5);
+        //Halfway between the keyframes, which is 45 degrees around z
+        let (sin, cos) = (FRAC_PI_4 / 2.0).sin_cos();
+        assert_relative_eq!(value[2], sin, epsilon = 1e-5);
+        assert_relative_eq!(value[3], cos, epsilon = 1e-5);
+    }
+}
@@ -1,7 +1,15 @@
+pub mod accessor;
+pub mod animation;
 pub mod cache;
 pub mod modelcache;
+pub mod pbrmaterial;
+pub mod specgloss;
 pub mod timer;
 
+pub use accessor::*;
+pub use animation::*;
 pub use cache::*;
 pub use modelcache::*;
+pub use pbrmaterial::*;
+pub use specgloss::*;
 pub use timer::*;
@@ -1,213 +1,612 @@
-use std::path::{Path, PathBuf};
-
-use byteorder::{ByteOrder, LittleEndian};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
 use gltf::buffer::Data as BufferData;
 use gltf::image::Data as ImageData;
 use gltf::Document;
 use itertools::izip;
-use katla_math::{Sphere, Vec3};
+use katla_math::{
+    meshgen::{generate_flat_normals, generate_tangents},
+    Mat4, Quat, Sphere, Transform, Vec3, Vec4,
+};
+
+use super::{
+    convert_specular_glossiness, read_floats, read_indices, read_joints, AnimationClip, PbrMaterial,
+};
+use crate::rendering::{
+    MorphDelta, VertexJoints, VertexMorph, VertexNormal, VertexPBR, VertexPosition, VertexSkinned,
+    MAX_ACTIVE_MORPH_TARGETS,
+};
+
+//Integer positions, normals and texture coordinates, handled by `read_floats`
+const MESH_QUANTIZATION: &str = "KHR_mesh_quantization";
+
+/// A node of the glTF scene tree, indexed like the nodes of the document.
+#[derive(Clone, Debug)]
+pub struct ModelNode {
+    pub name: Option<String>,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    /// Relative to the parent
+    pub transform: Transform,
+    /// Submeshes drawn with the transform of this node
+    pub submeshes: Vec<usize>,
+    /// Morph target weights of the mesh of this node, the defaults of the file until
+    /// an animation sets them
+    pub weights: Vec<f32>,
+}
+
+impl ModelNode {
+    fn from_gltf(node: &gltf::Node) -> Self {
+        let (translation, rotation, scale) = node.transform().decomposed();
+        Self {
+            name: node.name().map(str::to_owned),
+            parent: None,
+            children: node.children().map(|child| child.index()).collect(),
+            transform: Transform {
+                position: Vec3(translation),
+                scale: Vec3(scale),
+                rotation: Quat::new_from_xyzw(rotation[0], rotation[1], rotation[2], rotation[3]),
+            },
+            submeshes: vec![],
+            weights: node
+                .weights()
+                .or_else(|| node.mesh().and_then(|mesh| mesh.weights()))
+                .map(<[f32]>::to_vec)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Model space matrix of every node reachable from `roots`, identity for the rest.
+    pub fn world_matrices(nodes: &[ModelNode], roots: &[usize]) -> Vec<Mat4> {
+        let mut world = vec![Mat4::identity(); nodes.len()];
+        let mut stack: Vec<(usize, Mat4)> =
+            roots.iter().map(|&root| (root, Mat4::identity())).collect();
+        while let Some((index, parent)) = stack.pop() {
+            let matrix = parent.mul(&nodes[index].transform.make_mat4());
+            for &child in &nodes[index].children {
+                stack.push((child, matrix.clone()));
+            }
+            world[index] = matrix;
+        }
+        world
+    }
+}
+
+/// A glTF skin, the joints whose nodes deform the vertices of its submeshes.
+#[derive(Clone, Debug)]
+pub struct ModelSkin {
+    pub name: Option<String>,
+    /// Node of every joint
+    pub joints: Vec<usize>,
+    /// Per joint, from mesh space to the space of the joint in the bind pose
+    pub inverse_bind_matrices: Vec<Mat4>,
+    /// Index of the first joint of this skin in the joint matrices of the model
+    pub first_joint: usize,
+}
+
+impl ModelSkin {
+    fn from_gltf(skin: &gltf::Skin, buffers: &[BufferData], first_joint: usize) -> Self {
+        let joints: Vec<usize> = skin.joints().map(|joint| joint.index()).collect();
+        let reader = skin.reader(|buffer| buffers.get(buffer.index()).map(|data| &data[..]));
+        //Missing inverse bind matrices are identities
+        let mut inverse_bind_matrices: Vec<Mat4> = reader
+            .read_inverse_bind_matrices()
+            .map(|matrices| matrices.map(|matrix| Mat4(matrix.map(Vec4))).collect())
+            .unwrap_or_default();
+        inverse_bind_matrices.resize(joints.len(), Mat4::identity());
+        Self {
+            name: skin.name().map(str::to_owned),
+            joints,
+            inverse_bind_matrices,
+            first_joint,
+        }
+    }
 
-use crate::rendering::{VertexNormal, VertexPBR, VertexPosition};
+    //Joints of a vertex offset to the joint matrices of the model, with weights summing to 1.
+    //Vertices only with joints the skin does not have are not deformed.
+    fn vertex_joints(&self, mut joints: [u32; 4], mut weights: [f32; 4]) -> VertexJoints {
+        for (joint, weight) in joints.iter_mut().zip(weights.iter_mut()) {
+            if *joint as usize >= self.joints.len() {
+                *joint = 0;
+                *weight = 0.0;
+            }
+            *joint += self.first_joint as u32;
+        }
+        let total: f32 = weights.iter().sum();
+        if total <= 0.0 {
+            return VertexJoints::default();
+        }
+        VertexJoints {
+            joints,
+            weights: weights.map(|weight| weight / total),
+        }
+    }
+
+    /// Matrices moving vertices from mesh space to model space in the pose of `node_world`,
+    /// for the joints of all skins one after another.
+    ///
+    /// The node of a skinned mesh does not move it, only its joints do.
+    pub fn joint_matrices(skins: &[ModelSkin], node_world: &[Mat4]) -> Vec<Mat4> {
+        skins
+            .iter()
+            .flat_map(|skin| {
+                skin.joints
+                    .iter()
+                    .zip(&skin.inverse_bind_matrices)
+                    .map(|(&joint, inverse_bind)| node_world[joint].mul(inverse_bind))
+            })
+            .collect()
+    }
+}
+
+/// One glTF primitive, a range of `GLTFModel::index_data` drawn with one material.
+#[derive(Clone, Debug)]
+pub struct Submesh {
+    pub node: usize,
+    pub first_index: u32,
+    pub index_count: u32,
+    /// Index of the glTF material, None for the default material
+    pub material: Option<usize>,
+    /// Skin deforming the submesh, which then ignores the transform of `node`
+    pub skin: Option<usize>,
+    /// Bounds in the space of `node`, or in mesh space when skinned
+    pub bounds: Sphere,
+}
+
+impl Submesh {
+    /// Bounds in model space, in the pose of `node_world` and `joint_matrices`.
+    pub fn model_bounds(
+        &self,
+        skins: &[ModelSkin],
+        node_world: &[Mat4],
+        joint_matrices: &[Mat4],
+    ) -> Sphere {
+        let skin = match self.skin {
+            Some(skin) if !skins[skin].joints.is_empty() => &skins[skin],
+            _ => return self.bounds.transformed(&node_world[self.node]),
+        };
+        //A skinned vertex is a blend of its position moved by each of its joints, so it
+        //stays within the bounds moved by every joint
+        let joints = &joint_matrices[skin.first_joint..skin.first_joint + skin.joints.len()];
+        let mut bounds = self.bounds.transformed(&joints[0]);
+        for joint in &joints[1..] {
+            bounds.enclose(&self.bounds.transformed(joint));
+        }
+        bounds
+    }
+}
 
 #[derive(Clone)]
 pub struct GLTFModel {
+    /// File name of the asset, used to name its GPU objects
+    pub name: String,
     pub document: Document,
     pub buffers: Vec<BufferData>,
     pub images: Vec<ImageData>,
+    /// Vertices of all primitives, each in the space of the node it belongs to
     pub vertex_data: Vec<VertexPBR>,
+    /// Indices of all primitives, offset to point into `vertex_data`
     pub index_data: Vec<u8>,
     pub index_stride: u8,
+    pub nodes: Vec<ModelNode>,
+    /// Nodes of the scene without a parent
+    pub roots: Vec<usize>,
+    pub submeshes: Vec<Submesh>,
+    pub skins: Vec<ModelSkin>,
+    pub animations: Vec<AnimationClip>,
+    /// Joints of every vertex in `vertex_data`, empty unless `is_skinned`
+    pub joint_data: Vec<VertexJoints>,
+    /// Morph targets of every vertex in `vertex_data`, empty without morph targets
+    pub morph_data: Vec<VertexMorph>,
+    /// Deltas of all morph targets, see `VertexMorph`
+    pub morph_deltas: Vec<MorphDelta>,
+    /// Indexed like the materials of the document, see `Submesh::material`
+    pub materials: Vec<PbrMaterial>,
+    /// Bounds of the whole model in its bind pose
     pub bounds: Sphere,
 }
 
+//Everything parsed from one primitive
+struct PrimitiveData {
+    vertices: Vec<VertexPBR>,
+    //Empty without a skin
+    joints: Vec<VertexJoints>,
+    //Deltas of every vertex per morph target
+    morph_targets: Vec<Vec<MorphDelta>>,
+    indices: Vec<u32>,
+    //Of the indices in the file
+    index_stride: u8,
+    bounds: Sphere,
+}
+
+//Position, normal and tangent deltas of one morph target, missing ones are empty
+type MorphTargetData = [Vec<[f32; 3]>; 3];
+
+//Copies a vertex attribute to the vertices generated from `sources`. An attribute shorter
+//than the `vertex_count` vertices it was read for cannot be copied, it is cleared rather
+//than left matching the old vertices, and reads as missing.
+fn remap_vertices<T: Copy>(attribute: &mut Vec<T>, vertex_count: usize, sources: &[u32]) {
+    if attribute.len() >= vertex_count {
+        *attribute = sources
+            .iter()
+            .map(|&source| attribute[source as usize])
+            .collect();
+    } else {
+        attribute.clear();
+    }
+}
+
 impl GLTFModel {
-    fn parse_node(&self, node: &gltf::Node) -> (Vec<VertexPBR>, Vec<u8>, u8, Sphere) {
+    //Joints are offset to `skin` when it is given, otherwise they are left out
+    fn parse_primitive(
+        buffers: &[BufferData],
+        primitive: &gltf::Primitive,
+        skin: Option<&ModelSkin>,
+    ) -> PrimitiveData {
         let mut positions: Vec<[f32; 3]> = vec![];
         let mut normals: Vec<[f32; 3]> = vec![];
+        let mut tangents: Vec<[f32; 4]> = vec![];
         let mut tex_coords: Vec<[f32; 2]> = vec![];
-        let mut index_stride = 0u8;
-        let mut index_data = vec![];
-        let mut vertex_data = vec![];
+        let mut joints: Vec<[u32; 4]> = vec![];
+        let mut weights: Vec<[f32; 4]> = vec![];
         let mut sphere = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 0.0);
-        if let Some(mesh) = node.mesh() {
-            for primitive in mesh.primitives() {
-                let mut start_index: usize;
-                let mut end_index: usize;
-                //TODO: Upload entire buffer and sample from it as the accessor tells us:
-                let num_attributes = primitive.attributes().len();
-
-                for (semantic, accessor) in primitive.attributes() {
-                    let buffer_view = accessor.view().unwrap();
-                    let acc_total_size = accessor.size() * accessor.count();
-                    let acc_stride = accessor.size();
-                    let buf_index = buffer_view.buffer().index();
-                    let buf_stride = buffer_view.stride();
-                    let mut interleaving_step = num_attributes;
-                    if buf_stride.is_none() || buf_stride.unwrap() == acc_stride {
-                        interleaving_step = 1;
-                        end_index = acc_total_size;
-                    } else {
-                        end_index = buffer_view.length();
-                    }
-                    start_index = accessor.offset() + buffer_view.offset();
-                    end_index += start_index;
-                    let attr_buf = &self.buffers[buf_index];
-                    let attr_arr = (&attr_buf[start_index..end_index]).to_vec();
-                    let iter = attr_arr.chunks(acc_stride).step_by(interleaving_step);
-                    //Striding needs to be acknowledged
-                    match semantic {
-                        gltf::mesh::Semantic::Positions => {
-                            positions = iter
-                                .map(|bytes| {
-                                    [
-                                        LittleEndian::read_f32(&bytes[0..4]),
-                                        LittleEndian::read_f32(&bytes[4..8]),
-                                        LittleEndian::read_f32(&bytes[8..12]),
-                                    ]
-                                })
-                                .collect::<Vec<[f32; 3]>>();
-                        }
-                        gltf::mesh::Semantic::Normals => {
-                            normals = iter
-                                .map(|bytes| {
-                                    [
-                                        LittleEndian::read_f32(&bytes[0..4]),
-                                        LittleEndian::read_f32(&bytes[4..8]),
-                                        LittleEndian::read_f32(&bytes[8..12]),
-                                    ]
-                                })
-                                .collect::<Vec<[f32; 3]>>();
-                        }
-                        gltf::mesh::Semantic::TexCoords(0) => {
-                            tex_coords = iter
-                                .map(|bytes| {
-                                    [
-                                        LittleEndian::read_f32(&bytes[0..4]),
-                                        LittleEndian::read_f32(&bytes[4..8]),
-                                    ]
-                                })
-                                .collect::<Vec<[f32; 2]>>();
-                        }
-                        _ => {
-                            continue;
-                        }
-                    }
-                }
+        let mut morph_targets: Vec<MorphTargetData> = primitive
+            .morph_targets()
+            .map(|target| {
+                [target.positions(), target.normals(), target.tangents()].map(|accessor| {
+                    accessor
+                        .and_then(|accessor| read_floats(&accessor, buffers))
+                        .unwrap_or_default()
+                })
+            })
+            .collect();
 
-                if let Some(indices) = primitive.indices() {
-                    let ind_view = indices.view().unwrap();
-                    let ind_offset = ind_view.offset();
-                    let ind_size = ind_view.length();
-                    let acc_size = indices.size();
-                    index_stride = acc_size as u8;
-                    let buf_index = ind_view.buffer().index();
-                    let ind_buf = &self.buffers[buf_index];
-                    index_data = ind_buf[ind_offset..ind_offset + ind_size].to_vec();
+        for (semantic, accessor) in primitive.attributes() {
+            match semantic {
+                gltf::mesh::Semantic::Positions => {
+                    positions = read_floats(&accessor, buffers).unwrap_or_default();
+                }
+                gltf::mesh::Semantic::Normals => {
+                    normals = read_floats(&accessor, buffers).unwrap_or_default();
+                }
+                gltf::mesh::Semantic::Tangents => {
+                    tangents = read_floats(&accessor, buffers).unwrap_or_default();
+                }
+                gltf::mesh::Semantic::TexCoords(0) => {
+                    tex_coords = read_floats(&accessor, buffers).unwrap_or_default();
+                }
+                gltf::mesh::Semantic::Joints(0) if skin.is_some() => {
+                    joints = read_joints(&accessor, buffers).unwrap_or_default();
+                }
+                gltf::mesh::Semantic::Weights(0) if skin.is_some() => {
+                    weights = read_floats(&accessor, buffers).unwrap_or_default();
+                }
+                _ => {
+                    continue;
                 }
             }
-            let has_pos = !positions.is_empty();
-            let has_norm = !normals.is_empty();
-            let has_tex_coords = !tex_coords.is_empty();
+        }
+
+        let (mut index_data, index_stride) = match primitive.indices() {
+            Some(indices) => (
+                read_indices(&indices, buffers).unwrap_or_default(),
+                indices.data_type().size() as u8,
+            ),
+            //Non-indexed primitives draw their vertices in order
+            None => ((0..positions.len() as u32).collect(), 1),
+        };
 
-            if has_pos {
-                sphere = Sphere::create_from_verts(&positions);
+        if !positions.is_empty() {
+            sphere = Sphere::create_from_verts(&positions);
+        }
+
+        //The glTF spec asks for flat normals when they are missing, which needs a vertex
+        //per triangle corner. Tangents from the file would not match them.
+        if normals.len() < positions.len() {
+            let flat = generate_flat_normals(&positions, &index_data);
+            let count = positions.len();
+            remap_vertices(&mut tex_coords, count, &flat.sources);
+            remap_vertices(&mut joints, count, &flat.sources);
+            remap_vertices(&mut weights, count, &flat.sources);
+            //Normal deltas only apply to normals from the file, like tangent deltas
+            for [position_deltas, normal_deltas, _] in &mut morph_targets {
+                remap_vertices(position_deltas, count, &flat.sources);
+                normal_deltas.clear();
+            }
+            positions = flat.remap(&positions);
+            normals = flat.normals;
+            index_data = flat.indices;
+            tangents.clear();
+        }
+        if tangents.len() < positions.len() {
+            //MikkTSpace tangents, splits vertices whose triangles need different tangents
+            let generated = generate_tangents(&positions, &normals, &tex_coords, &index_data);
+            let count = positions.len();
+            remap_vertices(&mut tex_coords, count, &generated.sources);
+            remap_vertices(&mut joints, count, &generated.sources);
+            remap_vertices(&mut weights, count, &generated.sources);
+            for [position_deltas, normal_deltas, tangent_deltas] in &mut morph_targets {
+                remap_vertices(position_deltas, count, &generated.sources);
+                remap_vertices(normal_deltas, count, &generated.sources);
+                tangent_deltas.clear();
             }
+            positions = generated.remap(&positions);
+            normals = generated.remap(&normals);
+            index_data = generated.indices;
+            tangents = generated.tangents;
+        }
 
-            if has_pos && has_norm && has_tex_coords {
-                vertex_data = izip!(positions, normals, tex_coords)
-                    .map(|(position, normal, tex_coord)| VertexPBR {
-                        position,
-                        normal,
-                        tangent: [0.0, 0.0, 0.0, 0.0],
-                        tex_coord0: tex_coord,
-                    })
-                    .collect::<Vec<VertexPBR>>();
-            } else if has_pos && has_norm {
-                vertex_data = positions
-                    .into_iter()
-                    .zip(normals.into_iter())
-                    .map(|(position, normal)| VertexPBR {
-                        position,
-                        normal,
-                        tangent: [0.0, 0.0, 0.0, 0.0],
-                        tex_coord0: [0.0, 0.0],
-                    })
-                    .collect::<Vec<VertexPBR>>();
-            } else if has_pos && has_tex_coords {
-                vertex_data = positions
-                    .into_iter()
-                    .zip(tex_coords.into_iter())
-                    .map(|(position, tex_coord0)| VertexPBR {
-                        position,
-                        normal: [0.0, 0.0, 0.0],
-                        tangent: [0.0, 0.0, 0.0, 0.0],
-                        tex_coord0,
+        let vertex_count = positions.len();
+        //Texture coordinates missing or shorter than the positions are filled with zeros
+        let vertex_data = izip!(positions, normals, tangents)
+            .enumerate()
+            .map(|(i, (position, normal, tangent))| VertexPBR {
+                position,
+                normal,
+                tangent,
+                tex_coord0: tex_coords.get(i).copied().unwrap_or([0.0, 0.0]),
+            })
+            .collect();
+
+        let joint_data = match skin {
+            Some(skin) => (0..vertex_count)
+                .map(|i| match (joints.get(i), weights.get(i)) {
+                    (Some(&joints), Some(&weights)) => skin.vertex_joints(joints, weights),
+                    _ => VertexJoints::default(),
+                })
+                .collect(),
+            None => vec![],
+        };
+
+        //Deltas missing for some vertices are 0
+        let delta = |deltas: &[[f32; 3]], i: usize| {
+            let [x, y, z] = deltas.get(i).copied().unwrap_or([0.0; 3]);
+            [x, y, z, 0.0]
+        };
+        let morph_targets: Vec<Vec<MorphDelta>> = morph_targets
+            .iter()
+            .map(|[position_deltas, normal_deltas, tangent_deltas]| {
+                (0..vertex_count)
+                    .map(|i| MorphDelta {
+                        position: delta(position_deltas, i),
+                        normal: delta(normal_deltas, i),
+                        tangent: delta(tangent_deltas, i),
                     })
-                    .collect::<Vec<VertexPBR>>();
-            } else if has_pos {
-                //TODO: Auto-gen normals smoothly with triangle-data:
-                vertex_data = positions
-                    .into_iter()
-                    .map(|position| {
-                        let vert0 = katla_math::Vec3(position);
-                        let norm0 = vert0.normalize();
-                        VertexPBR {
-                            position,
-                            normal: norm0.0,
-                            tangent: [0.0, 0.0, 0.0, 0.0],
-                            tex_coord0: [0.0, 0.0],
-                        }
+                    .collect()
+            })
+            .collect();
+        //Enough for every vertex moved by the targets that can be active at once, at weights
+        //between -1 and 1
+        let mut reaches: Vec<f32> = morph_targets
+            .iter()
+            .map(|deltas| {
+                deltas
+                    .iter()
+                    .map(|delta| {
+                        let [x, y, z, _] = delta.position;
+                        Vec3::new(x, y, z).distance()
                     })
-                    .collect::<Vec<VertexPBR>>();
-            }
+                    .fold(0.0, f32::max)
+            })
+            .collect();
+        reaches.sort_by(|a, b| b.total_cmp(a));
+        sphere.radius += reaches.iter().take(MAX_ACTIVE_MORPH_TARGETS).sum::<f32>();
+
+        PrimitiveData {
+            vertices: vertex_data,
+            joints: joint_data,
+            morph_targets,
+            indices: index_data,
+            index_stride,
+            bounds: sphere,
         }
-        (vertex_data, index_data, index_stride, sphere)
     }
 
     fn parse_gltf(&mut self) {
-        let mut used_nodes = vec![];
-        for scene in self.document.scenes() {
-            for node in scene.nodes() {
-                used_nodes.push(node.index());
-                for child in node.children() {
-                    used_nodes.push(child.index());
-                }
+        for material in self.document.materials() {
+            let pbr = PbrMaterial::from_gltf(&material);
+            self.materials
+                .push(match material.pbr_specular_glossiness() {
+                    Some(spec_gloss) => {
+                        convert_specular_glossiness(&spec_gloss, pbr, &mut self.images)
+                    }
+                    None => pbr,
+                });
+        }
+        let mut first_joint = 0;
+        for skin in self.document.skins() {
+            let skin = ModelSkin::from_gltf(&skin, &self.buffers, first_joint);
+            first_joint += skin.joints.len();
+            self.skins.push(skin);
+        }
+        self.animations = self
+            .document
+            .animations()
+            .map(|animation| AnimationClip::from_gltf(&animation, &self.buffers))
+            .collect();
+        self.nodes = self
+            .document
+            .nodes()
+            .map(|node| ModelNode::from_gltf(&node))
+            .collect();
+        for node in self.document.nodes() {
+            for child in node.children() {
+                self.nodes[child.index()].parent = Some(node.index());
             }
         }
-        // let mut parsed_mats = vec![];
+        let scene = self
+            .document
+            .default_scene()
+            .or_else(|| self.document.scenes().next());
+        self.roots = match scene {
+            Some(scene) => scene.nodes().map(|node| node.index()).collect(),
+            None => vec![],
+        };
 
-        for node in self.document.nodes() {
-            if used_nodes.contains(&node.index()) {
-                let (vertex_data, index_data, index_stride, sphere) = self.parse_node(&node);
-                self.vertex_data.extend(vertex_data);
-                self.index_data.extend(index_data);
-                self.index_stride = index_stride;
-                self.bounds = sphere;
+        let mut vertex_data = vec![];
+        let mut joint_data = vec![];
+        let mut morph_data = vec![];
+        let mut morph_deltas = vec![];
+        let mut indices: Vec<u32> = vec![];
+        let mut index_stride = 1u8;
+        //Submeshes of every mesh and skin parsed so far, meshes used by several nodes with
+        //the same skin are shared
+        let mut parsed_meshes: HashMap<(usize, Option<usize>), Vec<Submesh>> = HashMap::new();
+        let nodes: Vec<gltf::Node> = self.document.nodes().collect();
+        let mut stack: Vec<usize> = self.roots.iter().rev().copied().collect();
+        while let Some(node_index) = stack.pop() {
+            let node = &nodes[node_index];
+            stack.extend(self.nodes[node_index].children.iter().rev());
+            let mesh = match node.mesh() {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            let skin = node.skin().map(|skin| skin.index());
+            let submeshes = parsed_meshes
+                .entry((mesh.index(), skin))
+                .or_insert_with(|| {
+                    let mut submeshes = vec![];
+                    for primitive in mesh.primitives() {
+                        if primitive.mode() != gltf::mesh::Mode::Triangles {
+                            println!(
+                                "Skipping {:?} primitive in {}, only triangles are supported",
+                                primitive.mode(),
+                                self.name
+                            );
+                            continue;
+                        }
+                        let mut data = Self::parse_primitive(
+                            &self.buffers,
+                            &primitive,
+                            skin.map(|skin| &self.skins[skin]),
+                        );
+                        let base_vertex = vertex_data.len() as u32;
+                        let vertex_count = data.vertices.len();
+                        submeshes.push(Submesh {
+                            node: node_index,
+                            first_index: indices.len() as u32,
+                            index_count: data.indices.len() as u32,
+                            material: primitive.material().index(),
+                            skin,
+                            bounds: data.bounds,
+                        });
+                        index_stride = index_stride.max(data.index_stride);
+                        indices.extend(data.indices.iter().map(|index| index + base_vertex));
+                        data.joints.resize(vertex_count, VertexJoints::default());
+                        joint_data.extend(data.joints);
+                        let first_delta = morph_deltas.len();
+                        morph_data.extend((0..vertex_count).map(|i| VertexMorph {
+                            first_delta: (first_delta + i) as u32,
+                            target_stride: vertex_count as u32,
+                            target_count: data.morph_targets.len() as u32,
+                        }));
+                        morph_deltas.extend(data.morph_targets.concat());
+                        vertex_data.extend(data.vertices);
+                    }
+                    submeshes
+                });
+            for submesh in submeshes.iter() {
+                self.nodes[node_index].submeshes.push(self.submeshes.len());
+                self.submeshes.push(Submesh {
+                    node: node_index,
+                    ..submesh.clone()
+                });
             }
         }
+
+        //Offset indices can outgrow the type they were stored as
+        let max_index = vertex_data.len().saturating_sub(1);
+        if max_index > u16::MAX as usize {
+            index_stride = 4;
+        } else if max_index > u8::MAX as usize {
+            index_stride = index_stride.max(2);
+        }
+        self.index_data = match index_stride {
+            1 => indices.iter().map(|&index| index as u8).collect(),
+            2 => indices
+                .iter()
+                .flat_map(|&index| (index as u16).to_le_bytes())
+                .collect(),
+            _ => indices
+                .iter()
+                .flat_map(|&index| index.to_le_bytes())
+                .collect(),
+        };
+        self.index_stride = index_stride;
+        self.vertex_data = vertex_data;
+        if self.is_skinned() {
+            self.joint_data = joint_data;
+        }
+        if !morph_deltas.is_empty() {
+            self.morph_data = morph_data;
+            self.morph_deltas = morph_deltas;
+        }
+
+        let world = ModelNode::world_matrices(&self.nodes, &self.roots);
+        let joint_matrices = ModelSkin::joint_matrices(&self.skins, &world);
+        let mut submesh_bounds = self
+            .submeshes
+            .iter()
+            .map(|submesh| submesh.model_bounds(&self.skins, &world, &joint_matrices));
+        if let Some(mut bounds) = submesh_bounds.next() {
+            submesh_bounds.for_each(|other| bounds.enclose(&other));
+            self.bounds = bounds;
+        }
+    }
+
+    //gltf::import rejects files requiring extensions it does not know, although decoding
+    //quantized attributes is up to the reader
+    fn import(path: &Path) -> (Document, Vec<BufferData>, Vec<ImageData>) {
+        let gltf::Gltf { document, blob } =
+            gltf::Gltf::from_slice_without_validation(&std::fs::read(path).unwrap()).unwrap();
+        let mut root = document.into_json();
+        root.extensions_required
+            .retain(|extension| extension != MESH_QUANTIZATION);
+        let document = Document::from_json(root).unwrap();
+        let base = path.parent();
+        let buffers = gltf::import_buffers(&document, base, blob).unwrap();
+        let images = gltf::import_images(&document, base, &buffers).unwrap();
+        (document, buffers, images)
     }
 
     fn new<P>(path: P) -> Self
     where
         P: AsRef<Path>,
     {
-        let (document, buffers, images) = gltf::import(path).unwrap();
+        let name = path
+            .as_ref()
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let (document, buffers, images) = Self::import(path.as_ref());
 
         let mut model = Self {
+            name,
             document,
             buffers,
             images,
             vertex_data: vec![],
             index_data: vec![],
             index_stride: 0,
+            nodes: vec![],
+            roots: vec![],
+            submeshes: vec![],
+            skins: vec![],
+            animations: vec![],
+            joint_data: vec![],
+            morph_data: vec![],
+            morph_deltas: vec![],
+            materials: vec![],
             bounds: Sphere::new(Vec3::new(0.0, 0.0, 0.0), 0.0),
         };
         model.parse_gltf();
         model
     }
 
-    //FIXME: This is only really valid for one node in the structure!
+    /// Positions of all vertices, each in the space of its node, see `Submesh::node`.
     pub fn vertpos(&self) -> Vec<VertexPosition> {
         self.vertex_data
             .iter()
@@ -231,6 +630,31 @@ impl GLTFModel {
         self.vertex_data.clone()
     }
 
+    /// Whether the model has any joints, then its vertices come with `joint_data`.
+    pub fn is_skinned(&self) -> bool {
+        self.skins.iter().any(|skin| !skin.joints.is_empty())
+    }
+
+    pub fn has_morph_targets(&self) -> bool {
+        !self.morph_deltas.is_empty()
+    }
+
+    /// Vertices with their joints, empty when the model has no skins.
+    pub fn vertskinned(&self) -> Vec<VertexSkinned> {
+        self.vertex_data
+            .iter()
+            .zip(&self.joint_data)
+            .map(|(x, joints)| VertexSkinned {
+                position: x.position,
+                normal: x.normal,
+                tangent: x.tangent,
+                tex_coord0: x.tex_coord0,
+                joints: joints.joints,
+                weights: joints.weights,
+            })
+            .collect()
+    }
+
     pub fn index_data(&self) -> Vec<u8> {
         self.index_data.clone()
     }
@@ -241,3 +665,184 @@ impl From<PathBuf> for GLTFModel {
         GLTFModel::new(pathbuf.as_path())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use katla_math::mat4_mul_vec3;
+
+    #[test]
+    fn test_remap_vertices() {
+        let sources = [0, 1, 2, 0, 2, 3];
+        let mut tex_coords = vec![[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+        remap_vertices(&mut tex_coords, 4, &sources);
+        assert_eq!(
+            tex_coords,
+            [
+                [0.0, 0.0],
+                [1.0, 0.0],
+                [1.0, 1.0],
+                [0.0, 0.0],
+                [1.0, 1.0],
+                [0.0, 1.0]
+            ]
+        );
+
+        //Too short to belong to the 4 vertices
+        let mut weights = vec![[1.0, 0.0, 0.0, 0.0]; 3];
+        remap_vertices(&mut weights, 4, &sources);
+        assert!(weights.is_empty());
+    }
+
+    fn skin(joints: Vec<usize>, inverse_bind_matrices: Vec<Mat4>, first_joint: usize) -> ModelSkin {
+        ModelSkin {
+            name: None,
+            joints,
+            inverse_bind_matrices,
+            first_joint,
+        }
+    }
+
+    #[test]
+    fn test_joint_matrices() {
+        //The bind pose scales by 0.5, undone by the inverse bind matrix
+        let inverse_bind = Transform::new_from_scale(Vec3::new(2.0, 2.0, 2.0)).make_mat4();
+        let skins = [
+            skin(vec![1], vec![inverse_bind.clone()], 0),
+            skin(vec![2, 0], vec![Mat4::identity(), inverse_bind], 1),
+        ];
+        let node_world = [
+            Mat4::identity(),
+            Mat4::from_translation([1.0, 0.0, 0.0]),
+            Mat4::from_translation([0.0, 5.0, 0.0]),
+        ];
+        let matrices = ModelSkin::joint_matrices(&skins, &node_world);
+        assert_eq!(matrices.len(), 3);
+        let point = Vec3::new(1.0, 0.0, 0.0);
+        //Inverse bind matrix first, then the joint: 1 * 2 + 1, not (1 + 1) * 2
+        assert_eq!(mat4_mul_vec3(&matrices[0], &point).0, [3.0, 0.0, 0.0]);
+        assert_eq!(mat4_mul_vec3(&matrices[1], &point).0, [1.0, 5.0, 0.0]);
+        assert_eq!(mat4_mul_vec3(&matrices[2], &point).0, [2.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_vertex_joints() {
+        let skin = skin(vec![3, 4, 5], vec![Mat4::identity(); 3], 10);
+        //Weights summing to 0.75 are scaled up
+        let vertex = skin.vertex_joints([0, 2, 1, 0], [0.5, 0.25, 0.0, 0.0]);
+        assert_eq!(vertex.joints, [10, 12, 11, 10]);
+        assert_relative_eq!(vertex.weights[0], 2.0 / 3.0);
+        assert_relative_eq!(vertex.weights[1], 1.0 / 3.0);
+        assert_eq!(vertex.weights[2..], [0.0, 0.0]);
+
+        //Joint 7 is not in the skin, its weight goes to the others
+        let vertex = skin.vertex_joints([7, 1, 0, 0], [0.5, 0.5, 0.0, 0.0]);
+        assert_eq!(vertex.joints, [10, 11, 10, 10]);
+        assert_eq!(vertex.weights, [0.0, 1.0, 0.0, 0.0]);
+
+        //Nothing left to deform the vertex
+        let vertex = skin.vertex_joints([7, 8, 0, 0], [0.5, 0.5, 0.0, 0.0]);
+        assert_eq!(vertex.joints, [0; 4]);
+        assert_eq!(vertex.weights, [0.0; 4]);
+    }
+
+    const TRIANGLE: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+
+    //A non-indexed triangle with a morph target per entry of `targets`, parsed as a primitive
+    fn parse_triangle(targets: &[[[f32; 3]; 3]]) -> PrimitiveData {
+        let mut data = vec![];
+        let mut views = vec![];
+        let mut accessors = vec![];
+        for vertices in std::iter::once(&TRIANGLE).chain(targets) {
+            let (mut min, mut max) = ([f32::MAX; 3], [f32::MIN; 3]);
+            for vertex in vertices {
+                for axis in 0..3 {
+                    min[axis] = min[axis].min(vertex[axis]);
+                    max[axis] = max[axis].max(vertex[axis]);
+                }
+            }
+            views.push(format!(
+                r#"{{ "buffer": 0, "byteOffset": {}, "byteLength": 36 }}"#,
+                data.len()
+            ));
+            accessors.push(format!(
+                r#"{{ "bufferView": {}, "componentType": 5126, "count": 3, "type": "VEC3",
+                      "min": {:?}, "max": {:?} }}"#,
+                accessors.len(),
+                min,
+                max
+            ));
+            for v in vertices.iter().flatten() {
+                data.extend(v.to_le_bytes());
+            }
+        }
+        let targets: Vec<String> = (1..accessors.len())
+            .map(|accessor| format!(r#"{{ "POSITION": {} }}"#, accessor))
+            .collect();
+        let json = format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "buffers": [{{ "byteLength": {} }}],
+                "bufferViews": [{}],
+                "accessors": [{}],
+                "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0 }},
+                                                "targets": [{}] }}] }}]
+            }}"#,
+            data.len(),
+            views.join(","),
+            accessors.join(","),
+            targets.join(",")
+        );
+        let gltf = gltf::Gltf::from_slice(json.as_bytes()).unwrap();
+        let buffers = gltf::import_buffers(&gltf.document, None, Some(data)).unwrap();
+        let primitive = gltf
+            .document
+            .meshes()
+            .next()
+            .unwrap()
+            .primitives()
+            .next()
+            .unwrap();
+        GLTFModel::parse_primitive(&buffers, &primitive, None)
+    }
+
+    #[test]
+    fn test_morph_target_bounds() {
+        let base = Sphere::create_from_verts(&TRIANGLE);
+        //At weight 1 the second vertex moves to x = 4
+        let moved = Vec3::new(4.0, 0.0, 0.0);
+        assert!(!base.point_inside(moved));
+        let data = parse_triangle(&[[[0.0; 3], [3.0, 0.0, 0.0], [0.0; 3]]]);
+        assert_eq!(data.morph_targets.len(), 1);
+        assert!(data.bounds.point_inside(moved));
+        //And to x = -2 at weight -1
+        assert!(data.bounds.point_inside(Vec3::new(-2.0, 0.0, 0.0)));
+
+        //Still enclosed in model space, where the node moves the submesh up by 10
+        let submesh = Submesh {
+            node: 0,
+            first_index: 0,
+            index_count: 3,
+            material: None,
+            skin: None,
+            bounds: data.bounds,
+        };
+        let node_world = [Mat4::from_translation([0.0, 10.0, 0.0])];
+        let bounds = submesh.model_bounds(&[], &node_world, &[]);
+        assert!(bounds.point_inside(Vec3::new(4.0, 10.0, 0.0)));
+    }
+
+    #[test]
+    fn test_morph_target_bounds_capped() {
+        let base = Sphere::create_from_verts(&TRIANGLE);
+        //Ten targets, of which only the eight largest can be active at once
+        let targets: Vec<[[f32; 3]; 3]> = (0..10)
+            .map(|i| [[0.0; 3], [0.0; 3], [0.0, 1.0 + i as f32 * 0.1, 0.0]])
+            .collect();
+        let data = parse_triangle(&targets);
+        assert_eq!(data.morph_targets.len(), 10);
+        let largest: f32 = (2..10).map(|i| 1.0 + i as f32 * 0.1).sum();
+        assert_relative_eq!(data.bounds.radius, base.radius + largest, epsilon = 1e-4);
+    }
+}
@@ -0,0 +1,190 @@
+/// How the alpha of the base color is used, see the glTF alphaMode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlphaMode {
+    Opaque,
+    /// Fragments below `PbrMaterial::alpha_cutoff` are discarded
+    Mask,
+    Blend,
+}
+
+/// A glTF metallic-roughness material. Textures are indices into `GLTFModel::images`.
+/// Spec-gloss materials are converted to it, see `convert_specular_glossiness`.
+#[derive(Clone, Debug)]
+pub struct PbrMaterial {
+    pub name: Option<String>,
+    /// Linear RGBA, multiplied with the base color texture
+    pub base_color_factor: [f32; 4],
+    pub base_color_texture: Option<usize>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    /// Roughness in G, metalness in B
+    pub metallic_roughness_texture: Option<usize>,
+    pub normal_texture: Option<usize>,
+    pub normal_scale: f32,
+    /// Occlusion in R
+    pub occlusion_texture: Option<usize>,
+    pub occlusion_strength: f32,
+    pub emissive_factor: [f32; 3],
+    pub emissive_texture: Option<usize>,
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+    pub double_sided: bool,
+}
+
+//The glTF defaults, used by primitives without a material
+impl Default for PbrMaterial {
+    fn default() -> Self {
+        Self {
+            name: None,
+            base_color_factor: [1.0, 1.0, 1.0, 1.0],
+            base_color_texture: None,
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+            occlusion_texture: None,
+            occlusion_strength: 1.0,
+            emissive_factor: [0.0, 0.0, 0.0],
+            emissive_texture: None,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false,
+        }
+    }
+}
+
+fn image_index(texture: gltf::texture::Texture) -> usize {
+    texture.source().index()
+}
+
+impl PbrMaterial {
+    pub fn from_gltf(material: &gltf::Material) -> Self {
+        let pbr = material.pbr_metallic_roughness();
+        let normal = material.normal_texture();
+        let occlusion = material.occlusion_texture();
+        Self {
+            name: material.name().map(str::to_owned),
+            base_color_factor: pbr.base_color_factor(),
+            base_color_texture: pbr
+                .base_color_texture()
+                .map(|info| image_index(info.texture())),
+            metallic_factor: pbr.metallic_factor(),
+            roughness_factor: pbr.roughness_factor(),
+            metallic_roughness_texture: pbr
+                .metallic_roughness_texture()
+                .map(|info| image_index(info.texture())),
+            normal_scale: normal.as_ref().map_or(1.0, |normal| normal.scale()),
+            normal_texture: normal.map(|normal| image_index(normal.texture())),
+            occlusion_strength: occlusion
+                .as_ref()
+                .map_or(1.0, |occlusion| occlusion.strength()),
+            occlusion_texture: occlusion.map(|occlusion| image_index(occlusion.texture())),
+            emissive_factor: material.emissive_factor(),
+            emissive_texture: material
+                .emissive_texture()
+                .map(|info| image_index(info.texture())),
+            alpha_mode: match material.alpha_mode() {
+                gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+                gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+                gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+            },
+            alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+            double_sided: material.double_sided(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //A material with the defaults and one with everything set. Textures refer to images
+    //in another order, so the texture and image indices differ.
+    const GLTF: &str = r#"{
+        "asset": { "version": "2.0" },
+        "images": [{ "uri": "a.png" }, { "uri": "b.png" }, { "uri": "c.png" }],
+        "textures": [{ "source": 2 }, { "source": 0 }, { "source": 1 }],
+        "materials": [
+            {},
+            {
+                "name": "full",
+                "pbrMetallicRoughness": {
+                    "baseColorFactor": [0.5, 0.25, 1.0, 0.75],
+                    "baseColorTexture": { "index": 0 },
+                    "metallicFactor": 0.2,
+                    "roughnessFactor": 0.6,
+                    "metallicRoughnessTexture": { "index": 1, "texCoord": 0 }
+                },
+                "normalTexture": { "index": 2, "scale": 0.5 },
+                "occlusionTexture": { "index": 0, "strength": 0.3 },
+                "emissiveTexture": { "index": 1 },
+                "emissiveFactor": [1.0, 0.5, 0.0],
+                "alphaMode": "MASK",
+                "alphaCutoff": 0.25,
+                "doubleSided": true
+            },
+            { "alphaMode": "BLEND", "normalTexture": { "index": 1 } }
+        ]
+    }"#;
+
+    fn materials() -> Vec<PbrMaterial> {
+        let document = gltf::Gltf::from_slice(GLTF.as_bytes()).unwrap().document;
+        document
+            .materials()
+            .map(|m| PbrMaterial::from_gltf(&m))
+            .collect()
+    }
+
+    #[test]
+    fn test_defaults() {
+        let material = &materials()[0];
+        let default = PbrMaterial::default();
+        assert_eq!(material.name, None);
+        assert_eq!(material.base_color_factor, default.base_color_factor);
+        assert_eq!(material.base_color_texture, None);
+        assert_eq!(material.metallic_factor, 1.0);
+        assert_eq!(material.roughness_factor, 1.0);
+        assert_eq!(material.metallic_roughness_texture, None);
+        assert_eq!(material.normal_texture, None);
+        assert_eq!(material.normal_scale, 1.0);
+        assert_eq!(material.occlusion_texture, None);
+        assert_eq!(material.occlusion_strength, 1.0);
+        assert_eq!(material.emissive_factor, [0.0; 3]);
+        assert_eq!(material.emissive_texture, None);
+        assert_eq!(material.alpha_mode, AlphaMode::Opaque);
+        assert_eq!(material.alpha_cutoff, 0.5);
+        assert!(!material.double_sided);
+    }
+
+    #[test]
+    fn test_all_set() {
+        let material = &materials()[1];
+        assert_eq!(material.name.as_deref(), Some("full"));
+        assert_eq!(material.base_color_factor, [0.5, 0.25, 1.0, 0.75]);
+        assert_eq!(material.metallic_factor, 0.2);
+        assert_eq!(material.roughness_factor, 0.6);
+        assert_eq!(material.normal_scale, 0.5);
+        assert_eq!(material.occlusion_strength, 0.3);
+        assert_eq!(material.emissive_factor, [1.0, 0.5, 0.0]);
+        assert_eq!(material.alpha_mode, AlphaMode::Mask);
+        assert_eq!(material.alpha_cutoff, 0.25);
+        assert!(material.double_sided);
+        //Image indices, not texture indices
+        assert_eq!(material.base_color_texture, Some(2));
+        assert_eq!(material.metallic_roughness_texture, Some(0));
+        assert_eq!(material.normal_texture, Some(1));
+        assert_eq!(material.occlusion_texture, Some(2));
+        assert_eq!(material.emissive_texture, Some(0));
+    }
+
+    #[test]
+    fn test_blend_and_default_scale() {
+        let material = &materials()[2];
+        assert_eq!(material.alpha_mode, AlphaMode::Blend);
+        //The cutoff only applies to masks, the default is kept
+        assert_eq!(material.alpha_cutoff, 0.5);
+        assert_eq!(material.normal_texture, Some(0));
+        assert_eq!(material.normal_scale, 1.0);
+    }
+}
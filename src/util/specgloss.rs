@@ -0,0 +1,308 @@
+use gltf::image::{Data as ImageData, Format};
+
+use super::PbrMaterial;
+
+//Reflectance of dielectrics at normal incidence in the metallic-roughness model
+const DIELECTRIC_SPECULAR: f32 = 0.04;
+const EPSILON: f32 = 1e-6;
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let encoded = if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (encoded * 255.0).round() as u8
+}
+
+fn perceived_brightness(color: [f32; 3]) -> f32 {
+    (0.299 * color[0] * color[0] + 0.587 * color[1] * color[1] + 0.114 * color[2] * color[2]).sqrt()
+}
+
+fn solve_metallic(diffuse: f32, specular: f32, one_minus_specular_strength: f32) -> f32 {
+    if specular < DIELECTRIC_SPECULAR {
+        return 0.0;
+    }
+    let a = DIELECTRIC_SPECULAR;
+    let b = diffuse * one_minus_specular_strength / (1.0 - DIELECTRIC_SPECULAR) + specular
+        - 2.0 * DIELECTRIC_SPECULAR;
+    let c = DIELECTRIC_SPECULAR - specular;
+    let discriminant = (b * b - 4.0 * a * c).max(0.0);
+    ((-b + discriminant.sqrt()) / (2.0 * a)).clamp(0.0, 1.0)
+}
+
+/// Converts linear spec-gloss values to linear base color, metallic and roughness, with the
+/// conversion from the glTF sample viewers. Diffuse alpha is kept as base color alpha.
+pub fn specular_glossiness_to_metallic_roughness(
+    diffuse: [f32; 4],
+    specular: [f32; 3],
+    glossiness: f32,
+) -> ([f32; 4], f32, f32) {
+    let diffuse_rgb = [diffuse[0], diffuse[1], diffuse[2]];
+    let one_minus_specular_strength = 1.0 - specular[0].max(specular[1]).max(specular[2]);
+    let metallic = solve_metallic(
+        perceived_brightness(diffuse_rgb),
+        perceived_brightness(specular),
+        one_minus_specular_strength,
+    );
+
+    let mut base_color = [0.0, 0.0, 0.0, diffuse[3]];
+    for channel in 0..3 {
+        let from_diffuse = diffuse_rgb[channel] * one_minus_specular_strength
+            / (1.0 - DIELECTRIC_SPECULAR)
+            / (1.0 - metallic).max(EPSILON);
+        let from_specular =
+            (specular[channel] - DIELECTRIC_SPECULAR * (1.0 - metallic)) / metallic.max(EPSILON);
+        let weight = metallic * metallic;
+        base_color[channel] =
+            (from_diffuse + (from_specular - from_diffuse) * weight).clamp(0.0, 1.0);
+    }
+    (base_color, metallic, 1.0 - glossiness)
+}
+
+//RGBA of the texel at `u`, `v` in [0, 1), nearest neighbor so images of any size line up
+fn sample(image: &ImageData, u: f32, v: f32) -> [u8; 4] {
+    let x = ((u * image.width as f32) as u32).min(image.width - 1);
+    let y = ((v * image.height as f32) as u32).min(image.height - 1);
+    let texel = (y * image.width + x) as usize;
+    match image.format {
+        Format::R8G8B8 => {
+            let p = &image.pixels[texel * 3..texel * 3 + 3];
+            [p[0], p[1], p[2], 255]
+        }
+        _ => {
+            let p = &image.pixels[texel * 4..texel * 4 + 4];
+            [p[0], p[1], p[2], p[3]]
+        }
+    }
+}
+
+//Only 8 bit RGB(A) images can be baked, the others are ignored like in `Material`
+fn usable_image(images: &[ImageData], index: Option<usize>) -> Option<&ImageData> {
+    let image = images.get(index?)?;
+    match image.format {
+        Format::R8G8B8 | Format::R8G8B8A8 => Some(image),
+        format => {
+            println!("Unsupported spec-gloss texture format: {:?}", format);
+            None
+        }
+    }
+}
+
+/// A KHR_materials_pbrSpecularGlossiness material converted to metallic-roughness.
+///
+/// Without textures only the factors are converted. Otherwise the conversion depends on
+/// every texel, so a base color and a metallic-roughness texture are baked at the size of
+/// the larger texture and appended to `images`, and the factors become 1.
+pub fn convert_specular_glossiness(
+    spec_gloss: &gltf::material::PbrSpecularGlossiness,
+    mut material: PbrMaterial,
+    images: &mut Vec<ImageData>,
+) -> PbrMaterial {
+    let diffuse_factor = spec_gloss.diffuse_factor();
+    let specular_factor = spec_gloss.specular_factor();
+    let glossiness_factor = spec_gloss.glossiness_factor();
+    let diffuse_index = spec_gloss
+        .diffuse_texture()
+        .map(|info| info.texture().source().index());
+    let spec_gloss_index = spec_gloss
+        .specular_glossiness_texture()
+        .map(|info| info.texture().source().index());
+
+    let diffuse_image = usable_image(images, diffuse_index);
+    let spec_gloss_image = usable_image(images, spec_gloss_index);
+    let (width, height) = match (diffuse_image, spec_gloss_image) {
+        (None, None) => {
+            let (base_color, metallic, roughness) = specular_glossiness_to_metallic_roughness(
+                diffuse_factor,
+                specular_factor,
+                glossiness_factor,
+            );
+            material.base_color_factor = base_color;
+            material.base_color_texture = None;
+            material.metallic_factor = metallic;
+            material.roughness_factor = roughness;
+            material.metallic_roughness_texture = None;
+            return material;
+        }
+        (Some(image), None) | (None, Some(image)) => (image.width, image.height),
+        (Some(diffuse), Some(spec_gloss)) => (
+            diffuse.width.max(spec_gloss.width),
+            diffuse.height.max(spec_gloss.height),
+        ),
+    };
+
+    let texels = (width * height) as usize;
+    let mut base_color_pixels = Vec::with_capacity(texels * 4);
+    let mut metallic_roughness_pixels = Vec::with_capacity(texels * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let u = (x as f32 + 0.5) / width as f32;
+            let v = (y as f32 + 0.5) / height as f32;
+            let mut diffuse = diffuse_factor;
+            if let Some(image) = diffuse_image {
+                let texel = sample(image, u, v);
+                for channel in 0..3 {
+                    diffuse[channel] *= srgb_to_linear(texel[channel]);
+                }
+                diffuse[3] *= texel[3] as f32 / 255.0;
+            }
+            let mut specular = specular_factor;
+            let mut glossiness = glossiness_factor;
+            if let Some(image) = spec_gloss_image {
+                let texel = sample(image, u, v);
+                for channel in 0..3 {
+                    specular[channel] *= srgb_to_linear(texel[channel]);
+                }
+                glossiness *= texel[3] as f32 / 255.0;
+            }
+            let (base_color, metallic, roughness) =
+                specular_glossiness_to_metallic_roughness(diffuse, specular, glossiness);
+            base_color_pixels.extend_from_slice(&[
+                linear_to_srgb(base_color[0]),
+                linear_to_srgb(base_color[1]),
+                linear_to_srgb(base_color[2]),
+                (base_color[3].clamp(0.0, 1.0) * 255.0).round() as u8,
+            ]);
+            metallic_roughness_pixels.extend_from_slice(&[
+                0,
+                (roughness.clamp(0.0, 1.0) * 255.0).round() as u8,
+                (metallic * 255.0).round() as u8,
+                255,
+            ]);
+        }
+    }
+
+    let mut add_image = |pixels: Vec<u8>| {
+        images.push(ImageData {
+            pixels,
+            format: Format::R8G8B8A8,
+            width,
+            height,
+        });
+        images.len() - 1
+    };
+    material.base_color_factor = [1.0, 1.0, 1.0, 1.0];
+    material.base_color_texture = Some(add_image(base_color_pixels));
+    material.metallic_factor = 1.0;
+    material.roughness_factor = 1.0;
+    material.metallic_roughness_texture = Some(add_image(metallic_roughness_pixels));
+    material
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn test_dielectric_specular() {
+        let diffuse = [0.5, 0.3, 0.2, 0.75];
+        let (base_color, metallic, _) =
+            specular_glossiness_to_metallic_roughness(diffuse, [0.04; 3], 1.0);
+        assert_relative_eq!(metallic, 0.0, epsilon = 1e-5);
+        for (base, diffuse) in base_color.iter().zip(&diffuse) {
+            assert_relative_eq!(base, diffuse, epsilon = 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_metal() {
+        let (base_color, metallic, _) =
+            specular_glossiness_to_metallic_roughness([0.0, 0.0, 0.0, 1.0], [1.0; 3], 1.0);
+        assert_relative_eq!(metallic, 1.0, epsilon = 1e-5);
+        //The base color of a metal is its specular color
+        for channel in base_color {
+            assert_relative_eq!(channel, 1.0, epsilon = 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_roughness() {
+        for glossiness in [0.0, 0.25, 1.0] {
+            let (_, _, roughness) =
+                specular_glossiness_to_metallic_roughness([1.0; 4], [0.04; 3], glossiness);
+            assert_relative_eq!(roughness, 1.0 - glossiness);
+        }
+    }
+
+    //One spec-gloss material with a diffuse and a spec-gloss texture
+    const GLTF: &str = r#"{
+        "asset": { "version": "2.0" },
+        "extensionsUsed": ["KHR_materials_pbrSpecularGlossiness"],
+        "images": [{ "uri": "diffuse.png" }, { "uri": "specular.png" }],
+        "textures": [{ "source": 0 }, { "source": 1 }],
+        "materials": [{
+            "extensions": {
+                "KHR_materials_pbrSpecularGlossiness": {
+                    "diffuseTexture": { "index": 0 },
+                    "specularGlossinessTexture": { "index": 1 }
+                }
+            }
+        }]
+    }"#;
+
+    fn texel(image: &ImageData, x: u32, y: u32) -> &[u8] {
+        let texel = ((y * image.width + x) * 4) as usize;
+        &image.pixels[texel..texel + 4]
+    }
+
+    #[test]
+    fn test_bake_mismatched_textures() {
+        let document = gltf::Gltf::from_slice(GLTF.as_bytes()).unwrap().document;
+        let material = document.materials().next().unwrap();
+        let spec_gloss = material.pbr_specular_glossiness().unwrap();
+        let mut images = vec![
+            //2x1, red then half transparent blue
+            ImageData {
+                pixels: vec![255, 0, 0, 255, 0, 0, 255, 128],
+                format: Format::R8G8B8A8,
+                width: 2,
+                height: 1,
+            },
+            //1x2 without alpha, so fully glossy: dark dielectric on top, white metal below
+            ImageData {
+                pixels: vec![10, 10, 10, 255, 255, 255],
+                format: Format::R8G8B8,
+                width: 1,
+                height: 2,
+            },
+        ];
+        let converted = convert_specular_glossiness(
+            &spec_gloss,
+            PbrMaterial::from_gltf(&material),
+            &mut images,
+        );
+        assert_eq!(images.len(), 4);
+        assert_eq!(converted.base_color_texture, Some(2));
+        assert_eq!(converted.metallic_roughness_texture, Some(3));
+        assert_eq!(converted.base_color_factor, [1.0; 4]);
+        assert_eq!(converted.metallic_factor, 1.0);
+        assert_eq!(converted.roughness_factor, 1.0);
+
+        //Baked at the size of both textures together
+        let (base_color, metallic_roughness) = (&images[2], &images[3]);
+        assert_eq!((base_color.width, base_color.height), (2, 2));
+        assert_eq!(
+            (metallic_roughness.width, metallic_roughness.height),
+            (2, 2)
+        );
+        assert_eq!(base_color.pixels.len(), 16);
+        assert_eq!(texel(base_color, 0, 0), [255, 0, 0, 255]);
+        assert_eq!(texel(base_color, 1, 0), [0, 0, 255, 128]);
+        assert_eq!(texel(metallic_roughness, 1, 0), [0, 0, 0, 255]);
+        //The white specular texel is metal whatever the diffuse
+        assert_eq!(texel(base_color, 0, 1), [255, 255, 255, 255]);
+        assert_eq!(texel(metallic_roughness, 0, 1), [0, 0, 255, 255]);
+    }
+}
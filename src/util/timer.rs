@@ -1,4 +1,6 @@
-use std::{collections::VecDeque, time::Instant};
+use std::{collections::VecDeque, fmt::Write, time::Instant};
+
+use katla_vulkan::GpuScopeTiming;
 
 pub struct Timer {
     timestamps: VecDeque<f64>,
@@ -27,6 +29,44 @@ impl Timer {
         self.delta
     }
 
+    /// Mean, min and max CPU frame time in milliseconds over the last timestamps.
+    pub fn get_frame_times(&self) -> (f64, f64, f64) {
+        (self.current_mean, self.current_min, self.current_max)
+    }
+
+    /// CPU frame times next to the per pass GPU times, a GPU total close to the
+    /// CPU frame time means the frame is GPU bound.
+    pub fn report(&self, gpu_timings: &[GpuScopeTiming]) -> String {
+        let (mean, min, max) = self.get_frame_times();
+        let mut report = format!("CPU frame: {:.2} ms (min {:.2}, max {:.2})", mean, min, max);
+        if !gpu_timings.is_empty() {
+            let gpu_total: f64 = gpu_timings.iter().map(|timing| timing.milliseconds).sum();
+            write!(report, " | GPU: {:.2} ms (", gpu_total).unwrap();
+            for (i, timing) in gpu_timings.iter().enumerate() {
+                if i > 0 {
+                    report.push_str(", ");
+                }
+                write!(report, "{} {:.3} ms", timing.name, timing.milliseconds).unwrap();
+            }
+            report.push(')');
+        }
+        for timing in gpu_timings {
+            if let Some(stats) = &timing.statistics {
+                write!(
+                    report,
+                    "\n  {}: {} vertices, {} VS invocations, {} primitives, {} FS invocations",
+                    timing.name,
+                    stats.input_assembly_vertices,
+                    stats.vertex_shader_invocations,
+                    stats.clipping_primitives,
+                    stats.fragment_shader_invocations
+                )
+                .unwrap();
+            }
+        }
+        report
+    }
+
     pub fn add_timestamp(&mut self) {
         let in_timestamp = self.last_frame.elapsed().as_micros() as f64 / 1000.0;
         self.delta = self.last_frame.elapsed().as_micros() as f64 / 1_000_000.0;